@@ -64,10 +64,13 @@ fn main() {
             glob::glob("src/picoquic/picoquic/*.c")
                 .expect("failed to find picoquic c files")
                 .filter_map(|p| match p {
+                    // `tls_api.c` is compiled as part of the shim
+                    Ok(ref p) if p.ends_with("tls_api.c") => None,
                     Ok(p) => Some(p),
                     _ => None,
                 }),
         )
+        .file("shim/shim.c")
         .include("src/picoquic/picoquic")
         .include("src/picotls/include/");
 
@@ -88,9 +91,14 @@ fn main() {
     // generate the rust bindings for the picoquic
     let bindings = bindgen::Builder::default()
         .clang_arg("-DNULL=0")
+        .clang_arg("-Isrc/picotls/include/")
+        .clang_arg("-Isrc/picoquic/picoquic/")
         .header("src/picotls/include/picotls.h")
         .header("src/picoquic/picoquic/picoquic.h")
+        .header("src/picoquic/picoquic/picoquic_internal.h")
+        .header("src/picoquic/picoquic/tls_api.h")
         .header("src/picoquic/picoquic/util.h")
+        .header("shim/shim.h")
         .generate()
         .expect("Unable to generate picoquic bindings");
 
@@ -0,0 +1,13 @@
+/*
+ * `picoquic_tls_ctx_t` is only defined in `tls_api.c`, so the accessors are compiled in the
+ * same translation unit. `build.rs` excludes `tls_api.c` from the picoquic sources.
+ */
+#include "../src/picoquic/picoquic/tls_api.c"
+#include "shim.h"
+
+ptls_t* picoquic_rs_get_tls(picoquic_cnx_t* cnx)
+{
+    picoquic_tls_ctx_t* ctx = (picoquic_tls_ctx_t*)cnx->tls_ctx;
+
+    return ctx == NULL ? NULL : ctx->tls;
+}
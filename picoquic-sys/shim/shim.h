@@ -0,0 +1,10 @@
+#ifndef PICOQUIC_RS_SHIM_H
+#define PICOQUIC_RS_SHIM_H
+
+#include "picoquic_internal.h"
+#include "picotls.h"
+
+/* Returns the TLS state of the given connection or NULL, if the connection has none. */
+ptls_t* picoquic_rs_get_tls(picoquic_cnx_t* cnx);
+
+#endif
@@ -0,0 +1,72 @@
+use config::ConnectionConfig;
+
+use std::net::SocketAddr;
+
+/// Information about an incoming `Connection`, that is given to the `AdmitConnection` handler.
+#[derive(Debug, Clone)]
+pub struct IncomingConnectionInfo {
+    /// The address of the client.
+    pub peer_addr: SocketAddr,
+    /// The server name(SNI) that was requested by the client.
+    pub server_name: Option<String>,
+}
+
+/// The `AdmitConnection` trait is used by the server to configure each incoming `Connection`.
+pub trait AdmitConnection: Send {
+    /// Will be called for each incoming `Connection`, before it is handed out by the `Context`.
+    ///
+    /// # Result
+    ///
+    /// The returned `ConnectionConfig` overrides the defaults of the `Context` for this
+    /// `Connection`.
+    fn admit(&mut self, info: &IncomingConnectionInfo) -> ConnectionConfig;
+}
+
+impl<F> AdmitConnection for F
+where
+    F: FnMut(&IncomingConnectionInfo) -> ConnectionConfig + Send,
+{
+    fn admit(&mut self, info: &IncomingConnectionInfo) -> ConnectionConfig {
+        self(info)
+    }
+}
+
+/// The decision of the `AcceptFilter` about an incoming `Connection`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcceptDecision {
+    /// The `Connection` is handed out by the `Context`.
+    Accept,
+    /// The `Connection` is closed with the given application error code.
+    RejectWithCode(u64),
+    /// The `Connection` is dropped without notifying the client, the client runs into its idle
+    /// timeout.
+    Drop,
+}
+
+/// The `AcceptFilter` trait is used by the server to decide, if an incoming `Connection` is
+/// accepted, e.g. to rate-limit or blocklist clients.
+pub trait AcceptFilter: Send {
+    /// Will be called for each incoming `Connection` before its handshake is finished and
+    /// before the `AdmitConnection` handler.
+    fn filter(&mut self, info: &IncomingConnectionInfo) -> AcceptDecision;
+}
+
+impl<F> AcceptFilter for F
+where
+    F: FnMut(&IncomingConnectionInfo) -> AcceptDecision + Send,
+{
+    fn filter(&mut self, info: &IncomingConnectionInfo) -> AcceptDecision {
+        self(info)
+    }
+}
+
+/// How a `Context` refuses new handshakes, while it does not listen for new `Connection`s.
+/// See `Context::pause_listening`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PauseMode {
+    /// The packets of new clients are dropped, before any state is allocated for them. The
+    /// clients retry, until their handshake times out.
+    Drop,
+    /// New `Connection`s are closed with the `SERVER_BUSY` transport error.
+    Busy,
+}
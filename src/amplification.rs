@@ -0,0 +1,170 @@
+use std::{
+    cmp,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// The minimum size of a datagram, that carries an Initial packet.
+const MIN_DATAGRAM_SIZE: usize = 1200;
+
+#[derive(Default)]
+struct Counters {
+    received: usize,
+    sent: usize,
+    /// Is the connection currently blocked by the limit?
+    limited: bool,
+}
+
+/// Enforces the anti-amplification limit for server connections.
+///
+/// Before the address of a client is validated, a server is only allowed to send `factor` times
+/// the number of bytes it received from the client. This prevents that a server is abused to
+/// flood a spoofed address with packets.
+///
+/// Only connections with a not yet validated address should be tracked. Connections are
+/// identified by an arbitrary `usize` key.
+pub struct AmplificationLimiter {
+    factor: usize,
+    connections: HashMap<usize, Counters>,
+    /// The number of connections that are currently blocked by the limit.
+    limited: Arc<AtomicUsize>,
+}
+
+impl AmplificationLimiter {
+    pub fn new(factor: u32) -> AmplificationLimiter {
+        AmplificationLimiter {
+            factor: factor as usize,
+            connections: HashMap::new(),
+            limited: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Sets the anti-amplification factor, that is used for all further sends.
+    pub fn set_factor(&mut self, factor: u32) {
+        self.factor = factor as usize;
+    }
+
+    /// Returns the shared number of connections that are currently blocked by the limit.
+    pub fn limited_connections(&self) -> Arc<AtomicUsize> {
+        self.limited.clone()
+    }
+
+    /// Needs to be called for all data that is received for a connection.
+    pub fn on_data_received(&mut self, key: usize, len: usize) {
+        self.connections.entry(key).or_default().received += len;
+    }
+
+    /// Needs to be called for all data that is sent for a connection.
+    pub fn on_data_sent(&mut self, key: usize, len: usize) {
+        if let Some(counters) = self.connections.get_mut(&key) {
+            counters.sent += len;
+        }
+    }
+
+    /// Returns if the given connection may send a datagram of up to `datagram_size` bytes.
+    ///
+    /// The connection is blocked by the limit, until the full datagram fits into its budget.
+    /// Picoquic pads the Initial packets to a full datagram, so the datagram can not be shrunk to
+    /// the remaining budget.
+    pub fn can_send(&mut self, key: usize, datagram_size: usize) -> bool {
+        let factor = self.factor;
+        let counters = match self.connections.get_mut(&key) {
+            Some(counters) => counters,
+            None => return true,
+        };
+
+        let budget = (counters.received * factor).saturating_sub(counters.sent);
+        let limited = budget < cmp::max(datagram_size, MIN_DATAGRAM_SIZE);
+
+        if limited != counters.limited {
+            counters.limited = limited;
+
+            if limited {
+                debug!("connection is blocked by the anti-amplification limit");
+                self.limited.fetch_add(1, Ordering::Relaxed);
+            } else {
+                self.limited.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        !limited
+    }
+
+    /// Stops tracking the given connection, because its address is validated or it is deleted.
+    pub fn remove(&mut self, key: usize) {
+        if let Some(counters) = self.connections.remove(&key) {
+            if counters.limited {
+                self.limited.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untracked_connection_is_not_limited() {
+        let mut limiter = AmplificationLimiter::new(3);
+
+        assert!(limiter.can_send(1, 1500));
+    }
+
+    #[test]
+    fn budget_is_factor_times_received_bytes() {
+        let mut limiter = AmplificationLimiter::new(3);
+        limiter.on_data_received(1, 1200);
+
+        assert!(limiter.can_send(1, 3600));
+        assert!(!limiter.can_send(1, 3601));
+        limiter.on_data_sent(1, 1500);
+        assert!(limiter.can_send(1, 2100));
+        assert!(!limiter.can_send(1, 2101));
+    }
+
+    #[test]
+    fn connection_waits_for_budget_of_full_datagram() {
+        let mut limiter = AmplificationLimiter::new(3);
+        limiter.on_data_received(1, 1200);
+        limiter.on_data_sent(1, 2400);
+
+        // The remaining 1200 bytes are not enough for a datagram of the current MTU.
+        assert!(!limiter.can_send(1, 1400));
+        // Smaller datagrams still need the space of a padded Initial packet.
+        assert!(limiter.can_send(1, 1000));
+        limiter.on_data_sent(1, 1);
+        assert!(!limiter.can_send(1, 1000));
+    }
+
+    #[test]
+    fn connection_is_blocked_until_more_data_is_received() {
+        let mut limiter = AmplificationLimiter::new(2);
+        let limited = limiter.limited_connections();
+        limiter.on_data_received(1, 1200);
+        limiter.on_data_sent(1, 2000);
+
+        assert!(!limiter.can_send(1, 1200));
+        assert_eq!(1, limited.load(Ordering::Relaxed));
+
+        limiter.on_data_received(1, 1200);
+        assert!(limiter.can_send(1, 1200));
+        assert_eq!(0, limited.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn removing_blocked_connection_updates_limited_connections() {
+        let mut limiter = AmplificationLimiter::new(1);
+        let limited = limiter.limited_connections();
+        limiter.on_data_received(1, 100);
+
+        assert!(!limiter.can_send(1, 1200));
+        assert_eq!(1, limited.load(Ordering::Relaxed));
+
+        limiter.remove(1);
+        assert_eq!(0, limited.load(Ordering::Relaxed));
+    }
+}
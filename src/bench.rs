@@ -0,0 +1,472 @@
+use config::Config;
+use connection::Connection;
+use context::Context;
+use ecn::Ecn;
+use error::*;
+use runtime::Socket;
+use stream::{Stream, Type as SType};
+use transfer::transfer;
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{
+    future,
+    stream::iter_ok,
+    Async::{self, Ready},
+    Future, Poll, Sink, Stream as FStream,
+};
+
+use tokio::{self, net::UdpSocket, runtime::Runtime, timer::Delay};
+
+use std::{
+    cmp, io,
+    net::{SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The size of the chunks that are sent by the throughput measurement.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The time the throughput measurement waits for the server to receive all data.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The result of `Bench::throughput`.
+#[derive(Debug, Clone)]
+pub struct Throughput {
+    /// The number of payload bytes that were transferred.
+    pub bytes: u64,
+    /// The number of bytes the client sent over the socket, including all QUIC overhead and
+    /// retransmissions.
+    pub wire_bytes: u64,
+    /// The time from the first sent byte until the server received all payload.
+    pub duration: Duration,
+}
+
+impl Throughput {
+    /// Returns the payload bytes per second.
+    pub fn goodput(&self) -> f64 {
+        self.bytes as f64 / as_secs(self.duration)
+    }
+
+    /// Returns the bytes per second the client sent over the socket.
+    pub fn throughput(&self) -> f64 {
+        self.wire_bytes as f64 / as_secs(self.duration)
+    }
+}
+
+/// The result of `Bench::handshake_rate`.
+#[derive(Debug, Clone)]
+pub struct HandshakeRate {
+    /// The number of `Connection`s that were established.
+    pub connections: usize,
+    /// The time until all `Connection`s were established.
+    pub duration: Duration,
+}
+
+impl HandshakeRate {
+    /// Returns the handshakes per second.
+    pub fn per_second(&self) -> f64 {
+        self.connections as f64 / as_secs(self.duration)
+    }
+}
+
+/// The result of `Bench::request_latency`.
+#[derive(Debug, Clone)]
+pub struct Latency {
+    /// The round trip time of each request, in ascending order.
+    pub samples: Vec<Duration>,
+}
+
+impl Latency {
+    fn new(mut samples: Vec<Duration>) -> Latency {
+        samples.sort();
+        Latency { samples }
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.samples.first().cloned()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.last().cloned()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let sum = self.samples.iter().fold(Duration::from_secs(0), |sum, s| sum + *s);
+        Some(sum / self.samples.len() as u32)
+    }
+
+    /// Returns the given percentile, e.g. `99.0` for the 99th percentile.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let rank = (percentile / 100.0 * self.samples.len() as f64).ceil() as usize;
+        let index = cmp::min(rank.saturating_sub(1), self.samples.len() - 1);
+        Some(self.samples[index])
+    }
+}
+
+/// Measures the performance of a client and a server `Context`, that run in the same process.
+///
+/// The server answers each bidirectional `Stream` with the received data and counts the data
+/// of each unidirectional `Stream`. The server `Config` needs a certificate and a private key,
+/// the client `Config` needs to be able to verify the server certificate.
+///
+/// ```no_run
+/// # extern crate picoquic;
+/// # use picoquic::{Bench, Config};
+/// # fn main() {
+/// # let (server_config, client_config) = (Config::new(), Config::new());
+/// let mut bench = Bench::loopback(server_config, client_config, "server.test").unwrap();
+///
+/// let throughput = bench.throughput(64 * 1024 * 1024).unwrap();
+/// println!("goodput: {} bytes/s", throughput.goodput());
+/// # }
+/// ```
+pub struct Bench {
+    runtime: Runtime,
+    client: Context,
+    server_addr: SocketAddr,
+    server_name: String,
+    /// The number of bytes the client sent over its socket.
+    client_sent: Arc<AtomicUsize>,
+    /// The length of each unidirectional `Stream` the server received, and the time point the
+    /// `Stream` was finished.
+    received: Receiver<(u64, Instant)>,
+}
+
+impl Bench {
+    /// Creates a `Bench` with a client and a server that communicate over loopback UDP sockets.
+    ///
+    /// server_name - The name that is used by the client to verify the server certificate.
+    pub fn loopback<T: Into<String>>(
+        server_config: Config,
+        client_config: Config,
+        server_name: T,
+    ) -> Result<Bench, Error> {
+        let localhost: SocketAddr = ([127, 0, 0, 1], 0).into();
+        let server_socket = UdpSocket::bind(&localhost).context(ErrorKind::NetworkError)?;
+        let client_socket = UdpSocket::bind(&localhost).context(ErrorKind::NetworkError)?;
+
+        Bench::with_sockets(
+            server_socket,
+            client_socket,
+            server_config,
+            client_config,
+            server_name,
+        )
+    }
+
+    /// Creates a `Bench` with a client and a server that use the given `Socket`s, e.g. a
+    /// simulated transport.
+    ///
+    /// server_name - The name that is used by the client to verify the server certificate.
+    pub fn with_sockets<S, C, T>(
+        server_socket: S,
+        client_socket: C,
+        server_config: Config,
+        client_config: Config,
+        server_name: T,
+    ) -> Result<Bench, Error>
+    where
+        S: Socket + 'static,
+        C: Socket + 'static,
+        T: Into<String>,
+    {
+        let runtime = Runtime::new().context(ErrorKind::Unknown)?;
+        let client_sent = Arc::new(AtomicUsize::new(0));
+        let client_socket = CountingSocket {
+            inner: client_socket,
+            sent: client_sent.clone(),
+        };
+
+        let (server, server_driver) =
+            Context::with_io(server_socket, Delay::new(Instant::now()), server_config)?;
+        let (client, client_driver) =
+            Context::with_io(client_socket, Delay::new(Instant::now()), client_config)?;
+        let server_addr = server.local_addr();
+
+        let (send_received, received) = channel();
+
+        runtime.executor().spawn(server_driver);
+        runtime.executor().spawn(client_driver);
+        runtime.executor().spawn(serve(server, send_received));
+
+        Ok(Bench {
+            runtime,
+            client,
+            server_addr,
+            server_name: server_name.into(),
+            client_sent,
+            received,
+        })
+    }
+
+    /// Measures the throughput of a single unidirectional `Stream` that transfers `bytes` bytes.
+    pub fn throughput(&mut self, bytes: u64) -> Result<Throughput, Error> {
+        let mut con = self.connect()?;
+        let stream = self.runtime.block_on(con.new_unidirectional_stream())?;
+
+        let chunk = Bytes::from(vec![0u8; CHUNK_SIZE]);
+        let chunks = (0..bytes).step_by(CHUNK_SIZE).map(move |offset| {
+            let len = cmp::min(CHUNK_SIZE as u64, bytes - offset) as usize;
+            chunk.slice_to(len)
+        });
+
+        let wire_start = self.client_sent.load(Ordering::Relaxed);
+        let start = Instant::now();
+
+        let (stream, _) = self.runtime.block_on(transfer(stream, iter_ok(chunks)))?;
+        // Dropping the `Stream` sends the FIN bit, which finishes the `Stream` at the server.
+        drop(stream);
+
+        let (received, end) = match self.received.recv_timeout(RECEIVE_TIMEOUT) {
+            Ok(received) => received,
+            Err(_) => bail!("server did not finish receiving the stream"),
+        };
+
+        if received != bytes {
+            bail!("server received {} bytes instead of {}", received, bytes);
+        }
+
+        let wire_bytes = self.client_sent.load(Ordering::Relaxed) - wire_start;
+        con.close_immediately();
+
+        Ok(Throughput {
+            bytes,
+            wire_bytes: wire_bytes as u64,
+            duration: end.duration_since(start),
+        })
+    }
+
+    /// Measures how fast the client can establish the given number of `Connection`s, that are
+    /// all started at once.
+    pub fn handshake_rate(&mut self, connections: usize) -> Result<HandshakeRate, Error> {
+        let start = Instant::now();
+
+        let cons = (0..connections)
+            .map(|_| {
+                self.client
+                    .new_connection(self.server_addr, self.server_name.clone())
+            })
+            .collect::<Vec<_>>();
+        let cons = self.runtime.block_on(future::join_all(cons))?;
+        let duration = start.elapsed();
+
+        cons.into_iter().for_each(Connection::close_immediately);
+
+        Ok(HandshakeRate {
+            connections,
+            duration,
+        })
+    }
+
+    /// Measures the round trip time of the given number of requests. Each request sends
+    /// `request_size` bytes on a bidirectional `Stream` and waits for the answer of the server.
+    pub fn request_latency(
+        &mut self,
+        requests: usize,
+        request_size: usize,
+    ) -> Result<Latency, Error> {
+        assert!(request_size > 0, "requests need to contain data");
+
+        let mut con = self.connect()?;
+        let mut stream = self.runtime.block_on(con.new_bidirectional_stream())?;
+        let request = Bytes::from(vec![0u8; request_size]);
+        let mut samples = Vec::with_capacity(requests);
+
+        for _ in 0..requests {
+            let start = Instant::now();
+            let sent = self.runtime.block_on(stream.send(request.clone()))?;
+            stream = self.runtime.block_on(RecvExact {
+                stream: Some(sent),
+                remaining: request_size,
+            })?;
+            samples.push(start.elapsed());
+        }
+
+        con.close_immediately();
+
+        Ok(Latency::new(samples))
+    }
+
+    fn connect(&mut self) -> Result<Connection, Error> {
+        let con = self
+            .client
+            .new_connection(self.server_addr, self.server_name.clone());
+        self.runtime.block_on(con)
+    }
+}
+
+/// The server side of a `Bench`.
+fn serve(server: Context, received: Sender<(u64, Instant)>) -> impl Future<Item = (), Error = ()> {
+    server
+        .for_each(move |con| {
+            let received = received.clone();
+
+            tokio::spawn(
+                con.for_each(move |stream| {
+                    match stream.get_type() {
+                        SType::Unidirectional => {
+                            let received = received.clone();
+                            tokio::spawn(
+                                stream
+                                    .fold(0, |len, data| Ok::<_, Error>(len + data.len() as u64))
+                                    .map(move |len| {
+                                        let _ = received.send((len, Instant::now()));
+                                    })
+                                    .map_err(|_| ()),
+                            );
+                        }
+                        SType::Bidirectional => {
+                            let (send, recv) = stream.split();
+                            tokio::spawn(
+                                send.send_all(recv.map(BytesMut::freeze))
+                                    .map(|_| ())
+                                    .map_err(|_| ()),
+                            );
+                        }
+                    }
+
+                    Ok(())
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+        .map_err(|_| ())
+}
+
+/// Receives exactly `remaining` bytes from the `Stream`.
+struct RecvExact {
+    stream: Option<Stream>,
+    remaining: usize,
+}
+
+impl Future for RecvExact {
+    type Item = Stream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while self.remaining > 0 {
+            let stream = self.stream.as_mut().expect("`RecvExact` polled after completion");
+
+            match try_ready!(stream.poll()) {
+                Some(data) => self.remaining = self.remaining.saturating_sub(data.len()),
+                None => bail!("stream finished before the answer was received"),
+            }
+        }
+
+        Ok(Ready(self.stream.take().expect("`RecvExact` polled after completion")))
+    }
+}
+
+/// Counts the bytes that are sent over the inner `Socket`.
+struct CountingSocket<S> {
+    inner: S,
+    sent: Arc<AtomicUsize>,
+}
+
+impl<S: Socket> Socket for CountingSocket<S> {
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        self.inner.poll_recv_from(buf)
+    }
+
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        let res = self.inner.poll_send_to(buf, target);
+
+        if let Ok(Async::Ready(len)) = res {
+            self.sent.fetch_add(len, Ordering::Relaxed);
+        }
+
+        res
+    }
+
+    fn poll_write_ready(&mut self) -> Poll<(), io::Error> {
+        self.inner.poll_write_ready()
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+
+    fn set_ipv6_traffic_class(&mut self, traffic_class: u8) -> io::Result<()> {
+        self.inner.set_ipv6_traffic_class(traffic_class)
+    }
+
+    fn register_ipv6_flow_label(&mut self, peer: &SocketAddrV6, label: u32) -> io::Result<()> {
+        self.inner.register_ipv6_flow_label(peer, label)
+    }
+
+    fn set_send_buffer_size(&mut self, size: usize) -> io::Result<()> {
+        self.inner.set_send_buffer_size(size)
+    }
+
+    fn set_recv_buffer_size(&mut self, size: usize) -> io::Result<()> {
+        self.inner.set_recv_buffer_size(size)
+    }
+
+    fn set_tos(&mut self, tos: u8) -> io::Result<()> {
+        self.inner.set_tos(tos)
+    }
+
+    fn set_ttl(&mut self, ttl: u32) -> io::Result<()> {
+        self.inner.set_ttl(ttl)
+    }
+
+    fn enable_ecn(&mut self) -> io::Result<()> {
+        self.inner.enable_ecn()
+    }
+
+    fn poll_recv_from_with_ecn(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Poll<(usize, SocketAddr, Ecn), io::Error> {
+        self.inner.poll_recv_from_with_ecn(buf)
+    }
+}
+
+fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(samples: &[u64]) -> Latency {
+        Latency::new(samples.iter().map(|s| Duration::from_millis(*s)).collect())
+    }
+
+    #[test]
+    fn latency_statistics() {
+        let latency = millis(&[5, 1, 4, 2, 3]);
+
+        assert_eq!(Some(Duration::from_millis(1)), latency.min());
+        assert_eq!(Some(Duration::from_millis(5)), latency.max());
+        assert_eq!(Some(Duration::from_millis(3)), latency.mean());
+        assert_eq!(Some(Duration::from_millis(3)), latency.percentile(50.0));
+        assert_eq!(Some(Duration::from_millis(5)), latency.percentile(99.0));
+    }
+
+    #[test]
+    fn latency_without_samples() {
+        let latency = millis(&[]);
+
+        assert_eq!(None, latency.min());
+        assert_eq!(None, latency.mean());
+        assert_eq!(None, latency.percentile(50.0));
+    }
+}
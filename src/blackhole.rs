@@ -0,0 +1,157 @@
+use std::{
+    cmp,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The minimum MTU every QUIC path needs to support.
+const MIN_MTU: usize = 1200;
+
+/// The number of retransmissions in the probation period that signal a blackhole.
+const LOSS_THRESHOLD: u64 = 3;
+
+/// The number of round trip times a new MTU or path needs to work, before it is trusted.
+const PROBATION_RTTS: u32 = 3;
+
+/// The minimum duration of the probation period.
+const MIN_PROBATION: Duration = Duration::from_millis(500);
+
+struct Probation {
+    start: Instant,
+    /// The number of retransmissions at the start of the probation.
+    retransmissions: u64,
+}
+
+/// Detects PMTU blackholes of a connection.
+///
+/// When the MTU of the path is increased or the path changes, the connection is put in
+/// probation. If the connection loses multiple packets in the probation period, the packets
+/// probably got dropped because of their size and the MTU needs to be clamped down to the last
+/// MTU that was known to work.
+pub struct BlackholeDetector {
+    /// The MTU that is known to work.
+    safe_mtu: usize,
+    /// The MTU that was observed the last time.
+    mtu: usize,
+    peer_addr: SocketAddr,
+    probation: Option<Probation>,
+}
+
+impl BlackholeDetector {
+    pub fn new(mtu: usize, peer_addr: SocketAddr) -> BlackholeDetector {
+        BlackholeDetector {
+            safe_mtu: cmp::min(mtu, MIN_MTU),
+            mtu,
+            peer_addr,
+            probation: None,
+        }
+    }
+
+    /// Checks the current state of the connection for a blackhole.
+    ///
+    /// # Returns
+    /// Some(_) is the MTU the connection needs to be clamped down to, because a blackhole was
+    /// detected. None intends that no blackhole was detected.
+    pub fn poll(
+        &mut self,
+        mtu: usize,
+        peer_addr: SocketAddr,
+        retransmissions: u64,
+        rtt: Duration,
+        now: Instant,
+    ) -> Option<usize> {
+        if peer_addr != self.peer_addr {
+            // Nothing is known about the new path.
+            self.peer_addr = peer_addr;
+            self.safe_mtu = cmp::min(mtu, MIN_MTU);
+            self.start_probation(retransmissions, now);
+        } else if mtu > self.mtu {
+            self.safe_mtu = self.mtu;
+            self.start_probation(retransmissions, now);
+        } else if mtu < self.mtu {
+            // The MTU was already decreased, there is nothing left to detect.
+            self.safe_mtu = cmp::min(self.safe_mtu, mtu);
+            self.probation = None;
+        }
+        self.mtu = mtu;
+
+        let (start, start_retransmissions) = match self.probation {
+            Some(ref probation) => (probation.start, probation.retransmissions),
+            None => return None,
+        };
+
+        if retransmissions.saturating_sub(start_retransmissions) >= LOSS_THRESHOLD {
+            self.probation = None;
+
+            if self.mtu > self.safe_mtu {
+                self.mtu = self.safe_mtu;
+                return Some(self.safe_mtu);
+            }
+        } else if now.duration_since(start) >= cmp::max(rtt * PROBATION_RTTS, MIN_PROBATION) {
+            // The new MTU or path works.
+            self.probation = None;
+            self.safe_mtu = self.mtu;
+        }
+
+        None
+    }
+
+    fn start_probation(&mut self, retransmissions: u64, now: Instant) {
+        self.probation = Some(Probation {
+            start: now,
+            retransmissions,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        ([127, 0, 0, 1], port).into()
+    }
+
+    #[test]
+    fn loss_after_mtu_increase_clamps_mtu() {
+        let now = Instant::now();
+        let rtt = Duration::from_millis(100);
+        let mut detector = BlackholeDetector::new(1200, addr(1));
+
+        assert_eq!(None, detector.poll(1400, addr(1), 0, rtt, now));
+        assert_eq!(None, detector.poll(1400, addr(1), 2, rtt, now));
+        assert_eq!(Some(1200), detector.poll(1400, addr(1), 3, rtt, now));
+        assert_eq!(None, detector.poll(1200, addr(1), 10, rtt, now));
+    }
+
+    #[test]
+    fn mtu_is_trusted_after_probation() {
+        let now = Instant::now();
+        let rtt = Duration::from_millis(100);
+        let mut detector = BlackholeDetector::new(1200, addr(1));
+
+        assert_eq!(None, detector.poll(1400, addr(1), 0, rtt, now));
+        let later = now + Duration::from_secs(1);
+        assert_eq!(None, detector.poll(1400, addr(1), 1, rtt, later));
+        assert_eq!(None, detector.poll(1400, addr(1), 10, rtt, later));
+    }
+
+    #[test]
+    fn loss_after_path_change_clamps_to_minimum_mtu() {
+        let now = Instant::now();
+        let rtt = Duration::from_millis(100);
+        let mut detector = BlackholeDetector::new(1400, addr(1));
+
+        assert_eq!(None, detector.poll(1400, addr(2), 5, rtt, now));
+        assert_eq!(Some(MIN_MTU), detector.poll(1400, addr(2), 8, rtt, now));
+    }
+
+    #[test]
+    fn loss_without_mtu_increase_is_ignored() {
+        let now = Instant::now();
+        let rtt = Duration::from_millis(100);
+        let mut detector = BlackholeDetector::new(1400, addr(1));
+
+        assert_eq!(None, detector.poll(1400, addr(1), 10, rtt, now));
+    }
+}
@@ -0,0 +1,192 @@
+use std::{
+    cmp,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The current round trip time confirms the saved one, if it is at most this factor bigger or
+/// smaller.
+const RTT_FACTOR: u32 = 10;
+
+/// The number of round trip times the resumed congestion window needs to work, before it is
+/// trusted.
+const VALIDATION_RTTS: u32 = 2;
+
+/// The parameters of a path, that a later `Connection` to the same peer resumes, instead of
+/// probing the path with the slow start again.
+///
+/// The parameters are exported by `Connection::export_path_parameters` and resumed with
+/// `ConnectionConfig::set_path_parameters`. All fields are public, so the parameters can be
+/// persisted in any format.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathParameters {
+    /// The address of the peer on the path.
+    pub peer_addr: SocketAddr,
+    /// The minimum round trip time that was observed on the path.
+    pub min_rtt: Duration,
+    /// The congestion window in bytes, that the path sustained.
+    pub congestion_window: u64,
+}
+
+enum Phase {
+    /// Waits for the first round trip time sample, that confirms the saved path.
+    Reconnaissance,
+    /// The congestion window was resumed, the path needs to prove that it sustains the window.
+    Validating {
+        start: Instant,
+        /// The congestion window before it was resumed.
+        window: u64,
+        /// The number of retransmissions at the start of the validation.
+        retransmissions: u64,
+    },
+    /// The congestion control continues on its own.
+    Normal,
+}
+
+/// Resumes the congestion window of a previous connection on the same path, based on Careful
+/// Resume(draft-ietf-tsvwg-careful-resume).
+///
+/// If the first round trip time sample confirms that the path did not change, the congestion
+/// window jumps to half of the saved window. When packets are lost before the window was
+/// validated for a few round trip times, the window retreats to the window before the jump.
+pub struct CarefulResume {
+    saved: PathParameters,
+    phase: Phase,
+}
+
+impl CarefulResume {
+    pub fn new(saved: PathParameters) -> CarefulResume {
+        CarefulResume {
+            saved,
+            phase: Phase::Reconnaissance,
+        }
+    }
+
+    /// Checks the current state of the path. Needs to be called after the handshake finished,
+    /// as only then a round trip time sample is available.
+    ///
+    /// # Returns
+    /// Some(_) is the congestion window the path needs to be set to. None intends that the
+    /// congestion window should not be changed.
+    pub fn poll(
+        &mut self,
+        peer_addr: SocketAddr,
+        min_rtt: Duration,
+        smoothed_rtt: Duration,
+        congestion_window: u64,
+        retransmissions: u64,
+        now: Instant,
+    ) -> Option<u64> {
+        match self.phase {
+            Phase::Reconnaissance => {
+                self.phase = Phase::Normal;
+
+                let saved = &self.saved;
+                let window = saved.congestion_window / 2;
+
+                if peer_addr != saved.peer_addr
+                    || retransmissions > 0
+                    || min_rtt * 2 < saved.min_rtt
+                    || min_rtt > saved.min_rtt * RTT_FACTOR
+                    || window <= congestion_window
+                {
+                    return None;
+                }
+
+                self.phase = Phase::Validating {
+                    start: now,
+                    window: congestion_window,
+                    retransmissions,
+                };
+                Some(window)
+            }
+            Phase::Validating {
+                start,
+                window,
+                retransmissions: start_retransmissions,
+            } => {
+                if retransmissions > start_retransmissions {
+                    // The path does not sustain the resumed window.
+                    self.phase = Phase::Normal;
+                    Some(cmp::min(window, congestion_window))
+                } else {
+                    if now.duration_since(start) >= smoothed_rtt * VALIDATION_RTTS {
+                        self.phase = Phase::Normal;
+                    }
+
+                    None
+                }
+            }
+            Phase::Normal => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        ([127, 0, 0, 1], port).into()
+    }
+
+    fn saved() -> PathParameters {
+        PathParameters {
+            peer_addr: addr(1),
+            min_rtt: Duration::from_millis(100),
+            congestion_window: 1_000_000,
+        }
+    }
+
+    #[test]
+    fn confirmed_path_resumes_half_of_saved_window() {
+        let mut resume = CarefulResume::new(saved());
+        let rtt = Duration::from_millis(120);
+        let now = Instant::now();
+
+        assert_eq!(Some(500_000), resume.poll(addr(1), rtt, rtt, 15_000, 0, now));
+        assert_eq!(None, resume.poll(addr(1), rtt, rtt, 500_000, 0, now + rtt));
+        assert_eq!(None, resume.poll(addr(1), rtt, rtt, 500_000, 1, now + rtt * 2));
+    }
+
+    #[test]
+    fn changed_path_is_not_resumed() {
+        let rtt = Duration::from_millis(100);
+        let now = Instant::now();
+
+        let mut resume = CarefulResume::new(saved());
+        assert_eq!(None, resume.poll(addr(2), rtt, rtt, 15_000, 0, now));
+
+        let mut resume = CarefulResume::new(saved());
+        let fast = Duration::from_millis(10);
+        assert_eq!(None, resume.poll(addr(1), fast, fast, 15_000, 0, now));
+
+        let mut resume = CarefulResume::new(saved());
+        let slow = Duration::from_secs(2);
+        assert_eq!(None, resume.poll(addr(1), slow, slow, 15_000, 0, now));
+
+        let mut resume = CarefulResume::new(saved());
+        assert_eq!(None, resume.poll(addr(1), rtt, rtt, 15_000, 1, now));
+        // Reconnaissance only happens once.
+        assert_eq!(None, resume.poll(addr(1), rtt, rtt, 15_000, 1, now));
+    }
+
+    #[test]
+    fn loss_before_validation_retreats_to_previous_window() {
+        let mut resume = CarefulResume::new(saved());
+        let rtt = Duration::from_millis(100);
+        let now = Instant::now();
+
+        assert_eq!(Some(500_000), resume.poll(addr(1), rtt, rtt, 15_000, 0, now));
+        assert_eq!(Some(15_000), resume.poll(addr(1), rtt, rtt, 250_000, 1, now + rtt));
+        assert_eq!(None, resume.poll(addr(1), rtt, rtt, 15_000, 2, now + rtt));
+    }
+
+    #[test]
+    fn bigger_current_window_is_kept() {
+        let mut resume = CarefulResume::new(saved());
+        let rtt = Duration::from_millis(100);
+
+        assert_eq!(None, resume.poll(addr(1), rtt, rtt, 600_000, 0, Instant::now()));
+    }
+}
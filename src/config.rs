@@ -1,13 +1,25 @@
-use super::VerifyCertificate;
-use picoquic_sys::picoquic::PICOQUIC_RESET_SECRET_SIZE;
+use super::{
+    AcceptFilter, AdmitConnection, CryptoBackend, HandshakeAudit, HandshakeRateLimit,
+    KeyUpdateInterval, MetricsSink, NewCongestionController, PacketObserver, PathParameters,
+    Priority, QuicVersion, SniIdentity,
+};
+#[cfg(feature = "openssl")]
+use super::{AsyncVerifyCertificate, VerifyCertificate};
+use ipv6::MAX_FLOW_LABEL;
+use picoquic_sys::picoquic::{PICOQUIC_RESET_SECRET_SIZE, PICOQUIC_RETRY_SECRET_SIZE};
 
+use bytes::Bytes;
+
+use std::env;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// A role can either be `Server` or `Client`.
 /// The role can be used to define which side is responsible for certain tasks, like sending
 /// keep alive packages.
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Role {
     Server,
     Client,
@@ -20,6 +32,176 @@ pub enum FileFormat {
     DER,
 }
 
+/// The path MTU discovery of the `Connection`s.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MtuDiscovery {
+    /// Picoquic probes for a bigger MTU on its own.
+    Default,
+    /// No probing is done, the initial MTU is used for the whole lifetime of a `Connection`.
+    Disabled,
+    /// The MTU is probed with the given packet sizes in ascending order. After all sizes are
+    /// probed or a probe failed, the probing is restarted after `interval`.
+    Probe { sizes: Vec<usize>, interval: Duration },
+}
+
+/// The congestion control algorithms of picoquic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CongestionAlgorithm {
+    /// NewReno, the default of picoquic.
+    NewReno,
+    /// Cubic, grows the congestion window faster on paths with a big bandwidth-delay product.
+    Cubic,
+    /// BBR, paces the packets based on the measured bandwidth and round trip time. Bulk
+    /// transfers usually reach the highest throughput with BBR.
+    Bbr,
+}
+
+/// Where the session tickets of outgoing `Connection`s are stored.
+/// A `Connection` to a server, that sent a session ticket before, resumes the session and can
+/// send 0-RTT data, see `Context::new_connection_with_early_data`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionTicketStore {
+    /// The tickets are stored in memory and are lost, when the `Context` is dropped.
+    InMemory,
+    /// The tickets are loaded from the given file, when the `Context` is created and are written
+    /// back to the file, when the `Context` is dropped.
+    File(PathBuf),
+}
+
+/// Configuration of a single `Connection`.
+/// All values that are set, override the defaults of the `Context`.
+/// Outgoing `Connection`s are configured with `Context::new_connection_with_config` and incoming
+/// `Connection`s by the `AdmitConnection` handler.
+#[derive(Default)]
+pub struct ConnectionConfig {
+    /// The application layer protocol that is offered to the server, instead of the
+    /// `alpn_protocols` of the `Context`.
+    /// Only used by outgoing `Connection`s.
+    pub alpn: Option<String>,
+    /// The interval between keep alive packages.
+    pub keep_alive_interval: Option<Duration>,
+    /// The time after which an idle `Connection` is closed.
+    pub idle_timeout: Option<Duration>,
+    /// The maximum number of `Stream`s of each type the peer is allowed to have open at the same
+    /// time, that is advertised in the transport parameters.
+    pub max_incoming_streams: Option<usize>,
+    /// The handler that verifies the certificate of the peer, instead of the handler of the
+    /// `Context`.
+    /// Only used by outgoing `Connection`s.
+    #[cfg(feature = "openssl")]
+    pub verify_certificate_handler: Option<Box<dyn VerifyCertificate + Send>>,
+    /// The local address the `Connection` sends its packets from. Needs to be the address of
+    /// one of the sockets of the `Context`.
+    /// Only used by outgoing `Connection`s.
+    pub local_addr: Option<SocketAddr>,
+    /// The IPv6 flow label of all packets of the `Connection`.
+    /// Only used by outgoing `Connection`s to IPv6 peers.
+    pub flow_label: Option<u32>,
+    /// The session ticket that is used to resume the session, see
+    /// `Connection::export_session_ticket`.
+    /// Only used by outgoing `Connection`s.
+    pub session_ticket: Option<Vec<u8>>,
+    /// The data that is sent as 0-RTT data on the first bidirectional `Stream`.
+    /// Only used by outgoing `Connection`s.
+    pub early_data: Option<Bytes>,
+    /// The congestion control algorithm of the `Connection`.
+    pub congestion_algorithm: Option<CongestionAlgorithm>,
+    /// The parameters of a previous path to the server, that are resumed, see
+    /// `set_path_parameters`.
+    /// Only used by outgoing `Connection`s.
+    pub path_parameters: Option<PathParameters>,
+}
+
+impl ConnectionConfig {
+    /// Creates a new `ConnectionConfig`, that does not override anything.
+    pub fn new() -> ConnectionConfig {
+        ConnectionConfig::default()
+    }
+
+    /// Sets the application layer protocol that is offered to the server.
+    pub fn set_alpn<A: Into<String>>(&mut self, alpn: A) {
+        self.alpn = Some(alpn.into());
+    }
+
+    /// Enables keep alive for this `Connection`, regardless of the `keep_alive_sender` of
+    /// the `Context`.
+    pub fn enable_keep_alive(&mut self, interval: Duration) {
+        self.keep_alive_interval = Some(interval);
+    }
+
+    /// Sets the idle timeout of this `Connection`, see `Config::set_idle_timeout`.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
+    /// Sets the maximum number of `Stream`s of each type the peer is allowed to have open at the
+    /// same time. This overrides the `initial_max_streams_bidi` and `initial_max_streams_uni`
+    /// transport parameters of the `Config`.
+    pub fn set_max_incoming_streams(&mut self, max: usize) {
+        self.max_incoming_streams = Some(max);
+    }
+
+    /// Sets the handler that verifies the certificate of the server, instead of the handler of
+    /// the `Context`. This can be used to pin the certificate of one peer, while all other
+    /// `Connection`s use the verification of the `Context`.
+    #[cfg(feature = "openssl")]
+    pub fn set_verify_certificate_handler<H: VerifyCertificate + Send + 'static>(
+        &mut self,
+        handler: H,
+    ) {
+        self.verify_certificate_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the local address this `Connection` sends its packets from. If the `Context` has
+    /// multiple sockets, this selects the socket that is used for this `Connection`.
+    /// Default: The address of the first socket of the `Context`.
+    pub fn set_local_addr(&mut self, addr: SocketAddr) {
+        self.local_addr = Some(addr);
+    }
+
+    /// Sets the IPv6 flow label of all packets of this `Connection`. Networks can use the flow
+    /// label to route all packets of a `Connection` over the same path(ECMP).
+    /// The socket needs to support flow labels, see `Socket::register_ipv6_flow_label`.
+    ///
+    /// Panics, if `label` is `0` or bigger than 20 bits.
+    pub fn set_flow_label(&mut self, label: u32) {
+        assert!(label > 0 && label <= MAX_FLOW_LABEL, "invalid flow label: {}", label);
+        self.flow_label = Some(label);
+    }
+
+    /// Sets the session ticket, that was exported by `Connection::export_session_ticket` of a
+    /// previous `Connection` to the same server. The ticket is added to the session tickets of
+    /// the `Context`, so this and all following `Connection`s to the server resume the session.
+    pub fn set_session_ticket(&mut self, ticket: Vec<u8>) {
+        self.session_ticket = Some(ticket);
+    }
+
+    /// Sets the data that is sent on the first bidirectional `Stream`, as early as possible.
+    /// If the session is resumed, the data is sent as 0-RTT data with the first flight of the
+    /// handshake. The `Stream` is returned by `Connection::early_data_stream`.
+    pub fn set_early_data(&mut self, data: Bytes) {
+        self.early_data = Some(data);
+    }
+
+    /// Sets the congestion control algorithm of this `Connection`.
+    pub fn set_congestion_algorithm(&mut self, algorithm: CongestionAlgorithm) {
+        self.congestion_algorithm = Some(algorithm);
+    }
+
+    /// Sets the parameters of a previous path to the server, that were exported by
+    /// `Connection::export_path_parameters`. If the first round trip time confirms that the path
+    /// did not change, the congestion window jumps to half of the saved window, instead of
+    /// growing it with the slow start. The window retreats, if packets are lost before the path
+    /// proved that it sustains the window(Careful Resume).
+    ///
+    /// NewReno and Cubic continue from the resumed window, while BBR and the
+    /// `CongestionController`s of `Config::set_congestion_controller` compute the window
+    /// themselves.
+    pub fn set_path_parameters(&mut self, params: PathParameters) {
+        self.path_parameters = Some(params);
+    }
+}
+
 /// Configuration used by `Context` to setup Picoquic.
 pub struct Config {
     /// The path to the certificate chain(PEM format).
@@ -34,6 +216,10 @@ pub struct Config {
     pub private_key_filename: Option<PathBuf>,
     /// The private private_key in memory in the given file format.
     pub private_key: Option<(FileFormat, Vec<u8>)>,
+    /// The certificates the server presents per requested server name, see
+    /// `add_sni_identity`.
+    /// Default: empty, all clients get the certificate chain of this `Config`
+    pub sni_identities: Vec<SniIdentity>,
     /// The reset seed is used to create the stateless resets per `Connection`.
     pub reset_seed: Option<[u8; PICOQUIC_RESET_SECRET_SIZE as usize]>,
     /// The interval between keep alive packages. If the value is set to `Some(interval)`,
@@ -43,11 +229,206 @@ pub struct Config {
     /// The side of a `Connection` that is responsible for sending the keep alive packages.
     /// Default: `Role::Client`
     pub keep_alive_sender: Role,
+    /// The time after which an idle `Connection` is closed, see `set_idle_timeout`.
+    /// Default: None, the default of picoquic
+    pub idle_timeout: Option<Duration>,
     /// Sets TLS client authentication on the server.
     /// Default: false
     pub client_authentication: bool,
+    /// The `Context` only creates outgoing `Connection`s and never accepts incoming ones.
+    /// Server only options, like `client_authentication` or the `admission_handler`, are ignored.
+    /// Default: false
+    pub client_only: bool,
+    /// The common name of the self-signed certificate that is generated, when the `Context` is
+    /// created. See `generate_self_signed`.
+    /// Default: None
+    #[cfg(feature = "self-signed")]
+    pub self_signed_name: Option<String>,
     /// The handler that should verify the peer certificate in the TLS handshake.
+    #[cfg(feature = "openssl")]
     pub verify_certificate_handler: Option<Box<VerifyCertificate>>,
+    /// The handler that should verify the peer certificate asynchronously, instead of the
+    /// `verify_certificate_handler`. See `set_async_verify_certificate_handler`.
+    #[cfg(feature = "openssl")]
+    pub async_verify_certificate_handler: Option<Box<dyn AsyncVerifyCertificate>>,
+    /// Use the callback driven send path for `Stream`s. The data is buffered in the `Stream`
+    /// and picoquic requests it, when it is able to send it. Otherwise, all data is directly
+    /// copied into the send queue of picoquic.
+    /// Default: false
+    pub callback_driven_send: bool,
+    /// Writes with at least this number of bytes are kept in the `Stream` and picoquic requests
+    /// them, when it is able to send them. This saves copying big writes into the send queue of
+    /// picoquic. See `set_zero_copy_send_threshold`.
+    /// Default: 64KiB
+    pub zero_copy_send_threshold: Option<usize>,
+    /// The maximum number of bytes of small writes that are coalesced per `Stream`, before they
+    /// are handed to picoquic. See `enable_write_coalescing`.
+    /// Default: None
+    pub write_coalescing: Option<usize>,
+    /// Merge the data of consecutive writes of a `Stream` into the same stream frames.
+    /// See `coalesce_stream_writes`.
+    /// Default: true
+    pub coalesce_stream_writes: bool,
+    /// The maximum number of bytes per `Stream` that were handed to the `Stream`, but not yet
+    /// sent by picoquic. See `set_max_send_backlog`.
+    /// Default: None
+    pub max_send_backlog: Option<u64>,
+    /// The maximum number of writes per `Stream` that wait to be processed by the `Context`.
+    /// See `set_max_pending_send_messages`.
+    /// Default: None
+    pub max_pending_send_messages: Option<usize>,
+    /// The maximum size of the receive window. If the value is set to `Some(max)`, the receive
+    /// window of each `Connection` grows automatically up to `max`, based on the observed
+    /// bandwidth-delay product.
+    /// Default: None
+    pub max_receive_window: Option<u64>,
+    /// The size of the buffers that the received `Stream` data of a `Connection` is copied
+    /// into. See `set_recv_buffer_chunk_size`.
+    /// Default: 64KiB
+    pub recv_buffer_chunk_size: Option<usize>,
+    /// The anti-amplification factor. A server sends at most `factor` times the number of bytes
+    /// it received from a client, before the address of the client is validated.
+    /// Default: 3
+    pub amplification_factor: u32,
+    /// Validates the address of each client with a retry packet, before any state is allocated
+    /// for its `Connection`, see `enable_stateless_retry`.
+    /// Default: false
+    pub stateless_retry: bool,
+    /// The key that protects the retry tokens, see `set_retry_token_key`.
+    /// Default: None, a random key is created per `Context`
+    pub retry_token_key: Option<[u8; PICOQUIC_RETRY_SECRET_SIZE as usize]>,
+    /// The rate limit of new handshakes per source IP, see `set_handshake_rate_limit`.
+    /// Default: None, unlimited
+    pub handshake_rate_limit: Option<HandshakeRateLimit>,
+    /// The path MTU discovery of the `Connection`s.
+    /// Default: `MtuDiscovery::Default`
+    pub mtu_discovery: MtuDiscovery,
+    /// The maximum UDP payload size that is advertised to the peer and that the `Context` is
+    /// able to receive.
+    /// Default: None, the default of picoquic(`PICOQUIC_MAX_PACKET_SIZE`)
+    pub max_udp_payload_size: Option<usize>,
+    /// The flow control credit of the connection, that is advertised to the peer in the
+    /// `initial_max_data` transport parameter.
+    /// Default: None, the default of picoquic
+    pub initial_max_data: Option<u64>,
+    /// The flow control credit of the bidirectional `Stream`s that are opened by this side,
+    /// the `initial_max_stream_data_bidi_local` transport parameter.
+    /// Default: None, the default of picoquic
+    pub initial_max_stream_data_bidi_local: Option<u64>,
+    /// The flow control credit of the bidirectional `Stream`s that are opened by the peer,
+    /// the `initial_max_stream_data_bidi_remote` transport parameter.
+    /// Default: None, the default of picoquic
+    pub initial_max_stream_data_bidi_remote: Option<u64>,
+    /// The flow control credit of the unidirectional `Stream`s that are opened by the peer,
+    /// the `initial_max_stream_data_uni` transport parameter.
+    /// Default: None, the default of picoquic
+    pub initial_max_stream_data_uni: Option<u64>,
+    /// The number of bidirectional `Stream`s the peer is allowed to open initially, the
+    /// `initial_max_streams_bidi` transport parameter.
+    /// Default: None, the default of picoquic
+    pub initial_max_streams_bidi: Option<u64>,
+    /// The number of unidirectional `Stream`s the peer is allowed to open initially, the
+    /// `initial_max_streams_uni` transport parameter.
+    /// Default: None, the default of picoquic
+    pub initial_max_streams_uni: Option<u64>,
+    /// The exponent that scales the ACK delay in the ACK frames that are sent to the peer.
+    /// Default: None, the default of picoquic
+    pub ack_delay_exponent: Option<u8>,
+    /// Negotiate the one-way time stamp extension with the peer, see `enable_time_stamps`.
+    /// Default: false
+    pub time_stamps: bool,
+    /// The congestion control algorithm of all `Connection`s.
+    /// Default: None, the default of picoquic(`CongestionAlgorithm::NewReno`)
+    pub congestion_algorithm: Option<CongestionAlgorithm>,
+    /// Creates the `CongestionController`s of all `Connection`s, these replace the
+    /// `congestion_algorithm`.
+    /// Default: None
+    pub congestion_controller: Option<Arc<dyn NewCongestionController>>,
+    /// Outgoing `Connection`s start with a reserved(greased) QUIC version, to force a version
+    /// negotiation with the server.
+    /// Default: false
+    pub grease_version: bool,
+    /// The handler that configures each incoming `Connection` of the server.
+    pub admission_handler: Option<Box<dyn AdmitConnection>>,
+    /// The filter that decides, if an incoming `Connection` of the server is accepted.
+    /// Default: None, all `Connection`s are accepted
+    pub accept_filter: Option<Box<dyn AcceptFilter>>,
+    /// The maximum number of incoming `Connection`s that are open at the same time, see
+    /// `set_max_concurrent_connections`.
+    /// Default: None, unlimited
+    pub max_concurrent_connections: Option<usize>,
+    /// The directory picoquic writes the congestion control event logs of each `Connection` to.
+    /// Default: None
+    pub cc_log_dir: Option<PathBuf>,
+    /// The maximum number of incoming `Stream`s per `Connection` that wait to be accepted by
+    /// the application. If more `Stream`s are waiting, the peer does not get any new stream
+    /// credit, until the application caught up.
+    /// Default: Some(64)
+    pub max_pending_incoming_streams: Option<usize>,
+    /// The handler that records the outcome of each handshake.
+    pub handshake_audit: Option<Box<dyn HandshakeAudit>>,
+    /// The sink the `Metrics` of the `Context` are reported to, see `set_metrics_sink`.
+    pub metrics_sink: Option<Box<dyn MetricsSink>>,
+    /// The interval the `Metrics` are reported to the `metrics_sink`.
+    /// Default: 10 seconds
+    pub metrics_interval: Duration,
+    /// The observer that inspects every packet the `Context` sends and receives, see
+    /// `set_packet_observer`.
+    /// Default: None
+    pub packet_observer: Option<Box<dyn PacketObserver>>,
+    /// The traffic class(DSCP and ECN bits) of all IPv6 packets sent by the `Context`.
+    /// Default: None, the traffic class of the operating system is used
+    pub ipv6_traffic_class: Option<u8>,
+    /// The TOS(DSCP and ECN bits) of all IPv4 packets sent by the `Context`.
+    /// Default: None, the TOS of the operating system is used
+    pub ip_tos: Option<u8>,
+    /// The TTL(hop limit for IPv6) of all packets sent by the `Context`.
+    /// Default: None, the TTL of the operating system is used
+    pub ttl: Option<u32>,
+    /// The size of the send buffer(`SO_SNDBUF`) of each socket of the `Context` in bytes.
+    /// Default: None, the size of the operating system is used
+    pub socket_send_buffer_size: Option<usize>,
+    /// The size of the receive buffer(`SO_RCVBUF`) of each socket of the `Context` in bytes.
+    /// Default: None, the size of the operating system is used
+    pub socket_recv_buffer_size: Option<usize>,
+    /// Binds the IPv6 sockets of the `Context` as IPv6 only(`IPV6_V6ONLY`), so they do not
+    /// accept IPv4 packets.
+    /// Default: None, a `Context` with multiple listen addresses binds IPv6 only sockets and a
+    /// `Context` with one listen address uses the default of the operating system
+    pub ipv6_only: Option<bool>,
+    /// Marks the sent packets as ECN capable and counts the ECN codepoints of the received
+    /// packets, see `enable_ecn`.
+    /// Default: false
+    pub ecn: bool,
+    /// The OpenSSL engine or provider that is used for the packet protection and the handshake
+    /// crypto.
+    /// Default: None, the builtin implementation of OpenSSL is used
+    pub crypto_backend: Option<CryptoBackend>,
+    /// The maximum DATAGRAM frame size that is advertised to the peer, see `enable_datagrams`.
+    /// Default: None, the DATAGRAM extension is disabled
+    pub max_datagram_frame_size: Option<usize>,
+    /// Where the session tickets of outgoing `Connection`s are stored.
+    /// Default: `SessionTicketStore::InMemory`
+    pub session_ticket_store: SessionTicketStore,
+    /// The priority of new `Stream`s, see `Stream::set_priority`.
+    /// Default: None, the default `Priority`
+    pub default_stream_priority: Option<Priority>,
+    /// The directory the qlog traces of each `Connection` are written to, see `enable_qlog`.
+    /// Default: None
+    pub qlog_dir: Option<PathBuf>,
+    /// The file the TLS secrets are written to, see `set_key_log_file`.
+    /// Default: None
+    pub key_log_file: Option<PathBuf>,
+    /// The application layer protocols, in order of preference, see `set_alpn_protocols`.
+    /// Default: empty, no application layer protocol is negotiated
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// The QUIC versions, in order of preference, see `set_supported_versions`.
+    /// Default: empty, all versions that are implemented by picoquic
+    pub supported_versions: Vec<QuicVersion>,
+    /// The interval after which each `Connection` updates its packet protection keys, see
+    /// `set_key_update_interval`.
+    /// Default: None, the keys are only updated by `Connection::initiate_key_update`
+    pub key_update_interval: Option<KeyUpdateInterval>,
 }
 
 impl Config {
@@ -57,7 +438,9 @@ impl Config {
     }
 
     /// Will create a new instance by cloning another `Config`.
-    /// The `verify_certificate_handler` will be set to `None` as it does not support to be cloned.
+    /// The `verify_certificate_handler`, the `async_verify_certificate_handler`, the
+    /// `admission_handler`, the `accept_filter`, the `handshake_audit`, the `metrics_sink` and the
+    /// `packet_observer` will be set to `None` as they do not support to be cloned.
     pub fn clone_from(other: &Config) -> Config {
         Config {
             certificate_chain_filename: other.certificate_chain_filename.clone(),
@@ -66,15 +449,75 @@ impl Config {
             root_certificate_filename: other.root_certificate_filename.clone(),
             private_key_filename: other.private_key_filename.clone(),
             private_key: other.private_key.clone(),
+            sni_identities: other.sni_identities.clone(),
             reset_seed: other.reset_seed,
             keep_alive_interval: other.keep_alive_interval,
             keep_alive_sender: other.keep_alive_sender,
+            idle_timeout: other.idle_timeout,
             client_authentication: other.client_authentication,
+            client_only: other.client_only,
+            #[cfg(feature = "self-signed")]
+            self_signed_name: other.self_signed_name.clone(),
+            #[cfg(feature = "openssl")]
             verify_certificate_handler: None,
+            #[cfg(feature = "openssl")]
+            async_verify_certificate_handler: None,
+            callback_driven_send: other.callback_driven_send,
+            zero_copy_send_threshold: other.zero_copy_send_threshold,
+            write_coalescing: other.write_coalescing,
+            coalesce_stream_writes: other.coalesce_stream_writes,
+            max_send_backlog: other.max_send_backlog,
+            max_pending_send_messages: other.max_pending_send_messages,
+            max_receive_window: other.max_receive_window,
+            recv_buffer_chunk_size: other.recv_buffer_chunk_size,
+            amplification_factor: other.amplification_factor,
+            stateless_retry: other.stateless_retry,
+            retry_token_key: other.retry_token_key,
+            handshake_rate_limit: other.handshake_rate_limit,
+            mtu_discovery: other.mtu_discovery.clone(),
+            max_udp_payload_size: other.max_udp_payload_size,
+            initial_max_data: other.initial_max_data,
+            initial_max_stream_data_bidi_local: other.initial_max_stream_data_bidi_local,
+            initial_max_stream_data_bidi_remote: other.initial_max_stream_data_bidi_remote,
+            initial_max_stream_data_uni: other.initial_max_stream_data_uni,
+            initial_max_streams_bidi: other.initial_max_streams_bidi,
+            initial_max_streams_uni: other.initial_max_streams_uni,
+            ack_delay_exponent: other.ack_delay_exponent,
+            time_stamps: other.time_stamps,
+            congestion_algorithm: other.congestion_algorithm,
+            congestion_controller: other.congestion_controller.clone(),
+            grease_version: other.grease_version,
+            admission_handler: None,
+            accept_filter: None,
+            max_concurrent_connections: other.max_concurrent_connections,
+            cc_log_dir: other.cc_log_dir.clone(),
+            max_pending_incoming_streams: other.max_pending_incoming_streams,
+            handshake_audit: None,
+            metrics_sink: None,
+            metrics_interval: other.metrics_interval,
+            packet_observer: None,
+            ipv6_traffic_class: other.ipv6_traffic_class,
+            ip_tos: other.ip_tos,
+            ttl: other.ttl,
+            socket_send_buffer_size: other.socket_send_buffer_size,
+            socket_recv_buffer_size: other.socket_recv_buffer_size,
+            ipv6_only: other.ipv6_only,
+            ecn: other.ecn,
+            crypto_backend: other.crypto_backend.clone(),
+            max_datagram_frame_size: other.max_datagram_frame_size,
+            session_ticket_store: other.session_ticket_store.clone(),
+            default_stream_priority: other.default_stream_priority,
+            qlog_dir: other.qlog_dir.clone(),
+            key_log_file: other.key_log_file.clone(),
+            alpn_protocols: other.alpn_protocols.clone(),
+            supported_versions: other.supported_versions.clone(),
+            key_update_interval: other.key_update_interval,
         }
     }
 
     /// Sets the certificate chain(PEM format) filename.
+    /// Picoquic uses the same certificate for both roles, so outgoing `Connection`s present it
+    /// as client certificate, if the server requests client authentication.
     pub fn set_certificate_chain_filename<C: Into<PathBuf>>(&mut self, path: C) {
         self.certificate_chain_filename = Some(path.into())
     }
@@ -96,28 +539,118 @@ impl Config {
         self.keep_alive_sender = role;
     }
 
+    /// Sets the idle timeout of all `Connection`s, that is advertised to the peer in the
+    /// transport parameters. A `Connection` without any packets for the idle timeout(the
+    /// smaller timeout of both peers) fails with `ErrorKind::IdleTimeout`. The timeout is
+    /// advertised in whole seconds, a fraction of a second is rounded up.
+    /// Connections through NATs should enable keep alive with a shorter interval, see
+    /// `enable_keep_alive`.
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
     /// Enables TLS client authentication on the server.
+    /// Clients need to present a certificate chain, which is verified by the
+    /// `verify_certificate_handler` (with `VerifyContext::role` set to `Role::Server`) or against
+    /// the root certificates. The verified chain is returned by `Connection::peer_certificates`.
     pub fn enable_client_authentication(&mut self) {
         self.client_authentication = true;
     }
 
+    /// Enables the client only mode, the `Context` will never accept incoming `Connection`s.
+    /// See `ContextBuilder::client_only`.
+    pub fn enable_client_only(&mut self) {
+        self.client_only = true;
+    }
+
+    /// Generates an ephemeral key pair and a self-signed certificate for the given common name,
+    /// when the `Context` is created. The certificate replaces the certificate chain and the
+    /// private key of this `Config`.
+    /// Peers can pin the certificate, that is returned by `Context::self_signed_certificate`,
+    /// e.g. with the `PinnedVerifier`.
+    #[cfg(feature = "self-signed")]
+    pub fn generate_self_signed(&mut self, common_name: &str) {
+        self.self_signed_name = Some(common_name.to_owned());
+    }
+
     /// Sets the handler that should verify the peer certificate in the TLS handshake.
+    #[cfg(feature = "openssl")]
     pub fn set_verify_certificate_handler<H: VerifyCertificate + 'static>(&mut self, handler: H) {
         self.verify_certificate_handler = Some(Box::new(handler));
     }
 
+    /// Sets the handler that should verify the peer certificate asynchronously in the TLS
+    /// handshake, e.g. if the verification requires an OCSP or revocation lookup. The handler is
+    /// used instead of the `verify_certificate_handler`.
+    ///
+    /// Picoquic finishes the TLS handshake, while the certificate is verified. An outgoing
+    /// `Connection` is created and an incoming `Connection` is handed out, after the
+    /// certificate was verified. If the verification fails, the connection is closed with a TLS
+    /// alert.
+    #[cfg(feature = "openssl")]
+    pub fn set_async_verify_certificate_handler<H: AsyncVerifyCertificate + 'static>(
+        &mut self,
+        handler: H,
+    ) {
+        self.async_verify_certificate_handler = Some(Box::new(handler));
+    }
+
     /// Sets the certificate.
     /// This option will overwrite `set_certificate_chain_filename`.
+    /// In the PEM format, each element can contain multiple certificates.
     pub fn set_certificate_chain(&mut self, certs: Vec<Vec<u8>>, format: FileFormat) {
         self.certificate_chain = Some((format, certs));
     }
 
+    /// Sets the certificate chain from DER encoded certificates, the own certificate first.
+    /// See `set_certificate_chain`.
+    pub fn set_certificate_chain_from_der(&mut self, certs: Vec<Vec<u8>>) {
+        self.set_certificate_chain(certs, FileFormat::DER);
+    }
+
+    /// Sets the certificate chain from PEM encoded certificates, e.g. the content of a chain
+    /// file with the own certificate first. See `set_certificate_chain`.
+    pub fn set_certificate_chain_from_pem(&mut self, pem: Vec<u8>) {
+        self.set_certificate_chain(vec![pem], FileFormat::PEM);
+    }
+
     /// Sets the private private_key.
     /// This option will overwrite `set_private_key_filename`.
     pub fn set_private_key(&mut self, private_key: Vec<u8>, format: FileFormat) {
         self.private_key = Some((format, private_key));
     }
 
+    /// Sets the DER encoded private key. See `set_private_key`.
+    pub fn set_private_key_from_der(&mut self, private_key: Vec<u8>) {
+        self.set_private_key(private_key, FileFormat::DER);
+    }
+
+    /// Sets the PEM encoded private key. See `set_private_key`.
+    pub fn set_private_key_from_pem(&mut self, private_key: Vec<u8>) {
+        self.set_private_key(private_key, FileFormat::PEM);
+    }
+
+    /// Adds a certificate chain and private key, that the server presents to clients that
+    /// request a server name(SNI) matching `hostname_pattern`, e.g. `example.com` or
+    /// `*.example.com`. The patterns are matched in the order they were added, all other
+    /// clients get the certificate chain of this `Config`. The requested name is available as
+    /// `Connection::sni`, to route the `Connection` to its handler.
+    /// Creating the `Context` fails, if the private key does not belong to the certificate.
+    pub fn add_sni_identity<P: Into<String>>(
+        &mut self,
+        hostname_pattern: P,
+        certificate_chain: Vec<Vec<u8>>,
+        private_key: Vec<u8>,
+        format: FileFormat,
+    ) {
+        self.sni_identities.push(SniIdentity {
+            hostname_pattern: hostname_pattern.into(),
+            format,
+            certificate_chain,
+            private_key,
+        });
+    }
+
     /// Sets the root certificate(PEM format) filename.
     pub fn set_root_certificate_filename<P: Into<PathBuf>>(&mut self, path: P) {
         self.root_certificate_filename = Some(path.into())
@@ -128,6 +661,444 @@ impl Config {
     pub fn set_root_certificates(&mut self, certificates: Vec<Vec<u8>>, format: FileFormat) {
         self.root_certificates = Some((format, certificates));
     }
+
+    /// Enables the callback driven send path.
+    /// `Stream` data is kept in the `Stream` until picoquic is able to send it, instead of
+    /// copying everything up-front into picoquic. This caps the memory used by picoquic and
+    /// improves the pacing for large transfers.
+    pub fn enable_callback_driven_send(&mut self) {
+        self.callback_driven_send = true;
+    }
+
+    /// Sets the minimum number of bytes of a write, that is sent without copying it into
+    /// picoquic. The `Bytes` of these writes are kept alive in the `Stream` and picoquic copies
+    /// the data directly into the packets, when it is able to send it. Smaller writes are still
+    /// copied into picoquic, as this is cheaper than requesting them separately.
+    /// `None` copies all writes, the callback driven send path
+    /// (see `enable_callback_driven_send`) keeps all writes.
+    pub fn set_zero_copy_send_threshold(&mut self, min_bytes: Option<usize>) {
+        self.zero_copy_send_threshold = min_bytes;
+    }
+
+    /// Enables the coalescing of small writes for all `Stream`s.
+    /// Similar to Nagle's algorithm, writes that are smaller than `max_bytes` are merged, while
+    /// picoquic still has unsent data of the `Stream`. The merged data is handed to picoquic at
+    /// once, when picoquic sent all previous data or `max_bytes` are collected. This results in
+    /// fewer and bigger stream frames for protocols that do many tiny writes. The first write on
+    /// an idle `Stream` is not delayed.
+    /// Latency critical `Stream`s can disable it with `Stream::set_write_coalescing`.
+    /// Has no effect with the callback driven send path, as picoquic requests the data in
+    /// chunks as big as possible anyway.
+    pub fn enable_write_coalescing(&mut self, max_bytes: usize) {
+        self.write_coalescing = Some(max_bytes);
+    }
+
+    /// Sets if picoquic may merge the data of consecutive writes of a `Stream` into the same
+    /// stream frames. A `Stream` is a sequence of bytes, by default the peer receives the data
+    /// in chunks that are unrelated to the writes.
+    /// With `false`, each write is handed to picoquic on its own, after picoquic sent all data
+    /// of the previous write. The peer receives a write that fits into a packet as one chunk,
+    /// as long as no packet is lost or reordered. QUIC gives no guarantee about the chunks,
+    /// protocols that need message boundaries should use `Stream::framed`.
+    /// This disables `enable_write_coalescing`, `set_zero_copy_send_threshold` and
+    /// `enable_callback_driven_send`. The data that is written behind a file
+    /// (see `Stream::send_file`) is not separated. As only one write per `Stream` is in
+    /// flight, many small writes reduce the throughput.
+    pub fn coalesce_stream_writes(&mut self, coalesce: bool) {
+        self.coalesce_stream_writes = coalesce;
+    }
+
+    /// Bounds the data that is buffered per `Stream`. As long as `max_bytes` or more bytes were
+    /// handed to a `Stream`, but not yet sent by picoquic (see `Stream::send_backlog`),
+    /// `start_send` and `poll_complete` of the `Stream` return `NotReady`. A single write
+    /// can still exceed the limit.
+    ///
+    /// Panics, if `max_bytes` is `0`.
+    pub fn set_max_send_backlog(&mut self, max_bytes: u64) {
+        assert!(max_bytes > 0, "the maximum send backlog must not be 0");
+        self.max_send_backlog = Some(max_bytes);
+    }
+
+    /// Bounds the number of writes per `Stream` that wait to be processed by the `Context`.
+    /// While `max_messages` writes are waiting, `start_send` and `poll_complete` of the `Stream`
+    /// return `NotReady`. This limits the memory of a fast producer, before picoquic sees the
+    /// data at all.
+    ///
+    /// Panics, if `max_messages` is `0`.
+    pub fn set_max_pending_send_messages(&mut self, max_messages: usize) {
+        assert!(max_messages > 0, "the maximum pending send messages must not be 0");
+        self.max_pending_send_messages = Some(max_messages);
+    }
+
+    /// Enables the auto tuning of the receive window.
+    /// The flow control windows of a `Connection` start with the default values of picoquic and
+    /// grow, if the peer is limited by them, up to `max_window` bytes. This enables full
+    /// throughput on paths with a high bandwidth-delay product.
+    pub fn enable_receive_window_auto_tuning(&mut self, max_window: u64) {
+        self.max_receive_window = Some(max_window);
+    }
+
+    /// Sets the size of the buffers that the received `Stream` data is copied into.
+    /// Each `Connection` copies the received data into a buffer of `chunk_size` bytes and hands
+    /// out slices of it, instead of allocating for each stream frame. The buffer is reused, when
+    /// all its slices were dropped. Data that is bigger than `chunk_size` gets its own buffer.
+    /// `None` allocates a buffer for each stream frame.
+    pub fn set_recv_buffer_chunk_size(&mut self, chunk_size: Option<usize>) {
+        self.recv_buffer_chunk_size = chunk_size;
+    }
+
+    /// Sets the anti-amplification factor.
+    /// Before the address of a client is validated, the server sends at most `factor` times the
+    /// number of bytes it received from the client. Values lower than the default of 3 make
+    /// the limit stricter.
+    ///
+    /// # Panics
+    /// Panics if `factor` is `0`.
+    pub fn set_amplification_factor(&mut self, factor: u32) {
+        assert!(factor > 0, "amplification factor must be at least 1");
+        self.amplification_factor = factor;
+    }
+
+    /// Enables or disables the stateless retry of the server.
+    /// The server answers the first packet of each client with a retry packet that carries a
+    /// token, instead of allocating the state of a new `Connection`. Only clients that repeat
+    /// the token prove that they own their address. This protects a public server against a
+    /// flood of handshakes from spoofed addresses, but costs one round trip per handshake.
+    ///
+    /// Can be switched on a running `Context` with `Context::update_config`, e.g. only while
+    /// the server is under attack.
+    pub fn enable_stateless_retry(&mut self, enabled: bool) {
+        self.stateless_retry = enabled;
+    }
+
+    /// Sets the key that protects the retry tokens, see `enable_stateless_retry`.
+    /// Servers behind a load balancer need to share the key, so that each of them accepts the
+    /// tokens of the others.
+    pub fn set_retry_token_key(&mut self, key: [u8; PICOQUIC_RETRY_SECRET_SIZE as usize]) {
+        self.retry_token_key = Some(key);
+    }
+
+    /// Sets the rate limit of new incoming handshakes per source IP.
+    /// Handshakes above the limit are answered with a stateless retry or their packets are
+    /// dropped, see `RateLimitAction`. With `RateLimitAction::Retry`, an abusive client needs to
+    /// prove that it owns its address, before the server allocates any state for it.
+    ///
+    /// # Panics
+    /// Panics if `per_second` is not positive.
+    pub fn set_handshake_rate_limit(&mut self, limit: HandshakeRateLimit) {
+        assert!(limit.per_second > 0.0, "the handshake rate must be positive");
+        self.handshake_rate_limit = Some(limit);
+    }
+
+    /// Disables the path MTU discovery.
+    pub fn disable_mtu_discovery(&mut self) {
+        self.mtu_discovery = MtuDiscovery::Disabled;
+    }
+
+    /// Enables the path MTU discovery with the given probe sizes, instead of the probing of
+    /// picoquic. Each size is probed for a few round trips and is kept, if no packets got lost.
+    /// If a probe failed or all sizes were probed, the probing restarts after `interval`.
+    ///
+    /// The packet buffers of the `Context` grow to the biggest probe size, so networks with jumbo
+    /// frames can use bigger packets.
+    pub fn set_mtu_probes(&mut self, mut sizes: Vec<usize>, interval: Duration) {
+        sizes.sort_unstable();
+        sizes.dedup();
+        self.mtu_discovery = MtuDiscovery::Probe { sizes, interval };
+    }
+
+    /// Sets the maximum UDP payload size that is advertised to the peer in the transport
+    /// parameters. The packet buffers of the `Context` grow to this size, so networks with
+    /// jumbo frames can receive bigger datagrams than the conservative default.
+    /// The packets that are sent are still limited by the MTU of the path, see
+    /// `set_mtu_probes`.
+    pub fn set_max_udp_payload_size(&mut self, size: usize) {
+        assert!(size >= 1200, "the maximum UDP payload size must be at least 1200 bytes");
+        self.max_udp_payload_size = Some(size);
+    }
+
+    /// Sets the flow control credit of each `Connection`, the `initial_max_data` transport
+    /// parameter. The receive window auto tuning starts with this credit, see
+    /// `set_max_receive_window`.
+    pub fn set_initial_max_data(&mut self, max: u64) {
+        self.initial_max_data = Some(max);
+    }
+
+    /// Sets the flow control credit of the bidirectional `Stream`s that are opened by this side,
+    /// the `initial_max_stream_data_bidi_local` transport parameter.
+    pub fn set_initial_max_stream_data_bidi_local(&mut self, max: u64) {
+        self.initial_max_stream_data_bidi_local = Some(max);
+    }
+
+    /// Sets the flow control credit of the bidirectional `Stream`s that are opened by the peer,
+    /// the `initial_max_stream_data_bidi_remote` transport parameter.
+    pub fn set_initial_max_stream_data_bidi_remote(&mut self, max: u64) {
+        self.initial_max_stream_data_bidi_remote = Some(max);
+    }
+
+    /// Sets the flow control credit of the unidirectional `Stream`s that are opened by the peer,
+    /// the `initial_max_stream_data_uni` transport parameter.
+    pub fn set_initial_max_stream_data_uni(&mut self, max: u64) {
+        self.initial_max_stream_data_uni = Some(max);
+    }
+
+    /// Sets the number of bidirectional `Stream`s the peer is allowed to open, before it
+    /// receives more stream credit. This is the `initial_max_streams_bidi` transport parameter.
+    pub fn set_initial_max_streams_bidi(&mut self, max: u64) {
+        self.initial_max_streams_bidi = Some(max);
+    }
+
+    /// Sets the number of unidirectional `Stream`s the peer is allowed to open, before it
+    /// receives more stream credit. This is the `initial_max_streams_uni` transport parameter.
+    pub fn set_initial_max_streams_uni(&mut self, max: u64) {
+        self.initial_max_streams_uni = Some(max);
+    }
+
+    /// Sets the exponent that scales the ACK delay in the ACK frames that are sent to the peer,
+    /// the `ack_delay_exponent` transport parameter.
+    pub fn set_ack_delay_exponent(&mut self, exponent: u8) {
+        assert!(exponent <= 20, "the ACK delay exponent must not be bigger than 20");
+        self.ack_delay_exponent = Some(exponent);
+    }
+
+    /// Enables the one-way time stamp extension. The `Connection`s advertise that they send and
+    /// receive the `TIME_STAMP` frames. If the peer supports the extension as well, the packets
+    /// with acknowledgements carry the time they were sent at. Picoquic measures the one-way
+    /// delay of the packets with them, see `PathStats::one_way_delay` and
+    /// `CongestionController::on_one_way_delay_sample`.
+    pub fn enable_time_stamps(&mut self) {
+        self.time_stamps = true;
+    }
+
+    /// Sets the congestion control algorithm of all `Connection`s. Single `Connection`s can use
+    /// another algorithm, see `ConnectionConfig::set_congestion_algorithm` and
+    /// `Connection::set_congestion_algorithm`.
+    pub fn set_congestion_algorithm(&mut self, algorithm: CongestionAlgorithm) {
+        self.congestion_algorithm = Some(algorithm);
+    }
+
+    /// Sets the handler that creates a `CongestionController` for each path of each
+    /// `Connection`. The controllers replace the builtin congestion control of picoquic, so new
+    /// algorithms can be implemented in Rust. `Connection::set_congestion_algorithm` can still
+    /// switch single `Connection`s to a builtin algorithm.
+    pub fn set_congestion_controller<C: NewCongestionController + 'static>(&mut self, new: C) {
+        self.congestion_controller = Some(Arc::new(new));
+    }
+
+    /// Enables greasing of the QUIC version.
+    /// The first packet of an outgoing `Connection` uses a reserved version of the form
+    /// `0x?a?a?a?a`, which the server can not know. This forces the server into a version
+    /// negotiation and can be used to test, that servers handle unknown versions correctly.
+    pub fn enable_version_greasing(&mut self) {
+        self.grease_version = true;
+    }
+
+    /// Sets the handler that configures each incoming `Connection`.
+    /// The handler can override the defaults of this `Config` per `Connection`, based on the
+    /// address of the client and the requested server name.
+    pub fn set_admission_handler<H: AdmitConnection + 'static>(&mut self, handler: H) {
+        self.admission_handler = Some(Box::new(handler));
+    }
+
+    /// Sets the filter that decides for each incoming `Connection`, if it is accepted, rejected
+    /// with an error code or dropped silently. The filter is called before the handshake is
+    /// finished, so rejected clients cost no further resources.
+    pub fn set_accept_filter<F: AcceptFilter + 'static>(&mut self, filter: F) {
+        self.accept_filter = Some(Box::new(filter));
+    }
+
+    /// Sets the maximum number of incoming `Connection`s that are open at the same time.
+    /// Further clients are rejected with the `SERVER_BUSY` error, until a `Connection` is closed.
+    /// Outgoing `Connection`s are not counted.
+    pub fn set_max_concurrent_connections(&mut self, max: usize) {
+        self.max_concurrent_connections = Some(max);
+    }
+
+    /// Sets the maximum number of incoming `Stream`s per `Connection` that wait to be accepted by
+    /// the application, before the stream credit of the peer is withheld.
+    pub fn set_max_pending_incoming_streams(&mut self, max: usize) {
+        self.max_pending_incoming_streams = Some(max);
+    }
+
+    /// Disables withholding the stream credit, the number of incoming `Stream`s that wait to be
+    /// accepted by the application is unbounded.
+    pub fn disable_incoming_stream_backpressure(&mut self) {
+        self.max_pending_incoming_streams = None;
+    }
+
+    /// Enables the congestion control event logs of picoquic.
+    /// Picoquic writes a binary log per `Connection` into the given directory. The log file is
+    /// named after the initial connection id, `<dir>/<initial cid>-log.bin`. The directory is
+    /// created, if it does not exist.
+    pub fn enable_cc_log<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.cc_log_dir = Some(dir.into());
+    }
+
+    /// Sets the handler that records the outcome of each handshake.
+    /// For every incoming and outgoing `Connection`, the handler gets a `HandshakeRecord` with
+    /// the peer address, the SNI, the ALPN, the fingerprints of the peer certificates and
+    /// whether the handshake was accepted or failed.
+    pub fn set_handshake_audit<H: HandshakeAudit + 'static>(&mut self, handler: H) {
+        self.handshake_audit = Some(Box::new(handler));
+    }
+
+    /// Sets the sink the `Metrics` of the `Context` are reported to, once per
+    /// `metrics_interval`. The `Metrics` contain the open connections, the handshake rate, the
+    /// sent and received bytes and packets, the lost packets and a histogram of the round trip
+    /// times.
+    pub fn set_metrics_sink<S: MetricsSink + 'static>(&mut self, sink: S) {
+        self.metrics_sink = Some(Box::new(sink));
+    }
+
+    /// Sets the interval the `Metrics` are reported to the `metrics_sink`.
+    pub fn set_metrics_interval(&mut self, interval: Duration) {
+        assert!(interval > Duration::from_secs(0), "the metrics interval must not be 0");
+        self.metrics_interval = interval;
+    }
+
+    /// Sets the observer that inspects every packet the `Context` sends and receives, e.g. to
+    /// capture the traffic. The observer gets the direction, the address of the peer, the
+    /// encrypted UDP payload and the `PacketMetadata` that were decoded from the header.
+    pub fn set_packet_observer<O: PacketObserver + 'static>(&mut self, observer: O) {
+        self.packet_observer = Some(Box::new(observer));
+    }
+
+    /// Sets the traffic class of all IPv6 packets sent by the `Context`, e.g. to mark the
+    /// packets for QoS. The traffic class is set on all IPv6 sockets of the `Context`, see
+    /// `Socket::set_ipv6_traffic_class`.
+    pub fn set_ipv6_traffic_class(&mut self, traffic_class: u8) {
+        self.ipv6_traffic_class = Some(traffic_class);
+    }
+
+    /// Sets the TOS of all IPv4 packets sent by the `Context`, e.g. to mark the packets with a
+    /// DSCP for QoS. The TOS is set on all sockets of the `Context`, see `Socket::set_tos`.
+    pub fn set_ip_tos(&mut self, tos: u8) {
+        self.ip_tos = Some(tos);
+    }
+
+    /// Sets the TTL of all packets sent by the `Context`, the hop limit for IPv6 packets.
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.ttl = Some(ttl);
+    }
+
+    /// Sets the size of the send buffer of each socket of the `Context` in bytes.
+    /// Bigger buffers prevent dropped packets on links with a high bandwidth, e.g. 10GbE. The
+    /// operating system may cap the size, on Linux at `net.core.wmem_max`.
+    pub fn set_socket_send_buffer_size(&mut self, size: usize) {
+        self.socket_send_buffer_size = Some(size);
+    }
+
+    /// Sets the size of the receive buffer of each socket of the `Context` in bytes.
+    /// Bigger buffers prevent dropped packets on links with a high bandwidth, e.g. 10GbE. The
+    /// operating system may cap the size, on Linux at `net.core.rmem_max`.
+    pub fn set_socket_recv_buffer_size(&mut self, size: usize) {
+        self.socket_recv_buffer_size = Some(size);
+    }
+
+    /// Sets if the IPv6 sockets that are bound by the `Context` are IPv6 only. Sockets that are
+    /// given to the `Context` (e.g. `ContextBuilder::socket`) are already bound and keep their
+    /// setting.
+    pub fn set_ipv6_only(&mut self, only_v6: bool) {
+        self.ipv6_only = Some(only_v6);
+    }
+
+    /// Enables or disables Explicit Congestion Notification(ECN).
+    /// All sockets of the `Context` mark the sent packets as ECN capable(`Ecn::Ect0`) and read
+    /// the codepoints of the received packets, see `Socket::enable_ecn`. The received codepoints
+    /// are counted in `ConnectionStats::ecn_received`. Routers on the path can then mark
+    /// packets with `Ecn::Ce` on congestion, instead of dropping them.
+    /// The codepoints are not yet reported to the peer in the ACK frames, as picoquic does not
+    /// support this. Creating the `Context` fails, if a socket does not support ECN.
+    pub fn enable_ecn(&mut self, enabled: bool) {
+        self.ecn = enabled;
+    }
+
+    /// Sets the OpenSSL engine or provider that is used for the packet protection and the
+    /// handshake crypto, e.g. a hardware accelerated or HSM backed implementation.
+    /// The backend is loaded when the `Context` is created and stays the default of OpenSSL for
+    /// the whole process. `ConnectionStats::crypto` shows the effect on the throughput.
+    pub fn set_crypto_backend(&mut self, backend: CryptoBackend) {
+        self.crypto_backend = Some(backend);
+    }
+
+    /// Enables the unreliable DATAGRAM extension(RFC 9221) and advertises the given maximum
+    /// DATAGRAM frame size to the peer. Datagrams can only be exchanged, if both peers enabled
+    /// the extension, see `Connection::send_datagram`.
+    pub fn enable_datagrams(&mut self, max_frame_size: usize) {
+        assert!(max_frame_size > 0, "the maximum DATAGRAM frame size must be greater than 0");
+        self.max_datagram_frame_size = Some(max_frame_size);
+    }
+
+    /// Sets where the session tickets of outgoing `Connection`s are stored. With
+    /// `SessionTicketStore::File`, sessions are also resumed by the next process that uses the
+    /// same file.
+    pub fn set_session_ticket_store(&mut self, store: SessionTicketStore) {
+        self.session_ticket_store = store;
+    }
+
+    /// Sets the priority of all new `Stream`s, incoming and outgoing. The priority of a single
+    /// `Stream` is changed with `Stream::set_priority`, e.g. to let a control `Stream` preempt
+    /// bulk transfers.
+    pub fn set_default_stream_priority(&mut self, priority: Priority) {
+        self.default_stream_priority = Some(priority);
+    }
+
+    /// Enables the qlog traces of the `Connection`s.
+    /// A JSON qlog trace with the sent, received, acknowledged and lost packets, the congestion
+    /// control metrics and the stream data is written per `Connection` into the given directory.
+    /// The trace is named after the local connection id and the vantage point,
+    /// `<dir>/<connection id>.<client|server>.qlog`. The directory is created, if it does not
+    /// exist.
+    pub fn enable_qlog<P: Into<PathBuf>>(&mut self, dir: P) {
+        self.qlog_dir = Some(dir.into());
+    }
+
+    /// Sets the file the TLS secrets of all `Connection`s are appended to, in the NSS key log
+    /// format. Wireshark uses the secrets to decrypt captured QUIC packets.
+    ///
+    /// Everybody with access to the file can decrypt the traffic, so this should only be
+    /// enabled for debugging.
+    pub fn set_key_log_file<P: Into<PathBuf>>(&mut self, path: P) {
+        self.key_log_file = Some(path.into());
+    }
+
+    /// Enables the TLS key log, if the `SSLKEYLOGFILE` environment variable is set, see
+    /// `set_key_log_file`.
+    pub fn enable_key_log_from_env(&mut self) {
+        if let Some(path) = env::var_os("SSLKEYLOGFILE") {
+            self.key_log_file = Some(path.into());
+        }
+    }
+
+    /// Sets the application layer protocols, in order of preference.
+    /// Outgoing `Connection`s offer all protocols to the server. Incoming `Connection`s select
+    /// the first protocol that is also offered by the client, the handshake fails, if the client
+    /// offers none of the protocols. The negotiated protocol is returned by
+    /// `Connection::negotiated_alpn`, so one `Context` can serve multiple protocols.
+    pub fn set_alpn_protocols(&mut self, protocols: Vec<Vec<u8>>) {
+        self.alpn_protocols = protocols;
+    }
+
+    /// Sets the QUIC versions, in order of preference.
+    /// Outgoing `Connection`s start with the first version that is implemented by picoquic,
+    /// the `Context` creation fails, if picoquic implements none of the versions. The server
+    /// drops the handshake packets of all other versions. The negotiated version is returned by
+    /// `Connection::negotiated_version`.
+    ///
+    /// Only the versions that are compiled into `picoquic-sys` are implemented, see
+    /// `QuicVersion::implemented`. `QuicVersion::V1`(RFC 9000) requires a `picoquic-sys` that
+    /// implements it.
+    pub fn set_supported_versions(&mut self, versions: Vec<QuicVersion>) {
+        self.supported_versions = versions;
+    }
+
+    /// Updates the packet protection keys of each `Connection`, after the given number of bytes
+    /// were protected with the same keys or after the keys were used for the given time.
+    /// The interval restarts with each key update, including the key updates of the peer.
+    /// A time based interval is checked, when the `Connection` sends or receives packets.
+    pub fn set_key_update_interval(&mut self, interval: KeyUpdateInterval) {
+        self.key_update_interval = Some(interval);
+    }
 }
 
 impl Default for Config {
@@ -139,11 +1110,97 @@ impl Default for Config {
             root_certificates: None,
             private_key_filename: None,
             private_key: None,
+            sni_identities: Vec::new(),
             reset_seed: None,
             keep_alive_interval: None,
             keep_alive_sender: Role::Client,
+            idle_timeout: None,
             client_authentication: false,
+            client_only: false,
+            #[cfg(feature = "self-signed")]
+            self_signed_name: None,
+            #[cfg(feature = "openssl")]
             verify_certificate_handler: None,
+            #[cfg(feature = "openssl")]
+            async_verify_certificate_handler: None,
+            callback_driven_send: false,
+            zero_copy_send_threshold: Some(64 * 1024),
+            write_coalescing: None,
+            coalesce_stream_writes: true,
+            max_send_backlog: None,
+            max_pending_send_messages: None,
+            max_receive_window: None,
+            recv_buffer_chunk_size: Some(64 * 1024),
+            amplification_factor: 3,
+            stateless_retry: false,
+            retry_token_key: None,
+            handshake_rate_limit: None,
+            mtu_discovery: MtuDiscovery::Default,
+            max_udp_payload_size: None,
+            initial_max_data: None,
+            initial_max_stream_data_bidi_local: None,
+            initial_max_stream_data_bidi_remote: None,
+            initial_max_stream_data_uni: None,
+            initial_max_streams_bidi: None,
+            initial_max_streams_uni: None,
+            ack_delay_exponent: None,
+            time_stamps: false,
+            congestion_algorithm: None,
+            congestion_controller: None,
+            grease_version: false,
+            admission_handler: None,
+            accept_filter: None,
+            max_concurrent_connections: None,
+            cc_log_dir: None,
+            max_pending_incoming_streams: Some(64),
+            handshake_audit: None,
+            metrics_sink: None,
+            metrics_interval: Duration::from_secs(10),
+            packet_observer: None,
+            ipv6_traffic_class: None,
+            ip_tos: None,
+            ttl: None,
+            socket_send_buffer_size: None,
+            socket_recv_buffer_size: None,
+            ipv6_only: None,
+            ecn: false,
+            crypto_backend: None,
+            max_datagram_frame_size: None,
+            session_ticket_store: SessionTicketStore::InMemory,
+            default_stream_priority: None,
+            qlog_dir: None,
+            key_log_file: None,
+            alpn_protocols: Vec::new(),
+            supported_versions: Vec::new(),
+            key_update_interval: None,
+        }
+    }
+}
+
+/// The transport parameters of the `Config` that override the defaults of picoquic.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct TransportParameterOverrides {
+    pub initial_max_data: Option<u64>,
+    pub initial_max_stream_data_bidi_local: Option<u64>,
+    pub initial_max_stream_data_bidi_remote: Option<u64>,
+    pub initial_max_stream_data_uni: Option<u64>,
+    pub initial_max_streams_bidi: Option<u64>,
+    pub initial_max_streams_uni: Option<u64>,
+    pub ack_delay_exponent: Option<u8>,
+    pub time_stamps: bool,
+}
+
+impl TransportParameterOverrides {
+    pub fn from_config(config: &Config) -> TransportParameterOverrides {
+        TransportParameterOverrides {
+            initial_max_data: config.initial_max_data,
+            initial_max_stream_data_bidi_local: config.initial_max_stream_data_bidi_local,
+            initial_max_stream_data_bidi_remote: config.initial_max_stream_data_bidi_remote,
+            initial_max_stream_data_uni: config.initial_max_stream_data_uni,
+            initial_max_streams_bidi: config.initial_max_streams_bidi,
+            initial_max_streams_uni: config.initial_max_streams_uni,
+            ack_delay_exponent: config.ack_delay_exponent,
+            time_stamps: config.time_stamps,
         }
     }
 }
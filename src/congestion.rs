@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+/// The state of the path, that is controlled by a `CongestionController`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathInfo {
+    /// The smoothed round trip time.
+    pub smoothed_rtt: Duration,
+    /// The minimum round trip time that was observed.
+    pub min_rtt: Duration,
+    /// The number of bytes that were sent, but not yet acknowledged or declared lost.
+    pub bytes_in_flight: u64,
+    /// The maximum size of the packets that are sent on the path.
+    pub mtu: usize,
+}
+
+/// A congestion control algorithm, that replaces the builtin algorithms of picoquic.
+///
+/// Each path of a `Connection` is controlled by its own `CongestionController`, which is created
+/// by the `NewCongestionController` of the `Config`, see `Config::set_congestion_controller`.
+/// After each event, picoquic applies the `congestion_window` and the `pacing_rate`.
+pub trait CongestionController: Send {
+    /// Returns the congestion window in bytes. Picoquic only sends new packets, while less bytes
+    /// are in flight.
+    fn congestion_window(&self) -> u64;
+
+    /// Returns the pacing rate in bytes per second, `None` sends the packets without pacing.
+    fn pacing_rate(&self) -> Option<u64> {
+        None
+    }
+
+    /// A packet with the given number of bytes was sent on the primary path.
+    fn on_packet_sent(&mut self, _bytes: usize, _path: &PathInfo) {}
+
+    /// The given number of bytes were acknowledged by the peer.
+    fn on_ack(&mut self, acked_bytes: u64, path: &PathInfo);
+
+    /// The packet with the given number was declared lost. `timeout` is set, if the loss was
+    /// detected by a retransmission timeout.
+    fn on_loss(&mut self, packet_number: u64, timeout: bool, path: &PathInfo);
+
+    /// A new round trip time sample was measured.
+    fn on_rtt_sample(&mut self, _rtt: Duration, _path: &PathInfo) {}
+
+    /// A new one-way delay sample was measured with the time stamps of the peer, see
+    /// `Config::enable_time_stamps`. The clocks of both sides are not synchronized, so only the
+    /// variation of the samples is meaningful, e.g. to detect growing queues.
+    fn on_one_way_delay_sample(&mut self, _delay: Duration, _path: &PathInfo) {}
+}
+
+/// Creates the `CongestionController` of each new path.
+pub trait NewCongestionController: Send + Sync {
+    fn new_controller(&self) -> Box<dyn CongestionController>;
+}
+
+impl<F> NewCongestionController for F
+where
+    F: Fn() -> Box<dyn CongestionController> + Send + Sync,
+{
+    fn new_controller(&self) -> Box<dyn CongestionController> {
+        self()
+    }
+}
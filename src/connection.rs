@@ -1,17 +1,40 @@
+use blackhole::BlackholeDetector;
+use careful_resume::{CarefulResume, PathParameters};
+use config::{CongestionAlgorithm, ConnectionConfig, MtuDiscovery, TransportParameterOverrides};
+use context_inner::socket_index_by_addr;
+use datagram::{max_datagram_payload, DatagramSender, Datagrams};
 use error::*;
+use event_monitor::{TransportEventMonitor, TransportState};
 use ffi::{self, QuicCtx};
+use key_update::{KeyUpdateInterval, KeyUpdateScheduler};
+use mtu_discovery::MtuProber;
+use priority::Priority;
+use qlog::QlogWriter;
+use receive_window::ReceiveWindowTuner;
+use recv_pool::RecvPool;
+use stats::{ConnectionStats, CryptoMeter, EcnMeter};
 use stream::{self, Stream};
+use stream_credit::StreamCreditGate;
+use trace::Span;
 use unbounded_with_error::{unbounded_with_error, Receiver, SendError, Sender};
+use version::QuicVersion;
 
 use picoquic_sys::picoquic::{
-    self, picoquic_call_back_event_t, picoquic_cnx_t, picoquic_set_callback,
+    self, picoquic_call_back_event_t, picoquic_cnx_t, picoquic_provide_stream_data_buffer,
+    picoquic_set_callback,
 };
 
+use bytes::{Bytes, BytesMut};
+
+#[cfg(feature = "openssl")]
+use openssl::x509::X509;
+
 use futures::{
     sync::{
         mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
         oneshot,
     },
+    task::{self, Task},
     Async::{NotReady, Ready},
     Future, Poll, Stream as FStream,
 };
@@ -19,18 +42,129 @@ use futures::{
 use std::{
     collections::{
         hash_map::Entry::{Occupied, Vacant},
-        HashMap,
+        HashMap, VecDeque,
     },
     mem,
     net::SocketAddr,
     os::raw::c_void,
+    path::PathBuf,
     slice,
-    sync::{Arc, Mutex},
-    time::Duration,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 pub type Id = u64;
 
+/// The settings that are applied to a new `Connection`.
+#[derive(Clone)]
+pub(crate) struct Settings {
+    /// The keep alive interval, if this side of the connection sends the keep alive packages.
+    pub keep_alive_interval: Option<Duration>,
+    /// Decides which data the `Stream`s keep, until picoquic requests it.
+    pub send_path: stream::SendPath,
+    /// The maximum number of bytes of small writes that are coalesced per `Stream`.
+    pub write_coalescing: Option<usize>,
+    /// The limits for the unsent data per `Stream`.
+    pub send_watermark: stream::SendWatermark,
+    /// The maximum size the receive window auto tuning is allowed to grow to.
+    pub max_receive_window: Option<u64>,
+    /// The size of the buffers that the received data of the `Stream`s is copied into.
+    pub recv_buffer_chunk_size: Option<usize>,
+    /// The path MTU discovery.
+    pub mtu_discovery: MtuDiscovery,
+    /// The maximum UDP payload size that is advertised to the peer.
+    pub max_udp_payload_size: Option<usize>,
+    /// Start outgoing connections with a greased version.
+    pub grease_version: bool,
+    /// The QUIC version of outgoing connections, `None` uses the default version of picoquic.
+    pub quic_version: Option<QuicVersion>,
+    /// The application layer protocols that are offered by outgoing connections and are
+    /// selected by incoming connections, in order of preference.
+    pub alpn_protocols: Vec<Vec<u8>>,
+    /// The idle timeout of the connection.
+    pub idle_timeout: Option<Duration>,
+    /// The maximum number of incoming streams that wait to be accepted by the application,
+    /// before the stream credit of the peer is withheld.
+    pub max_pending_incoming_streams: Option<usize>,
+    /// The maximum DATAGRAM frame size that is advertised to the peer.
+    pub max_datagram_frame_size: Option<usize>,
+    /// The data that is sent as 0-RTT data on the first bidirectional `Stream`.
+    pub early_data: Option<Bytes>,
+    /// The priority of new `Stream`s.
+    pub default_stream_priority: Option<Priority>,
+    /// The directory the qlog trace of the connection is written to.
+    pub qlog_dir: Option<PathBuf>,
+    /// The local addresses of the sockets of the `Context`, a connection can migrate to.
+    pub local_addrs: Vec<SocketAddr>,
+    /// The transport parameters that are advertised to the peer.
+    pub transport_parameters: TransportParameterOverrides,
+    /// The congestion control algorithm, `None` uses the algorithm of the `Context`.
+    pub congestion_algorithm: Option<CongestionAlgorithm>,
+    /// The interval after which the packet protection keys are updated.
+    pub key_update_interval: Option<KeyUpdateInterval>,
+    /// The parameters of a previous path, that are resumed.
+    pub path_parameters: Option<PathParameters>,
+}
+
+impl Settings {
+    /// Overrides these settings with all values that are set in the given `ConnectionConfig`.
+    pub fn with_overrides(mut self, config: ConnectionConfig) -> Settings {
+        if let Some(alpn) = config.alpn {
+            self.alpn_protocols = vec![alpn.into_bytes()];
+        }
+
+        if config.keep_alive_interval.is_some() {
+            self.keep_alive_interval = config.keep_alive_interval;
+        }
+
+        if config.idle_timeout.is_some() {
+            self.idle_timeout = config.idle_timeout;
+        }
+
+        if let Some(max) = config.max_incoming_streams {
+            // Picoquic grants the peer new stream credit, when its `Stream`s are closed.
+            self.transport_parameters.initial_max_streams_bidi = Some(max as u64);
+            self.transport_parameters.initial_max_streams_uni = Some(max as u64);
+        }
+
+        if config.early_data.is_some() {
+            self.early_data = config.early_data;
+        }
+
+        if config.congestion_algorithm.is_some() {
+            self.congestion_algorithm = config.congestion_algorithm;
+        }
+
+        if config.path_parameters.is_some() {
+            self.path_parameters = config.path_parameters;
+        }
+
+        self
+    }
+}
+
+/// A request of the `Connection` to change the connection.
+enum Control {
+    /// Migrate to the given local address.
+    Migrate(SocketAddr),
+    SetCongestionAlgorithm(CongestionAlgorithm),
+    /// Send a `PING` and report the round trip time to the sender.
+    Ping(oneshot::Sender<Result<Duration, Error>>),
+    /// Update the packet protection keys.
+    KeyUpdate,
+}
+
+/// A `PING` that waits to be acknowledged by the peer.
+struct PendingPing {
+    queued_at: Instant,
+    /// The first packet number the `PING` can be sent with.
+    packet_number: u64,
+    done: oneshot::Sender<Result<Duration, Error>>,
+}
+
 #[derive(Debug)]
 enum Message {
     NewStream(Stream),
@@ -38,6 +172,14 @@ enum Message {
     Error(Error),
 }
 
+/// A request of the application to close the connection.
+struct CloseRequest {
+    error_code: u64,
+    reason: String,
+    /// Is notified after the `CONNECTION_CLOSE` was sent, `None` closes the connection at once.
+    done: Option<oneshot::Sender<()>>,
+}
+
 /// A `Connection` can either be `Incoming` or `Outgoing`.
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Type {
@@ -47,42 +189,339 @@ pub enum Type {
     Outgoing,
 }
 
+/// An event that occurred on a `Connection`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Event {
+    /// Packets got persistently lost after the MTU was increased or the path changed. The MTU
+    /// was clamped down to the given value.
+    BlackholeDetected { mtu: usize },
+    /// The maximum datagram size decreased to the given value, e.g. because the MTU was clamped
+    /// down. See `Connection::max_datagram_size`.
+    MaxDatagramSizeDecreased { size: usize },
+    /// The `Connection` moved to a new path, because the client migrated it with
+    /// `Connection::migrate` or the address of the client changed (NAT rebinding).
+    PathMigrated {
+        local_addr: SocketAddr,
+        peer_addr: SocketAddr,
+    },
+    /// A new path to the given peer address was opened and is validated with a
+    /// `PATH_CHALLENGE`.
+    PathChallenge { peer_addr: SocketAddr },
+    /// The keys of the 1-RTT packets were updated, by us or by the peer, see
+    /// `Connection::initiate_key_update`.
+    KeyUpdate { initiated_by_peer: bool },
+    /// A new `Stream` of the given type waits, because the peer does not grant the stream
+    /// credit for it (`STREAMS_BLOCKED`).
+    StreamsBlocked { stype: stream::Type },
+    /// The data of all `Stream`s reached the flow control limit of the connection, that was
+    /// granted by the peer (`DATA_BLOCKED`).
+    DataBlocked { limit: u64 },
+    /// The data of the given `Stream` reached the flow control limit of the `Stream`, that was
+    /// granted by the peer (`STREAM_DATA_BLOCKED`). See `Stream::send_capacity`.
+    StreamDataBlocked { id: stream::Id, limit: u64 },
+    /// The `Connection` did not receive a packet for three quarters of the idle timeout and
+    /// closes after the `remaining` time, if no packet is received.
+    IdleTimeoutWarning { remaining: Duration },
+    /// The server sent a session ticket, see `Connection::export_session_ticket`.
+    /// Only reported to clients.
+    SessionTicketReceived,
+}
+
+/// The negotiated TLS parameters of a `Connection`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TlsInfo {
+    /// The IANA id of the cipher suite, e.g. `0x1301` for `TLS_AES_128_GCM_SHA256`.
+    pub cipher_suite: u16,
+    /// The TLS version, QUIC always uses TLS 1.3 (`0x0304`).
+    pub version: u16,
+    /// Was the session resumed with a session ticket?
+    pub session_resumed: bool,
+    /// Was the 0-RTT data accepted by the server?
+    pub early_data_accepted: bool,
+    /// The negotiated application layer protocol.
+    pub alpn: Option<Vec<u8>>,
+}
+
+/// The QUIC connection ids of a `Connection`, e.g. to correlate it with qlog or pcap traces.
+/// The connection ids are not bound to the address of the peer, so they survive a migration.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ConnectionIds {
+    /// The destination connection id of the first Initial packet of the client. This id does not
+    /// change over the lifetime of the `Connection`.
+    pub initial: Vec<u8>,
+    /// The current connection id the peer sends its packets to.
+    pub local: Vec<u8>,
+    /// The current connection id of the peer, the packets are sent to.
+    pub remote: Vec<u8>,
+}
+
+impl TlsInfo {
+    /// Returns the IANA name of the cipher suite.
+    pub fn cipher_suite_name(&self) -> Option<&'static str> {
+        match self.cipher_suite {
+            0x1301 => Some("TLS_AES_128_GCM_SHA256"),
+            0x1302 => Some("TLS_AES_256_GCM_SHA384"),
+            0x1303 => Some("TLS_CHACHA20_POLY1305_SHA256"),
+            0x1304 => Some("TLS_AES_128_CCM_SHA256"),
+            0x1305 => Some("TLS_AES_128_CCM_8_SHA256"),
+            _ => None,
+        }
+    }
+}
+
+/// The transport parameters the peer advertised in the handshake of a `Connection`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TransportParameters {
+    /// The flow control credit of the connection.
+    pub initial_max_data: u64,
+    /// The flow control credit of the bidirectional `Stream`s the peer opens.
+    pub initial_max_stream_data_bidi_local: u64,
+    /// The flow control credit of the bidirectional `Stream`s we open.
+    pub initial_max_stream_data_bidi_remote: u64,
+    /// The flow control credit of the unidirectional `Stream`s we open.
+    pub initial_max_stream_data_uni: u64,
+    /// The number of bidirectional `Stream`s we are allowed to open initially.
+    pub initial_max_streams_bidi: u64,
+    /// The number of unidirectional `Stream`s we are allowed to open initially.
+    pub initial_max_streams_uni: u64,
+    /// The maximum UDP payload size the peer is able to receive.
+    pub max_udp_payload_size: usize,
+    /// The exponent that scales the ACK delay in the ACK frames of the peer.
+    pub ack_delay_exponent: u8,
+    /// Does the peer support the one-way time stamp extension?
+    pub time_stamps: bool,
+}
+
+/// The certificate chain the peer presented, handed to the `Connection` after the handshake.
+#[cfg(feature = "openssl")]
+#[derive(Clone, Default)]
+pub(crate) struct PeerCertificates(Arc<Mutex<Vec<X509>>>);
+
+#[cfg(feature = "openssl")]
+impl PeerCertificates {
+    pub fn set(&self, certs: Vec<X509>) {
+        *self.0.lock().unwrap() = certs;
+    }
+
+    pub fn get(&self) -> Vec<X509> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+/// The state that is shared between a `Connection` and its `Context`.
+#[derive(Default)]
+struct Shared {
+    /// The negotiated TLS parameters.
+    tls_info: Mutex<Option<TlsInfo>>,
+    /// The transport parameters of the peer.
+    peer_transport_parameters: Mutex<Option<TransportParameters>>,
+    /// The transport statistics.
+    stats: Mutex<ConnectionStats>,
+    /// The crypto throughput, collected by the `Context`.
+    crypto: CryptoMeter,
+    /// The ECN codepoints of the received packets, collected by the `Context`.
+    ecn: EcnMeter,
+    /// The number of incoming `Stream`s that were not yet taken by the application.
+    pending_streams: AtomicUsize,
+    /// Close the connection, when all `Stream`s are finished and all data is acknowledged.
+    close_when_idle: AtomicBool,
+    /// The maximum payload of a datagram, `0` if datagrams are not supported.
+    max_datagram_size: Arc<AtomicUsize>,
+    /// The session ticket the server sent for this connection.
+    session_ticket: Mutex<Option<Vec<u8>>>,
+    /// The certificate chain of the peer.
+    #[cfg(feature = "openssl")]
+    peer_certificates: PeerCertificates,
+    /// The local and the peer address, after the connection migrated to a new path.
+    path: Mutex<Option<(SocketAddr, SocketAddr)>>,
+    /// The completion of the handshake.
+    handshake: Mutex<Handshake>,
+    /// The current connection ids.
+    connection_ids: Mutex<ConnectionIds>,
+    /// The QUIC version, after the handshake finished.
+    quic_version: Mutex<Option<QuicVersion>>,
+}
+
+/// The state of the handshake, shared between the `HandshakeCompleted` futures of a
+/// `Connection` and its `Context`.
+#[derive(Default)]
+struct Handshake {
+    completed: bool,
+    /// The error of the connection, if it failed before the handshake was completed.
+    error: Option<Box<dyn ErrorFn<Output = Error>>>,
+    /// The `HandshakeCompleted` futures that wait for the handshake.
+    tasks: Vec<Task>,
+}
+
+impl Handshake {
+    fn complete(&mut self) {
+        if !self.completed {
+            self.completed = true;
+            self.tasks.drain(..).for_each(|t| t.notify());
+        }
+    }
+
+    fn fail<F: ErrorFn<Output = Error>>(&mut self, err: F) {
+        if !self.completed && self.error.is_none() {
+            self.error = Some(Box::new(err));
+            self.tasks.drain(..).for_each(|t| t.notify());
+        }
+    }
+}
+
+/// The `Stream` of `Event`s of a `Connection`.
+/// This stream is created by `Connection::events`.
+pub struct Events {
+    recv: UnboundedReceiver<Event>,
+}
+
+impl FStream for Events {
+    type Item = Event;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.recv.poll().map_err(|_| ErrorKind::Unknown.into())
+    }
+}
+
+/// The incoming `Stream`s of a `Connection`, shared between the `Connection` and its
+/// `IncomingStreams`.
+struct Incoming {
+    recv: UnboundedReceiver<Message>,
+    /// The received `Stream`s that wait for a consumer of their type.
+    buffered: VecDeque<Stream>,
+    /// The consumers that wait for a `Stream`, these are notified when a `Stream` is buffered.
+    tasks: Vec<Task>,
+    finished: bool,
+}
+
+impl Incoming {
+    fn new(recv: UnboundedReceiver<Message>) -> Incoming {
+        Incoming {
+            recv,
+            buffered: VecDeque::new(),
+            tasks: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// Polls the next incoming `Stream` of the given type, `None` accepts all types.
+    fn poll(&mut self, stype: Option<stream::Type>) -> Poll<Option<Stream>, Error> {
+        let accepts = |s: &Stream| stype.is_none() || stype == Some(s.get_type());
+
+        if let Some(pos) = self.buffered.iter().position(accepts) {
+            return Ok(Ready(self.buffered.remove(pos)));
+        }
+
+        while !self.finished {
+            match self.recv.poll().map_err(|_| Error::from(ErrorKind::Unknown))? {
+                NotReady => {
+                    if !self.tasks.iter().any(|t| t.will_notify_current()) {
+                        self.tasks.push(task::current());
+                    }
+
+                    return Ok(NotReady);
+                }
+                Ready(Some(Message::NewStream(s))) => {
+                    if accepts(&s) {
+                        return Ok(Ready(Some(s)));
+                    }
+
+                    self.buffered.push_back(s);
+                    self.notify_all();
+                }
+                Ready(Some(Message::Error(e))) => {
+                    self.finished = true;
+                    self.notify_all();
+                    return Err(e);
+                }
+                Ready(Some(Message::Close)) | Ready(None) => {
+                    self.finished = true;
+                    self.notify_all();
+                }
+            }
+        }
+
+        Ok(Ready(None))
+    }
+
+    fn notify_all(&mut self) {
+        self.tasks.drain(..).for_each(|t| t.notify());
+    }
+}
+
+fn poll_incoming(
+    incoming: &Mutex<Incoming>,
+    shared: &Shared,
+    stype: Option<stream::Type>,
+) -> Poll<Option<Stream>, Error> {
+    let stream = try_ready!(incoming.lock().unwrap().poll(stype));
+
+    if stream.is_some() {
+        shared.pending_streams.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    Ok(Ready(stream))
+}
+
+/// The incoming `Stream`s of one type of a `Connection`.
+/// This stream is created by `Connection::incoming_bidirectional` and
+/// `Connection::incoming_unidirectional`.
+#[derive(Clone)]
+pub struct IncomingStreams {
+    incoming: Arc<Mutex<Incoming>>,
+    shared: Arc<Shared>,
+    stype: stream::Type,
+}
+
+impl IncomingStreams {
+    /// Returns the type of the `Stream`s, this stream yields.
+    pub fn get_type(&self) -> stream::Type {
+        self.stype
+    }
+}
+
+impl FStream for IncomingStreams {
+    type Item = Stream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        poll_incoming(&self.incoming, &self.shared, Some(self.stype))
+    }
+}
+
 struct ConnectionBuilder {
     msg_recv: UnboundedReceiver<Message>,
-    close_send: oneshot::Sender<()>,
+    event_recv: UnboundedReceiver<Event>,
+    close_send: oneshot::Sender<CloseRequest>,
     peer_addr: SocketAddr,
     local_addr: SocketAddr,
+    local_addrs: Vec<SocketAddr>,
+    send_control: UnboundedSender<Control>,
     new_stream_handle: NewStreamHandle,
+    datagrams: Datagrams,
+    early_data_stream: Option<Stream>,
     ctype: Type,
+    server_name: Option<String>,
 }
 
 impl ConnectionBuilder {
-    fn new(
-        msg_recv: UnboundedReceiver<Message>,
-        close_send: oneshot::Sender<()>,
-        peer_addr: SocketAddr,
-        local_addr: SocketAddr,
-        new_stream_handle: NewStreamHandle,
-        ctype: Type,
-    ) -> ConnectionBuilder {
-        ConnectionBuilder {
-            msg_recv,
-            close_send,
-            peer_addr,
-            local_addr,
-            new_stream_handle,
-            ctype,
-        }
-    }
-
-    fn build(self, id: Id) -> Connection {
+    fn build(self, id: Id, shared: Arc<Shared>) -> Connection {
         Connection {
-            msg_recv: self.msg_recv,
+            incoming: Arc::new(Mutex::new(Incoming::new(self.msg_recv))),
+            event_recv: Some(self.event_recv),
+            datagram_sender: self.datagrams.sender(),
+            datagrams: Some(self.datagrams),
+            early_data_stream: self.early_data_stream,
+            shared,
             close_send: Some(self.close_send),
             peer_addr: self.peer_addr,
             local_addr: self.local_addr,
+            local_addrs: self.local_addrs,
+            send_control: self.send_control,
             new_stream_handle: self.new_stream_handle,
             ctype: self.ctype,
+            server_name: self.server_name,
             id,
         }
     }
@@ -90,24 +529,111 @@ impl ConnectionBuilder {
 
 /// Represents a connection to a peer.
 pub struct Connection {
-    msg_recv: UnboundedReceiver<Message>,
-    close_send: Option<oneshot::Sender<()>>,
+    incoming: Arc<Mutex<Incoming>>,
+    event_recv: Option<UnboundedReceiver<Event>>,
+    datagram_sender: DatagramSender,
+    datagrams: Option<Datagrams>,
+    early_data_stream: Option<Stream>,
+    shared: Arc<Shared>,
+    close_send: Option<oneshot::Sender<CloseRequest>>,
     peer_addr: SocketAddr,
     local_addr: SocketAddr,
+    /// The local addresses of the `Context`, this `Connection` can migrate to.
+    local_addrs: Vec<SocketAddr>,
+    send_control: UnboundedSender<Control>,
     new_stream_handle: NewStreamHandle,
     id: Id,
     ctype: Type,
+    /// The server name(SNI) of the TLS handshake.
+    server_name: Option<String>,
 }
 
 impl Connection {
     /// Returns the address of the peer, this `Connection` is connected to.
+    /// The address changes, when the `Connection` migrates, see `Event::PathMigrated`.
     pub fn peer_addr(&self) -> SocketAddr {
-        self.peer_addr
+        self.shared
+            .path
+            .lock()
+            .unwrap()
+            .map(|(_, peer_addr)| peer_addr)
+            .unwrap_or(self.peer_addr)
     }
 
     /// Returns the address of the local `Context`, where it is listening on.
+    /// If the `Context` listens on multiple addresses, this is the address of the socket the
+    /// `Connection` uses, see `Context::local_addrs`.
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr
+        self.shared
+            .path
+            .lock()
+            .unwrap()
+            .map(|(local_addr, _)| local_addr)
+            .unwrap_or(self.local_addr)
+    }
+
+    /// Returns the server name(SNI) of the TLS handshake. For an incoming `Connection`, this is
+    /// the name the client requested, e.g. to route the `Connection` to the handler of the
+    /// virtual host. See `Config::add_sni_identity`.
+    pub fn sni(&self) -> Option<&str> {
+        self.server_name.as_ref().map(String::as_str)
+    }
+
+    /// Migrates this `Connection` to the socket of the given local address, e.g. when the
+    /// client moves from WiFi to LTE. Picoquic validates the new path, before the `Connection`
+    /// uses it, `Event::PathMigrated` reports the migration.
+    ///
+    /// Only outgoing `Connection`s can migrate. The `Context` requires a socket that is bound to
+    /// the given address, see `ContextBuilder::listen_addresses`.
+    pub fn migrate(&self, new_local_addr: SocketAddr) -> Result<(), Error> {
+        if self.ctype != Type::Outgoing {
+            return Err(ErrorKind::MigrationNotAllowed.into());
+        }
+
+        if socket_index_by_addr(&self.local_addrs, new_local_addr).is_none() {
+            return Err(ErrorKind::UnknownLocalAddress(new_local_addr).into());
+        }
+
+        self.request_control(Control::Migrate(new_local_addr))
+    }
+
+    /// Switches the congestion control algorithm of this `Connection`.
+    /// This overrides the algorithm of the `Config` or `ConnectionConfig`.
+    pub fn set_congestion_algorithm(&self, algorithm: CongestionAlgorithm) -> Result<(), Error> {
+        self.request_control(Control::SetCongestionAlgorithm(algorithm))
+    }
+
+    /// Sends a `PING` frame to the peer, e.g. to probe the liveness of this `Connection` or to
+    /// display the latency. The returned future resolves with the round trip time, after the
+    /// peer acknowledged the `PING`. The future fails, if the `Connection` is closed before.
+    pub fn ping(&self) -> PingFuture {
+        let (done, recv) = oneshot::channel();
+        // A closed `Connection` drops the sender, which fails the future.
+        let _ = self.request_control(Control::Ping(done));
+
+        PingFuture { recv }
+    }
+
+    /// Updates the packet protection keys of this `Connection`, e.g. to limit the data that is
+    /// protected with the same keys. `Event::KeyUpdate` reports the update.
+    ///
+    /// Picoquic updates the keys after the handshake finished and after the peer acknowledged a
+    /// packet of the previous key update, so the update can be delayed. Automatic key updates
+    /// are configured with `Config::set_key_update_interval`.
+    pub fn initiate_key_update(&self) -> Result<(), Error> {
+        self.request_control(Control::KeyUpdate)
+    }
+
+    fn request_control(&self, control: Control) -> Result<(), Error> {
+        self.send_control
+            .unbounded_send(control)
+            .map_err(|_| ErrorKind::Disconnected.into())
+    }
+
+    /// Returns the QUIC connection ids of this `Connection`. The local and the remote connection
+    /// id can change over the lifetime of the `Connection`.
+    pub fn connection_ids(&self) -> ConnectionIds {
+        self.shared.connection_ids.lock().unwrap().clone()
     }
 
     /// Returns the id of this `Connection`.
@@ -120,6 +646,185 @@ impl Connection {
     pub fn get_type(&self) -> Type {
         self.ctype
     }
+
+    /// Returns the incoming bidirectional `Stream`s of this `Connection`.
+    ///
+    /// The `Connection` itself yields the incoming `Stream`s of all types, a `Stream` is only
+    /// yielded once, by the `Connection` or by the `IncomingStreams` of the `Stream` type that
+    /// is polled first. The `Connection` needs to be kept alive, while the `IncomingStreams` are
+    /// used.
+    pub fn incoming_bidirectional(&self) -> IncomingStreams {
+        self.incoming_streams(stream::Type::Bidirectional)
+    }
+
+    /// Returns the incoming unidirectional `Stream`s of this `Connection`.
+    /// See `incoming_bidirectional`.
+    pub fn incoming_unidirectional(&self) -> IncomingStreams {
+        self.incoming_streams(stream::Type::Unidirectional)
+    }
+
+    fn incoming_streams(&self, stype: stream::Type) -> IncomingStreams {
+        IncomingStreams {
+            incoming: self.incoming.clone(),
+            shared: self.shared.clone(),
+            stype,
+        }
+    }
+
+    /// Returns the `Stream` of `Event`s of this `Connection`.
+    /// Returns `None`, if the `Events` were already taken.
+    pub fn events(&mut self) -> Option<Events> {
+        self.event_recv.take().map(|recv| Events { recv })
+    }
+
+    /// Sends the given data in an unreliable DATAGRAM frame. The datagram is neither
+    /// retransmitted, nor ordered with the other datagrams or the `Stream`s.
+    ///
+    /// Fails with `ErrorKind::DatagramsUnsupported`, if the handshake is not finished or one of
+    /// the peers did not enable datagrams(`Config::enable_datagrams`), and with
+    /// `ErrorKind::DatagramTooLarge`, if the data exceeds `max_datagram_size`.
+    pub fn send_datagram(&self, data: BytesMut) -> Result<(), Error> {
+        self.datagram_sender.send(data.freeze())
+    }
+
+    /// Returns the `Stream` of the received datagrams, which is also a `Sink` to send datagrams.
+    /// Returns `None`, if the `Datagrams` were already taken.
+    pub fn incoming_datagrams(&mut self) -> Option<Datagrams> {
+        self.datagrams.take()
+    }
+
+    pub(crate) fn datagram_sender(&self) -> DatagramSender {
+        self.datagram_sender.clone()
+    }
+
+    /// Returns the maximum payload of a datagram, limited by the DATAGRAM frame size of the peer
+    /// and the current MTU of the path. Returns `0`, if datagrams are not supported.
+    /// A decrease is reported as `Event::MaxDatagramSizeDecreased`.
+    pub fn max_datagram_size(&self) -> usize {
+        self.datagram_sender.max_size()
+    }
+
+    /// Returns the negotiated TLS parameters of this `Connection`.
+    /// Returns `None`, if the handshake is not finished yet.
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.shared.tls_info.lock().unwrap().clone()
+    }
+
+    /// Returns a future that resolves, when the handshake is completed.
+    ///
+    /// The `Context` hands out incoming `Connection`s and resolves outgoing `Connection`s as
+    /// soon as data can be queued, for incoming `Connection`s this is before the client
+    /// finished the handshake. After the handshake is completed, the certificate of the peer was
+    /// verified, e.g. before sensitive data is sent. The future fails, if the `Connection`
+    /// fails before.
+    pub fn handshake_completed(&self) -> HandshakeCompleted {
+        HandshakeCompleted {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Is the handshake completed? See `handshake_completed`.
+    pub fn is_handshake_complete(&self) -> bool {
+        self.shared.handshake.lock().unwrap().completed
+    }
+
+    /// Returns the transport parameters the peer advertised in the handshake.
+    /// Returns `None`, if the handshake is not finished yet.
+    pub fn peer_transport_parameters(&self) -> Option<TransportParameters> {
+        self.shared.peer_transport_parameters.lock().unwrap().clone()
+    }
+
+    /// Returns the session ticket the server sent for this `Connection`. The ticket can be
+    /// stored by the application and resumes the session of a later `Connection` to the same
+    /// server, see `ConnectionConfig::set_session_ticket`.
+    /// Returns `None`, if the server did not send a ticket yet or this is an incoming
+    /// `Connection`.
+    pub fn export_session_ticket(&self) -> Option<Vec<u8>> {
+        self.shared.session_ticket.lock().unwrap().clone()
+    }
+
+    /// Returns the parameters of the primary path, that a later `Connection` to the same server
+    /// resumes, see `ConnectionConfig::set_path_parameters`. The parameters should be exported
+    /// after the `Connection` transferred some data, as the congestion window is still small
+    /// before. Returns `None`, before a round trip time was measured.
+    pub fn export_path_parameters(&self) -> Option<PathParameters> {
+        self.stats()
+            .primary_path()
+            .filter(|p| p.min_rtt > Duration::from_secs(0))
+            .map(|p| PathParameters {
+                peer_addr: p.peer_addr,
+                min_rtt: p.min_rtt,
+                congestion_window: p.congestion_window,
+            })
+    }
+
+    /// Returns the `Stream` that carries the early data of this `Connection`, see
+    /// `Context::new_connection_with_early_data`.
+    /// Returns `None`, if no early data was sent or the `Stream` was already taken.
+    pub fn early_data_stream(&mut self) -> Option<Stream> {
+        self.early_data_stream.take()
+    }
+
+    /// Returns if the server accepted the 0-RTT data of this `Connection`. If the server rejected
+    /// the 0-RTT data, the early data was sent again after the handshake.
+    pub fn is_early_data_accepted(&self) -> bool {
+        self.tls_info()
+            .map(|info| info.early_data_accepted)
+            .unwrap_or(false)
+    }
+
+    /// Returns the certificate chain the peer presented in the handshake, the peer certificate
+    /// first. With `Config::enable_client_authentication`, the server gets the chain of the
+    /// client, after it was verified.
+    /// Returns an empty chain, if the handshake is not finished yet or the peer did not present
+    /// any certificate.
+    #[cfg(feature = "openssl")]
+    pub fn peer_certificates(&self) -> Vec<X509> {
+        self.shared.peer_certificates.get()
+    }
+
+    /// Returns the certificate of the peer, e.g. to check its subject alternative names or to pin
+    /// its public key.
+    /// Returns `None`, if the handshake is not finished yet or the peer did not present any
+    /// certificate.
+    #[cfg(feature = "openssl")]
+    pub fn peer_certificate(&self) -> Option<X509> {
+        self.peer_certificates().into_iter().next()
+    }
+
+    /// Returns the negotiated application layer protocol of this `Connection`, see
+    /// `Config::set_alpn_protocols`.
+    /// Returns `None`, if the handshake is not finished yet or no protocol was negotiated.
+    pub fn negotiated_alpn(&self) -> Option<Vec<u8>> {
+        self.tls_info().and_then(|info| info.alpn)
+    }
+
+    /// Returns the negotiated QUIC version of this `Connection`, see
+    /// `Config::set_supported_versions`.
+    /// Returns `None`, if the handshake is not finished yet.
+    pub fn negotiated_version(&self) -> Option<QuicVersion> {
+        *self.shared.quic_version.lock().unwrap()
+    }
+
+    /// Returns the transport statistics of this `Connection`.
+    /// The statistics are updated each time the `Context` processes this `Connection`.
+    pub fn stats(&self) -> ConnectionStats {
+        let mut stats = self.shared.stats.lock().unwrap().clone();
+        stats.crypto = self.shared.crypto.get();
+        stats.ecn_received = self.shared.ecn.get();
+        stats
+    }
+
+    /// Closes this `Connection` automatically, as soon as it is idle. The `Connection` is idle,
+    /// when all its `Stream`s are finished(all `Stream` handles are dropped) and the peer
+    /// acknowledged all data. Opening new `Stream`s before that, keeps the `Connection` open
+    /// until these `Stream`s are finished as well.
+    ///
+    /// This enables request/response clients to drop the `Connection` right after sending the
+    /// last request, without tracking the completion of its `Stream`s.
+    pub fn close_when_idle(&self) {
+        self.shared.close_when_idle.store(true, Ordering::Relaxed);
+    }
 }
 
 impl FStream for Connection {
@@ -127,15 +832,7 @@ impl FStream for Connection {
     type Error = Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
-        match try_ready!(self
-            .msg_recv
-            .poll()
-            .map_err(|_| Error::from(ErrorKind::Unknown)))
-        {
-            Some(Message::Close) | None => Ok(Ready(None)),
-            Some(Message::NewStream(s)) => Ok(Ready(Some(s))),
-            Some(Message::Error(e)) => Err(e),
-        }
+        poll_incoming(&self.incoming, &self.shared, None)
     }
 }
 
@@ -147,19 +844,16 @@ impl Connection {
         data: *mut u8,
         len: usize,
         event: picoquic_call_back_event_t,
-        keep_alive_interval: Option<Duration>,
+        settings: Settings,
     ) -> (Connection, Arc<Mutex<Context>>) {
         let cnx = ffi::Connection::from(cnx);
 
-        let (builder, ctx, c_ctx) = Self::create_builder(
-            cnx,
-            cnx.peer_addr(),
-            cnx.local_addr(),
-            false,
-            keep_alive_interval,
-        );
+        Self::set_transport_settings(cnx, &settings);
+        let (builder, ctx, c_ctx) =
+            Self::create_builder(cnx, cnx.peer_addr(), cnx.local_addr(), false, settings);
 
-        let con = builder.build(cnx.local_id());
+        let shared = ctx.lock().unwrap().shared.clone();
+        let con = builder.build(cnx.local_id(), shared);
 
         // Now we need to call the callback once manually to process the received data
         unsafe {
@@ -176,13 +870,31 @@ impl Connection {
         local_addr: SocketAddr,
         server_name: String,
         current_time: u64,
-        keep_alive_interval: Option<Duration>,
+        mut settings: Settings,
         created_sender: oneshot::Sender<Result<Connection, Error>>,
     ) -> Result<(Arc<Mutex<Context>>), Error> {
-        let cnx = ffi::Connection::new(quic, peer_addr, current_time, server_name)?;
+        let early_data = settings.early_data.take();
 
-        let (builder, ctx, _) =
-            Self::create_builder(cnx, peer_addr, local_addr, true, keep_alive_interval);
+        let cnx = ffi::Connection::new(
+            quic,
+            peer_addr,
+            current_time,
+            server_name,
+            &settings.alpn_protocols,
+            settings.grease_version,
+            settings.quic_version.map(QuicVersion::value),
+        )?;
+        Self::set_transport_settings(cnx, &settings);
+        cnx.start_client()?;
+
+        let (mut builder, ctx, _) =
+            Self::create_builder(cnx, peer_addr, local_addr, true, settings);
+
+        // The early data is queued before the first packet is sent, so it can be sent as 0-RTT
+        // data.
+        if let Some(data) = early_data {
+            builder.early_data_stream = Some(ctx.lock().unwrap().open_early_data_stream(data));
+        }
 
         // set the builder and the sender as waiting for ready state payload
         ctx.lock()
@@ -192,41 +904,82 @@ impl Connection {
         Ok(ctx)
     }
 
+    /// Applies the settings, that are advertised to the peer in the transport parameters.
+    /// Outgoing connections need them before `ffi::Connection::start_client`.
+    fn set_transport_settings(cnx: ffi::Connection, settings: &Settings) {
+        if let Some(timeout) = settings.idle_timeout {
+            cnx.set_idle_timeout(timeout);
+        }
+
+        if let Some(size) = settings.max_udp_payload_size {
+            cnx.set_max_udp_payload_size(size);
+        }
+
+        if let Some(size) = settings.max_datagram_frame_size {
+            cnx.set_max_datagram_frame_size(size);
+        }
+
+        cnx.set_transport_parameters(&settings.transport_parameters);
+    }
+
     fn create_builder(
         cnx: ffi::Connection,
         peer_addr: SocketAddr,
         local_addr: SocketAddr,
         is_client: bool,
-        keep_alive_interval: Option<Duration>,
+        settings: Settings,
     ) -> (ConnectionBuilder, Arc<Mutex<Context>>, *mut c_void) {
         let (sender, msg_recv) = unbounded();
+        let (event_send, event_recv) = unbounded();
         let (close_send, close_recv) = oneshot::channel();
 
-        let (ctx, c_ctx, new_stream_handle) =
-            Context::new(cnx, sender, close_recv, is_client, local_addr);
-
-        if let Some(interval) = keep_alive_interval {
+        if let Some(interval) = settings.keep_alive_interval {
             cnx.enable_keep_alive(interval);
         }
 
-        let builder = ConnectionBuilder::new(
+        if let Some(algorithm) = settings.congestion_algorithm {
+            cnx.set_congestion_algorithm(algorithm);
+        }
+
+        let local_addrs = settings.local_addrs.clone();
+        let (ctx, c_ctx, new_stream_handle, datagrams, send_control) = Context::new(
+            cnx,
+            sender,
+            event_send,
+            close_recv,
+            is_client,
+            local_addr,
+            settings,
+        );
+
+        let builder = ConnectionBuilder {
             msg_recv,
+            event_recv,
             close_send,
             peer_addr,
             local_addr,
+            local_addrs,
+            send_control,
             new_stream_handle,
-            cnx.con_type(),
-        );
+            datagrams,
+            early_data_stream: None,
+            ctype: cnx.con_type(),
+            server_name: cnx.server_name(),
+        };
 
         (builder, ctx, c_ctx)
     }
 
     /// Creates a new bidirectional `Stream`.
+    /// The returned future resolves, when the peer allows us to open the `Stream`. If the
+    /// stream limit of the peer is reached, the `Stream` is created after the peer raised the
+    /// limit with a `MAX_STREAMS` frame.
     pub fn new_bidirectional_stream(&mut self) -> NewStreamFuture {
         self.new_stream_handle.new_bidirectional_stream()
     }
 
     /// Creates a new unidirectional `Stream`.
+    /// See `new_bidirectional_stream` for when the returned future resolves.
     pub fn new_unidirectional_stream(&mut self) -> NewStreamFuture {
         self.new_stream_handle.new_unidirectional_stream()
     }
@@ -241,20 +994,57 @@ impl Connection {
     /// This function should only be used, if the application layer negotiated a close of the
     /// connection.
     pub fn close_immediately(mut self) {
-        self.close_send.take().map(|s| s.send(()));
+        let request = CloseRequest {
+            error_code: 0,
+            reason: String::new(),
+            done: None,
+        };
+        self.close_send.take().map(|s| s.send(request));
+    }
+
+    /// Closes this connection gracefully with the given application error code and reason.
+    /// Buffered data that was not sent yet will be discarded. The returned future resolves,
+    /// after the `CONNECTION_CLOSE` was sent to the peer.
+    ///
+    /// Picoquic does not send reason phrases, so the `reason` is only logged locally.
+    pub fn close(mut self, error_code: u64, reason: &str) -> CloseFuture {
+        let (done, recv) = oneshot::channel();
+        let request = CloseRequest {
+            error_code,
+            reason: reason.to_owned(),
+            done: Some(done),
+        };
+        self.close_send.take().map(|s| s.send(request));
+
+        CloseFuture { recv }
     }
 }
 
 pub(crate) struct Context {
     send_msg: UnboundedSender<Message>,
-    close_recv: oneshot::Receiver<()>,
+    send_event: UnboundedSender<Event>,
+    /// Sends the received datagrams to the `Connection`, `None` after the connection was closed.
+    send_datagram: Option<UnboundedSender<BytesMut>>,
+    /// Receives the datagrams of the `Connection` that should be sent.
+    recv_datagram: UnboundedReceiver<Bytes>,
+    close_recv: oneshot::Receiver<CloseRequest>,
+    /// Receives the requests of the `Connection` to change the connection.
+    recv_control: UnboundedReceiver<Control>,
+    /// The local and the peer address of the current path.
+    path: (SocketAddr, SocketAddr),
+    /// Is notified after the `CONNECTION_CLOSE` of a graceful close was sent.
+    close_done: Option<oneshot::Sender<()>>,
     recv_create_stream: Receiver<(stream::Type, oneshot::Sender<Result<Stream, Error>>)>,
+    /// The requested `Stream`s that wait for stream credit of the peer, in request order.
+    blocked_create_stream: VecDeque<(stream::Type, oneshot::Sender<Result<Stream, Error>>)>,
     streams: HashMap<stream::Id, stream::Context>,
     cnx: ffi::Connection,
     closed: bool,
     /// Is the connection initiated by us?
     is_client: bool,
-    next_stream_id: u64,
+    /// The next (bidirectional, unidirectional) `Stream` ids that are given to
+    /// `generate_stream_id`.
+    next_stream_ids: (u64, u64),
     /// If we create an outgoing connection, we postpone the `Connection` creation to the point
     /// where the connection state is ready. This is necessary, because some information that we
     /// require for the `Connection` object is not available up to this point.
@@ -263,33 +1053,141 @@ pub(crate) struct Context {
         oneshot::Sender<Result<Connection, Error>>,
     )>,
     local_addr: SocketAddr,
+    /// Decides which data the `Stream`s of this connection keep, until picoquic requests it.
+    send_path: stream::SendPath,
+    /// The default write coalescing of the `Stream`s of this connection.
+    write_coalescing: Option<usize>,
+    /// The limits for the unsent data of the `Stream`s of this connection.
+    send_watermark: stream::SendWatermark,
+    /// The priority of new `Stream`s of this connection.
+    default_stream_priority: Option<Priority>,
+    /// Grows the receive window of this connection, if auto tuning is enabled.
+    receive_window_tuner: Option<ReceiveWindowTuner>,
+    /// The buffers for the data that is received on the `Stream`s of this connection.
+    recv_pool: RecvPool,
+    /// Detects PMTU blackholes and clamps down the MTU.
+    blackhole_detector: BlackholeDetector,
+    /// Probes for a bigger MTU, if the probe sizes are configured.
+    mtu_prober: Option<MtuProber>,
+    /// Resumes the congestion window of a previous path, if its parameters are configured.
+    careful_resume: Option<CarefulResume>,
+    /// The state that is shared with the `Connection`.
+    shared: Arc<Shared>,
+    /// Withhold the stream credit of the peer, while the application lags behind with accepting
+    /// incoming `Stream`s.
+    bidirectional_credit: Option<StreamCreditGate>,
+    unidirectional_credit: Option<StreamCreditGate>,
+    /// Writes the qlog trace of this connection, if the qlog is enabled.
+    qlog: Option<QlogWriter>,
+    /// Derives the transport events from the state of the connection.
+    event_monitor: TransportEventMonitor,
+    /// Decides, when the packet protection keys are updated.
+    key_updates: KeyUpdateScheduler,
+    /// Are the requested (bidirectional, unidirectional) `Stream`s blocked by the stream credit
+    /// of the peer? Each blocking is reported once.
+    streams_blocked: (bool, bool),
+    /// The `PING`s that wait to be acknowledged, in the order they were sent.
+    pending_pings: Vec<PendingPing>,
+    /// The versions the server offered in a version negotiation packet, if it was received.
+    offered_versions: Option<Vec<QuicVersion>>,
+    /// The span of this connection, the spans of its `Stream`s are its children.
+    span: Span,
 }
 
 impl Context {
     fn new(
         cnx: ffi::Connection,
         send_msg: UnboundedSender<Message>,
-        close_recv: oneshot::Receiver<()>,
+        send_event: UnboundedSender<Event>,
+        close_recv: oneshot::Receiver<CloseRequest>,
         is_client: bool,
         local_addr: SocketAddr,
-    ) -> (Arc<Mutex<Context>>, *mut c_void, NewStreamHandle) {
+        settings: Settings,
+    ) -> (
+        Arc<Mutex<Context>>,
+        *mut c_void,
+        NewStreamHandle,
+        Datagrams,
+        UnboundedSender<Control>,
+    ) {
         let (send_create_stream, recv_create_stream) = unbounded_with_error();
+        let (send_control, recv_control) = unbounded();
 
         let new_stream_handle = NewStreamHandle {
             send: send_create_stream,
         };
 
+        let shared = Arc::new(Shared::default());
+
+        let (send_datagram, datagram_recv) = unbounded();
+        let (datagram_send, recv_datagram) = unbounded();
+        let datagrams = Datagrams::new(
+            DatagramSender::new(datagram_send, shared.max_datagram_size.clone()),
+            datagram_recv,
+        );
+
+        let mtu_prober = match settings.mtu_discovery {
+            MtuDiscovery::Default => None,
+            MtuDiscovery::Disabled => {
+                cnx.disable_mtu_probing();
+                None
+            }
+            MtuDiscovery::Probe { sizes, interval } => {
+                cnx.disable_mtu_probing();
+                Some(MtuProber::new(sizes, interval))
+            }
+        };
+
+        let span = Span::connection(cnx.local_id(), cnx.peer_addr(), is_client);
+        span_event!(span, debug, "connection opened");
+
+        let qlog = settings.qlog_dir.and_then(|dir| {
+            QlogWriter::create(&dir, cnx.local_id(), is_client)
+                .map_err(|e| {
+                    span_event!(span, warn, "could not create qlog trace in {:?}: {:?}", dir, e)
+                })
+                .ok()
+        });
+
         let ctx = Arc::new(Mutex::new(Context {
             send_msg,
+            send_event,
+            send_datagram: Some(send_datagram),
+            recv_datagram,
             streams: Default::default(),
             cnx,
             closed: false,
             recv_create_stream,
+            blocked_create_stream: VecDeque::new(),
             is_client,
-            next_stream_id: 0,
+            next_stream_ids: (0, 0),
             wait_for_ready_state: None,
             local_addr,
             close_recv,
+            recv_control,
+            path: (local_addr, cnx.peer_addr()),
+            close_done: None,
+            send_path: settings.send_path,
+            write_coalescing: settings.write_coalescing,
+            send_watermark: settings.send_watermark,
+            default_stream_priority: settings.default_stream_priority,
+            receive_window_tuner: settings
+                .max_receive_window
+                .map(|max| ReceiveWindowTuner::new(cnx.receive_window(), max)),
+            recv_pool: RecvPool::new(settings.recv_buffer_chunk_size),
+            blackhole_detector: BlackholeDetector::new(cnx.send_mtu(), cnx.peer_addr()),
+            mtu_prober,
+            careful_resume: settings.path_parameters.map(CarefulResume::new),
+            shared,
+            bidirectional_credit: settings.max_pending_incoming_streams.map(StreamCreditGate::new),
+            unidirectional_credit: settings.max_pending_incoming_streams.map(StreamCreditGate::new),
+            qlog,
+            event_monitor: TransportEventMonitor::new(Instant::now()),
+            key_updates: KeyUpdateScheduler::new(settings.key_update_interval, Instant::now()),
+            streams_blocked: (false, false),
+            pending_pings: Vec::new(),
+            offered_versions: None,
+            span,
         }));
 
         // Convert the `Context` to a `*mut c_void` and reset the callback to the
@@ -303,65 +1201,435 @@ impl Context {
         // The reference counter needs to be 2 at this point
         assert_eq!(2, Arc::strong_count(&ctx));
 
-        (ctx, c_ctx, new_stream_handle)
+        (ctx, c_ctx, new_stream_handle, datagrams, send_control)
     }
 
     fn recv_data(&mut self, id: stream::Id, data: &[u8], event: picoquic_call_back_event_t) {
+        // The spans of the incoming `Stream`s are children of the connection span.
+        let span = self.span.clone();
+        let _entered = span.enter();
+
+        if let Some(ref mut tuner) = self.receive_window_tuner {
+            tuner.on_data_received(data.len());
+        }
+
+        if let Some(ref mut qlog) = self.qlog {
+            let fin = event == picoquic::picoquic_call_back_event_t_picoquic_callback_stream_fin;
+
+            if !data.is_empty() || fin {
+                qlog.on_stream_data_received(id, data.len(), fin);
+            }
+        }
+
         let new_stream_handle = match self.streams.entry(id) {
             Occupied(mut entry) => {
-                entry.get_mut().recv_data(data, event);
+                entry.get_mut().recv_data(data, event, &mut self.recv_pool);
                 None
             }
             Vacant(entry) => {
-                let (stream, mut ctx) = Stream::new(id, self.cnx, self.local_addr, self.is_client);
+                let (mut stream, mut ctx) = Stream::new(
+                    id,
+                    self.cnx,
+                    self.local_addr,
+                    self.is_client,
+                    self.send_path,
+                    self.write_coalescing,
+                    self.send_watermark,
+                );
+
+                if let Some(priority) = self.default_stream_priority {
+                    stream.set_initial_priority(priority);
+                    ctx.set_priority(priority);
+                }
 
-                ctx.recv_data(data, event);
+                ctx.recv_data(data, event, &mut self.recv_pool);
                 entry.insert(ctx);
                 Some(stream)
             }
         };
 
         if let Some(stream) = new_stream_handle {
+            self.shared.pending_streams.fetch_add(1, Ordering::Relaxed);
             let _ = self.send_msg.unbounded_send(Message::NewStream(stream));
         }
     }
 
-    /// Check for new streams to create and create these requested streams.
+    /// Forwards a received datagram to the `Connection`.
+    fn recv_datagram(&mut self, data: &[u8]) {
+        if let Some(ref send) = self.send_datagram {
+            let _ = send.unbounded_send(BytesMut::from(data));
+        }
+    }
+
+    /// Queues the datagrams of the `Connection` in picoquic.
+    fn send_queued_datagrams(&mut self) {
+        while let Ok(Ready(Some(data))) = self.recv_datagram.poll() {
+            if let Err(e) = self.cnx.queue_datagram(&data) {
+                span_event!(self.span, debug, "dropping datagram of {} bytes: {:?}", data.len(), e);
+            }
+        }
+    }
+
+    /// Updates the maximum datagram size and reports a decrease.
+    fn update_max_datagram_size(&self) {
+        let size = max_datagram_payload(self.cnx.max_datagram_frame_size(), self.cnx.send_mtu());
+        let old = self.shared.max_datagram_size.swap(size, Ordering::Relaxed);
+
+        if size < old {
+            let _ = self
+                .send_event
+                .unbounded_send(Event::MaxDatagramSizeDecreased { size });
+        }
+    }
+
+    /// Withholds the stream credit of the peer, while the application lags behind with accepting
+    /// incoming `Stream`s.
+    fn withhold_stream_credit(&mut self) {
+        let pending = self.shared.pending_streams.load(Ordering::Relaxed);
+        let (bidirectional, unidirectional) = self.cnx.stream_credit();
+
+        let bidirectional = self
+            .bidirectional_credit
+            .as_mut()
+            .and_then(|gate| gate.poll(pending, bidirectional));
+        let unidirectional = self
+            .unidirectional_credit
+            .as_mut()
+            .and_then(|gate| gate.poll(pending, unidirectional));
+
+        if bidirectional.is_some() || unidirectional.is_some() {
+            self.cnx.set_stream_credit(bidirectional, unidirectional);
+        }
+    }
+
+    /// Returns the picoquic connection.
+    pub(crate) fn cnx(&self) -> ffi::Connection {
+        self.cnx
+    }
+
+    /// Returns the meter that collects the crypto throughput of this `Connection`.
+    pub(crate) fn crypto_meter(&self) -> CryptoMeter {
+        self.shared.crypto.clone()
+    }
+
+    pub(crate) fn ecn_meter(&self) -> EcnMeter {
+        self.shared.ecn.clone()
+    }
+
+    /// Returns the certificate chain of the peer, that is filled after the handshake.
+    #[cfg(feature = "openssl")]
+    pub(crate) fn peer_certificates(&self) -> PeerCertificates {
+        self.shared.peer_certificates.clone()
+    }
+
+    /// Picoquic is ready to send data of the given `Stream`.
+    fn prepare_to_send(&mut self, id: stream::Id, context: *mut c_void, max_len: usize) {
+        match self.streams.get_mut(&id) {
+            Some(stream) => stream.prepare_to_send(context, max_len),
+            None => unsafe {
+                // The `Stream` is already gone, tell picoquic that there is nothing to send.
+                picoquic_provide_stream_data_buffer(context, 0, 0, 0);
+            },
+        }
+
+        // If the `Stream` sent all its data, the less urgent `Stream`s can send in the same
+        // packet.
+        self.schedule_streams();
+    }
+
+    /// Only lets picoquic send the queued data of the most urgent `Stream`s.
+    fn schedule_streams(&mut self) {
+        let urgency = self
+            .streams
+            .values()
+            .filter(|s| s.has_queued_data())
+            .map(|s| s.priority().urgency)
+            .min()
+            .unwrap_or(u8::max_value());
+
+        self.streams
+            .values_mut()
+            .for_each(|s| s.set_scheduled(s.priority().urgency <= urgency));
+    }
+
+    /// Check for new streams to create and create these requested streams, as soon as the peer
+    /// grants the stream credit for them.
     fn check_create_stream_requests(&mut self) {
         loop {
             match self.recv_create_stream.poll() {
                 Ok(Ready(None)) | Ok(NotReady) | Err(_) => break,
-                Ok(Ready(Some((stype, sender)))) => {
-                    let id = ffi::Connection::generate_stream_id(
-                        self.next_stream_id,
-                        self.is_client,
-                        stype,
-                    );
-                    self.next_stream_id += 1;
-
-                    let (stream, ctx) = Stream::new(id, self.cnx, self.local_addr, self.is_client);
-                    assert!(self.streams.insert(id, ctx).is_none());
-
-                    let _ = sender.send(Ok(stream));
+                Ok(Ready(Some(request))) => self.blocked_create_stream.push_back(request),
+            }
+        }
+
+        let requests = mem::replace(&mut self.blocked_create_stream, VecDeque::new());
+        for (stype, sender) in requests {
+            if sender.is_canceled() {
+                continue;
+            }
+
+            if self.has_stream_credit(stype) {
+                *self.streams_blocked(stype) = false;
+                let stream = self.create_stream(stype);
+                let _ = sender.send(Ok(stream));
+            } else {
+                if !mem::replace(self.streams_blocked(stype), true) {
+                    let _ = self.send_event.unbounded_send(Event::StreamsBlocked { stype });
                 }
+
+                self.blocked_create_stream.push_back((stype, sender));
+            }
+        }
+    }
+
+    fn streams_blocked(&mut self, stype: stream::Type) -> &mut bool {
+        match stype {
+            stream::Type::Bidirectional => &mut self.streams_blocked.0,
+            stream::Type::Unidirectional => &mut self.streams_blocked.1,
+        }
+    }
+
+    fn next_stream_id(&mut self, stype: stream::Type) -> &mut u64 {
+        match stype {
+            stream::Type::Bidirectional => &mut self.next_stream_ids.0,
+            stream::Type::Unidirectional => &mut self.next_stream_ids.1,
+        }
+    }
+
+    /// Checks if the peer allows us to open the next `Stream` of the given type.
+    fn has_stream_credit(&mut self, stype: stream::Type) -> bool {
+        let next_id = *self.next_stream_id(stype);
+        let id = ffi::Connection::generate_stream_id(next_id, self.is_client, stype);
+        let (bidirectional, unidirectional) = self.cnx.peer_stream_limit();
+
+        match stype {
+            stream::Type::Bidirectional => id <= bidirectional,
+            stream::Type::Unidirectional => id <= unidirectional,
+        }
+    }
+
+    /// Creates a new outgoing `Stream`.
+    fn create_stream(&mut self, stype: stream::Type) -> Stream {
+        let span = self.span.clone();
+        let _entered = span.enter();
+
+        let is_client = self.is_client;
+        let next_id = self.next_stream_id(stype);
+        let id = ffi::Connection::generate_stream_id(*next_id, is_client, stype);
+        *next_id += 1;
+
+        let (mut stream, mut ctx) = Stream::new(
+            id,
+            self.cnx,
+            self.local_addr,
+            self.is_client,
+            self.send_path,
+            self.write_coalescing,
+            self.send_watermark,
+        );
+
+        if let Some(priority) = self.default_stream_priority {
+            stream.set_initial_priority(priority);
+            ctx.set_priority(priority);
+        }
+
+        assert!(self.streams.insert(id, ctx).is_none());
+
+        stream
+    }
+
+    /// Creates the first bidirectional `Stream` and hands the given data to picoquic.
+    fn open_early_data_stream(&mut self, data: Bytes) -> Stream {
+        let stream = self.create_stream(stream::Type::Bidirectional);
+
+        if let Some(ctx) = self.streams.get_mut(&stream.id()) {
+            ctx.send_data(data);
+        }
+
+        stream
+    }
+
+    /// Stores the session ticket, as soon as the server sent it.
+    fn update_session_ticket(&self) {
+        if !self.is_client || !self.cnx.is_ready() {
+            return;
+        }
+
+        let mut ticket = self.shared.session_ticket.lock().unwrap();
+
+        if ticket.is_none() {
+            *ticket = self.cnx.session_ticket();
+
+            if ticket.is_some() {
+                let _ = self.send_event.unbounded_send(Event::SessionTicketReceived);
             }
         }
     }
 
     fn close(&mut self) {
+        if !self.closed {
+            span_event!(self.span, debug, "connection closed");
+        }
+
+        // Completes the qlog trace.
+        self.write_qlog();
+        self.qlog = None;
+
         self.cnx.close();
         self.closed = true;
+        self.shared
+            .handshake
+            .lock()
+            .unwrap()
+            .fail(|| Error::from(ErrorKind::Disconnected));
+        self.close_done.take().map(|s| s.send(()));
+        self.send_datagram = None;
+        self.pending_pings.clear();
         self.streams
             .values_mut()
             .for_each(|s| s.handle_connection_close());
         let _ = self.send_msg.unbounded_send(Message::Close);
     }
 
+    /// Starts to close the connection with the error code of the application.
+    fn handle_close_request(&mut self, request: CloseRequest) {
+        span_event!(
+            self.span,
+            debug,
+            "closing connection with error code {}: {}",
+            request.error_code, request.reason
+        );
+
+        match request.done {
+            Some(done) => {
+                // Picoquic sends the `CONNECTION_CLOSE` with the next packet.
+                self.cnx.close_with_error(request.error_code);
+                self.close_done = Some(done);
+            }
+            None => self.close(),
+        }
+    }
+
+    /// Returns if `close_when_idle` was requested and the connection is idle.
+    fn is_idle_and_should_close(&self) -> bool {
+        self.shared.close_when_idle.load(Ordering::Relaxed)
+            && self.wait_for_ready_state.is_none()
+            && self.streams.is_empty()
+            && !self.cnx.has_pending_data()
+    }
+
+    /// Stores the TLS parameters, after the handshake is finished.
+    fn update_tls_info(&self) {
+        let mut tls_info = self.shared.tls_info.lock().unwrap();
+
+        if tls_info.is_none() {
+            *tls_info = self.cnx.tls_info();
+        }
+    }
+
+    fn update_peer_transport_parameters(&self) {
+        let mut params = self.shared.peer_transport_parameters.lock().unwrap();
+
+        if params.is_none() {
+            *params = Some(self.cnx.peer_transport_parameters());
+        }
+    }
+
+    fn update_connection_ids(&self) {
+        *self.shared.connection_ids.lock().unwrap() = self.cnx.connection_ids();
+    }
+
+    /// Remembers the versions the server offered, to report them, if the version negotiation
+    /// fails.
+    pub fn set_offered_versions(&mut self, versions: Vec<QuicVersion>) {
+        self.offered_versions = Some(versions);
+    }
+
+    fn update_stats(&self) {
+        *self.shared.stats.lock().unwrap() = self.cnx.stats();
+    }
+
+    /// Reports the transport events since the last poll.
+    fn report_transport_events(&mut self) {
+        let (data_sent, max_data) = self.cnx.data_sent_and_limit();
+        let stats = self.shared.stats.lock().unwrap();
+        let paths = stats.paths.iter().map(|p| p.peer_addr).collect::<Vec<_>>();
+
+        let events = self.event_monitor.poll(
+            &TransportState {
+                paths: &paths,
+                key_phase: self.cnx.key_phase(),
+                data_sent,
+                max_data,
+                packets_received: stats.packets_received,
+                idle_timeout: self.cnx.idle_timeout(),
+            },
+            Instant::now(),
+        );
+
+        for event in events {
+            if let Event::KeyUpdate {
+                initiated_by_peer: true,
+            } = event
+            {
+                let protected_bytes = self.shared.crypto.get().protected_bytes;
+                self.key_updates.on_key_update(Instant::now(), protected_bytes, true);
+            }
+
+            let _ = self.send_event.unbounded_send(event);
+        }
+    }
+
+    /// Reports the `Stream`s that are blocked by their flow control limit.
+    fn report_blocked_streams(&mut self) {
+        for (id, stream) in &mut self.streams {
+            if let Some(limit) = stream.check_data_blocked() {
+                span_event!(self.span, debug, "stream({}) blocked at {}", id, limit);
+                let _ = self
+                    .send_event
+                    .unbounded_send(Event::StreamDataBlocked { id: *id, limit });
+            }
+        }
+    }
+
+    /// Updates the packet protection keys, if a key update is due.
+    fn update_keys(&mut self) {
+        let now = Instant::now();
+        let protected_bytes = self.shared.crypto.get().protected_bytes;
+
+        if !self.key_updates.is_due(now, protected_bytes) {
+            return;
+        }
+
+        // Picoquic refuses the update, until the previous update was acknowledged. The update
+        // is retried, the next time this connection is processed.
+        if self.cnx.start_key_update().is_ok() {
+            span_event!(self.span, debug, "updated the packet protection keys");
+            self.key_updates.on_key_update(now, protected_bytes, false);
+            self.event_monitor.on_local_key_update();
+        }
+    }
+
+    /// Writes the events since the last poll to the qlog trace.
+    fn write_qlog(&mut self) {
+        let cnx = self.cnx;
+
+        if let Some(ref mut qlog) = self.qlog {
+            qlog.on_stats(&self.shared.stats.lock().unwrap());
+            qlog.on_stream_data_sent(
+                self.streams
+                    .keys()
+                    .filter_map(|id| cnx.stream_sent_offset(*id).map(|sent| (*id, sent))),
+            );
+            qlog.flush();
+        }
+    }
+
     fn process_wait_for_ready_state(&mut self) {
         match self.wait_for_ready_state.take() {
             Some((builder, sender)) => {
                 let id = self.cnx.local_id();
-                let con = builder.build(id);
+                let con = builder.build(id, self.shared.clone());
 
                 let _ = sender.send(Ok(con));
             }
@@ -377,29 +1645,202 @@ impl Context {
         self.wait_for_ready_state = Some((builder, sender));
     }
 
+    /// Grows the receive window, if the auto tuning detects that the peer is limited by it.
+    fn tune_receive_window(&mut self) {
+        let cnx = self.cnx;
+
+        if let Some(ref mut tuner) = self.receive_window_tuner {
+            if let Some(window) = tuner.poll(cnx.smoothed_rtt(), Instant::now()) {
+                cnx.set_receive_window(window);
+                // The peer only learns about the new window with the flow control updates.
+                self.streams
+                    .keys()
+                    .for_each(|id| cnx.open_flow_control(*id, window));
+            }
+        }
+    }
+
+    /// Raises the MTU to the next probe size, if the MTU probing is enabled.
+    fn probe_mtu(&mut self) {
+        let cnx = self.cnx;
+
+        if !cnx.is_ready() {
+            return;
+        }
+
+        if let Some(ref mut prober) = self.mtu_prober {
+            if let Some(mtu) = prober.poll(cnx.send_mtu(), Instant::now()) {
+                span_event!(self.span, debug, "probing MTU of {}", mtu);
+                cnx.set_send_mtu(mtu);
+            }
+        }
+    }
+
+    /// Resumes the congestion window of a previous path, if the path did not change.
+    fn resume_congestion_window(&mut self) {
+        let cnx = self.cnx;
+
+        if !cnx.is_ready() {
+            return;
+        }
+
+        if let Some(ref mut resume) = self.careful_resume {
+            if let Some(window) = resume.poll(
+                cnx.peer_addr(),
+                cnx.min_rtt(),
+                cnx.smoothed_rtt(),
+                cnx.congestion_window(),
+                cnx.retransmissions(),
+                Instant::now(),
+            ) {
+                span_event!(self.span, debug, "resuming congestion window of {}", window);
+                cnx.set_congestion_window(window);
+            }
+        }
+    }
+
+    /// Clamps down the MTU, if the connection runs into a PMTU blackhole.
+    fn detect_blackhole(&mut self) {
+        let cnx = self.cnx;
+
+        if let Some(mtu) = self.blackhole_detector.poll(
+            cnx.send_mtu(),
+            cnx.peer_addr(),
+            cnx.retransmissions(),
+            cnx.smoothed_rtt(),
+            Instant::now(),
+        ) {
+            span_event!(self.span, warn, "detected PMTU blackhole, clamping MTU down to {}", mtu);
+            cnx.set_send_mtu(mtu);
+            let _ = self
+                .send_event
+                .unbounded_send(Event::BlackholeDetected { mtu });
+        }
+    }
+
+    /// Probes the paths to the local addresses the `Connection` should migrate to.
+    fn check_control_requests(&mut self) {
+        while let Ok(Ready(Some(control))) = self.recv_control.poll() {
+            match control {
+                Control::Migrate(local_addr) => {
+                    let peer_addr = self.cnx.peer_addr();
+
+                    if let Err(e) = self.cnx.probe_new_path(peer_addr, local_addr) {
+                        span_event!(
+                            self.span,
+                            warn,
+                            "could not migrate to {}: {:?}",
+                            local_addr,
+                            e
+                        );
+                    }
+                }
+                Control::SetCongestionAlgorithm(algorithm) => {
+                    self.cnx.set_congestion_algorithm(algorithm);
+                }
+                Control::KeyUpdate => self.key_updates.request(),
+                Control::Ping(done) => match self.cnx.queue_ping() {
+                    Ok(()) => self.pending_pings.push(PendingPing {
+                        queued_at: Instant::now(),
+                        packet_number: self.cnx.application_space_stats().packets_sent,
+                        done,
+                    }),
+                    Err(e) => {
+                        let _ = done.send(Err(e));
+                    }
+                },
+            }
+        }
+    }
+
+    /// Reports the round trip time of the `PING`s that were acknowledged by the peer.
+    /// A `PING` is acknowledged, when the packet it was sent with or a later packet is
+    /// acknowledged.
+    fn check_pending_pings(&mut self) {
+        if self.pending_pings.is_empty() {
+            return;
+        }
+
+        let largest_acked = match self.cnx.application_space_stats().largest_acked {
+            Some(largest_acked) => largest_acked,
+            None => return,
+        };
+
+        let (acked, pending): (Vec<_>, Vec<_>) = self
+            .pending_pings
+            .drain(..)
+            .partition(|p| p.packet_number <= largest_acked);
+        self.pending_pings = pending;
+
+        let now = Instant::now();
+        for ping in acked {
+            let _ = ping.done.send(Ok(now.duration_since(ping.queued_at)));
+        }
+    }
+
+    /// Reports the migration of the connection to a new path.
+    fn update_path(&mut self) {
+        let peer_addr = self.cnx.peer_addr();
+        let local_addr = self.cnx.path_local_addr().unwrap_or(self.path.0);
+
+        if (local_addr, peer_addr) == self.path {
+            return;
+        }
+
+        span_event!(self.span, debug, "connection migrated to {} -> {}", local_addr, peer_addr);
+        self.path = (local_addr, peer_addr);
+        *self.shared.path.lock().unwrap() = Some(self.path);
+        let _ = self.send_event.unbounded_send(Event::PathMigrated {
+            local_addr,
+            peer_addr,
+        });
+    }
+
     /// Checks if the connection had an error and handles it.
+    /// A connection that closes after the server offered none of the supported versions, fails
+    /// with `VersionNegotiationFailed`.
     fn check_and_handle_error(&mut self) {
-        if let Some(err) = self.cnx.error() {
-            self.streams
-                .values_mut()
-                .for_each(|s| s.handle_connection_error(err.clone()));
-
-            self.recv_create_stream.propagate_error(err.clone());
-            self.recv_create_stream.close();
-            while let Ok(Ready(Some((_, sender)))) = self.recv_create_stream.poll() {
-                let _ = sender.send(Err(err()));
+        match self.offered_versions.take() {
+            Some(offered) if !self.cnx.is_ready() => {
+                self.handle_error(move || {
+                    ErrorKind::VersionNegotiationFailed(offered.clone()).into()
+                })
             }
+            _ => {
+                let handshake_completed = self.shared.handshake.lock().unwrap().completed;
 
-            match self.wait_for_ready_state.take() {
-                Some((_, send)) => {
-                    let _ = send.send(Err(err()));
-                }
-                None => {
-                    let _ = self.send_msg.unbounded_send(Message::Error(err()));
+                if let Some(err) = self.cnx.error(handshake_completed) {
+                    self.handle_error(err);
                 }
             }
         }
     }
+
+    fn handle_error<F: ErrorFn + Clone>(&mut self, err: F) {
+        span_event!(self.span, info, "connection failed: {}", err());
+        self.shared.handshake.lock().unwrap().fail(err.clone());
+        self.streams
+            .values_mut()
+            .for_each(|s| s.handle_connection_error(err.clone()));
+
+        self.recv_create_stream.propagate_error(err.clone());
+        self.recv_create_stream.close();
+        while let Ok(Ready(Some((_, sender)))) = self.recv_create_stream.poll() {
+            let _ = sender.send(Err(err()));
+        }
+        for (_, sender) in self.blocked_create_stream.drain(..) {
+            let _ = sender.send(Err(err()));
+        }
+
+        match self.wait_for_ready_state.take() {
+            Some((_, send)) => {
+                let _ = send.send(Err(err()));
+            }
+            None => {
+                let _ = self.send_msg.unbounded_send(Message::Error(err()));
+            }
+        }
+    }
 }
 
 impl Future for Context {
@@ -411,6 +1852,25 @@ impl Future for Context {
             return Ok(Ready(()));
         }
 
+        self.update_tls_info();
+
+        self.update_session_ticket();
+
+        self.update_stats();
+
+        self.update_connection_ids();
+
+        self.write_qlog();
+
+        if self.cnx.is_ready() {
+            self.update_max_datagram_size();
+            self.update_peer_transport_parameters();
+            *self.shared.quic_version.lock().unwrap() = Some(self.cnx.version().into());
+            self.shared.handshake.lock().unwrap().complete();
+            self.update_keys();
+            self.report_transport_events();
+        }
+
         if self.wait_for_ready_state.is_some() && self.cnx.is_ready() {
             self.process_wait_for_ready_state();
         }
@@ -418,10 +1878,38 @@ impl Future for Context {
         self.streams
             .retain(|_, s| s.poll().map(|r| r.is_not_ready()).unwrap_or(false));
 
+        self.report_blocked_streams();
+
         self.check_create_stream_requests();
 
+        self.send_queued_datagrams();
+
+        self.check_control_requests();
+
+        self.check_pending_pings();
+
+        self.update_path();
+
+        self.schedule_streams();
+
+        self.tune_receive_window();
+
+        self.probe_mtu();
+
+        self.resume_congestion_window();
+
+        self.detect_blackhole();
+
+        self.withhold_stream_credit();
+
         // Check if the connection should be closed
-        if let Ok(Ready(_)) = self.close_recv.poll() {
+        if let Ok(Ready(request)) = self.close_recv.poll() {
+            self.handle_close_request(request);
+        } else if self.close_done.is_some() && self.cnx.is_going_to_close() {
+            span_event!(self.span, debug, "sent `CONNECTION_CLOSE`");
+            self.close();
+        } else if self.is_idle_and_should_close() {
+            span_event!(self.span, debug, "closing idle connection");
             self.close();
         }
 
@@ -451,6 +1939,21 @@ unsafe extern "C" fn recv_data_callback(
         let mut ctx = ctx.lock().unwrap();
         ctx.check_and_handle_error();
         ctx.close();
+    } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_prepare_to_send {
+        // `bytes` is the context that is required to provide the data to picoquic
+        ctx.lock()
+            .unwrap()
+            .prepare_to_send(stream_id, bytes as *mut c_void, length);
+
+        // the context must not be dereferenced!
+        mem::forget(ctx);
+    } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_datagram {
+        let data = slice::from_raw_parts(bytes, length as usize);
+
+        ctx.lock().unwrap().recv_datagram(data);
+
+        // the context must not be dereferenced!
+        mem::forget(ctx);
     } else {
         let data = slice::from_raw_parts(bytes, length as usize);
 
@@ -493,6 +1996,71 @@ impl NewStreamHandle {
     }
 }
 
+/// A future that resolves, after a `Connection` was closed gracefully.
+/// This future is created by `Connection::close`.
+pub struct CloseFuture {
+    recv: oneshot::Receiver<()>,
+}
+
+impl Future for CloseFuture {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.recv.poll() {
+            Ok(NotReady) => Ok(NotReady),
+            // The `Context` is dropped, when the connection is closed.
+            Ok(Ready(())) | Err(_) => Ok(Ready(())),
+        }
+    }
+}
+
+/// A future that resolves, when the handshake of a `Connection` is completed.
+/// This future is created by `Connection::handshake_completed`.
+pub struct HandshakeCompleted {
+    shared: Arc<Shared>,
+}
+
+impl Future for HandshakeCompleted {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut handshake = self.shared.handshake.lock().unwrap();
+
+        if handshake.completed {
+            Ok(Ready(()))
+        } else if let Some(ref err) = handshake.error {
+            Err(err())
+        } else {
+            if !handshake.tasks.iter().any(|t| t.will_notify_current()) {
+                handshake.tasks.push(task::current());
+            }
+
+            Ok(NotReady)
+        }
+    }
+}
+
+/// A future that resolves with the round trip time of a `PING`.
+/// This future is created by `Connection::ping`.
+pub struct PingFuture {
+    recv: oneshot::Receiver<Result<Duration, Error>>,
+}
+
+impl Future for PingFuture {
+    type Item = Duration;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.recv.poll() {
+            Ok(NotReady) => Ok(NotReady),
+            Ok(Ready(res)) => res.map(Ready),
+            Err(_) => Err(ErrorKind::Disconnected.into()),
+        }
+    }
+}
+
 /// A future that resolves to a `Stream`.
 /// This future is created by the `NewStreamHandle`.
 pub struct NewStreamFuture {
@@ -1,49 +1,256 @@
-use config::Config;
-use connection::Connection;
-use context_inner::{ContextInner, NewConnectionFuture, NewConnectionHandle};
+use admission::PauseMode;
+use config::{Config, ConnectionConfig, FileFormat};
+use connection::{Connection, Id as ConnectionId};
+use context_inner::{ConfigUpdate, ContextInner, NewConnectionFuture, NewConnectionHandle};
 use error::*;
+use registry::{ConnectionInfo, ConnectionRegistry};
+use runtime::{Socket, Spawn, Timer};
 
-use std::net::SocketAddr;
+use std::{
+    io,
+    net::{self, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use tokio::runtime::TaskExecutor;
+use bytes::Bytes;
 
-use futures::sync::mpsc::UnboundedReceiver;
-use futures::{Poll, Stream};
+use socket2::{self, Domain, Protocol, SockAddr, Type};
+
+use tokio::{self, net::UdpSocket, reactor::Handle, runtime::TaskExecutor, timer::Delay};
+
+use futures::{
+    sync::{
+        mpsc::{UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    Async::{NotReady, Ready},
+    Future, Poll, Stream,
+};
+
+#[cfg(feature = "self-signed")]
+use openssl::x509::X509;
 
 /// The `Picoquic` context. It setups and controls the `UdpSocket`. Every incoming `Connection`
 /// can be obtained by polling this context.
 pub struct Context {
     recv_con: UnboundedReceiver<Connection>,
-    local_addr: SocketAddr,
+    local_addrs: Vec<SocketAddr>,
     new_connection_handle: NewConnectionHandle,
+    amplification_limited: Arc<AtomicUsize>,
+    receive_buffer_len: Arc<AtomicUsize>,
+    send_config_update: UnboundedSender<ConfigUpdate>,
+    registry: ConnectionRegistry,
+    #[cfg(feature = "self-signed")]
+    self_signed_certificate: Option<X509>,
 }
 
 impl Context {
     /// Creates a new `Context`.
     ///
     /// name - Will be used as SNI for TLS.
+    ///
+    /// See `ContextBuilder` for more options.
     pub fn new(
         listen_address: &SocketAddr,
         handle: TaskExecutor,
         config: Config,
     ) -> Result<Context, Error> {
-        let (inner, recv_con, new_connection_handle) = ContextInner::new(listen_address, config)?;
+        ContextBuilder::new(config)
+            .listen_address(*listen_address)
+            .executor(handle)
+            .build()
+    }
 
-        let local_addr = inner.local_addr();
+    /// Creates a new `Context` that listens on all the given addresses, e.g. on an IPv4 and an
+    /// IPv6 address. Each incoming `Connection` is answered from the address it arrived on, see
+    /// `Connection::local_addr`.
+    ///
+    /// See `ContextBuilder::listen_addresses` for more options.
+    pub fn with_listen_addresses(
+        listen_addresses: &[SocketAddr],
+        handle: TaskExecutor,
+        config: Config,
+    ) -> Result<Context, Error> {
+        ContextBuilder::new(config)
+            .listen_addresses(listen_addresses.iter().cloned())
+            .executor(handle)
+            .build()
+    }
 
-        // start the inner future
-        handle.spawn(inner);
+    /// Creates a new `Context` that uses the given, already bound, socket. This enables socket
+    /// options that need to be set before binding(e.g. `SO_REUSEPORT` or binding to a device) or
+    /// sockets that are inherited from the service manager(e.g. systemd socket activation).
+    ///
+    /// See `ContextBuilder::socket` for more options.
+    pub fn from_socket(
+        socket: net::UdpSocket,
+        handle: TaskExecutor,
+        config: Config,
+    ) -> Result<Context, Error> {
+        ContextBuilder::new(config)
+            .socket(socket)
+            .executor(handle)
+            .build()
+    }
 
-        Ok(Context {
+    /// Creates a new `Context` that is spawned with the given `Spawn`, instead of a tokio
+    /// executor. See `Spawn` and `ContextBuilder::spawner`.
+    pub fn new_with_spawner<S>(
+        listen_address: &SocketAddr,
+        spawner: S,
+        config: Config,
+    ) -> Result<Context, Error>
+    where
+        S: Spawn + 'static,
+    {
+        ContextBuilder::new(config)
+            .listen_address(*listen_address)
+            .spawner(spawner)
+            .build()
+    }
+
+    /// Creates a new client only `Context`, that binds to an ephemeral port and never accepts
+    /// incoming `Connection`s. See `ContextBuilder::client_only`.
+    pub fn new_client(handle: TaskExecutor, config: Config) -> Result<Context, Error> {
+        ContextBuilder::new(config)
+            .client_only()
+            .executor(handle)
+            .build()
+    }
+
+    /// Creates a new `Context` that uses the given `Socket` and `Timer`, instead of the tokio
+    /// ones. This makes it possible to run the `Context` on any executor or reactor.
+    ///
+    /// The returned `ContextDriver` drives the `Context` and all its `Connection`s and needs to
+    /// be spawned on an executor.
+    pub fn with_io<S, T>(
+        socket: S,
+        timer: T,
+        config: Config,
+    ) -> Result<(Context, ContextDriver), Error>
+    where
+        S: Socket + 'static,
+        T: Timer + 'static,
+    {
+        Context::with_sockets(vec![Box::new(socket) as Box<dyn Socket>], timer, config)
+    }
+
+    /// Creates a new `Context` that uses all the given `Socket`s, like `with_io`.
+    ///
+    /// Incoming `Connection`s are answered from the socket the client sent its packets to.
+    /// Outgoing `Connection`s use the first socket, if `ConnectionConfig::set_local_addr` does not
+    /// select another one. The `local_addr` of this `Context` is the address of the first socket.
+    pub fn with_sockets<T>(
+        sockets: Vec<Box<dyn Socket>>,
+        timer: T,
+        config: Config,
+    ) -> Result<(Context, ContextDriver), Error>
+    where
+        T: Timer + 'static,
+    {
+        let (inner, recv_con, new_connection_handle) =
+            ContextInner::new(sockets, Box::new(timer), config)?;
+
+        let local_addrs = inner.local_addrs();
+        let amplification_limited = inner.amplification_limited_connections();
+        let receive_buffer_len = inner.receive_buffer_len();
+        let send_config_update = inner.config_update_sender();
+        let registry = inner.connection_registry();
+
+        let context = Context {
             recv_con,
-            local_addr,
+            local_addrs,
             new_connection_handle,
-        })
+            amplification_limited,
+            receive_buffer_len,
+            send_config_update,
+            registry,
+            #[cfg(feature = "self-signed")]
+            self_signed_certificate: inner.self_signed_certificate(),
+        };
+
+        Ok((context, ContextDriver { inner }))
     }
 
     /// Returns the local address, this `Context` is bound to.
+    /// If the `Context` is bound to multiple addresses, this is the first one, see `local_addrs`.
     pub fn local_addr(&self) -> SocketAddr {
-        self.local_addr
+        self.local_addrs[0]
+    }
+
+    /// Returns all local addresses, this `Context` is bound to, in the order of its sockets.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns the self-signed certificate that was generated for this `Context`, see
+    /// `Config::generate_self_signed`.
+    #[cfg(feature = "self-signed")]
+    pub fn self_signed_certificate(&self) -> Option<&X509> {
+        self.self_signed_certificate.as_ref()
+    }
+
+    /// Returns the number of incoming connections that are currently blocked by the
+    /// anti-amplification limit. These connections wait for more data from the client, before
+    /// the handshake can continue.
+    pub fn amplification_limited_connections(&self) -> usize {
+        self.amplification_limited.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size of the buffer, the `Context` receives datagrams into. Bigger datagrams
+    /// are truncated. The buffer holds at least the `max_udp_payload_size` and the biggest MTU
+    /// probe of the `Config`.
+    pub fn receive_buffer_size(&self) -> usize {
+        self.receive_buffer_len.load(Ordering::Relaxed)
+    }
+
+    /// Returns the information about the live `Connection` with the given id, see
+    /// `Connection::id`. Unlike the address of the peer, the id does not change when the
+    /// `Connection` migrates. Returns `None`, if the `Connection` is closed.
+    pub fn get_connection(&self, id: ConnectionId) -> Option<ConnectionInfo> {
+        self.registry.get(id)
+    }
+
+    /// Updates the `Config` of this `Context`, without rebinding the socket.
+    /// The new `Config` is applied at once to all handshakes that start afterwards, existing
+    /// `Connection`s keep their settings. Certificates and keys are loaded before this function
+    /// returns, so an invalid `Config` is reported here.
+    ///
+    /// The `reset_seed`, the `retry_token_key`, the `sni_identities`, the `cc_log_dir`, the
+    /// `verify_certificate_handler`, the `async_verify_certificate_handler`, the
+    /// `handshake_audit`, the `metrics_sink` with its `metrics_interval`, the `packet_observer`,
+    /// `client_only`, the socket options(e.g. `ipv6_traffic_class`, `ecn` or `ttl`), the
+    /// `crypto_backend`, the `session_ticket_store` and the `key_log_file` can not be updated.
+    /// Without the `openssl` feature, the root certificates can not be updated either.
+    /// Certificates that are not set in the new `Config` are kept.
+    pub fn update_config(&mut self, config: Config) -> Result<(), Error> {
+        let update = ConfigUpdate::new(config)?;
+
+        self.send_config_update
+            .unbounded_send(update)
+            .map_err(|_| ErrorKind::Unknown.into())
+    }
+
+    /// Replaces the certificate chain and the private key of this `Context`.
+    /// The new certificates are used by all handshakes that start afterwards, existing
+    /// `Connection`s are not affected. The chain and the key are checked before this function
+    /// returns, a key that does not belong to the first certificate of the chain is rejected.
+    /// Without the `openssl` feature, such a key is only detected by the failing handshakes.
+    pub fn reload_certificates(
+        &mut self,
+        chain: Vec<Vec<u8>>,
+        private_key: Vec<u8>,
+        format: FileFormat,
+    ) -> Result<(), Error> {
+        let update = ConfigUpdate::credentials(chain, private_key, format)?;
+
+        self.send_config_update
+            .unbounded_send(update)
+            .map_err(|_| ErrorKind::Unknown.into())
     }
 
     /// Connects to the given address and returns a future that resolves into a `Connection`.
@@ -58,10 +265,125 @@ impl Context {
         self.new_connection_handle.new_connection(addr, server_name)
     }
 
+    /// Connects to the given address with the given `ConnectionConfig` and returns a future that
+    /// resolves into a `Connection`.
+    ///
+    /// addr - Address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// config - Overrides the defaults of this `Context` for the new `Connection`.
+    pub fn new_connection_with_config<T: Into<String>>(
+        &mut self,
+        addr: SocketAddr,
+        server_name: T,
+        config: ConnectionConfig,
+    ) -> NewConnectionFuture {
+        self.new_connection_handle
+            .new_connection_with_config(addr, server_name, config)
+    }
+
+    /// Connects to the given address and sends the given data on the first bidirectional
+    /// `Stream`, see `Connection::early_data_stream`. If this `Context` has a session ticket of
+    /// the server, the data is sent as 0-RTT data with the first flight of the handshake.
+    ///
+    /// The data can be replayed by an attacker, so it should only contain idempotent requests.
+    ///
+    /// addr - Address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// data - The early data.
+    pub fn new_connection_with_early_data<T: Into<String>>(
+        &mut self,
+        addr: SocketAddr,
+        server_name: T,
+        data: Bytes,
+    ) -> NewConnectionFuture {
+        self.new_connection_handle
+            .new_connection_with_early_data(addr, server_name, data)
+    }
+
     /// Returns the handle to create new connections.
     pub fn get_new_connection_handle(&self) -> NewConnectionHandle {
         self.new_connection_handle.clone()
     }
+
+    /// Stops to accept new `Connection`s, e.g. while the server is overloaded. New handshakes are
+    /// refused with the given `PauseMode`, established `Connection`s are still served.
+    pub fn pause_listening(&mut self, mode: PauseMode) -> Result<(), Error> {
+        self.listener_control().pause(mode)
+    }
+
+    /// Accepts new `Connection`s again, after `pause_listening`.
+    pub fn resume_listening(&mut self) -> Result<(), Error> {
+        self.listener_control().resume()
+    }
+
+    /// Returns the handle to pause and resume listening for new `Connection`s. The handle is
+    /// still usable, after the `Context` was moved into a task that handles its `Connection`s.
+    pub fn listener_control(&self) -> ListenerControl {
+        ListenerControl {
+            send_config_update: self.send_config_update.clone(),
+        }
+    }
+
+    /// Shuts this `Context` down gracefully. Dropping the `Context` does not stop its
+    /// `ContextDriver`.
+    ///
+    /// The `Context` stops to accept new `Connection`s and closes all open `Connection`s with a
+    /// `CONNECTION_CLOSE`. The returned future resolves, after all `Connection`s are drained or
+    /// the `grace` period elapsed. The remaining `Connection`s are dropped and the sockets of the
+    /// `Context` are released. New `Connection`s that are requested by a `NewConnectionHandle`
+    /// fail with `ErrorKind::Disconnected`.
+    pub fn close(self, grace: Duration) -> ShutdownFuture {
+        let (done, recv) = oneshot::channel();
+        let _ = self
+            .send_config_update
+            .unbounded_send(ConfigUpdate::Shutdown(grace, done));
+
+        ShutdownFuture { recv }
+    }
+}
+
+/// A handle to pause and resume listening for new `Connection`s of a `Context`.
+/// This handle is created by `Context::listener_control`.
+#[derive(Clone)]
+pub struct ListenerControl {
+    send_config_update: UnboundedSender<ConfigUpdate>,
+}
+
+impl ListenerControl {
+    /// See `Context::pause_listening`.
+    pub fn pause(&self, mode: PauseMode) -> Result<(), Error> {
+        self.send(Some(mode))
+    }
+
+    /// See `Context::resume_listening`.
+    pub fn resume(&self) -> Result<(), Error> {
+        self.send(None)
+    }
+
+    fn send(&self, paused: Option<PauseMode>) -> Result<(), Error> {
+        self.send_config_update
+            .unbounded_send(ConfigUpdate::Listening(paused))
+            .map_err(|_| ErrorKind::Unknown.into())
+    }
+}
+
+/// A future that resolves, after a `Context` was shut down and released its sockets.
+/// This future is created by `Context::close`.
+pub struct ShutdownFuture {
+    recv: oneshot::Receiver<()>,
+}
+
+impl Future for ShutdownFuture {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.recv.poll() {
+            Ok(NotReady) => Ok(NotReady),
+            // The `ContextInner` is dropped, when it is shut down.
+            Ok(Ready(())) | Err(_) => Ok(Ready(())),
+        }
+    }
 }
 
 impl Stream for Context {
@@ -72,3 +394,163 @@ impl Stream for Context {
         self.recv_con.poll().map_err(|_| ErrorKind::Unknown.into())
     }
 }
+
+/// Builds a `Context`.
+///
+/// ```no_run
+/// # extern crate picoquic;
+/// # extern crate tokio;
+/// # use picoquic::{Config, ContextBuilder};
+/// # fn main() {
+/// let evt_loop = tokio::runtime::Runtime::new().unwrap();
+///
+/// let context = ContextBuilder::new(Config::new())
+///     .listen_address(([0, 0, 0, 0], 22222).into())
+///     .executor(evt_loop.executor())
+///     .build()
+///     .unwrap();
+/// # }
+/// ```
+pub struct ContextBuilder {
+    config: Config,
+    listen_addresses: Vec<SocketAddr>,
+    sockets: Vec<net::UdpSocket>,
+    spawner: Option<Box<dyn Spawn>>,
+}
+
+impl ContextBuilder {
+    /// Creates a new `ContextBuilder` with the given `Config`.
+    pub fn new(config: Config) -> ContextBuilder {
+        ContextBuilder {
+            config,
+            listen_addresses: vec![([0, 0, 0, 0], 0).into()],
+            sockets: Vec::new(),
+            spawner: None,
+        }
+    }
+
+    /// The address the `Context` binds to.
+    /// Default: `0.0.0.0:0`
+    pub fn listen_address(mut self, address: SocketAddr) -> ContextBuilder {
+        self.listen_addresses = vec![address];
+        self
+    }
+
+    /// The addresses the `Context` binds to, one socket per address. This replaces the
+    /// `listen_address`. IPv6 sockets are bound as IPv6 only, so an IPv4 and an IPv6 address
+    /// can use the same port. Outgoing `Connection`s use the first address, see
+    /// `Context::with_sockets`.
+    /// Default: `0.0.0.0:0`
+    pub fn listen_addresses<I>(mut self, addresses: I) -> ContextBuilder
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        self.listen_addresses = addresses.into_iter().collect();
+        self
+    }
+
+    /// Use the given, already bound, socket instead of binding a new one.
+    /// The `listen_address` and `listen_addresses` are ignored, if a socket is given.
+    ///
+    /// Can be called multiple times, to use multiple sockets, e.g. one per local address of a
+    /// multi-homed server. See `Context::with_sockets`.
+    pub fn socket(mut self, socket: net::UdpSocket) -> ContextBuilder {
+        self.sockets.push(socket);
+        self
+    }
+
+    /// The `Context` only creates outgoing `Connection`s and drops all packets that do not
+    /// belong to one of them, so it never yields an incoming `Connection`. No certificate is
+    /// required and the `Context` binds to an ephemeral port, if no `listen_address` is given.
+    /// See `Config::client_only`.
+    pub fn client_only(mut self) -> ContextBuilder {
+        self.config.enable_client_only();
+        self
+    }
+
+    /// The executor the `Context` is spawned on.
+    /// Default: The executor of the current tokio runtime, via `tokio::spawn`.
+    pub fn executor(self, executor: TaskExecutor) -> ContextBuilder {
+        self.spawner(executor)
+    }
+
+    /// The `Spawn` the `Context` is spawned with and that creates the `Timer` of the `Context`.
+    /// This replaces the `executor`.
+    /// Default: The executor of the current tokio runtime, via `tokio::spawn`.
+    pub fn spawner<S: Spawn + 'static>(mut self, spawner: S) -> ContextBuilder {
+        self.spawner = Some(Box::new(spawner));
+        self
+    }
+
+    /// Builds the `Context` and spawns it on the executor.
+    pub fn build(self) -> Result<Context, Error> {
+        let sockets = if self.sockets.is_empty() {
+            let only_v6 = match self.config.ipv6_only {
+                Some(only_v6) => Some(only_v6),
+                None if self.listen_addresses.len() > 1 => Some(true),
+                None => None,
+            };
+            self.listen_addresses
+                .iter()
+                .map(|addr| bind_socket(addr, only_v6))
+                .collect()
+        } else {
+            self.sockets
+                .into_iter()
+                .map(|socket| UdpSocket::from_std(socket, &Handle::default()))
+                .collect()
+        };
+        let sockets = sockets
+            .into_iter()
+            .map(|socket| socket.map(|s| Box::new(s) as Box<dyn Socket>))
+            .collect::<Result<Vec<_>, _>>()
+            .context(ErrorKind::NetworkError)?;
+        let timer = match self.spawner {
+            Some(ref spawner) => spawner.timer(),
+            None => Box::new(Delay::new(Instant::now() + Duration::from_secs(10))),
+        };
+
+        let (context, driver) = Context::with_sockets(sockets, timer, self.config)?;
+
+        // start the inner future
+        match self.spawner {
+            Some(spawner) => spawner.spawn(driver)?,
+            None => {
+                tokio::spawn(driver);
+            }
+        }
+
+        Ok(context)
+    }
+}
+
+/// Binds a socket to the given address. With `only_v6`, an IPv6 socket does not accept IPv4
+/// packets, so the port stays free for an IPv4 socket. `None` uses the default of the operating
+/// system.
+fn bind_socket(addr: &SocketAddr, only_v6: Option<bool>) -> io::Result<UdpSocket> {
+    let only_v6 = match only_v6 {
+        Some(only_v6) if addr.is_ipv6() => only_v6,
+        _ => return UdpSocket::bind(addr),
+    };
+
+    let socket = socket2::Socket::new(Domain::ipv6(), Type::dgram(), Some(Protocol::udp()))?;
+    socket.set_only_v6(only_v6)?;
+    socket.bind(&SockAddr::from(*addr))?;
+
+    UdpSocket::from_std(socket.into_udp_socket(), &Handle::default())
+}
+
+/// The future that drives a `Context`, created by `Context::with_io`.
+/// It finishes after `Context::close` and needs to be spawned on an executor.
+pub struct ContextDriver {
+    inner: ContextInner,
+}
+
+impl Future for ContextDriver {
+    type Item = ();
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
@@ -1,23 +1,48 @@
-use config::{Config, Role};
+use admission::{AcceptDecision, AcceptFilter, AdmitConnection, IncomingConnectionInfo, PauseMode};
+use amplification::AmplificationLimiter;
+use config::{Config, ConnectionConfig, FileFormat, MtuDiscovery, Role, TransportParameterOverrides};
+#[cfg(feature = "openssl")]
+use connection::PeerCertificates;
 use connection::{self, Connection};
+use driver_thread::DriverThread;
+use ecn::Ecn;
 use error::*;
-use ffi::{self, QuicCtx};
+use ffi::{self, PendingVerifications, QuicCtx, TlsConfig};
+use handshake_audit::HandshakeAuditor;
+use ipv6;
+use metrics::{ConnectionSample, MetricsReporter};
+use packet_observer::{
+    is_long_header, PacketDirection, PacketHeader, PacketMetadata, PacketObserver,
+};
+use rate_limit::{HandshakeRateLimit, HandshakeRateLimiter, RateLimitAction};
+use registry::ConnectionRegistry;
+use runtime::{Socket, Timer};
+#[cfg(feature = "self-signed")]
+use self_signed;
+use stats::{CryptoMeter, EcnMeter};
 use stream;
+use trace::Span;
+use version::{self, QuicVersion};
+use ConnectionType;
 
 use picoquic_sys::picoquic::{
-    picoquic_call_back_event_t, picoquic_cnx_t, PICOQUIC_MAX_PACKET_SIZE,
+    picoquic_call_back_event_t, picoquic_cnx_t, picoquic_get_default_callback_context,
+    picoquic_quic_t, ptls_iovec_t, PICOQUIC_MAX_PACKET_SIZE,
 };
 
 use std::{
+    collections::{HashMap, HashSet},
     io, mem,
     net::SocketAddr,
     os::raw::c_void,
-    sync::{Arc, Mutex},
+    cmp, slice,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, Instant},
 };
 
-use tokio::{net::UdpSocket, timer::Delay};
-
 use futures::{
     sync::{
         mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
@@ -28,30 +53,74 @@ use futures::{
     Future, Poll, Stream,
 };
 
+use bytes::Bytes;
+
+#[cfg(feature = "openssl")]
+use openssl::x509::X509;
+
 type NewConnectionMsg = (
     SocketAddr,
     String,
+    ConnectionConfig,
     oneshot::Sender<Result<Connection, Error>>,
 );
 
 pub struct ContextInner {
-    socket: UdpSocket,
+    /// The sockets of this context, the first one is the default socket.
+    sockets: Vec<Box<dyn Socket>>,
+    /// The local addresses of the `sockets`.
+    local_addrs: Vec<SocketAddr>,
+    /// The socket of each outgoing connection, until picoquic knows the local address of the
+    /// connection. The key is the address of the connection and the value the index of the socket.
+    outgoing_sockets: HashMap<usize, usize>,
+    /// The IPv6 flow label of each connection that uses one.
+    flow_labels: HashMap<usize, u32>,
     context: Arc<Mutex<CContext>>,
     quic: QuicCtx,
     /// Temporary buffer used for receiving and sending
     buffer: Vec<u8>,
+    /// The length of `buffer`, shared with the `Context`.
+    buffer_len: Arc<AtomicUsize>,
     /// Picoquic requires to be woken up to handle resend,
     /// drop of connections(because of inactivity), etc..
-    timer: Delay,
+    timer: Box<dyn Timer>,
     recv_connect: UnboundedReceiver<NewConnectionMsg>,
-    /// The keep alive interval for client connections
-    client_keep_alive_interval: Option<Duration>,
+    /// The settings for client connections
+    client_settings: connection::Settings,
+    /// Enforces the anti-amplification limit for server connections.
+    amplification: AmplificationLimiter,
+    /// Enforces the handshake rate limit per source IP.
+    rate_limiter: HandshakeRateLimiter,
+    send_config_update: UnboundedSender<ConfigUpdate>,
+    recv_config_update: UnboundedReceiver<ConfigUpdate>,
+    /// Enables the `Stream`s to call directly into picoquic, while used on the driver thread.
+    driver: Arc<DriverThread>,
+    /// Records the outcome of each handshake, if enabled.
+    handshake_auditor: Option<HandshakeAuditor>,
+    /// Reports the metrics of this context, if a `MetricsSink` is set.
+    metrics: Option<MetricsReporter>,
+    /// Inspects every sent and received packet, if set.
+    packet_observer: Option<Box<dyn PacketObserver>>,
+    /// Drop all packets that do not belong to an existing connection.
+    client_only: bool,
+    /// The QUIC versions of the `Config`, empty if all implemented versions are supported.
+    supported_versions: Vec<QuicVersion>,
+    /// Set, after the `Context` started to shut down.
+    shutdown: Option<Shutdown>,
+    /// The live connections, shared with the `Context`.
+    registry: ConnectionRegistry,
+    /// The span of this context, the spans of its connections are its children.
+    span: Span,
+    /// The certificate that was generated for this context.
+    #[cfg(feature = "self-signed")]
+    self_signed_certificate: Option<X509>,
 }
 
 impl ContextInner {
     pub fn new(
-        listen_address: &SocketAddr,
-        config: Config,
+        mut sockets: Vec<Box<dyn Socket>>,
+        timer: Box<dyn Timer>,
+        mut config: Config,
     ) -> Result<
         (
             ContextInner,
@@ -60,37 +129,300 @@ impl ContextInner {
         ),
         Error,
     > {
-        let (client_keep_alive_interval, server_keep_alive_interval) =
-            match config.keep_alive_sender {
-                Role::Client => (config.keep_alive_interval, None),
-                Role::Server => (None, config.keep_alive_interval),
-            };
+        if sockets.is_empty() {
+            bail!("A `Context` requires at least one socket");
+        }
+
+        let local_addrs = sockets
+            .iter()
+            .map(|s| s.local_addr())
+            .collect::<Result<Vec<_>, _>>()
+            .context(ErrorKind::NetworkError)?;
+
+        if let Some(traffic_class) = config.ipv6_traffic_class {
+            for (socket, _) in sockets.iter_mut().zip(&local_addrs).filter(|(_, a)| a.is_ipv6()) {
+                socket
+                    .set_ipv6_traffic_class(traffic_class)
+                    .context(ErrorKind::NetworkError)?;
+            }
+        }
+
+        for socket in &mut sockets {
+            configure_socket(&mut **socket, &config).context(ErrorKind::NetworkError)?;
+        }
+
+        // After the traffic class and the TOS, to keep their DSCP bits.
+        if config.ecn {
+            for socket in &mut sockets {
+                socket.enable_ecn().context(ErrorKind::NetworkError)?;
+            }
+        }
+
+        #[cfg(feature = "self-signed")]
+        let self_signed_certificate = match config.self_signed_name.take() {
+            Some(name) => Some(self_signed::apply(&mut config, &name)?),
+            None => None,
+        };
+
+        let (mut client_settings, mut server_settings) = settings_from_config(&config);
+        client_settings.quic_version =
+            version::select_version(&config.supported_versions, &QuicVersion::implemented())?;
+        client_settings.local_addrs = local_addrs.clone();
+        server_settings.local_addrs = local_addrs.clone();
+
+        let amplification = AmplificationLimiter::new(config.amplification_factor);
+        let rate_limiter = HandshakeRateLimiter::new(config.handshake_rate_limit);
+
+        let buffer_len = buffer_len(&client_settings);
 
         let (send, recv) = unbounded();
-        let (context, c_ctx) = CContext::new(send, server_keep_alive_interval);
+        let admission_handler = config.admission_handler.take();
+        let accept_filter = config.accept_filter.take();
+        let handshake_auditor = config.handshake_audit.take().map(HandshakeAuditor::new);
+        let metrics_interval = config.metrics_interval;
+        let metrics = config
+            .metrics_sink
+            .take()
+            .map(|sink| MetricsReporter::new(sink, metrics_interval, Instant::now()));
+        let packet_observer = config.packet_observer.take();
+        let client_only = config.client_only;
+        let supported_versions = config.supported_versions.clone();
+        #[cfg(feature = "openssl")]
+        let async_verifier = config.async_verify_certificate_handler.take();
+        let pending_verifications = PendingVerifications::default();
+        let (context, c_ctx) = CContext::new(
+            send,
+            server_settings,
+            admission_handler,
+            accept_filter,
+            config.max_concurrent_connections,
+            pending_verifications.clone(),
+        );
 
-        let quic = QuicCtx::new(config, c_ctx, Some(new_connection_callback))?;
+        let mut quic = QuicCtx::new(config, c_ctx, Some(new_connection_callback))?;
+        quic.set_alpn_select_fn(Some(select_alpn_callback));
+
+        #[cfg(feature = "openssl")]
+        if let Some(handler) = async_verifier {
+            quic.set_async_verifier(handler, pending_verifications);
+        }
 
         let (send_connect, recv_connect) = unbounded();
         let connect = NewConnectionHandle { send: send_connect };
 
+        let (send_config_update, recv_config_update) = unbounded();
+
+        let span = Span::context(&local_addrs);
+        span_event!(span, info, "context started on {:?}", local_addrs);
+
         Ok((
             ContextInner {
-                socket: UdpSocket::bind(listen_address).context(ErrorKind::NetworkError)?,
+                sockets,
+                local_addrs,
+                outgoing_sockets: HashMap::new(),
+                flow_labels: HashMap::new(),
                 context,
                 quic,
-                buffer: vec![0; PICOQUIC_MAX_PACKET_SIZE as usize],
-                timer: Delay::new(Instant::now() + Duration::from_secs(10)),
+                buffer: vec![0; buffer_len],
+                buffer_len: Arc::new(AtomicUsize::new(buffer_len)),
+                timer,
                 recv_connect,
-                client_keep_alive_interval,
+                client_settings,
+                amplification,
+                rate_limiter,
+                send_config_update,
+                recv_config_update,
+                driver: DriverThread::new(),
+                handshake_auditor,
+                metrics,
+                packet_observer,
+                client_only,
+                supported_versions,
+                shutdown: None,
+                registry: ConnectionRegistry::default(),
+                span,
+                #[cfg(feature = "self-signed")]
+                self_signed_certificate,
             },
             recv,
             connect,
         ))
     }
 
-    pub fn local_addr(&self) -> SocketAddr {
-        self.socket.local_addr().unwrap()
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.local_addrs.clone()
+    }
+
+    /// Returns the index of the socket the given connection sends its packets with.
+    fn socket_index(&self, con: ffi::Connection) -> usize {
+        match self.outgoing_sockets.get(&(con.as_ptr() as usize)) {
+            Some(index) => *index,
+            None => socket_index_by_addr(&self.local_addrs, con.local_addr()).unwrap_or(0),
+        }
+    }
+
+    /// Returns the shared number of connections that are currently blocked by the
+    /// anti-amplification limit.
+    pub fn amplification_limited_connections(&self) -> Arc<AtomicUsize> {
+        self.amplification.limited_connections()
+    }
+
+    /// Returns the shared length of the buffer for receiving packets.
+    pub fn receive_buffer_len(&self) -> Arc<AtomicUsize> {
+        self.buffer_len.clone()
+    }
+
+    /// Returns the certificate that was generated for this context.
+    #[cfg(feature = "self-signed")]
+    pub fn self_signed_certificate(&self) -> Option<X509> {
+        self.self_signed_certificate.clone()
+    }
+
+    /// Returns the shared registry of the live connections.
+    pub fn connection_registry(&self) -> ConnectionRegistry {
+        self.registry.clone()
+    }
+
+    /// Returns the sender for `ConfigUpdate`s, that are applied by this context.
+    pub fn config_update_sender(&self) -> UnboundedSender<ConfigUpdate> {
+        self.send_config_update.clone()
+    }
+
+    /// Check if the configuration should be updated
+    fn check_for_config_update(&mut self) {
+        while let Ok(Ready(Some(update))) = self.recv_config_update.poll() {
+            let mut update = match update {
+                ConfigUpdate::Config(update) => *update,
+                ConfigUpdate::Credentials(tls) => {
+                    if let Err(e) = self.quic.update_tls(tls) {
+                        span_event!(
+                            self.span,
+                            error,
+                            "could not replace the certificates: {:?}",
+                            e
+                        );
+                    }
+                    continue;
+                }
+                ConfigUpdate::Shutdown(grace, done) => {
+                    self.start_shutdown(grace, done);
+                    continue;
+                }
+                ConfigUpdate::Listening(paused) => {
+                    match paused {
+                        Some(mode) => span_event!(self.span, info, "paused listening: {:?}", mode),
+                        None => span_event!(self.span, info, "resumed listening"),
+                    }
+
+                    self.context.lock().unwrap().paused = paused;
+                    continue;
+                }
+            };
+
+            if let Err(e) = self.quic.update_tls(update.tls) {
+                span_event!(self.span, error, "could not update the TLS configuration: {:?}", e);
+            }
+
+            let buffer_len = buffer_len(&update.client_settings);
+            if buffer_len > self.buffer.len() {
+                self.buffer.resize(buffer_len, 0);
+                self.buffer_len.store(buffer_len, Ordering::Relaxed);
+            }
+
+            // The sockets can not be updated.
+            update.client_settings.local_addrs = self.local_addrs.clone();
+            update.server_settings.local_addrs = self.local_addrs.clone();
+
+            self.client_settings = update.client_settings;
+            self.supported_versions = update.supported_versions;
+            self.amplification.set_factor(update.amplification_factor);
+            self.quic.set_stateless_retry(update.stateless_retry);
+            self.rate_limiter.set_limit(update.handshake_rate_limit);
+
+            let mut context = self.context.lock().unwrap();
+            context.server_settings = update.server_settings;
+            context.admission_handler = update.admission_handler;
+            context.accept_filter = update.accept_filter;
+            context.max_concurrent_connections = update.max_concurrent_connections;
+        }
+    }
+
+    /// Closes all connections and stops to accept new ones. The context finishes, after all
+    /// connections are drained or the grace period elapsed.
+    fn start_shutdown(&mut self, grace: Duration, done: oneshot::Sender<()>) {
+        let connections = self
+            .quic
+            .connection_iter()
+            .filter(|con| !con.is_going_to_close())
+            .collect::<Vec<_>>();
+
+        span_event!(
+            self.span,
+            info,
+            "closing {} connections: server shutting down",
+            connections.len()
+        );
+
+        // Picoquic does not send reason phrases, so the connections are closed without error.
+        connections.iter().for_each(|con| con.close());
+
+        self.shutdown = Some(Shutdown {
+            deadline: Instant::now() + grace,
+            done,
+        });
+    }
+
+    /// Is the shutdown finished? All connections need to be drained or the grace period needs to
+    /// be elapsed.
+    fn is_shut_down(&self) -> bool {
+        let finished = match self.shutdown {
+            Some(ref shutdown) => {
+                Instant::now() >= shutdown.deadline || self.quic.connection_iter().next().is_none()
+            }
+            None => false,
+        };
+
+        if finished {
+            span_event!(self.span, info, "context shut down");
+        }
+
+        finished
+    }
+
+    /// Polls the asynchronous certificate verifications and hands out the `Connection`s, whose
+    /// certificate was verified.
+    #[cfg(feature = "openssl")]
+    fn check_certificate_verifications(&mut self) {
+        for (cnx, verified) in self.quic.poll_certificate_verifications() {
+            self.context
+                .lock()
+                .unwrap()
+                .finish_certificate_verification(cnx, verified);
+        }
+    }
+
+    /// Hands the peer certificates to the connections that finished their handshake.
+    #[cfg(feature = "openssl")]
+    fn update_peer_certificates(&mut self) {
+        let quic = &self.quic;
+
+        self.context
+            .lock()
+            .unwrap()
+            .peer_certificates
+            .retain(|_, (cnx, certs)| {
+                if !cnx.is_ready() {
+                    return true;
+                }
+
+                certs.set(
+                    quic.peer_certificates(*cnx)
+                        .iter()
+                        .filter_map(|der| X509::from_der(der).ok())
+                        .collect(),
+                );
+                false
+            });
     }
 
     /// Check if we should create a new connection
@@ -98,24 +430,100 @@ impl ContextInner {
         loop {
             match self.recv_connect.poll() {
                 Err(_) | Ok(NotReady) | Ok(Ready(None)) => break,
-                Ok(Ready(Some((addr, server_name, sender)))) => {
+                Ok(Ready(Some((addr, server_name, mut config, sender)))) => {
+                    if self.shutdown.is_some() {
+                        let _ = sender.send(Err(ErrorKind::Disconnected.into()));
+                        continue;
+                    }
+
+                    #[cfg(feature = "openssl")]
+                    let verifier = config.verify_certificate_handler.take();
+                    let flow_label = config.flow_label.take();
+
+                    if let Some(ticket) = config.session_ticket.take() {
+                        // The ticket is stored for the most preferred protocol.
+                        let alpn = config.alpn.as_ref().map(|a| a.as_bytes()).or_else(|| {
+                            self.client_settings.alpn_protocols.first().map(Vec::as_slice)
+                        });
+
+                        if let Err(e) = self.quic.store_session_ticket(
+                            &server_name,
+                            alpn,
+                            &ticket,
+                            current_time,
+                        ) {
+                            span_event!(self.span, warn, "could not store session ticket: {:?}", e);
+                        }
+                    }
+
+                    let socket: Result<usize, Error> = match config.local_addr.take() {
+                        Some(local_addr) => socket_index_by_addr(&self.local_addrs, local_addr)
+                            .ok_or_else(|| ErrorKind::UnknownLocalAddress(local_addr).into()),
+                        None => Ok(0),
+                    };
+
+                    let socket = match socket {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            let _ = sender.send(Err(e));
+                            continue;
+                        }
+                    };
+
                     let ctx = match Connection::new(
                         &self.quic,
                         addr,
-                        self.local_addr(),
+                        self.local_addrs[socket],
                         server_name,
                         current_time,
-                        self.client_keep_alive_interval,
+                        self.client_settings.clone().with_overrides(config),
                         sender,
                     ) {
                         Ok(r) => r,
                         Err(e) => {
-                            error!("could not create new connection: {:?}", e);
+                            span_event!(
+                                self.span,
+                                error,
+                                "could not create new connection: {:?}",
+                                e
+                            );
                             continue;
                         }
                     };
 
-                    self.context.lock().unwrap().connections.push(ctx);
+                    let cnx = ctx.lock().unwrap().cnx();
+                    self.outgoing_sockets.insert(cnx.as_ptr() as usize, socket);
+
+                    if let (Some(label), SocketAddr::V6(peer)) = (flow_label, addr) {
+                        match self.sockets[socket].register_ipv6_flow_label(&peer, label) {
+                            Ok(()) => {
+                                self.flow_labels.insert(cnx.as_ptr() as usize, label);
+                            }
+                            Err(e) => span_event!(
+                                self.span,
+                                warn,
+                                "could not set flow label {}: {:?}",
+                                label,
+                                e
+                            ),
+                        }
+                    }
+
+                    #[cfg(feature = "openssl")]
+                    if let Some(verifier) = verifier {
+                        if let Err(e) = self.quic.set_connection_verifier(cnx, verifier) {
+                            // Never fall back to the verification of the `Context`
+                            span_event!(
+                                self.span,
+                                error,
+                                "could not set certificate verifier of connection: {:?}",
+                                e
+                            );
+                            cnx.close();
+                        }
+                    }
+
+                    self.context.lock().unwrap().add_connection(ctx);
                 }
             }
         }
@@ -126,72 +534,283 @@ impl ContextInner {
         let itr = self.quic.connection_iter();
 
         for con in itr {
-            if self
-                .socket
+            let socket = self.socket_index(con);
+
+            if self.sockets[socket]
                 .poll_write_ready()
                 .map(|s| s.is_not_ready())
                 .unwrap_or(true)
             {
-                // The socket is not ready to send data
-                break;
+                // The socket of this connection is not ready to send data
+                continue;
             }
 
-            if con.is_disconnected() {
+            let key = con.as_ptr() as usize;
+
+            if con.is_disconnected() || self.context.lock().unwrap().dropped.contains(&key) {
+                if let Some(ref mut auditor) = self.handshake_auditor {
+                    auditor.on_disconnected(&self.quic, con);
+                }
+
+                if let Some(ref mut metrics) = self.metrics {
+                    metrics.on_disconnected(key, con.stats().packets_lost);
+                }
+
+                self.registry.remove(key);
+                self.amplification.remove(key);
+                self.outgoing_sockets.remove(&key);
+                self.flow_labels.remove(&key);
+                {
+                    let mut context = self.context.lock().unwrap();
+                    context.crypto_meters.remove(&key);
+                    context.ecn_meters.remove(&key);
+                    #[cfg(feature = "openssl")]
+                    context.peer_certificates.remove(&key);
+                    context.held_connections.remove(&key);
+                    context.dropped.remove(&key);
+                }
+                #[cfg(feature = "openssl")]
+                self.quic.remove_connection_verifier(con);
                 con.delete();
                 break;
             } else {
+                if let Some(ref mut auditor) = self.handshake_auditor {
+                    auditor.check_connection(&self.quic, con);
+                }
+
+                if let Some(ref mut metrics) = self.metrics {
+                    metrics.check_connection(key, con.is_ready());
+                }
+
+                self.registry.update(key, con);
+
+                if con.is_address_validated() {
+                    self.amplification.remove(key);
+                }
+
+                if !self.amplification.can_send(key, con.send_mtu()) {
+                    // The connection needs to wait for more data from the client
+                    continue;
+                }
+
+                let start = Instant::now();
                 match con.prepare_packet(&mut self.buffer, current_time) {
-                    Ok(Some((len, addr))) => {
-                        let _ = self.socket.poll_send_to(&self.buffer[..len], &addr);
+                    Ok(Some((len, addr, local_addr))) => {
+                        // After a migration, the packets of the new path are sent from the
+                        // socket of its local address.
+                        let socket = match local_addr
+                            .and_then(|a| socket_index_by_addr(&self.local_addrs, a))
+                        {
+                            Some(index) if index != socket => {
+                                self.outgoing_sockets.insert(key, index);
+                                index
+                            }
+                            _ => socket,
+                        };
+
+                        if let Some(meter) = self.context.lock().unwrap().crypto_meters.get(&key) {
+                            meter.on_protected(len, start.elapsed());
+                        }
+
+                        self.amplification.on_data_sent(key, len);
+                        con.notify_packet_sent(len);
+                        let addr = match self.flow_labels.get(&key) {
+                            Some(label) => with_flow_label(addr, *label),
+                            None => addr,
+                        };
+                        let _ = self.sockets[socket].poll_send_to(&self.buffer[..len], &addr);
+
+                        if let Some(ref mut metrics) = self.metrics {
+                            metrics.on_packet_sent(len);
+                        }
+
+                        if let Some(ref mut observer) = self.packet_observer {
+                            let packet = &self.buffer[..len];
+                            let metadata = PacketMetadata::new(packet, Some(con.local_id()));
+                            observer.observe(PacketDirection::Sent, addr, packet, &metadata);
+                        }
                     }
                     Ok(None) => {}
                     Err(e) => {
-                        debug!("error while sending connections packets: {:?}", e);
+                        span_event!(
+                            self.span,
+                            debug,
+                            "error while sending connections packets: {:?}",
+                            e
+                        );
                     }
                 };
             }
         }
     }
 
-    /// Checks the `UdpSocket` for incoming data
+    /// Checks the `Socket`s for incoming data
     fn check_for_incoming_data(&mut self, current_time: u64) {
         fn wrapper(
             buf: &mut [u8],
-            socket: &mut UdpSocket,
+            socket: &mut dyn Socket,
+            local_addr: SocketAddr,
             quic: &mut QuicCtx,
             current_time: u64,
+            check_packet: &mut dyn FnMut(
+                Option<ffi::Connection>,
+                SocketAddr,
+                &[u8],
+            ) -> Option<RateLimitAction>,
+            on_received: &mut dyn FnMut(Option<ffi::Connection>, usize, Duration, Ecn),
         ) -> Poll<Option<()>, io::Error> {
             loop {
-                let (len, addr) = try_ready!(socket.poll_recv_from(buf));
-                quic.incoming_data(
-                    &mut buf[..len],
-                    socket.local_addr().unwrap(),
-                    addr,
-                    current_time,
-                );
+                let (len, addr, ecn) = try_ready!(socket.poll_recv_from_with_ecn(buf));
+
+                // The connection is found by its connection id, before picoquic decrypts the
+                // packet in place. This keeps a connection, when the address of the peer changes.
+                let con = quic.connection_by_packet(&buf[..len], addr);
+
+                let action = check_packet(con, addr, &buf[..len]);
+
+                let start = Instant::now();
+                match action {
+                    None => quic.incoming_data(&mut buf[..len], local_addr, addr, current_time),
+                    Some(RateLimitAction::Retry) => quic.incoming_data_with_retry(
+                        &mut buf[..len],
+                        local_addr,
+                        addr,
+                        current_time,
+                    ),
+                    Some(RateLimitAction::Drop) => {
+                        on_received(None, len, start.elapsed(), ecn);
+                        continue;
+                    }
+                }
+
+                let con = con.or_else(|| quic.connection_by_addr(addr));
+                on_received(con, len, start.elapsed(), ecn);
             }
         }
 
-        let _ = wrapper(
-            &mut self.buffer,
-            &mut self.socket,
-            &mut self.quic,
-            current_time,
-        );
+        // No new connections are accepted, while the context shuts down or is paused.
+        let client_only = self.client_only
+            || self.shutdown.is_some()
+            || self.context.lock().unwrap().paused == Some(PauseMode::Drop);
+        let rate_limiter = &mut self.rate_limiter;
+        let packet_observer = &mut self.packet_observer;
+        let supported_versions = &self.supported_versions;
+        let context = &self.context;
+        let span = &self.span;
+        let mut check_packet = |con: Option<ffi::Connection>, addr: SocketAddr, packet: &[u8]| {
+            // The observer sees the packet, before picoquic decrypts it in place.
+            if let Some(ref mut observer) = *packet_observer {
+                let metadata = PacketMetadata::new(packet, con.map(|c| c.local_id()));
+                observer.observe(PacketDirection::Received, addr, packet, &metadata);
+            }
+
+            if let Some(con) = con {
+                if let Some(offered) = version::offered_versions(packet) {
+                    if !offered.iter().any(|v| version::is_supported(*v, supported_versions)) {
+                        span_event!(
+                            span,
+                            warn,
+                            "server offered none of the supported QUIC versions: {:?}",
+                            offered
+                        );
+                        context.lock().unwrap().on_version_negotiation(con, offered);
+                    }
+                }
+
+                None
+            } else if client_only {
+                // Picoquic would create a new server connection for a packet of an unknown peer.
+                Some(RateLimitAction::Drop)
+            } else if let Some(version) = unsupported_version(packet, supported_versions) {
+                span_event!(span, debug, "dropped handshake with QUIC version {}", version);
+                Some(RateLimitAction::Drop)
+            } else if is_long_header(packet) {
+                rate_limiter.on_new_handshake(addr.ip(), current_time)
+            } else {
+                None
+            }
+        };
+
+        let amplification = &mut self.amplification;
+        let metrics = &mut self.metrics;
+        let mut on_received = |con: Option<ffi::Connection>, len: usize, time: Duration, ecn: Ecn| {
+            if let Some(ref mut metrics) = *metrics {
+                metrics.on_packet_received(len);
+            }
+
+            let con = match con {
+                Some(con) => con,
+                None => return,
+            };
+            let key = con.as_ptr() as usize;
+
+            if !con.is_address_validated() {
+                amplification.on_data_received(key, len);
+            }
+
+            let context = context.lock().unwrap();
+
+            if let Some(meter) = context.crypto_meters.get(&key) {
+                meter.on_unprotected(len, time);
+            }
+
+            if let Some(meter) = context.ecn_meters.get(&key) {
+                meter.on_received(ecn);
+            }
+        };
+
+        for (socket, local_addr) in self.sockets.iter_mut().zip(self.local_addrs.iter()) {
+            let _ = wrapper(
+                &mut self.buffer,
+                &mut **socket,
+                *local_addr,
+                &mut self.quic,
+                current_time,
+                &mut check_packet,
+                &mut on_received,
+            );
+        }
     }
 
     fn send_stateless_packets(&mut self) -> Poll<(), Error> {
         let itr = self.quic.stateless_packet_iter();
 
         for packet in itr {
-            try_ready!(self
-                .socket
+            // Answer from the address the packet was sent to.
+            let socket = packet
+                .get_local_addr()
+                .and_then(|addr| socket_index_by_addr(&self.local_addrs, addr))
+                .unwrap_or(0);
+
+            try_ready!(self.sockets[socket]
                 .poll_send_to(packet.get_data(), &packet.get_peer_addr()));
+
+            if let Some(ref mut metrics) = self.metrics {
+                metrics.on_packet_sent(packet.get_data().len());
+            }
+
+            if let Some(ref mut observer) = self.packet_observer {
+                let data = packet.get_data();
+                let metadata = PacketMetadata::new(data, None);
+                observer.observe(PacketDirection::Sent, packet.get_peer_addr(), data, &metadata);
+            }
         }
 
         Ok(Ready(()))
     }
 
+    /// Reports the metrics of this context, if the interval of the `MetricsSink` elapsed.
+    fn report_metrics(&mut self) {
+        if let Some(ref mut metrics) = self.metrics {
+            let connections = self.quic.connection_iter().map(|con| ConnectionSample {
+                packets_lost: con.stats().packets_lost,
+                rtt: con.smoothed_rtt(),
+            });
+
+            metrics.report(connections, Instant::now());
+        }
+    }
+
     /// Resets the timer to fire next at the given time.
     /// Returns true if polling the timer returned `NotReady`, otherwise false.
     fn reset_timer(&mut self, at: Instant) -> bool {
@@ -202,6 +821,195 @@ impl ContextInner {
     }
 }
 
+/// Applies the socket options of the `Config` to the given socket.
+fn configure_socket(socket: &mut dyn Socket, config: &Config) -> io::Result<()> {
+    if let Some(size) = config.socket_send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+
+    if let Some(size) = config.socket_recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+
+    if let Some(tos) = config.ip_tos {
+        socket.set_tos(tos)?;
+    }
+
+    if let Some(ttl) = config.ttl {
+        socket.set_ttl(ttl)?;
+    }
+
+    Ok(())
+}
+
+/// Creates the settings for client and server connections.
+fn settings_from_config(config: &Config) -> (connection::Settings, connection::Settings) {
+    let settings = connection::Settings {
+        keep_alive_interval: None,
+        send_path: stream::SendPath {
+            callback_driven: config.callback_driven_send,
+            zero_copy_threshold: config.zero_copy_send_threshold,
+            preserve_write_boundaries: !config.coalesce_stream_writes,
+        },
+        write_coalescing: config.write_coalescing,
+        send_watermark: stream::SendWatermark {
+            bytes: config.max_send_backlog,
+            messages: config.max_pending_send_messages,
+        },
+        max_receive_window: config.max_receive_window,
+        recv_buffer_chunk_size: config.recv_buffer_chunk_size,
+        mtu_discovery: config.mtu_discovery.clone(),
+        max_udp_payload_size: config.max_udp_payload_size,
+        grease_version: config.grease_version,
+        quic_version: None,
+        alpn_protocols: config.alpn_protocols.clone(),
+        idle_timeout: config.idle_timeout,
+        max_pending_incoming_streams: config.max_pending_incoming_streams,
+        max_datagram_frame_size: config.max_datagram_frame_size,
+        early_data: None,
+        default_stream_priority: config.default_stream_priority,
+        qlog_dir: config.qlog_dir.clone(),
+        local_addrs: Vec::new(),
+        transport_parameters: TransportParameterOverrides::from_config(config),
+        congestion_algorithm: None,
+        key_update_interval: config.key_update_interval,
+        path_parameters: None,
+    };
+    let mut client_settings = settings.clone();
+    let mut server_settings = settings;
+
+    match config.keep_alive_sender {
+        Role::Client => client_settings.keep_alive_interval = config.keep_alive_interval,
+        Role::Server => server_settings.keep_alive_interval = config.keep_alive_interval,
+    }
+
+    (client_settings, server_settings)
+}
+
+/// Returns the version of the given handshake packet, if the version is not supported.
+fn unsupported_version(packet: &[u8], supported: &[QuicVersion]) -> Option<QuicVersion> {
+    match PacketHeader::parse(packet) {
+        Some(PacketHeader::Long { version, .. })
+            if !version::is_supported(version.into(), supported) =>
+        {
+            Some(version.into())
+        }
+        _ => None,
+    }
+}
+
+/// Returns the index of the socket that is bound to the given local address.
+/// A socket that is bound to the unspecified address matches every address with the same port
+/// and ip version.
+pub fn socket_index_by_addr(local_addrs: &[SocketAddr], addr: SocketAddr) -> Option<usize> {
+    local_addrs.iter().position(|a| *a == addr).or_else(|| {
+        local_addrs.iter().position(|a| {
+            a.ip().is_unspecified() && a.port() == addr.port() && a.is_ipv4() == addr.is_ipv4()
+        })
+    })
+}
+
+/// Returns the index of the offered protocol that is selected, the first protocol of the
+/// `supported` protocols that is offered wins. If no protocols are supported, the most preferred
+/// protocol of the client is selected.
+fn select_alpn(supported: &[Vec<u8>], offered: &[&[u8]]) -> Option<usize> {
+    if supported.is_empty() {
+        return if offered.is_empty() { None } else { Some(0) };
+    }
+
+    supported
+        .iter()
+        .find_map(|s| offered.iter().position(|o| *o == &s[..]))
+}
+
+/// Sets the flow label of the given IPv6 address, the traffic class in the `flowinfo` is kept.
+/// The `flowinfo` is passed as is to the socket, so it needs to be in network byte order.
+fn with_flow_label(addr: SocketAddr, label: u32) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(mut addr) => {
+            let flowinfo = (u32::from_be(addr.flowinfo()) & !ipv6::MAX_FLOW_LABEL) | label;
+            addr.set_flowinfo(flowinfo.to_be());
+            SocketAddr::V6(addr)
+        }
+        addr => addr,
+    }
+}
+
+/// Returns the length of the buffer for receiving and sending packets.
+fn buffer_len(settings: &connection::Settings) -> usize {
+    // The buffer needs to be able to hold the biggest probed packet
+    let len = match settings.mtu_discovery {
+        MtuDiscovery::Probe { ref sizes, .. } => sizes.last().cloned().unwrap_or(0),
+        _ => 0,
+    };
+    // and the biggest packet the peer is allowed to send.
+    let len = cmp::max(len, settings.max_udp_payload_size.unwrap_or(0));
+    cmp::max(len, PICOQUIC_MAX_PACKET_SIZE as usize)
+}
+
+/// An update that is applied to a running context.
+pub enum ConfigUpdate {
+    /// Applies the parts of a new `Config`.
+    Config(Box<SettingsUpdate>),
+    /// Replaces the certificate chain and the private key.
+    Credentials(TlsConfig),
+    /// Shuts the context down with the given grace period, the sender is notified afterwards.
+    Shutdown(Duration, oneshot::Sender<()>),
+    /// Pauses listening for new connections with the given mode, `None` resumes listening.
+    Listening(Option<PauseMode>),
+}
+
+/// The state of a context that shuts down.
+struct Shutdown {
+    /// The connections that are not drained until this time point are dropped.
+    deadline: Instant,
+    done: oneshot::Sender<()>,
+}
+
+/// The parts of a `Config` that can be applied to a running context.
+pub struct SettingsUpdate {
+    client_settings: connection::Settings,
+    server_settings: connection::Settings,
+    amplification_factor: u32,
+    stateless_retry: bool,
+    handshake_rate_limit: Option<HandshakeRateLimit>,
+    admission_handler: Option<Box<dyn AdmitConnection>>,
+    accept_filter: Option<Box<dyn AcceptFilter>>,
+    max_concurrent_connections: Option<usize>,
+    supported_versions: Vec<QuicVersion>,
+    tls: TlsConfig,
+}
+
+impl ConfigUpdate {
+    pub fn new(mut config: Config) -> Result<ConfigUpdate, Error> {
+        let (mut client_settings, server_settings) = settings_from_config(&config);
+        client_settings.quic_version =
+            version::select_version(&config.supported_versions, &QuicVersion::implemented())?;
+        let tls = TlsConfig::new(&config)?;
+
+        Ok(ConfigUpdate::Config(Box::new(SettingsUpdate {
+            client_settings,
+            server_settings,
+            amplification_factor: config.amplification_factor,
+            stateless_retry: config.stateless_retry,
+            handshake_rate_limit: config.handshake_rate_limit,
+            admission_handler: config.admission_handler.take(),
+            accept_filter: config.accept_filter.take(),
+            max_concurrent_connections: config.max_concurrent_connections,
+            supported_versions: config.supported_versions.clone(),
+            tls,
+        })))
+    }
+
+    pub fn credentials(
+        chain: Vec<Vec<u8>>,
+        private_key: Vec<u8>,
+        format: FileFormat,
+    ) -> Result<ConfigUpdate, Error> {
+        TlsConfig::credentials(chain, private_key, format).map(ConfigUpdate::Credentials)
+    }
+}
+
 impl Future for ContextInner {
     type Item = ();
     type Error = ();
@@ -214,23 +1022,55 @@ impl Future for ContextInner {
         // loop count, we queue the current task to be woken up again and return `Ok(NotReady)`.
         let max_loops_without_sleep = 50;
 
+        let _driver = DriverThread::enter(&self.driver);
+        // The spans of new connections are children of the context span.
+        let span = self.span.clone();
+        let _entered = span.enter();
+
         loop {
             let current_time = self.quic.get_current_time();
 
+            self.check_for_config_update();
+
             self.check_for_new_connection_request(current_time);
 
             self.check_for_incoming_data(current_time);
 
             let _ = self.send_stateless_packets();
 
+            #[cfg(feature = "openssl")]
+            {
+                self.check_certificate_verifications();
+                self.update_peer_certificates();
+            }
+
             // This checks all connection contexts if there is data that needs to be send
             assert!(self.context.lock().unwrap().poll().is_ok());
 
             // All data that was send by the connection contexts, is collected to `Packet`'s per
-            // connection and is send via the `UdpSocket`.
+            // connection and is send via the `Socket`.
             self.send_connection_packets(current_time);
 
+            self.report_metrics();
+
+            if self.is_shut_down() {
+                // The sockets are released, before the shutdown is reported.
+                self.sockets.clear();
+                self.registry.clear();
+                self.shutdown.take().map(|s| s.done.send(()));
+                return Ok(Ready(()));
+            }
+
             let next_wake = self.quic.get_next_wake_up_time(current_time);
+            // Wake up for the next report of the metrics and at the end of the shutdown.
+            let next_report = self.metrics.as_ref().map(MetricsReporter::next_report);
+            let deadline = self.shutdown.as_ref().map(|s| s.deadline);
+            let next_wake = next_wake.map(|wake| {
+                [next_report, deadline]
+                    .iter()
+                    .filter_map(|t| *t)
+                    .fold(wake, cmp::min)
+            });
 
             if loops_without_sleep >= max_loops_without_sleep {
                 task::current().notify();
@@ -254,19 +1094,60 @@ impl Future for ContextInner {
 /// The callback context that is given as `ctx` argument to `new_connection_callback`.
 struct CContext {
     connections: Vec<Arc<Mutex<connection::Context>>>,
+    /// The crypto meter of each connection, the key is the address of the connection.
+    crypto_meters: HashMap<usize, CryptoMeter>,
+    /// The ECN meter of each connection, the key is the address of the connection.
+    ecn_meters: HashMap<usize, EcnMeter>,
+    /// The peer certificates of the connections that are still in the handshake, the key is the
+    /// address of the connection.
+    #[cfg(feature = "openssl")]
+    peer_certificates: HashMap<usize, (ffi::Connection, PeerCertificates)>,
     send_con: UnboundedSender<Connection>,
-    server_keep_alive_interval: Option<Duration>,
+    /// The settings for server connections
+    server_settings: connection::Settings,
+    /// Overrides the `server_settings` per connection.
+    admission_handler: Option<Box<dyn AdmitConnection>>,
+    /// Decides, if an incoming connection is accepted.
+    accept_filter: Option<Box<dyn AcceptFilter>>,
+    /// The maximum number of open incoming connections.
+    max_concurrent_connections: Option<usize>,
+    /// Refuses all new incoming connections with the given mode, if set.
+    paused: Option<PauseMode>,
+    /// The connections whose certificate is verified asynchronously. These connections are not
+    /// polled, so outgoing connections do not become ready.
+    pending_verifications: PendingVerifications,
+    /// The incoming `Connection`s that are handed out, after the certificate was verified.
+    held_connections: HashMap<usize, Connection>,
+    /// The incoming connections that are deleted, without sending anything to the peer. Picoquic
+    /// still processes the packet that created such a connection, so it is deleted together
+    /// with the disconnected connections.
+    dropped: HashSet<usize>,
 }
 
 impl CContext {
     fn new(
         send_con: UnboundedSender<Connection>,
-        server_keep_alive_interval: Option<Duration>,
+        server_settings: connection::Settings,
+        admission_handler: Option<Box<dyn AdmitConnection>>,
+        accept_filter: Option<Box<dyn AcceptFilter>>,
+        max_concurrent_connections: Option<usize>,
+        pending_verifications: PendingVerifications,
     ) -> (Arc<Mutex<CContext>>, *mut c_void) {
         let ctx = Arc::new(Mutex::new(CContext {
             connections: Vec::new(),
+            crypto_meters: HashMap::new(),
+            ecn_meters: HashMap::new(),
+            #[cfg(feature = "openssl")]
+            peer_certificates: HashMap::new(),
             send_con,
-            server_keep_alive_interval,
+            server_settings,
+            admission_handler,
+            accept_filter,
+            max_concurrent_connections,
+            paused: None,
+            pending_verifications,
+            held_connections: HashMap::new(),
+            dropped: HashSet::new(),
         }));
 
         let c_ctx = Arc::into_raw(ctx.clone()) as *mut c_void;
@@ -276,8 +1157,131 @@ impl CContext {
         (ctx, c_ctx)
     }
 
-    fn new_connection(&mut self, con: Connection, ctx: Arc<Mutex<connection::Context>>) {
+    /// Returns the number of incoming connections that are not closing.
+    fn open_incoming_connections(&self) -> usize {
+        self.connections
+            .iter()
+            .map(|c| c.lock().unwrap().cnx())
+            .filter(|cnx| cnx.con_type() == ConnectionType::Incoming && !cnx.is_going_to_close())
+            .count()
+    }
+
+    /// Decides, if the given incoming connection is accepted.
+    /// While listening is paused, all connections are refused. Connections above the
+    /// `max_concurrent_connections` are rejected as busy, before the `accept_filter` is asked.
+    fn accept_incoming(&mut self, cnx: ffi::Connection) -> bool {
+        // The connection is not deleted yet and receives the next packets of the peer.
+        if self.dropped.contains(&(cnx.as_ptr() as usize)) {
+            return false;
+        }
+
+        match self.paused {
+            Some(PauseMode::Busy) => {
+                cnx.close_as_busy();
+                return false;
+            }
+            // Only connections that were created before the pause get here.
+            Some(PauseMode::Drop) => {
+                self.dropped.insert(cnx.as_ptr() as usize);
+                return false;
+            }
+            None => {}
+        }
+
+        if let Some(max) = self.max_concurrent_connections {
+            if self.open_incoming_connections() >= max {
+                cnx.close_as_busy();
+                return false;
+            }
+        }
+
+        let decision = match self.accept_filter {
+            Some(ref mut filter) => filter.filter(&IncomingConnectionInfo {
+                peer_addr: cnx.peer_addr(),
+                server_name: cnx.server_name(),
+            }),
+            None => AcceptDecision::Accept,
+        };
+
+        match decision {
+            AcceptDecision::Accept => true,
+            AcceptDecision::RejectWithCode(code) => {
+                cnx.close_with_error(code);
+                false
+            }
+            AcceptDecision::Drop => {
+                self.dropped.insert(cnx.as_ptr() as usize);
+                false
+            }
+        }
+    }
+
+    /// Returns the settings for the given incoming connection.
+    fn incoming_settings(&mut self, cnx: ffi::Connection) -> connection::Settings {
+        let settings = self.server_settings.clone();
+
+        match self.admission_handler {
+            Some(ref mut handler) => {
+                let info = IncomingConnectionInfo {
+                    peer_addr: cnx.peer_addr(),
+                    server_name: cnx.server_name(),
+                };
+
+                settings.with_overrides(handler.admit(&info))
+            }
+            None => settings,
+        }
+    }
+
+    /// Remembers the versions the server offered to the given outgoing connection, if none of
+    /// them is supported.
+    fn on_version_negotiation(&mut self, cnx: ffi::Connection, offered: Vec<QuicVersion>) {
+        let ctx = self
+            .connections
+            .iter()
+            .find(|c| c.lock().unwrap().cnx().as_ptr() == cnx.as_ptr());
+
+        if let Some(ctx) = ctx {
+            ctx.lock().unwrap().set_offered_versions(offered);
+        }
+    }
+
+    fn add_connection(&mut self, ctx: Arc<Mutex<connection::Context>>) {
+        {
+            let ctx = ctx.lock().unwrap();
+            let key = ctx.cnx().as_ptr() as usize;
+            self.crypto_meters.insert(key, ctx.crypto_meter());
+            self.ecn_meters.insert(key, ctx.ecn_meter());
+            #[cfg(feature = "openssl")]
+            self.peer_certificates.insert(key, (ctx.cnx(), ctx.peer_certificates()));
+        }
+
         self.connections.push(ctx);
+    }
+
+    fn new_connection(&mut self, con: Connection, ctx: Arc<Mutex<connection::Context>>) {
+        let cnx = ctx.lock().unwrap().cnx();
+        self.add_connection(ctx);
+
+        if self.pending_verifications.contains(cnx) {
+            self.held_connections.insert(cnx.as_ptr() as usize, con);
+        } else {
+            self.send_connection(con);
+        }
+    }
+
+    /// Hands out the held `Connection`, if its certificate was verified. Otherwise, the
+    /// `Connection` is dropped.
+    #[cfg(feature = "openssl")]
+    fn finish_certificate_verification(&mut self, cnx: ffi::Connection, verified: bool) {
+        if let Some(con) = self.held_connections.remove(&(cnx.as_ptr() as usize)) {
+            if verified {
+                self.send_connection(con);
+            }
+        }
+    }
+
+    fn send_connection(&mut self, con: Connection) {
         if self.send_con.unbounded_send(con).is_err() {
             error!("error propagating new `Connection`, the receiving side probably closed!");
             //TODO: yeah we should end the `ServerInner` future here
@@ -290,12 +1294,16 @@ impl Future for CContext {
     type Error = ();
 
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let pending = &self.pending_verifications;
+
         self.connections.retain(|c| {
-            c.lock()
-                .unwrap()
-                .poll()
-                .map(|v| v.is_not_ready())
-                .unwrap_or(false)
+            let mut c = c.lock().unwrap();
+
+            if pending.contains(c.cnx()) {
+                return true;
+            }
+
+            c.poll().map(|v| v.is_not_ready()).unwrap_or(false)
         });
         Ok(NotReady)
     }
@@ -324,21 +1332,42 @@ unsafe extern "C" fn new_connection_callback(
     {
         let mut ctx_locked = ctx.lock().unwrap();
 
-        let (con, con_ctx) = Connection::from_incoming(
-            cnx,
-            stream_id,
-            bytes,
-            length,
-            event,
-            ctx_locked.server_keep_alive_interval,
-        );
+        // Rejected connections are closed by picoquic, no `Connection` is created for them.
+        if ctx_locked.accept_incoming(ffi::Connection::from(cnx)) {
+            let settings = ctx_locked.incoming_settings(ffi::Connection::from(cnx));
+
+            let (con, con_ctx) =
+                Connection::from_incoming(cnx, stream_id, bytes, length, event, settings);
 
-        ctx_locked.new_connection(con, con_ctx);
+            ctx_locked.new_connection(con, con_ctx);
+        }
     }
 
     mem::forget(ctx);
 }
 
+/// Selects the application layer protocol of an incoming connection from the `list` that the
+/// client offered. Returning `count` rejects all protocols.
+unsafe extern "C" fn select_alpn_callback(
+    quic: *mut picoquic_quic_t,
+    list: *mut ptls_iovec_t,
+    count: usize,
+) -> usize {
+    let ctx = picoquic_get_default_callback_context(quic);
+    assert!(!ctx.is_null());
+    let ctx = get_context(ctx);
+
+    let offered = slice::from_raw_parts(list, count)
+        .iter()
+        .map(|p| slice::from_raw_parts(p.base as *const u8, p.len))
+        .collect::<Vec<_>>();
+    let selected = select_alpn(&ctx.lock().unwrap().server_settings.alpn_protocols, &offered);
+
+    mem::forget(ctx);
+
+    selected.unwrap_or(count)
+}
+
 #[derive(Clone)]
 pub struct NewConnectionHandle {
     send: UnboundedSender<NewConnectionMsg>,
@@ -353,10 +1382,44 @@ impl NewConnectionHandle {
         &mut self,
         addr: SocketAddr,
         server_name: T,
+    ) -> NewConnectionFuture {
+        self.new_connection_with_config(addr, server_name, ConnectionConfig::default())
+    }
+
+    /// Creates a new connection to the given server and sends the given data on the first
+    /// bidirectional `Stream`, see `Connection::early_data_stream`.
+    /// If a session ticket of the server is known, the data is sent as 0-RTT data.
+    ///
+    /// addr - The address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// data - The early data.
+    pub fn new_connection_with_early_data<T: Into<String>>(
+        &mut self,
+        addr: SocketAddr,
+        server_name: T,
+        data: Bytes,
+    ) -> NewConnectionFuture {
+        let mut config = ConnectionConfig::default();
+        config.set_early_data(data);
+        self.new_connection_with_config(addr, server_name, config)
+    }
+
+    /// Creates a new connection to the given server, with the given `ConnectionConfig`.
+    ///
+    /// addr - The address of the server.
+    /// server_name - The name of the server that will be used by TLS to verify the certificate.
+    /// config - Overrides the defaults of the `Context` for this connection.
+    pub fn new_connection_with_config<T: Into<String>>(
+        &mut self,
+        addr: SocketAddr,
+        server_name: T,
+        config: ConnectionConfig,
     ) -> NewConnectionFuture {
         let (sender, recv) = oneshot::channel();
 
-        let _ = self.send.unbounded_send((addr, server_name.into(), sender));
+        let _ = self
+            .send
+            .unbounded_send((addr, server_name.into(), config, sender));
 
         NewConnectionFuture { recv }
     }
@@ -374,3 +1437,67 @@ impl Future for NewConnectionFuture {
         try_ready!(self.recv.poll()).map(Ready)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv6Addr, SocketAddrV6};
+
+    #[test]
+    fn socket_index_by_addr_prefers_exact_match() {
+        let addrs: Vec<SocketAddr> = vec![
+            ([0, 0, 0, 0], 4433).into(),
+            ([127, 0, 0, 1], 4433).into(),
+            ([0, 0, 0, 0, 0, 0, 0, 0], 4433).into(),
+        ];
+
+        assert_eq!(Some(1), socket_index_by_addr(&addrs, ([127, 0, 0, 1], 4433).into()));
+        assert_eq!(Some(0), socket_index_by_addr(&addrs, ([10, 0, 0, 1], 4433).into()));
+        assert_eq!(
+            Some(2),
+            socket_index_by_addr(&addrs, ([0, 0, 0, 0, 0, 0, 0, 1], 4433).into())
+        );
+        assert_eq!(None, socket_index_by_addr(&addrs, ([127, 0, 0, 1], 4434).into()));
+    }
+
+    #[test]
+    fn with_flow_label_keeps_traffic_class() {
+        let flowinfo = 0x0AB0_0001u32.to_be();
+        let addr = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 4433, flowinfo, 0));
+
+        match with_flow_label(addr, 0xF_1234) {
+            SocketAddr::V6(addr) => assert_eq!(0x0ABF_1234, u32::from_be(addr.flowinfo())),
+            _ => panic!("expected an IPv6 address"),
+        }
+
+        let addr = ([127, 0, 0, 1], 4433).into();
+        assert_eq!(addr, with_flow_label(addr, 0xF_1234));
+    }
+
+    #[test]
+    fn select_alpn_prefers_supported_order() {
+        let supported = vec![b"h3".to_vec(), b"echo".to_vec()];
+
+        let (h3, echo, other): (&[u8], &[u8], &[u8]) = (b"h3", b"echo", b"other");
+
+        assert_eq!(Some(1), select_alpn(&supported, &[echo, h3]));
+        assert_eq!(Some(0), select_alpn(&supported, &[echo, other]));
+        assert_eq!(None, select_alpn(&supported, &[other]));
+        assert_eq!(Some(0), select_alpn(&[], &[other]));
+        assert_eq!(None, select_alpn(&[], &[]));
+    }
+
+    #[test]
+    fn buffer_len_holds_max_udp_payload_size() {
+        let mut config = Config::new();
+        let default_len = buffer_len(&settings_from_config(&config).0);
+        assert_eq!(PICOQUIC_MAX_PACKET_SIZE as usize, default_len);
+
+        config.set_max_udp_payload_size(9000);
+        assert_eq!(9000, buffer_len(&settings_from_config(&config).0));
+
+        config.set_max_udp_payload_size(1200);
+        assert_eq!(default_len, buffer_len(&settings_from_config(&config).0));
+    }
+}
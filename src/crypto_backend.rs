@@ -0,0 +1,116 @@
+use error::*;
+
+use libc::{self, c_char, c_int, c_uint, c_void};
+
+use std::{ffi::CString, mem, ptr};
+
+/// The OpenSSL implementation that is used for the packet protection and the handshake crypto.
+///
+/// The OpenSSL configuration is global, so the backend is used by all `Context`s and every other
+/// user of OpenSSL in this process, once a `Context` was created with it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CryptoBackend {
+    /// The OpenSSL engine with the given id, e.g. `rdrand` or the engine of a HSM.
+    /// The engine becomes the default for all algorithms it implements.
+    Engine(String),
+    /// The OpenSSL(>= 3.0) provider with the given name. The algorithms of the provider are
+    /// preferred, all other algorithms are taken from the default provider.
+    Provider(String),
+}
+
+/// `ENGINE_METHOD_ALL` of `openssl/engine.h`.
+const ENGINE_METHOD_ALL: c_uint = 0xFFFF;
+
+#[allow(non_camel_case_types)]
+enum ENGINE {}
+
+extern "C" {
+    fn ENGINE_load_builtin_engines();
+    fn ENGINE_by_id(id: *const c_char) -> *mut ENGINE;
+    fn ENGINE_init(e: *mut ENGINE) -> c_int;
+    fn ENGINE_set_default(e: *mut ENGINE, flags: c_uint) -> c_int;
+    fn ENGINE_finish(e: *mut ENGINE) -> c_int;
+    fn ENGINE_free(e: *mut ENGINE) -> c_int;
+}
+
+type ProviderLoad = unsafe extern "C" fn(*mut c_void, *const c_char) -> *mut c_void;
+type SetDefaultProperties = unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int;
+
+/// Loads the given backend and makes it the default of OpenSSL.
+pub fn load(backend: &CryptoBackend) -> Result<(), Error> {
+    match *backend {
+        CryptoBackend::Engine(ref id) => load_engine(id),
+        CryptoBackend::Provider(ref name) => load_provider(name),
+    }
+}
+
+fn load_engine(id: &str) -> Result<(), Error> {
+    let c_id = CString::new(id).map_err(|_| ErrorKind::CryptoBackend(id.to_owned()))?;
+
+    unsafe {
+        ENGINE_load_builtin_engines();
+
+        let engine = ENGINE_by_id(c_id.as_ptr());
+        if engine.is_null() {
+            return Err(ErrorKind::CryptoBackend(id.to_owned()).into());
+        }
+
+        if ENGINE_init(engine) != 1 {
+            ENGINE_free(engine);
+            return Err(ErrorKind::CryptoBackend(id.to_owned()).into());
+        }
+
+        // The default registration holds its own reference to the engine.
+        let res = ENGINE_set_default(engine, ENGINE_METHOD_ALL);
+        ENGINE_finish(engine);
+        ENGINE_free(engine);
+
+        if res == 1 {
+            Ok(())
+        } else {
+            Err(ErrorKind::CryptoBackend(id.to_owned()).into())
+        }
+    }
+}
+
+/// Looks up the given OpenSSL function at runtime, as providers only exist since OpenSSL 3.0.
+fn lookup<T>(name: &'static [u8]) -> Option<T> {
+    let sym = unsafe { libc::dlsym(libc::RTLD_DEFAULT, name.as_ptr() as *const c_char) };
+
+    if sym.is_null() {
+        None
+    } else {
+        Some(unsafe { mem::transmute_copy(&sym) })
+    }
+}
+
+fn load_provider(name: &str) -> Result<(), Error> {
+    let c_name = CString::new(name).map_err(|_| ErrorKind::CryptoBackend(name.to_owned()))?;
+    let properties = CString::new(format!("?provider={}", name))
+        .map_err(|_| ErrorKind::CryptoBackend(name.to_owned()))?;
+
+    let provider_load = lookup::<ProviderLoad>(b"OSSL_PROVIDER_load\0");
+    let set_default_properties = lookup::<SetDefaultProperties>(b"EVP_set_default_properties\0");
+
+    let (provider_load, set_default_properties) = match (provider_load, set_default_properties) {
+        (Some(load), Some(set)) => (load, set),
+        _ => bail!("The linked OpenSSL does not support providers, version 3.0 is required"),
+    };
+
+    unsafe {
+        // The provider stays loaded for the lifetime of the process.
+        if provider_load(ptr::null_mut(), c_name.as_ptr()).is_null() {
+            return Err(ErrorKind::CryptoBackend(name.to_owned()).into());
+        }
+
+        // The default provider is loaded implicitly only as long as no other provider is loaded.
+        let default = CString::new("default").expect("valid C string");
+        if provider_load(ptr::null_mut(), default.as_ptr()).is_null()
+            || set_default_properties(ptr::null_mut(), properties.as_ptr()) != 1
+        {
+            return Err(ErrorKind::CryptoBackend(name.to_owned()).into());
+        }
+    }
+
+    Ok(())
+}
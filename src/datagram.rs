@@ -0,0 +1,123 @@
+use error::*;
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{
+    sync::mpsc::{UnboundedReceiver, UnboundedSender},
+    Async, AsyncSink, Poll, Sink, StartSend, Stream as FStream,
+};
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+/// The worst case overhead of a 1-RTT packet: the first byte, a connection id of 20 bytes, a
+/// packet number of 4 bytes and the AEAD tag of 16 bytes.
+const PACKET_OVERHEAD: usize = 1 + 20 + 4 + 16;
+/// The overhead of a DATAGRAM frame: the frame type and a length of up to 2 bytes.
+const FRAME_OVERHEAD: usize = 3;
+
+/// Returns the maximum payload of a datagram that fits into a single DATAGRAM frame and packet.
+/// `max_frame_size` is the maximum DATAGRAM frame size of the peer, `0` if the DATAGRAM
+/// extension was not negotiated.
+pub fn max_datagram_payload(max_frame_size: usize, mtu: usize) -> usize {
+    if max_frame_size == 0 {
+        return 0;
+    }
+
+    let by_frame = max_frame_size.saturating_sub(FRAME_OVERHEAD);
+    let by_packet = mtu.saturating_sub(PACKET_OVERHEAD + FRAME_OVERHEAD);
+
+    by_frame.min(by_packet)
+}
+
+/// Sends datagrams to the `Context` of a `Connection`.
+#[derive(Clone)]
+pub(crate) struct DatagramSender {
+    send: UnboundedSender<Bytes>,
+    /// The maximum datagram payload, updated by the `Context`.
+    max_size: Arc<AtomicUsize>,
+}
+
+impl DatagramSender {
+    pub fn new(send: UnboundedSender<Bytes>, max_size: Arc<AtomicUsize>) -> DatagramSender {
+        DatagramSender { send, max_size }
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size.load(Ordering::Relaxed)
+    }
+
+    pub fn send(&self, data: Bytes) -> Result<(), Error> {
+        match self.max_size() {
+            0 => Err(ErrorKind::DatagramsUnsupported.into()),
+            max if data.len() > max => Err(ErrorKind::DatagramTooLarge(data.len(), max).into()),
+            _ => self
+                .send
+                .unbounded_send(data)
+                .map_err(|_| ErrorKind::Disconnected.into()),
+        }
+    }
+}
+
+/// The unreliable datagrams of a `Connection`.
+/// This is a `Stream` of the received and a `Sink` for the sent datagrams, it is created by
+/// `Connection::incoming_datagrams`.
+///
+/// Datagrams are neither retransmitted nor ordered. A datagram that does not fit into the
+/// current `Connection::max_datagram_size` is rejected with `ErrorKind::DatagramTooLarge`.
+pub struct Datagrams {
+    sender: DatagramSender,
+    recv: UnboundedReceiver<BytesMut>,
+}
+
+impl Datagrams {
+    pub(crate) fn new(sender: DatagramSender, recv: UnboundedReceiver<BytesMut>) -> Datagrams {
+        Datagrams { sender, recv }
+    }
+
+    pub(crate) fn sender(&self) -> DatagramSender {
+        self.sender.clone()
+    }
+
+    /// Returns the maximum payload of a datagram, see `Connection::max_datagram_size`.
+    pub fn max_datagram_size(&self) -> usize {
+        self.sender.max_size()
+    }
+}
+
+impl FStream for Datagrams {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.recv.poll().map_err(|_| ErrorKind::Unknown.into())
+    }
+}
+
+impl Sink for Datagrams {
+    type SinkItem = Bytes;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.sender.send(item).map(|_| AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn datagram_payload_is_limited_by_frame_size_and_mtu() {
+        assert_eq!(0, max_datagram_payload(0, 1440));
+        assert_eq!(97, max_datagram_payload(100, 1440));
+        assert_eq!(1440 - 44, max_datagram_payload(65535, 1440));
+        assert_eq!(0, max_datagram_payload(65535, 20));
+    }
+}
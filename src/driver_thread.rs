@@ -0,0 +1,108 @@
+use futures::task::AtomicTask;
+
+use parking_lot::Mutex;
+
+use std::{
+    cell::RefCell,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, ThreadId},
+};
+
+thread_local! {
+    /// The `DriverThread` of the `Context` that is currently polled on this thread.
+    static CURRENT: RefCell<Option<Arc<DriverThread>>> = RefCell::new(None);
+}
+
+/// Tracks the thread the driver of a `Context` runs on.
+///
+/// Picoquic is not thread safe, so all calls into picoquic are done by the driver. A `Stream`
+/// that is used on the same thread as the driver, can call into picoquic directly, while the
+/// driver is not running. This saves the round trip through the channel to the driver.
+#[derive(Debug, Default)]
+pub struct DriverThread {
+    /// Is picoquic currently used, either by the driver or by a `Stream`?
+    busy: AtomicBool,
+    /// The thread the driver was polled on the last time.
+    thread: Mutex<Option<ThreadId>>,
+    /// The task of the driver.
+    task: AtomicTask,
+}
+
+impl DriverThread {
+    pub fn new() -> Arc<DriverThread> {
+        Arc::new(DriverThread::default())
+    }
+
+    /// Returns the `DriverThread` of the `Context` that is currently polled on this thread.
+    pub fn current() -> Option<Arc<DriverThread>> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Marks the driver as running on the current thread, until the returned `Guard` is dropped.
+    /// If a `Stream` currently calls into picoquic, this waits until the `Stream` is finished.
+    pub fn enter(this: &Arc<DriverThread>) -> Guard {
+        while !this.try_acquire() {
+            thread::yield_now();
+        }
+
+        *this.thread.lock() = Some(thread::current().id());
+        this.task.register();
+        let previous = CURRENT.with(|current| current.replace(Some(this.clone())));
+
+        Guard {
+            driver: this.clone(),
+            previous,
+        }
+    }
+
+    /// Runs the given function, if this is called on the thread of the driver and the driver is
+    /// not running. The driver is notified afterwards, to send the data to the peer.
+    ///
+    /// # Returns
+    /// None, if the function could not be run.
+    pub fn run<R, F: FnOnce() -> R>(&self, f: F) -> Option<R> {
+        if *self.thread.lock() != Some(thread::current().id()) || !self.try_acquire() {
+            return None;
+        }
+
+        let res = {
+            let _busy = Busy(&self.busy);
+            f()
+        };
+
+        self.task.notify();
+        Some(res)
+    }
+
+    fn try_acquire(&self) -> bool {
+        self.busy
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+/// Releases the `busy` flag, when dropped.
+struct Busy<'a>(&'a AtomicBool);
+
+impl<'a> Drop for Busy<'a> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Release);
+    }
+}
+
+/// Marks the driver as running, created by `DriverThread::enter`.
+pub struct Guard {
+    driver: Arc<DriverThread>,
+    previous: Option<Arc<DriverThread>>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+        self.driver.busy.store(false, Ordering::Release);
+    }
+}
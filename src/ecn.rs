@@ -0,0 +1,187 @@
+//! Explicit Congestion Notification(ECN) for UDP sockets.
+
+#[cfg(target_os = "linux")]
+use socket2::SockAddr;
+#[cfg(target_os = "linux")]
+use socket_options::set_option;
+
+#[cfg(target_os = "linux")]
+use std::{io, mem, net::SocketAddr, os::unix::io::RawFd, ptr};
+
+/// The ECN codepoint of a packet, the lowest two bits of the TOS/traffic class field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ecn {
+    /// The packet was sent by a transport that does not support ECN.
+    NotEct,
+    /// The packet was sent by an ECN capable transport, ECT(1).
+    Ect1,
+    /// The packet was sent by an ECN capable transport, ECT(0).
+    Ect0,
+    /// A router on the path experienced congestion.
+    Ce,
+}
+
+impl Ecn {
+    /// Returns the codepoint of the given TOS/traffic class.
+    pub fn from_traffic_class(traffic_class: u8) -> Ecn {
+        match traffic_class & 0b11 {
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            0b11 => Ecn::Ce,
+            _ => Ecn::NotEct,
+        }
+    }
+
+    /// Returns the given TOS/traffic class with this codepoint, the DSCP bits are kept.
+    pub fn apply_to_traffic_class(self, traffic_class: u8) -> u8 {
+        let bits = match self {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        };
+
+        (traffic_class & !0b11) | bits
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn get_option(fd: RawFd, level: libc::c_int, name: libc::c_int) -> io::Result<libc::c_int> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let res = libc::getsockopt(
+        fd,
+        level,
+        name,
+        &mut value as *mut libc::c_int as *mut libc::c_void,
+        &mut len,
+    );
+
+    if res == 0 {
+        Ok(value)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Marks all packets that are sent by the given socket as ECT(0) and enables receiving the
+/// codepoints of incoming packets. The DSCP bits that are already set for the socket are kept.
+#[cfg(target_os = "linux")]
+pub fn enable(fd: RawFd, is_ipv6: bool) -> io::Result<()> {
+    unsafe fn enable_for(
+        fd: RawFd,
+        level: libc::c_int,
+        tos: libc::c_int,
+        recv: libc::c_int,
+    ) -> io::Result<()> {
+        let traffic_class = get_option(fd, level, tos)? as u8;
+        let traffic_class = libc::c_int::from(Ecn::Ect0.apply_to_traffic_class(traffic_class));
+        set_option(fd, level, tos, &traffic_class)?;
+        set_option(fd, level, recv, &(1 as libc::c_int))
+    }
+
+    unsafe {
+        if is_ipv6 {
+            enable_for(fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, libc::IPV6_RECVTCLASS)?;
+            // Dual stack sockets also send and receive IPv4 packets, IPv6 only sockets reject
+            // the IPv4 options.
+            let _ = enable_for(fd, libc::IPPROTO_IP, libc::IP_TOS, libc::IP_RECVTOS);
+            Ok(())
+        } else {
+            enable_for(fd, libc::IPPROTO_IP, libc::IP_TOS, libc::IP_RECVTOS)
+        }
+    }
+}
+
+/// Receives a single packet from the given socket.
+/// Returns the number of bytes received, the address of the sender and the ECN codepoint of the
+/// packet. The codepoint is `Ecn::NotEct`, if receiving the codepoints is not enabled.
+#[cfg(target_os = "linux")]
+pub fn recv_from(fd: RawFd, buf: &mut [u8]) -> io::Result<(usize, SocketAddr, Ecn)> {
+    unsafe {
+        let mut addr: libc::sockaddr_storage = mem::zeroed();
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // `u64` aligns the buffer for the control messages.
+        let mut control = [0u64; 8];
+
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+
+        let len = libc::recvmsg(fd, &mut msg, 0);
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut ecn = Ecn::NotEct;
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = libc::CMSG_DATA(cmsg);
+
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                // `IP_TOS` carries a single byte, `IPV6_TCLASS` an integer.
+                (libc::IPPROTO_IP, libc::IP_TOS) => ecn = Ecn::from_traffic_class(*data),
+                (libc::IPPROTO_IPV6, libc::IPV6_TCLASS) => {
+                    let traffic_class = ptr::read_unaligned(data as *const libc::c_int);
+                    ecn = Ecn::from_traffic_class(traffic_class as u8);
+                }
+                _ => {}
+            }
+
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+
+        let addr = SockAddr::from_raw_parts(
+            &addr as *const libc::sockaddr_storage as *const libc::sockaddr,
+            msg.msg_namelen,
+        );
+        let addr = addr
+            .as_inet()
+            .map(SocketAddr::V4)
+            .or_else(|| addr.as_inet6().map(SocketAddr::V6))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "unknown address family"))?;
+
+        Ok((len as usize, addr, ecn))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codepoints_are_read_from_and_applied_to_traffic_class() {
+        assert_eq!(Ecn::NotEct, Ecn::from_traffic_class(0xb8));
+        assert_eq!(Ecn::Ect1, Ecn::from_traffic_class(0xb9));
+        assert_eq!(Ecn::Ect0, Ecn::from_traffic_class(0xba));
+        assert_eq!(Ecn::Ce, Ecn::from_traffic_class(0xbb));
+
+        assert_eq!(0xba, Ecn::Ect0.apply_to_traffic_class(0xb8));
+        assert_eq!(0xba, Ecn::Ect0.apply_to_traffic_class(0xbb));
+        assert_eq!(0x02, Ecn::Ect0.apply_to_traffic_class(0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn codepoint_is_received() {
+        use std::net::UdpSocket;
+        use std::os::unix::io::AsRawFd;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        enable(socket.as_raw_fd(), false).unwrap();
+        socket.send_to(b"hello", socket.local_addr().unwrap()).unwrap();
+
+        let mut buf = [0; 16];
+        let (len, addr, ecn) = recv_from(socket.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(&b"hello"[..], &buf[..len]);
+        assert_eq!(socket.local_addr().unwrap(), addr);
+        assert_eq!(Ecn::Ect0, ecn);
+    }
+}
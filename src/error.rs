@@ -1,4 +1,4 @@
-use std::{ffi, fmt, io};
+use std::{ffi, fmt, io, net::SocketAddr};
 
 pub use failure::ResultExt;
 use failure::{self, Backtrace, Context, Fail};
@@ -7,8 +7,11 @@ use bytes::Bytes;
 
 use futures;
 
+#[cfg(feature = "openssl")]
 use openssl;
 
+use version::QuicVersion;
+
 #[derive(Debug)]
 pub struct Error {
     inner: Context<ErrorKind>,
@@ -62,6 +65,7 @@ impl From<ffi::NulError> for Error {
     }
 }
 
+#[cfg(feature = "openssl")]
 impl From<openssl::error::ErrorStack> for Error {
     fn from(_: openssl::error::ErrorStack) -> Error {
         ErrorKind::OpenSSLError.into()
@@ -96,6 +100,8 @@ pub enum ErrorKind {
     SendError(Bytes),
     #[fail(display = "An error occurred in the TLS handshake.")]
     TLSHandshakeError,
+    #[fail(display = "An error occurred in the TLS handshake. {}", _0)]
+    TLSAlert(TlsAlert),
     #[fail(display = "An internal error occurred.")]
     InternalError,
     #[fail(display = "A string contains none unicode symbols.")]
@@ -106,6 +112,128 @@ pub enum ErrorKind {
     Custom(failure::Error),
     #[fail(display = "IO error {}", _0)]
     Io(io::Error),
+    #[fail(display = "The stream is closed for sending.")]
+    StreamClosed,
+    #[fail(display = "Picoquic failed to add data to the stream ({}).", _0)]
+    AddToStreamError(i32),
+    #[fail(display = "The stream is an incoming unidirectional stream and can not send data.")]
+    ReceiveOnlyStream,
+    #[fail(display = "The stream was reset by the peer with error code {}.", _0)]
+    StreamReset(u64),
+    #[fail(display = "The peer asked to stop sending on the stream with error code {}.", _0)]
+    StopSending(u64),
+    #[fail(display = "The `Context` has no socket bound to the local address {}.", _0)]
+    UnknownLocalAddress(SocketAddr),
+    #[fail(display = "Could not load the OpenSSL crypto backend `{}`.", _0)]
+    CryptoBackend(String),
+    #[fail(display = "The connection was closed, because it was idle for too long.")]
+    IdleTimeout,
+    #[fail(display = "The peer does not support the DATAGRAM extension.")]
+    DatagramsUnsupported,
+    #[fail(display = "The datagram of {} bytes exceeds the maximum datagram size of {}.", _0, _1)]
+    DatagramTooLarge(usize, usize),
+    #[fail(display = "Only the client can migrate a connection.")]
+    MigrationNotAllowed,
+    #[fail(display = "Picoquic failed to probe the new path ({}).", _0)]
+    MigrationFailed(i32),
+    #[fail(display = "Picoquic implements none of the QUIC versions {:?}.", _0)]
+    UnsupportedVersions(Vec<QuicVersion>),
+    #[fail(display = "The server offered none of the supported QUIC versions, only {:?}.", _0)]
+    VersionNegotiationFailed(Vec<QuicVersion>),
+    #[fail(display = "The connection was closed with the transport error {:#x}.", code)]
+    TransportError {
+        /// The QUIC transport error code, e.g. `0x3` for `FLOW_CONTROL_ERROR`.
+        code: u64,
+        /// The type of the frame that triggered the error, if it is known.
+        frame_type: Option<u64>,
+        /// The reason phrase of the `CONNECTION_CLOSE`, if it is known.
+        reason: Option<String>,
+    },
+    #[fail(display = "The peer closed the connection with the application error {}.", code)]
+    ApplicationError {
+        /// The error code of the application protocol.
+        code: u64,
+        /// The reason phrase of the `CONNECTION_CLOSE`, if it is known.
+        reason: Option<String>,
+    },
+    #[fail(display = "The handshake did not finish before the idle timeout.")]
+    HandshakeTimeout,
+    #[fail(display = "The WebTransport session was closed with the error code {}.", code)]
+    SessionClosed {
+        /// The application error code of the `CLOSE_WEBTRANSPORT_SESSION` capsule, `0` if the
+        /// session was closed without a capsule.
+        code: u32,
+        /// The reason phrase of the `CLOSE_WEBTRANSPORT_SESSION` capsule.
+        reason: String,
+    },
+}
+
+/// The base of the QUIC error codes that carry a TLS alert.
+const CRYPTO_ERROR_BASE: u16 = 0x100;
+
+/// A TLS alert that failed the handshake of a `Connection`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TlsAlert {
+    /// The alert code, as defined in RFC 8446, e.g. `42` for `bad_certificate`.
+    pub code: u8,
+    /// Was the alert sent by the local side? Otherwise, it was received from the peer.
+    pub sent: bool,
+}
+
+impl TlsAlert {
+    /// Extracts the TLS alert from the given QUIC error code.
+    /// Returns `None`, if the error code does not carry a TLS alert.
+    pub(crate) fn from_error_code(error_code: u16, sent: bool) -> Option<TlsAlert> {
+        if error_code & 0xff00 == CRYPTO_ERROR_BASE {
+            Some(TlsAlert {
+                code: (error_code & 0xff) as u8,
+                sent,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the name of the alert, as defined in RFC 8446.
+    pub fn description(&self) -> &'static str {
+        match self.code {
+            0 => "close_notify",
+            10 => "unexpected_message",
+            20 => "bad_record_mac",
+            22 => "record_overflow",
+            40 => "handshake_failure",
+            42 => "bad_certificate",
+            43 => "unsupported_certificate",
+            44 => "certificate_revoked",
+            45 => "certificate_expired",
+            46 => "certificate_unknown",
+            47 => "illegal_parameter",
+            48 => "unknown_ca",
+            49 => "access_denied",
+            50 => "decode_error",
+            51 => "decrypt_error",
+            70 => "protocol_version",
+            71 => "insufficient_security",
+            80 => "internal_error",
+            86 => "inappropriate_fallback",
+            90 => "user_canceled",
+            109 => "missing_extension",
+            110 => "unsupported_extension",
+            112 => "unrecognized_name",
+            113 => "bad_certificate_status_response",
+            115 => "unknown_psk_identity",
+            116 => "certificate_required",
+            120 => "no_application_protocol",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for TlsAlert {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let direction = if self.sent { "Sent" } else { "Received" };
+        write!(f, "{} TLS alert {} ({}).", direction, self.code, self.description())
+    }
 }
 
 //FIXME: Remove when upstream provides a better bail macro
@@ -121,3 +249,23 @@ macro_rules! bail {
 /// A function that returns the an error.
 pub trait ErrorFn: Send + 'static + Sync + Fn() -> Error {}
 impl<T: Fn() -> Error + Send + 'static + Sync> ErrorFn for T {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tls_alert_is_extracted_from_crypto_error() {
+        let alert = TlsAlert::from_error_code(0x12a, false).expect("carries an alert");
+
+        assert_eq!(42, alert.code);
+        assert_eq!("bad_certificate", alert.description());
+        assert_eq!("Received TLS alert 42 (bad_certificate).", alert.to_string());
+    }
+
+    #[test]
+    fn other_errors_carry_no_tls_alert() {
+        assert_eq!(None, TlsAlert::from_error_code(0x0a, true));
+        assert_eq!(None, TlsAlert::from_error_code(0x201, true));
+    }
+}
@@ -0,0 +1,214 @@
+use connection::Event;
+
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// The share of the idle timeout after which an `Event::IdleTimeoutWarning` is reported.
+const IDLE_WARNING_NUMERATOR: u32 = 3;
+const IDLE_WARNING_DENOMINATOR: u32 = 4;
+
+/// The transport state of a connection, that the `TransportEventMonitor` observes.
+pub struct TransportState<'a> {
+    /// The peer addresses of all paths, the primary path first.
+    pub paths: &'a [SocketAddr],
+    /// The key phase of the outgoing 1-RTT packets.
+    pub key_phase: bool,
+    /// The number of bytes that were sent on all streams.
+    pub data_sent: u64,
+    /// The flow control limit of the connection, granted by the peer.
+    pub max_data: u64,
+    /// The total number of received packets.
+    pub packets_received: u64,
+    /// The idle timeout that applies to the connection.
+    pub idle_timeout: Option<Duration>,
+}
+
+/// Derives the `Event`s of a connection from the changes of its transport state.
+///
+/// Picoquic does not report these transport events to us, so the state of the connection is
+/// compared at each poll of its `Context`.
+pub struct TransportEventMonitor {
+    paths: usize,
+    key_phase: bool,
+    /// Was a key update initiated by us, that is not reported yet?
+    local_key_update: bool,
+    /// The flow control limit that was reported as blocking.
+    data_blocked_at: Option<u64>,
+    packets_received: u64,
+    /// The time the last packet was received.
+    last_activity: Instant,
+    idle_warned: bool,
+}
+
+impl TransportEventMonitor {
+    pub fn new(now: Instant) -> TransportEventMonitor {
+        TransportEventMonitor {
+            paths: 1,
+            key_phase: false,
+            local_key_update: false,
+            data_blocked_at: None,
+            packets_received: 0,
+            last_activity: now,
+            idle_warned: false,
+        }
+    }
+
+    /// Needs to be called, after we initiated a key update. All other key updates are initiated
+    /// by the peer.
+    pub fn on_local_key_update(&mut self) {
+        self.local_key_update = true;
+    }
+
+    /// Returns the events that happened since the last poll.
+    pub fn poll(&mut self, state: &TransportState, now: Instant) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        // Picoquic validates each new path with a `PATH_CHALLENGE`.
+        for peer_addr in state.paths.iter().skip(self.paths) {
+            events.push(Event::PathChallenge {
+                peer_addr: *peer_addr,
+            });
+        }
+        self.paths = state.paths.len().max(1);
+
+        if state.key_phase != self.key_phase {
+            self.key_phase = state.key_phase;
+            events.push(Event::KeyUpdate {
+                initiated_by_peer: !self.local_key_update,
+            });
+            self.local_key_update = false;
+        }
+
+        if state.data_sent >= state.max_data {
+            if self.data_blocked_at != Some(state.max_data) {
+                self.data_blocked_at = Some(state.max_data);
+                events.push(Event::DataBlocked {
+                    limit: state.max_data,
+                });
+            }
+        } else {
+            self.data_blocked_at = None;
+        }
+
+        if state.packets_received != self.packets_received {
+            self.packets_received = state.packets_received;
+            self.last_activity = now;
+            self.idle_warned = false;
+        } else if let Some(timeout) = state.idle_timeout {
+            let idle = now.duration_since(self.last_activity);
+
+            if !self.idle_warned
+                && idle >= timeout * IDLE_WARNING_NUMERATOR / IDLE_WARNING_DENOMINATOR
+            {
+                self.idle_warned = true;
+                events.push(Event::IdleTimeoutWarning {
+                    remaining: timeout.checked_sub(idle).unwrap_or_default(),
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(paths: &[SocketAddr]) -> TransportState {
+        TransportState {
+            paths,
+            key_phase: false,
+            data_sent: 0,
+            max_data: 1000,
+            packets_received: 1,
+            idle_timeout: None,
+        }
+    }
+
+    #[test]
+    fn new_paths_are_reported() {
+        let now = Instant::now();
+        let mut monitor = TransportEventMonitor::new(now);
+        let paths: [SocketAddr; 2] = [([127, 0, 0, 1], 1).into(), ([127, 0, 0, 1], 2).into()];
+
+        assert!(monitor.poll(&state(&paths[..1]), now).is_empty());
+        assert_eq!(
+            vec![Event::PathChallenge {
+                peer_addr: paths[1]
+            }],
+            monitor.poll(&state(&paths), now)
+        );
+        assert!(monitor.poll(&state(&paths), now).is_empty());
+    }
+
+    #[test]
+    fn key_updates_are_reported() {
+        let now = Instant::now();
+        let mut monitor = TransportEventMonitor::new(now);
+        let paths: [SocketAddr; 1] = [([127, 0, 0, 1], 1).into()];
+        let mut state = state(&paths);
+
+        assert!(monitor.poll(&state, now).is_empty());
+        state.key_phase = true;
+        assert_eq!(
+            vec![Event::KeyUpdate {
+                initiated_by_peer: true
+            }],
+            monitor.poll(&state, now)
+        );
+
+        monitor.on_local_key_update();
+        state.key_phase = false;
+        assert_eq!(
+            vec![Event::KeyUpdate {
+                initiated_by_peer: false
+            }],
+            monitor.poll(&state, now)
+        );
+        assert!(monitor.poll(&state, now).is_empty());
+    }
+
+    #[test]
+    fn data_blocked_is_reported_once_per_limit() {
+        let now = Instant::now();
+        let mut monitor = TransportEventMonitor::new(now);
+        let paths: [SocketAddr; 1] = [([127, 0, 0, 1], 1).into()];
+        let mut state = state(&paths);
+
+        state.data_sent = 1000;
+        assert_eq!(vec![Event::DataBlocked { limit: 1000 }], monitor.poll(&state, now));
+        assert!(monitor.poll(&state, now).is_empty());
+
+        state.max_data = 2000;
+        assert!(monitor.poll(&state, now).is_empty());
+        state.data_sent = 2000;
+        assert_eq!(vec![Event::DataBlocked { limit: 2000 }], monitor.poll(&state, now));
+    }
+
+    #[test]
+    fn idle_timeout_warning_is_reported_once_per_idle_period() {
+        let now = Instant::now();
+        let mut monitor = TransportEventMonitor::new(now);
+        let paths: [SocketAddr; 1] = [([127, 0, 0, 1], 1).into()];
+        let mut state = state(&paths);
+        state.idle_timeout = Some(Duration::from_secs(4));
+
+        assert!(monitor.poll(&state, now).is_empty());
+        assert!(monitor.poll(&state, now + Duration::from_secs(2)).is_empty());
+        assert_eq!(
+            vec![Event::IdleTimeoutWarning {
+                remaining: Duration::from_secs(1)
+            }],
+            monitor.poll(&state, now + Duration::from_secs(3))
+        );
+        assert!(monitor.poll(&state, now + Duration::from_secs(4)).is_empty());
+
+        // A received packet starts a new idle period.
+        state.packets_received = 2;
+        assert!(monitor.poll(&state, now + Duration::from_secs(5)).is_empty());
+        assert_eq!(1, monitor.poll(&state, now + Duration::from_secs(8)).len());
+    }
+}
@@ -0,0 +1,373 @@
+use super::quic_ctx::MicroSeconds;
+use config::CongestionAlgorithm;
+use congestion::{CongestionController, NewCongestionController, PathInfo};
+
+use picoquic_sys::picoquic::{
+    picoquic_bbr_algorithm, picoquic_cnx_t, picoquic_congestion_algorithm_t,
+    picoquic_congestion_notification_t,
+    picoquic_congestion_notification_t_picoquic_congestion_notification_acknowledgement,
+    picoquic_congestion_notification_t_picoquic_congestion_notification_repeat,
+    picoquic_congestion_notification_t_picoquic_congestion_notification_rtt_measurement,
+    picoquic_congestion_notification_t_picoquic_congestion_notification_timeout,
+    picoquic_cubic_algorithm, picoquic_newreno_algorithm, picoquic_path_t,
+    picoquic_update_pacing_rate,
+};
+
+use std::{
+    collections::HashMap,
+    os::raw::c_char,
+    ptr,
+    sync::{Arc, Mutex, Once},
+    time::Duration,
+};
+
+/// The id of the `CustomAlgorithm`, picoquic uses it in the logs.
+const CUSTOM_ALGORITHM_ID: &[u8] = b"rust\0";
+/// The number of the `CustomAlgorithm`, that is not used by any builtin algorithm.
+const CUSTOM_ALGORITHM_NUMBER: u8 = 0xff;
+
+/// Returns the picoquic implementation of the given algorithm, that records the one-way delay
+/// samples, see `observed`.
+pub fn algorithm(algorithm: CongestionAlgorithm) -> *mut picoquic_congestion_algorithm_t {
+    let builtin = unsafe {
+        match algorithm {
+            CongestionAlgorithm::NewReno => picoquic_newreno_algorithm,
+            CongestionAlgorithm::Cubic => picoquic_cubic_algorithm,
+            CongestionAlgorithm::Bbr => picoquic_bbr_algorithm,
+        }
+    };
+
+    observed(builtin)
+}
+
+/// The latest one-way delay samples of the paths, identified by the address of the path.
+///
+/// Picoquic measures the samples with the time stamps of the peer, see
+/// `Config::enable_time_stamps`, and passes them along with the round trip time samples.
+#[derive(Default)]
+struct OneWayDelays(Mutex<HashMap<usize, u64>>);
+
+impl OneWayDelays {
+    fn on_sample(&self, path: *mut picoquic_path_t, delay: u64) {
+        self.0.lock().unwrap().insert(path as usize, delay);
+    }
+
+    fn get(&self, path: *mut picoquic_path_t) -> Option<Duration> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&(path as usize))
+            .map(|d| Duration::from_micro_seconds(*d))
+    }
+
+    fn remove(&self, path: *mut picoquic_path_t) {
+        self.0.lock().unwrap().remove(&(path as usize));
+    }
+}
+
+/// A builtin algorithm of picoquic, that records the one-way delay samples, before it passes the
+/// notifications on to the builtin algorithm.
+///
+/// The `vtable` is a copy of the builtin one, with the same id and number, only the
+/// notifications and the cleanup of a path go through this algorithm first.
+#[repr(C)]
+struct ObservedAlgorithm {
+    vtable: picoquic_congestion_algorithm_t,
+    builtin: *mut picoquic_congestion_algorithm_t,
+    one_way_delays: OneWayDelays,
+}
+
+static OBSERVED_ALGORITHMS_INIT: Once = Once::new();
+/// The `ObservedAlgorithm`s of the builtin algorithms, picoquic references them as long as
+/// connections use them, so they are never freed.
+static mut OBSERVED_ALGORITHMS: [*mut ObservedAlgorithm; 3] = [ptr::null_mut(); 3];
+
+impl ObservedAlgorithm {
+    unsafe fn create(
+        builtin: *mut picoquic_congestion_algorithm_t,
+        delete: unsafe extern "C" fn(*mut picoquic_path_t),
+    ) -> *mut ObservedAlgorithm {
+        Box::into_raw(Box::new(ObservedAlgorithm {
+            vtable: picoquic_congestion_algorithm_t {
+                alg_notify: Some(observed_notify),
+                alg_delete: Some(delete),
+                ..*builtin
+            },
+            builtin,
+            one_way_delays: OneWayDelays::default(),
+        }))
+    }
+}
+
+/// Returns the given builtin algorithm, wrapped to record the one-way delay samples of the paths.
+/// Other algorithms are returned as they are.
+pub fn observed(
+    algorithm: *mut picoquic_congestion_algorithm_t,
+) -> *mut picoquic_congestion_algorithm_t {
+    unsafe {
+        OBSERVED_ALGORITHMS_INIT.call_once(|| {
+            OBSERVED_ALGORITHMS = [
+                ObservedAlgorithm::create(picoquic_newreno_algorithm, observed_delete::<NewReno>),
+                ObservedAlgorithm::create(picoquic_cubic_algorithm, observed_delete::<Cubic>),
+                ObservedAlgorithm::create(picoquic_bbr_algorithm, observed_delete::<Bbr>),
+            ];
+        });
+
+        OBSERVED_ALGORITHMS
+            .iter()
+            .find(|o| (***o).builtin == algorithm)
+            .map(|o| *o as *mut picoquic_congestion_algorithm_t)
+            .unwrap_or(algorithm)
+    }
+}
+
+unsafe extern "C" fn observed_notify(
+    cnx: *mut picoquic_cnx_t,
+    path: *mut picoquic_path_t,
+    notification: picoquic_congestion_notification_t,
+    rtt_measurement: u64,
+    one_way_delay: u64,
+    acked_bytes: u64,
+    lost_packet_number: u64,
+    current_time: u64,
+) {
+    let alg = &*((*cnx).congestion_alg as *const ObservedAlgorithm);
+
+    match notification {
+        picoquic_congestion_notification_t_picoquic_congestion_notification_rtt_measurement
+            if one_way_delay > 0 =>
+        {
+            alg.one_way_delays.on_sample(path, one_way_delay)
+        }
+        _ => {}
+    }
+
+    if let Some(notify) = (*alg.builtin).alg_notify {
+        notify(
+            cnx,
+            path,
+            notification,
+            rtt_measurement,
+            one_way_delay,
+            acked_bytes,
+            lost_packet_number,
+            current_time,
+        );
+    }
+}
+
+/// The index of a builtin algorithm in `OBSERVED_ALGORITHMS`. The cleanup of a path does not know
+/// the connection, so each `ObservedAlgorithm` needs its own function.
+trait Builtin {
+    const INDEX: usize;
+}
+
+enum NewReno {}
+enum Cubic {}
+enum Bbr {}
+
+impl Builtin for NewReno {
+    const INDEX: usize = 0;
+}
+
+impl Builtin for Cubic {
+    const INDEX: usize = 1;
+}
+
+impl Builtin for Bbr {
+    const INDEX: usize = 2;
+}
+
+unsafe extern "C" fn observed_delete<B: Builtin>(path: *mut picoquic_path_t) {
+    let alg = &*OBSERVED_ALGORITHMS[B::INDEX];
+    alg.one_way_delays.remove(path);
+
+    if let Some(delete) = (*alg.builtin).alg_delete {
+        delete(path);
+    }
+}
+
+/// Returns the latest one-way delay sample of the given path, if the algorithm of the connection
+/// records them.
+pub unsafe fn one_way_delay(
+    cnx: *mut picoquic_cnx_t,
+    path: *mut picoquic_path_t,
+) -> Option<Duration> {
+    if custom_algorithm(cnx).is_some() {
+        let state = (*path).congestion_alg_state as *const PathState;
+        return if state.is_null() {
+            None
+        } else {
+            (*state).one_way_delay
+        };
+    }
+
+    let alg = (*cnx).congestion_alg;
+
+    if alg.is_null() || (*alg).alg_notify != Some(observed_notify) {
+        None
+    } else {
+        (*(alg as *const ObservedAlgorithm)).one_way_delays.get(path)
+    }
+}
+
+/// A picoquic congestion algorithm, that runs the `CongestionController`s of a
+/// `NewCongestionController`.
+///
+/// Picoquic only knows the pointer to the `vtable`, so it needs to be the first field. The
+/// callbacks cast the algorithm of the connection back to the `CustomAlgorithm`.
+#[repr(C)]
+pub struct CustomAlgorithm {
+    vtable: picoquic_congestion_algorithm_t,
+    new_controller: Arc<dyn NewCongestionController>,
+}
+
+impl CustomAlgorithm {
+    pub fn new(new_controller: Arc<dyn NewCongestionController>) -> Box<CustomAlgorithm> {
+        Box::new(CustomAlgorithm {
+            vtable: picoquic_congestion_algorithm_t {
+                congestion_algorithm_id: CUSTOM_ALGORITHM_ID.as_ptr() as *const c_char,
+                congestion_algorithm_number: CUSTOM_ALGORITHM_NUMBER,
+                alg_init: Some(init),
+                alg_notify: Some(notify),
+                alg_delete: Some(delete),
+                alg_observe: None,
+            },
+            new_controller,
+        })
+    }
+
+    pub fn as_ptr(&self) -> *mut picoquic_congestion_algorithm_t {
+        &self.vtable as *const _ as *mut _
+    }
+}
+
+/// Returns the `CustomAlgorithm` of the given connection, if it uses one.
+unsafe fn custom_algorithm<'a>(cnx: *mut picoquic_cnx_t) -> Option<&'a CustomAlgorithm> {
+    let alg = (*cnx).congestion_alg;
+
+    if alg.is_null() || (*alg).congestion_algorithm_number != CUSTOM_ALGORITHM_NUMBER {
+        None
+    } else {
+        Some(&*(alg as *const CustomAlgorithm))
+    }
+}
+
+/// The state of a path, that is controlled by a `CustomAlgorithm`.
+struct PathState {
+    controller: Box<dyn CongestionController>,
+    /// The latest one-way delay sample.
+    one_way_delay: Option<Duration>,
+}
+
+/// Returns the `PathState` of the given path, the controller is created on first use.
+unsafe fn path_state<'a>(alg: &CustomAlgorithm, path: *mut picoquic_path_t) -> &'a mut PathState {
+    if (*path).congestion_alg_state.is_null() {
+        let state = Box::new(PathState {
+            controller: alg.new_controller.new_controller(),
+            one_way_delay: None,
+        });
+        (*path).congestion_alg_state = Box::into_raw(state) as *mut _;
+    }
+
+    &mut *((*path).congestion_alg_state as *mut PathState)
+}
+
+unsafe fn path_info(path: *mut picoquic_path_t) -> PathInfo {
+    PathInfo {
+        smoothed_rtt: Duration::from_micro_seconds((*path).smoothed_rtt),
+        min_rtt: Duration::from_micro_seconds((*path).rtt_min),
+        bytes_in_flight: (*path).bytes_in_transit as u64,
+        mtu: (*path).send_mtu as usize,
+    }
+}
+
+/// Applies the congestion window and the pacing rate of the controller to the path.
+unsafe fn apply(
+    cnx: *mut picoquic_cnx_t,
+    path: *mut picoquic_path_t,
+    controller: &dyn CongestionController,
+) {
+    (*path).cwin = controller.congestion_window() as _;
+
+    if let Some(rate) = controller.pacing_rate() {
+        // Allow bursts of a few packets.
+        let quantum = 4 * (*path).send_mtu as u64;
+        picoquic_update_pacing_rate(cnx, path, rate as f64, quantum);
+    }
+}
+
+/// Notifies the controller of the primary path, that a packet was sent.
+pub unsafe fn packet_sent(cnx: *mut picoquic_cnx_t, bytes: usize) {
+    let alg = match custom_algorithm(cnx) {
+        Some(alg) => alg,
+        None => return,
+    };
+
+    if (*cnx).nb_paths <= 0 {
+        return;
+    }
+
+    let path = *(*cnx).path;
+    let controller = &mut path_state(alg, path).controller;
+    controller.on_packet_sent(bytes, &path_info(path));
+    apply(cnx, path, &**controller);
+}
+
+unsafe extern "C" fn init(path: *mut picoquic_path_t, _: u64) {
+    // The controller is created by the first notification, as only that knows the connection.
+    (*path).congestion_alg_state = ptr::null_mut();
+}
+
+unsafe extern "C" fn notify(
+    cnx: *mut picoquic_cnx_t,
+    path: *mut picoquic_path_t,
+    notification: picoquic_congestion_notification_t,
+    rtt_measurement: u64,
+    one_way_delay: u64,
+    acked_bytes: u64,
+    lost_packet_number: u64,
+    _: u64,
+) {
+    let alg = match custom_algorithm(cnx) {
+        Some(alg) => alg,
+        None => return,
+    };
+
+    let state = path_state(alg, path);
+    let controller = &mut state.controller;
+    let info = path_info(path);
+
+    match notification {
+        picoquic_congestion_notification_t_picoquic_congestion_notification_acknowledgement => {
+            controller.on_ack(acked_bytes, &info)
+        }
+        picoquic_congestion_notification_t_picoquic_congestion_notification_repeat => {
+            controller.on_loss(lost_packet_number, false, &info)
+        }
+        picoquic_congestion_notification_t_picoquic_congestion_notification_timeout => {
+            controller.on_loss(lost_packet_number, true, &info)
+        }
+        picoquic_congestion_notification_t_picoquic_congestion_notification_rtt_measurement => {
+            controller.on_rtt_sample(Duration::from_micro_seconds(rtt_measurement), &info);
+
+            if one_way_delay > 0 {
+                let delay = Duration::from_micro_seconds(one_way_delay);
+                state.one_way_delay = Some(delay);
+                controller.on_one_way_delay_sample(delay, &info);
+            }
+        }
+        _ => {}
+    }
+
+    apply(cnx, path, &**controller);
+}
+
+unsafe extern "C" fn delete(path: *mut picoquic_path_t) {
+    let state = (*path).congestion_alg_state;
+
+    if !state.is_null() {
+        drop(Box::from_raw(state as *mut PathState));
+        (*path).congestion_alg_state = ptr::null_mut();
+    }
+}
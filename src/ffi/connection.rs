@@ -1,42 +1,67 @@
 use super::{
-    quic_ctx::{socket_addr_from_c, MicroSeconds, QuicCtx},
+    congestion,
+    quic_ctx::{implemented_versions, socket_addr_from_c, MicroSeconds, QuicCtx},
     Pointer,
 };
-use connection;
+use config::{CongestionAlgorithm, TransportParameterOverrides};
+use connection::{self, ConnectionIds, TlsInfo, TransportParameters};
 use error::*;
+use stats::{ConnectionStats, PacketNumberSpaceStats, PathStats};
 use stream;
 use ConnectionType;
 
 use picoquic_sys::picoquic::{
-    self, picoquic_close, picoquic_cnx_t, picoquic_create_client_cnx, picoquic_delete_cnx,
-    picoquic_enable_keep_alive, picoquic_get_cnx_state, picoquic_get_first_cnx,
-    picoquic_get_local_addr, picoquic_get_local_cnxid, picoquic_get_local_error,
-    picoquic_get_next_cnx, picoquic_get_peer_addr, picoquic_get_remote_error, picoquic_is_client,
-    picoquic_is_handshake_error, picoquic_prepare_packet, picoquic_quic_t,
-    picoquic_state_enum_picoquic_state_client_ready,picoquic_state_enum_picoquic_state_closing,
+    self, picoquic_add_proposed_alpn, picoquic_close, picoquic_cnx_t, picoquic_connection_error,
+    picoquic_create_cnx, picoquic_current_time, picoquic_delete_cnx, picoquic_enable_keep_alive,
+    picoquic_find_stream, picoquic_get_application_error, picoquic_get_cnx_state,
+    picoquic_get_first_cnx, picoquic_get_local_addr, picoquic_connection_id_t,
+    picoquic_get_initial_cnxid, picoquic_get_local_cnxid, picoquic_get_local_error,
+    picoquic_get_remote_cnxid, picoquic_get_next_cnx, picoquic_get_peer_addr, picoquic_get_quic_ctx,
+    picoquic_get_remote_error, picoquic_get_remote_stream_error, picoquic_get_ticket,
+    picoquic_is_client, picoquic_is_handshake_error, picoquic_null_connection_id,
+    picoquic_open_flow_control, picoquic_prepare_packet, picoquic_probe_new_path,
+    picoquic_queue_datagram_frame, picoquic_queue_misc_frame, picoquic_quic_t, picoquic_rs_get_tls,
+    picoquic_set_congestion_algorithm, picoquic_start_client_cnx, picoquic_start_key_rotation,
+    picoquic_state_enum_picoquic_state_client_ready, picoquic_state_enum_picoquic_state_closing,
     picoquic_state_enum_picoquic_state_disconnected,
-    picoquic_state_enum_picoquic_state_server_ready, picoquic_val64_connection_id,
-    PICOQUIC_ERROR_DISCONNECTED,
+    picoquic_state_enum_picoquic_state_server_ready, picoquic_tls_get_negotiated_alpn,
+    picoquic_tls_get_sni, picoquic_val64_connection_id, ptls_get_cipher, ptls_is_psk_handshake,
+    PICOQUIC_ERROR_DISCONNECTED, PICOQUIC_ERROR_IDLE_TIMEOUT,
+    PICOQUIC_TRANSPORT_INTERNAL_ERROR, PICOQUIC_TRANSPORT_SERVER_BUSY,
 };
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::net::SocketAddr;
 use std::ptr;
+use std::slice;
 use std::time::Duration;
 
 use socket2::SockAddr;
 
+/// The only TLS version picotls supports and QUIC uses.
+const TLS_VERSION_1_3: u16 = 0x0304;
+
+/// A reserved QUIC version, that is used to force a version negotiation.
+const GREASED_VERSION: u32 = 0x1a2a_3a4a;
+
+/// The frame type of the `PING` frame, the frame consists only of its type.
+const PING_FRAME_TYPE: u8 = 0x01;
+
 #[derive(Copy, Clone)]
 pub struct Connection {
     cnx: Pointer<picoquic_cnx_t>,
 }
 
 impl Connection {
+    /// Creates a new outgoing connection, that needs to be started with `start_client`.
     pub fn new(
         quic: &QuicCtx,
         server_addr: SocketAddr,
         current_time: u64,
         server_name: String,
+        alpn_protocols: &[Vec<u8>],
+        grease_version: bool,
+        version: Option<u32>,
     ) -> Result<Connection, Error> {
         assert!(
             !server_addr.ip().is_unspecified(),
@@ -46,17 +71,29 @@ impl Connection {
         let server_addr = SockAddr::from(server_addr);
 
         let server_name = CString::new(server_name)?;
+        let alpn_protocols = alpn_protocols
+            .iter()
+            .map(|p| CString::new(p.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // `0` selects the default version of picoquic
+        let version = if grease_version {
+            GREASED_VERSION
+        } else {
+            version.unwrap_or(0)
+        };
 
         let cnx = unsafe {
-            picoquic_create_client_cnx(
+            picoquic_create_cnx(
                 quic.as_ptr(),
+                picoquic_null_connection_id,
+                picoquic_null_connection_id,
                 server_addr.as_ptr() as *mut picoquic::sockaddr,
                 current_time,
-                0,
+                version,
                 server_name.as_c_str().as_ptr(),
-                ptr::null_mut(),
-                None,
-                ptr::null_mut(),
+                alpn_protocols.first().map(|a| a.as_ptr()).unwrap_or_else(ptr::null),
+                1,
             )
         };
 
@@ -64,9 +101,39 @@ impl Connection {
             Err(ErrorKind::Unknown)?;
         }
 
+        // All protocols need to be proposed, before the client hello is created.
+        let res = unsafe {
+            alpn_protocols
+                .iter()
+                .map(|a| picoquic_add_proposed_alpn((*cnx).tls_ctx, a.as_ptr()))
+                .find(|res| *res != 0)
+                .unwrap_or(0)
+        };
+
+        if res != 0 {
+            unsafe {
+                picoquic_delete_cnx(cnx);
+            }
+            Err(ErrorKind::Unknown)?;
+        }
+
         Ok(Connection { cnx: Pointer(cnx) })
     }
 
+    /// Starts the outgoing connection, by creating the client hello. Everything that is
+    /// advertised in the transport parameters needs to be set before, later changes are not
+    /// sent to the server. The connection is deleted, if it could not be started.
+    pub fn start_client(self) -> Result<(), Error> {
+        unsafe {
+            if picoquic_start_client_cnx(self.as_ptr()) != 0 {
+                picoquic_delete_cnx(self.as_ptr());
+                Err(ErrorKind::Unknown)?;
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn as_ptr(self) -> *mut picoquic_cnx_t {
         *self.cnx
     }
@@ -95,20 +162,41 @@ impl Connection {
         }
     }
 
+    /// Returns the local address of the primary path, if picoquic knows it. Picoquic does not
+    /// know the local address of an outgoing connection, before it migrated.
+    pub fn path_local_addr(self) -> Option<SocketAddr> {
+        let mut addr_len = 0;
+        let mut addr: *mut picoquic::sockaddr = ptr::null_mut();
+
+        unsafe {
+            picoquic_get_local_addr(*self.cnx, &mut addr, &mut addr_len);
+        }
+
+        if addr.is_null() || addr_len <= 0 {
+            None
+        } else {
+            Some(socket_addr_from_c(addr, addr_len))
+        }
+    }
+
     /// Prepares a `Packet`.
     /// The `Packet` contains any data from this connection(data from streams, ACK's, ...).
     /// The `Packet` will be stored in the given buffer.
     ///
     /// # Returns
-    /// The length of the `Packet` in the buffer or `None` if the packet does not contains any data.
+    /// The length of the `Packet` in the buffer, the address of the peer and the local address
+    /// of the path the `Packet` is sent on, if picoquic knows it. `None` if the packet does not
+    /// contains any data.
     pub fn prepare_packet(
         self,
         buffer: &mut [u8],
         current_time: u64,
-    ) -> Result<Option<(usize, SocketAddr)>, Error> {
+    ) -> Result<Option<(usize, SocketAddr, Option<SocketAddr>)>, Error> {
         let mut send_len = 0;
         let mut addr_len = 0;
         let mut addr: *mut picoquic::sockaddr = ptr::null_mut();
+        let mut local_addr_len = 0;
+        let mut local_addr: *mut picoquic::sockaddr = ptr::null_mut();
         let ret = unsafe {
             picoquic_prepare_packet(
                 self.as_ptr(),
@@ -118,8 +206,8 @@ impl Connection {
                 &mut send_len,
                 &mut addr,
                 &mut addr_len,
-                ptr::null_mut(),
-                ptr::null_mut(),
+                &mut local_addr,
+                &mut local_addr_len,
             )
         };
 
@@ -127,7 +215,13 @@ impl Connection {
             Err(ErrorKind::Disconnected.into())
         } else if ret == 0 {
             if send_len > 0 {
-                Ok(Some((send_len, socket_addr_from_c(addr, addr_len).into())))
+                let local_addr = if local_addr.is_null() || local_addr_len <= 0 {
+                    None
+                } else {
+                    Some(socket_addr_from_c(local_addr, local_addr_len))
+                };
+
+                Ok(Some((send_len, socket_addr_from_c(addr, addr_len), local_addr)))
             } else {
                 Ok(None)
             }
@@ -154,6 +248,13 @@ impl Connection {
             || state == picoquic_state_enum_picoquic_state_server_ready
     }
 
+    /// Is the address of the peer validated?
+    /// The server validates the address of the client in the handshake, before that it is
+    /// subject to the anti-amplification limit.
+    pub fn is_address_validated(self) -> bool {
+        self.con_type() == ConnectionType::Outgoing || self.is_ready()
+    }
+
     /// Is the connection going to close? (aka in closing, draining or disconnected state)
     pub fn is_going_to_close(&self) -> bool {
         self.state() >= picoquic_state_enum_picoquic_state_closing
@@ -164,9 +265,60 @@ impl Connection {
     }
 
     pub fn close(&self) {
-        //TODO maybe replace 0 with an appropriate error code
+        self.close_with_error(0);
+    }
+
+    /// Closes the connection with the given application error code.
+    pub fn close_with_error(&self, error_code: u64) {
         unsafe {
-            picoquic_close(*self.cnx, 0);
+            picoquic_close(*self.cnx, error_code as _);
+        }
+    }
+
+    /// Closes the connection with the `SERVER_BUSY` transport error.
+    pub fn close_as_busy(&self) {
+        unsafe {
+            picoquic_connection_error(*self.cnx, PICOQUIC_TRANSPORT_SERVER_BUSY as _, 0);
+        }
+    }
+
+    /// Closes the connection, because its handshake failed with the given picotls error.
+    pub fn close_with_tls_error(&self, error: u32) {
+        // TLS alerts are sent as crypto errors, all other errors as internal error.
+        let error_code = if error < 0x100 {
+            0x100 | error
+        } else {
+            PICOQUIC_TRANSPORT_INTERNAL_ERROR
+        };
+
+        unsafe {
+            picoquic_connection_error(*self.cnx, error_code as _, 0);
+        }
+    }
+
+    /// Starts to validate a new path from the given local address to the given peer address.
+    /// Picoquic migrates the connection to the new path, after the peer answered the probe.
+    pub fn probe_new_path(
+        self,
+        peer_addr: SocketAddr,
+        local_addr: SocketAddr,
+    ) -> Result<(), Error> {
+        let peer_addr = SockAddr::from(peer_addr);
+        let local_addr = SockAddr::from(local_addr);
+
+        let ret = unsafe {
+            picoquic_probe_new_path(
+                *self.cnx,
+                local_addr.as_ptr() as *const picoquic::sockaddr,
+                peer_addr.as_ptr() as *const picoquic::sockaddr,
+                picoquic_current_time(),
+            )
+        };
+
+        if ret == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::MigrationFailed(ret).into())
         }
     }
 
@@ -204,6 +356,597 @@ impl Connection {
         }
     }
 
+    /// Returns the smoothed round trip time of the primary path.
+    pub fn smoothed_rtt(self) -> Duration {
+        let rtt = unsafe { (**(*self.as_ptr()).path).smoothed_rtt };
+        Duration::from_micro_seconds(rtt)
+    }
+
+    /// Returns the minimum round trip time that was observed on the primary path.
+    pub fn min_rtt(self) -> Duration {
+        let rtt = unsafe { (**(*self.as_ptr()).path).rtt_min };
+        Duration::from_micro_seconds(rtt)
+    }
+
+    /// Returns the congestion window of the primary path.
+    pub fn congestion_window(self) -> u64 {
+        unsafe { (**(*self.as_ptr()).path).cwin as u64 }
+    }
+
+    /// Sets the congestion window of the primary path. NewReno and Cubic keep the window of the
+    /// path, so they continue from the new window.
+    pub fn set_congestion_window(self, window: u64) {
+        unsafe {
+            (**(*self.as_ptr()).path).cwin = window as _;
+        }
+    }
+
+    /// Returns the maximum packet size that is used on the primary path.
+    pub fn send_mtu(self) -> usize {
+        unsafe { (**(*self.as_ptr()).path).send_mtu as usize }
+    }
+
+    /// Sets the maximum packet size that is used on the primary path.
+    pub fn set_send_mtu(self, mtu: usize) {
+        unsafe {
+            (**(*self.as_ptr()).path).send_mtu = mtu as _;
+        }
+    }
+
+    /// Disables the MTU probing of picoquic on the primary path.
+    pub fn disable_mtu_probing(self) {
+        unsafe {
+            let path = *(*self.as_ptr()).path;
+            // Picoquic only probes, if it did not try a bigger MTU than the current one
+            (*path).send_mtu_max_tried = (*path).send_mtu;
+        }
+    }
+
+    /// Returns the total number of retransmitted packets.
+    pub fn retransmissions(self) -> u64 {
+        unsafe { (*self.as_ptr()).nb_retransmission_total as u64 }
+    }
+
+    /// Sets the idle timeout of the connection. Picoquic advertises the idle timeout in whole
+    /// seconds, so it is rounded up. A sub-second timeout would otherwise disable the idle
+    /// timeout.
+    pub fn set_idle_timeout(self, timeout: Duration) {
+        unsafe {
+            (*self.as_ptr()).local_parameters.idle_timeout = idle_timeout_secs(timeout) as _;
+        }
+    }
+
+    /// Returns the idle timeout that applies to the connection, the smaller one of the local and
+    /// the peer idle timeout. `0` disables the idle timeout of a peer.
+    pub fn idle_timeout(self) -> Option<Duration> {
+        unsafe {
+            let cnx = self.as_ptr();
+            let local = u64::from((*cnx).local_parameters.idle_timeout);
+            let remote = u64::from((*cnx).remote_parameters.idle_timeout);
+
+            match (local, remote) {
+                (0, 0) => None,
+                (0, timeout) | (timeout, 0) => Some(Duration::from_secs(timeout)),
+                (local, remote) => Some(Duration::from_secs(local.min(remote))),
+            }
+        }
+    }
+
+    /// Returns the key phase of the outgoing 1-RTT packets, it flips with each key update.
+    pub fn key_phase(self) -> bool {
+        unsafe { (*self.as_ptr()).key_phase_enc() != 0 }
+    }
+
+    /// Sets the maximum UDP payload size that is advertised to the peer.
+    pub fn set_max_udp_payload_size(self, size: usize) {
+        unsafe {
+            (*self.as_ptr()).local_parameters.max_packet_size = size as _;
+        }
+    }
+
+    /// Sets the transport parameters that are advertised to the peer. The flow control and stream
+    /// credit of the parameters is granted to the peer.
+    pub fn set_transport_parameters(self, params: &TransportParameterOverrides) {
+        let peer_is_client = self.con_type() == ConnectionType::Incoming;
+
+        unsafe {
+            let cnx = self.as_ptr();
+            let local = &mut (*cnx).local_parameters;
+
+            if let Some(max) = params.initial_max_data {
+                local.initial_max_data = max as _;
+                (*cnx).maxdata_local = max as _;
+            }
+
+            if let Some(max) = params.initial_max_stream_data_bidi_local {
+                local.initial_max_stream_data_bidi_local = max as _;
+            }
+
+            if let Some(max) = params.initial_max_stream_data_bidi_remote {
+                local.initial_max_stream_data_bidi_remote = max as _;
+            }
+
+            if let Some(max) = params.initial_max_stream_data_uni {
+                local.initial_max_stream_data_uni = max as _;
+            }
+
+            if let Some(streams) = params.initial_max_streams_bidi {
+                let id = max_stream_id(streams, peer_is_client, stream::Type::Bidirectional);
+                local.initial_max_stream_id_bidir = id as _;
+                (*cnx).max_stream_id_bidir_local = id;
+                (*cnx).max_stream_id_bidir_local_computed = id;
+            }
+
+            if let Some(streams) = params.initial_max_streams_uni {
+                let id = max_stream_id(streams, peer_is_client, stream::Type::Unidirectional);
+                local.initial_max_stream_id_unidir = id as _;
+                (*cnx).max_stream_id_unidir_local = id;
+                (*cnx).max_stream_id_unidir_local_computed = id;
+            }
+
+            if let Some(exponent) = params.ack_delay_exponent {
+                local.ack_delay_exponent = exponent;
+            }
+
+            if params.time_stamps {
+                // Send and receive the time stamps.
+                local.enable_time_stamp = 3;
+            }
+        }
+    }
+
+    /// Switches the congestion control algorithm of the connection.
+    pub fn set_congestion_algorithm(self, algorithm: CongestionAlgorithm) {
+        unsafe {
+            picoquic_set_congestion_algorithm(self.as_ptr(), congestion::algorithm(algorithm));
+        }
+    }
+
+    /// Notifies the `CongestionController` of the connection, that a packet was sent.
+    pub fn notify_packet_sent(self, len: usize) {
+        unsafe { congestion::packet_sent(self.as_ptr(), len) }
+    }
+
+    /// Returns the transport parameters the peer advertised in the handshake.
+    pub fn peer_transport_parameters(self) -> TransportParameters {
+        unsafe {
+            let remote = &(*self.as_ptr()).remote_parameters;
+
+            TransportParameters {
+                initial_max_data: u64::from(remote.initial_max_data),
+                initial_max_stream_data_bidi_local: u64::from(
+                    remote.initial_max_stream_data_bidi_local,
+                ),
+                initial_max_stream_data_bidi_remote: u64::from(
+                    remote.initial_max_stream_data_bidi_remote,
+                ),
+                initial_max_stream_data_uni: u64::from(remote.initial_max_stream_data_uni),
+                initial_max_streams_bidi: max_streams(u64::from(
+                    remote.initial_max_stream_id_bidir,
+                )),
+                initial_max_streams_uni: max_streams(u64::from(
+                    remote.initial_max_stream_id_unidir,
+                )),
+                max_udp_payload_size: remote.max_packet_size as usize,
+                ack_delay_exponent: remote.ack_delay_exponent,
+                time_stamps: remote.enable_time_stamp != 0,
+            }
+        }
+    }
+
+    /// Enables the DATAGRAM extension, by advertising the given maximum DATAGRAM frame size to
+    /// the peer.
+    pub fn set_max_datagram_frame_size(self, size: usize) {
+        unsafe {
+            (*self.as_ptr()).local_parameters.max_datagram_frame_size = size as _;
+        }
+    }
+
+    /// Returns the maximum DATAGRAM frame size of the peer, `0` if one of the peers does not
+    /// support the DATAGRAM extension.
+    pub fn max_datagram_frame_size(self) -> usize {
+        unsafe {
+            let cnx = self.as_ptr();
+            let local = (*cnx).local_parameters.max_datagram_frame_size as usize;
+            let remote = (*cnx).remote_parameters.max_datagram_frame_size as usize;
+
+            if local == 0 {
+                0
+            } else {
+                remote
+            }
+        }
+    }
+
+    /// Queues the given data to be sent in a DATAGRAM frame.
+    pub fn queue_datagram(self, data: &[u8]) -> Result<(), Error> {
+        let res =
+            unsafe { picoquic_queue_datagram_frame(self.as_ptr(), data.len(), data.as_ptr()) };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::FFIError.into())
+        }
+    }
+
+    /// Queues a `PING` frame, that is sent with the next packet of the application packet number
+    /// space.
+    pub fn queue_ping(self) -> Result<(), Error> {
+        let frame = [PING_FRAME_TYPE];
+        let res = unsafe { picoquic_queue_misc_frame(self.as_ptr(), frame.as_ptr(), frame.len()) };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::FFIError.into())
+        }
+    }
+
+    /// Updates the packet protection keys of the 1-RTT packets.
+    /// Fails, if the handshake is not finished or the peer did not acknowledge a packet of the
+    /// previous key update yet.
+    pub fn start_key_update(self) -> Result<(), Error> {
+        let res = unsafe { picoquic_start_key_rotation(self.as_ptr()) };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::FFIError.into())
+        }
+    }
+
+    /// Returns the `PacketNumberSpaceStats` of the application packet number space.
+    pub fn application_space_stats(self) -> PacketNumberSpaceStats {
+        self.packet_number_space_stats(
+            picoquic::picoquic_packet_context_enum_picoquic_packet_context_application,
+        )
+    }
+
+    /// Returns the receive window. This is the flow control credit that is granted to the peer
+    /// with each `MAX_DATA` or `MAX_STREAM_DATA` update.
+    pub fn receive_window(self) -> u64 {
+        unsafe { u64::from((*self.as_ptr()).local_parameters.initial_max_data) }
+    }
+
+    /// Sets the receive window of the connection and the streams that are opened afterwards.
+    /// The window of the existing streams is raised with `open_flow_control`.
+    pub fn set_receive_window(self, window: u64) {
+        unsafe {
+            let params = &mut (*self.as_ptr()).local_parameters;
+            params.initial_max_data = window as _;
+            params.initial_max_stream_data_bidi_local = window as _;
+            params.initial_max_stream_data_bidi_remote = window as _;
+            params.initial_max_stream_data_uni = window as _;
+        }
+    }
+
+    /// Grants the peer to send `window` bytes on the given stream and the connection, beyond the
+    /// data that was consumed so far. Picoquic sends the `MAX_STREAM_DATA` and `MAX_DATA` frames,
+    /// if the limits grow.
+    pub fn open_flow_control(self, id: stream::Id, window: u64) {
+        unsafe { picoquic_open_flow_control(self.as_ptr(), id, window) }
+    }
+
+    /// Returns the number of bytes sent on all streams and the flow control limit of the
+    /// connection, that was granted by the peer.
+    pub fn data_sent_and_limit(self) -> (u64, u64) {
+        unsafe {
+            let cnx = self.as_ptr();
+            ((*cnx).data_sent, (*cnx).maxdata_remote)
+        }
+    }
+
+    /// Returns the stream credit that picoquic computed for the (bidirectional, unidirectional)
+    /// streams of the peer. The credit is the maximum stream id picoquic grants the peer next.
+    pub fn stream_credit(self) -> (u64, u64) {
+        unsafe {
+            let cnx = self.as_ptr();
+            (
+                (*cnx).max_stream_id_bidir_local_computed,
+                (*cnx).max_stream_id_unidir_local_computed,
+            )
+        }
+    }
+
+    /// Returns the maximum (bidirectional, unidirectional) stream ids the peer allows us to open.
+    /// The limits are granted by the transport parameters and `MAX_STREAMS` frames of the peer.
+    pub fn peer_stream_limit(self) -> (u64, u64) {
+        unsafe {
+            let cnx = self.as_ptr();
+            (
+                (*cnx).max_stream_id_bidir_remote,
+                (*cnx).max_stream_id_unidir_remote,
+            )
+        }
+    }
+
+    /// Overrides the stream credit for the (bidirectional, unidirectional) streams of the peer.
+    pub fn set_stream_credit(self, bidirectional: Option<u64>, unidirectional: Option<u64>) {
+        unsafe {
+            let cnx = self.as_ptr();
+
+            if let Some(credit) = bidirectional {
+                (*cnx).max_stream_id_bidir_local_computed = credit;
+            }
+
+            if let Some(credit) = unidirectional {
+                (*cnx).max_stream_id_unidir_local_computed = credit;
+            }
+        }
+    }
+
+    /// Returns the error code the peer used to reset the given stream.
+    pub fn remote_stream_error(self, id: stream::Id) -> u64 {
+        unsafe { u64::from(picoquic_get_remote_stream_error(self.as_ptr(), id)) }
+    }
+
+    /// Returns the error code of the `STOP_SENDING` the peer sent for the given stream.
+    pub fn remote_stop_sending_error(self, id: stream::Id) -> u64 {
+        unsafe {
+            let stream = picoquic_find_stream(self.as_ptr(), id, 0);
+
+            if stream.is_null() {
+                0
+            } else {
+                u64::from((*stream).remote_stop_error)
+            }
+        }
+    }
+
+    /// Returns the number of bytes picoquic sent on the given stream.
+    /// Returns `None`, if picoquic does not know the stream.
+    pub fn stream_sent_offset(self, id: stream::Id) -> Option<u64> {
+        unsafe {
+            let stream = picoquic_find_stream(self.as_ptr(), id, 0);
+
+            if stream.is_null() {
+                None
+            } else {
+                Some((*stream).sent_offset)
+            }
+        }
+    }
+
+    /// Returns the flow control limit of the given stream, that was granted by the peer.
+    /// Returns `None`, if picoquic does not know the stream.
+    pub fn stream_send_limit(self, id: stream::Id) -> Option<u64> {
+        unsafe {
+            let stream = picoquic_find_stream(self.as_ptr(), id, 0);
+
+            if stream.is_null() {
+                None
+            } else {
+                Some((*stream).maxdata_remote)
+            }
+        }
+    }
+
+    /// Returns if picoquic has data of the given stream, that is not sent yet.
+    pub fn stream_has_queued_data(self, id: stream::Id) -> bool {
+        unsafe {
+            let stream = picoquic_find_stream(self.as_ptr(), id, 0);
+            !stream.is_null() && !(*stream).send_queue.is_null()
+        }
+    }
+
+    /// Grants the peer to send up to `max` bytes on the given stream. The limit is raised with
+    /// `open_flow_control`, so picoquic sends the `MAX_STREAM_DATA` frame and keeps tracking the
+    /// limit itself. A limit that is not bigger than the current limit is ignored, as the limit
+    /// can not be decreased.
+    pub fn set_stream_max_data(self, id: stream::Id, max: u64) -> Result<(), Error> {
+        let stream = unsafe { picoquic_find_stream(self.as_ptr(), id, 0) };
+
+        if stream.is_null() {
+            return Err(ErrorKind::FFIError.into());
+        }
+
+        let (limit, consumed) = unsafe { ((*stream).maxdata_local, (*stream).consumed_offset) };
+
+        if max > limit {
+            self.open_flow_control(id, max - consumed);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the server name(SNI) that was requested by the client.
+    pub fn server_name(self) -> Option<String> {
+        unsafe {
+            let sni = picoquic_tls_get_sni(self.as_ptr());
+
+            if sni.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(sni).to_string_lossy().into_owned())
+            }
+        }
+    }
+
+    /// Returns the negotiated application layer protocol.
+    pub fn alpn(self) -> Option<String> {
+        self.negotiated_alpn()
+            .map(|alpn| String::from_utf8_lossy(&alpn).into_owned())
+    }
+
+    /// Returns the raw bytes of the negotiated application layer protocol.
+    pub fn negotiated_alpn(self) -> Option<Vec<u8>> {
+        unsafe {
+            let alpn = picoquic_tls_get_negotiated_alpn(self.as_ptr());
+
+            if alpn.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(alpn).to_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Returns the session ticket the server sent for this connection.
+    pub fn session_ticket(self) -> Option<Vec<u8>> {
+        unsafe {
+            let cnx = self.as_ptr();
+            let sni = picoquic_tls_get_sni(cnx);
+
+            if sni.is_null() {
+                return None;
+            }
+
+            let alpn = picoquic_tls_get_negotiated_alpn(cnx);
+            let alpn_len = if alpn.is_null() {
+                0
+            } else {
+                CStr::from_ptr(alpn).to_bytes().len()
+            };
+
+            let mut ticket = ptr::null_mut();
+            let mut ticket_len = 0;
+            let res = picoquic_get_ticket(
+                (*picoquic_get_quic_ctx(cnx)).p_first_ticket,
+                picoquic_current_time(),
+                sni,
+                CStr::from_ptr(sni).to_bytes().len() as u16,
+                alpn,
+                alpn_len as u16,
+                &mut ticket,
+                &mut ticket_len,
+                ptr::null_mut(),
+                0,
+            );
+
+            if res == 0 && !ticket.is_null() {
+                Some(slice::from_raw_parts(ticket, ticket_len as usize).to_vec())
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Returns the negotiated TLS parameters, if the handshake is finished.
+    pub fn tls_info(self) -> Option<TlsInfo> {
+        if !self.is_ready() {
+            return None;
+        }
+
+        unsafe {
+            let tls = picoquic_rs_get_tls(self.as_ptr());
+
+            if tls.is_null() {
+                return None;
+            }
+
+            let cipher = ptls_get_cipher(tls);
+
+            if cipher.is_null() {
+                return None;
+            }
+
+            Some(TlsInfo {
+                cipher_suite: (*cipher).id,
+                version: TLS_VERSION_1_3,
+                session_resumed: ptls_is_psk_handshake(tls) != 0,
+                early_data_accepted: (*self.as_ptr()).zero_rtt_data_accepted != 0,
+                alpn: self.negotiated_alpn(),
+            })
+        }
+    }
+
+    /// Returns if picoquic has data of this connection that is not yet sent or not yet
+    /// acknowledged by the peer.
+    pub fn has_pending_data(self) -> bool {
+        unsafe {
+            let cnx = self.as_ptr();
+
+            if (**(*cnx).path).bytes_in_transit > 0 {
+                return true;
+            }
+
+            let mut stream = (*cnx).first_stream;
+            while !stream.is_null() {
+                if !(*stream).send_queue.is_null() {
+                    return true;
+                }
+
+                stream = (*stream).next_stream;
+            }
+
+            false
+        }
+    }
+
+    /// Returns the transport statistics of this connection.
+    pub fn stats(self) -> ConnectionStats {
+        unsafe {
+            let cnx = self.as_ptr();
+
+            let paths = (0..(*cnx).nb_paths.max(0) as usize)
+                .map(|i| {
+                    let path = *(*cnx).path.add(i);
+
+                    PathStats {
+                        peer_addr: socket_addr_from_c(
+                            &mut (*path).peer_addr as *mut _ as *mut picoquic::sockaddr,
+                            (*path).peer_addr_len as i32,
+                        ),
+                        smoothed_rtt: Duration::from_micro_seconds((*path).smoothed_rtt),
+                        rtt_variance: Duration::from_micro_seconds((*path).rtt_variant),
+                        min_rtt: Duration::from_micro_seconds((*path).rtt_min),
+                        congestion_window: (*path).cwin as u64,
+                        bytes_in_flight: (*path).bytes_in_transit as u64,
+                        mtu: (*path).send_mtu as usize,
+                        pacing_rate: (*path).pacing_rate as u64,
+                        one_way_delay: congestion::one_way_delay(cnx, path),
+                    }
+                })
+                .collect();
+
+            let retransmissions = self.retransmissions();
+            let spurious_retransmissions = (*cnx).nb_spurious as u64;
+
+            ConnectionStats {
+                initial: self.packet_number_space_stats(
+                    picoquic::picoquic_packet_context_enum_picoquic_packet_context_initial,
+                ),
+                handshake: self.packet_number_space_stats(
+                    picoquic::picoquic_packet_context_enum_picoquic_packet_context_handshake,
+                ),
+                application: self.packet_number_space_stats(
+                    picoquic::picoquic_packet_context_enum_picoquic_packet_context_application,
+                ),
+                paths,
+                packets_sent: (*cnx).nb_packets_sent as u64,
+                packets_received: (*cnx).nb_packets_received as u64,
+                packets_lost: retransmissions.saturating_sub(spurious_retransmissions),
+                retransmissions,
+                spurious_retransmissions,
+                crypto: Default::default(),
+                ecn_received: Default::default(),
+            }
+        }
+    }
+
+    fn packet_number_space_stats(self, space: u32) -> PacketNumberSpaceStats {
+        // Picoquic uses `u64::MAX` for "no packet acknowledged/received yet".
+        fn packet_number(pn: u64) -> Option<u64> {
+            if pn == u64::max_value() {
+                None
+            } else {
+                Some(pn)
+            }
+        }
+
+        unsafe {
+            let pkt_ctx = &(*self.as_ptr()).pkt_ctx[space as usize];
+
+            PacketNumberSpaceStats {
+                packets_sent: pkt_ctx.send_sequence,
+                largest_acked: packet_number(pkt_ctx.highest_acknowledged),
+                largest_received: packet_number(pkt_ctx.first_sack_item.start_of_sack_range)
+                    .map(|_| pkt_ctx.first_sack_item.end_of_sack_range),
+            }
+        }
+    }
+
     /// Returns the local connection id for this connection.
     pub fn local_id(&self) -> connection::Id {
         unsafe {
@@ -212,6 +955,26 @@ impl Connection {
         }
     }
 
+    /// Returns the QUIC version of this connection. Before the version negotiation finished, this
+    /// is the proposed version.
+    pub fn version(self) -> u32 {
+        unsafe {
+            let index = (*self.as_ptr()).version_index as usize;
+            implemented_versions().get(index).cloned().unwrap_or(0)
+        }
+    }
+
+    /// Returns the connection ids of this connection.
+    pub fn connection_ids(self) -> ConnectionIds {
+        unsafe {
+            ConnectionIds {
+                initial: connection_id_bytes(&picoquic_get_initial_cnxid(self.as_ptr())),
+                local: connection_id_bytes(&picoquic_get_local_cnxid(self.as_ptr())),
+                remote: connection_id_bytes(&picoquic_get_remote_cnxid(self.as_ptr())),
+            }
+        }
+    }
+
     /// Returns the type of this connection.
     pub fn con_type(&self) -> ConnectionType {
         unsafe {
@@ -225,31 +988,111 @@ impl Connection {
 
     /// Checks if the connection had an error.
     /// The returned closure, will always construct the same error.
-    pub fn error(&self) -> Option<impl ErrorFn + Clone> {
-        let error_code = unsafe {
+    pub fn error(&self, handshake_completed: bool) -> Option<impl ErrorFn + Clone> {
+        let (transport_error, local) = unsafe {
             let error = picoquic_get_local_error(self.as_ptr());
             if error != 0 {
-                error
+                (error as u64, true)
             } else {
-                picoquic_get_remote_error(self.as_ptr())
+                (picoquic_get_remote_error(self.as_ptr()) as u64, false)
             }
         };
 
-        let is_handshake_error = unsafe { picoquic_is_handshake_error(error_code as u16) == 1 };
-        if error_code == 0 {
+        let codes = CloseCodes {
+            transport_error,
+            local,
+            is_handshake_error: unsafe { picoquic_is_handshake_error(transport_error as _) == 1 },
+            application_error: unsafe { picoquic_get_application_error(self.as_ptr()) as u64 },
+            handshake_completed,
+        };
+
+        if codes.is_error() {
+            Some(move || codes.error_kind().into())
+        } else {
             None
+        }
+    }
+}
+
+/// The error codes of a closed connection.
+#[derive(Clone, Copy)]
+struct CloseCodes {
+    /// The transport error, `0` if the connection was not closed by a transport error.
+    transport_error: u64,
+    /// Was the transport error sent by us? Otherwise, it was received from the peer.
+    local: bool,
+    /// Does picoquic consider the transport error as a failure of the handshake?
+    is_handshake_error: bool,
+    /// The application error code of the peer, `0` if the peer did not close the connection
+    /// with an error.
+    application_error: u64,
+    handshake_completed: bool,
+}
+
+impl CloseCodes {
+    fn is_error(&self) -> bool {
+        self.transport_error != 0 || self.application_error != 0
+    }
+
+    fn error_kind(&self) -> ErrorKind {
+        let code = self.transport_error;
+        // Picoquic only supports TLS alerts in 16 bit error codes.
+        let alert = if code <= u64::from(u16::max_value()) {
+            TlsAlert::from_error_code(code as u16, self.local)
         } else {
-            Some(move || {
-                if is_handshake_error {
-                    ErrorKind::TLSHandshakeError.into()
+            None
+        };
+
+        match alert {
+            _ if code == 0 => ErrorKind::ApplicationError {
+                code: self.application_error,
+                reason: None,
+            },
+            _ if self.local && code == u64::from(PICOQUIC_ERROR_IDLE_TIMEOUT) => {
+                if self.handshake_completed {
+                    ErrorKind::IdleTimeout
                 } else {
-                    ErrorKind::Unknown.into()
+                    ErrorKind::HandshakeTimeout
                 }
-            })
+            }
+            Some(alert) => ErrorKind::TLSAlert(alert),
+            None if self.is_handshake_error => ErrorKind::TLSHandshakeError,
+            // Picoquic does not keep the frame type and the reason phrase of the error.
+            None => ErrorKind::TransportError {
+                code,
+                frame_type: None,
+                reason: None,
+            },
         }
     }
 }
 
+/// Converts the maximum number of `Stream`s of the given type, that the peer is allowed to open,
+/// into the maximum stream id.
+fn max_stream_id(streams: u64, peer_is_client: bool, stype: stream::Type) -> stream::Id {
+    if streams == 0 {
+        0
+    } else {
+        Connection::generate_stream_id(streams - 1, peer_is_client, stype)
+    }
+}
+
+/// Converts the maximum stream id into the maximum number of `Stream`s, the reverse of
+/// `max_stream_id`.
+fn max_streams(id: stream::Id) -> u64 {
+    id >> 2
+}
+
+/// Converts the idle timeout into the whole seconds picoquic advertises, rounded up.
+fn idle_timeout_secs(timeout: Duration) -> u64 {
+    timeout.as_secs() + if timeout.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+/// Returns the bytes of the given connection id.
+fn connection_id_bytes(id: &picoquic_connection_id_t) -> Vec<u8> {
+    id.id[..id.id_len as usize].to_vec()
+}
+
 impl From<*mut picoquic_cnx_t> for Connection {
     fn from(cnx: *mut picoquic_cnx_t) -> Connection {
         Connection { cnx: Pointer(cnx) }
@@ -359,6 +1202,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn max_stream_id_and_max_streams_are_reversible() {
+        assert_eq!(0, max_stream_id(0, true, stream::Type::Bidirectional));
+        assert_eq!(4, max_stream_id(1, true, stream::Type::Bidirectional));
+        assert_eq!(11, max_stream_id(2, false, stream::Type::Unidirectional));
+
+        for streams in 0..10 {
+            for peer_is_client in &[true, false] {
+                for stype in &[stream::Type::Bidirectional, stream::Type::Unidirectional] {
+                    let id = max_stream_id(streams, *peer_is_client, *stype);
+                    assert_eq!(streams, max_streams(id));
+                }
+            }
+        }
+    }
+
     #[test]
     #[should_panic(expected = "server address must not be unspecified!")]
     fn do_not_accept_unspecified_ip_address() {
@@ -367,6 +1226,70 @@ mod tests {
             ([0, 0, 0, 0], 12345).into(),
             0,
             "server".into(),
+            &[],
+            false,
+            None,
         );
     }
+
+    fn close_codes(transport_error: u64, local: bool) -> CloseCodes {
+        CloseCodes {
+            transport_error,
+            local,
+            is_handshake_error: false,
+            application_error: 0,
+            handshake_completed: true,
+        }
+    }
+
+    #[test]
+    fn close_codes_are_mapped_to_error_kinds() {
+        assert!(!close_codes(0, false).is_error());
+
+        match close_codes(0x3, false).error_kind() {
+            ErrorKind::TransportError { code: 0x3, .. } => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        match close_codes(0x12a, false).error_kind() {
+            ErrorKind::TLSAlert(alert) => assert_eq!((42, false), (alert.code, alert.sent)),
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        let codes = CloseCodes {
+            application_error: 7,
+            ..close_codes(0, false)
+        };
+        assert!(codes.is_error());
+        match codes.error_kind() {
+            ErrorKind::ApplicationError { code: 7, .. } => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn idle_timeout_before_the_handshake_is_a_handshake_timeout() {
+        let timeout = u64::from(PICOQUIC_ERROR_IDLE_TIMEOUT);
+
+        match close_codes(timeout, true).error_kind() {
+            ErrorKind::IdleTimeout => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+
+        let codes = CloseCodes {
+            handshake_completed: false,
+            ..close_codes(timeout, true)
+        };
+        match codes.error_kind() {
+            ErrorKind::HandshakeTimeout => {}
+            kind => panic!("unexpected error: {}", kind),
+        }
+    }
+
+    #[test]
+    fn idle_timeout_is_rounded_up_to_seconds() {
+        assert_eq!(1, idle_timeout_secs(Duration::from_millis(1)));
+        assert_eq!(2, idle_timeout_secs(Duration::from_millis(1500)));
+        assert_eq!(30, idle_timeout_secs(Duration::from_secs(30)));
+    }
 }
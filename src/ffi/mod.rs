@@ -1,13 +1,25 @@
-use std::ops::Deref;
+#[cfg(feature = "openssl")]
+use picoquic_sys::picoquic::picoquic_cnx_t;
 
+use std::{
+    collections::HashSet,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+
+mod congestion;
 mod connection;
 mod quic_ctx;
+mod sni;
 mod stateless_packet;
+#[cfg(feature = "openssl")]
 mod verify_certificate;
 
 pub use self::connection::Connection;
+pub use self::quic_ctx::implemented_versions;
 pub use self::quic_ctx::MicroSeconds;
 pub use self::quic_ctx::QuicCtx;
+pub use self::quic_ctx::TlsConfig;
 
 #[derive(Copy, Clone)]
 pub struct Pointer<T>(*mut T);
@@ -22,3 +34,24 @@ impl<T> Deref for Pointer<T> {
 
 unsafe impl<T> Send for Pointer<T> {}
 unsafe impl<T> Sync for Pointer<T> {}
+
+/// The connections whose certificate is verified by the `AsyncVerifyCertificate` handler at the
+/// moment.
+#[derive(Clone, Default)]
+pub struct PendingVerifications(Arc<Mutex<HashSet<usize>>>);
+
+impl PendingVerifications {
+    pub fn contains(&self, cnx: Connection) -> bool {
+        self.0.lock().unwrap().contains(&(cnx.as_ptr() as usize))
+    }
+
+    #[cfg(feature = "openssl")]
+    fn insert(&self, cnx: *mut picoquic_cnx_t) {
+        self.0.lock().unwrap().insert(cnx as usize);
+    }
+
+    #[cfg(feature = "openssl")]
+    fn remove(&self, cnx: *mut picoquic_cnx_t) {
+        self.0.lock().unwrap().remove(&(cnx as usize));
+    }
+}
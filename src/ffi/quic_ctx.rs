@@ -1,22 +1,40 @@
-use super::{connection::ConnectionIter, stateless_packet::StatelessPacketIter, Pointer};
-use config::{Config, FileFormat};
+use super::{
+    congestion::{self, CustomAlgorithm},
+    connection::{Connection, ConnectionIter},
+    sni::SniCertificates,
+    stateless_packet::StatelessPacketIter,
+    Pointer,
+};
+use config::{Config, FileFormat, SessionTicketStore};
+use crypto_backend;
 use error::*;
-use ffi::verify_certificate;
+#[cfg(feature = "openssl")]
+use ffi::{
+    verify_certificate::{self, Handlers, StoreVerifier},
+    PendingVerifications,
+};
+use pem;
+use sni::SniIdentity;
+#[cfg(feature = "openssl")]
+use verify_certificate::{AsyncVerifyCertificate, VerifyCertificate};
 
 use picoquic_sys::picoquic::{
-    self, picoquic_create, picoquic_current_time, picoquic_free, picoquic_get_next_wake_delay,
-    picoquic_incoming_packet, picoquic_quic_t, picoquic_set_client_authentication,
+    self, picoquic_alpn_select_fn, picoquic_cnx_by_id, picoquic_cnx_by_net, picoquic_create,
+    picoquic_current_time, picoquic_free, picoquic_get_next_wake_delay, picoquic_incoming_packet,
+    picoquic_null_connection_id, picoquic_quic_t, picoquic_set_alpn_select_fn, picoquic_set_cc_log,
+    picoquic_set_client_authentication, picoquic_set_cookie_mode,
+    picoquic_set_default_congestion_algorithm, picoquic_set_key_log_file,
     picoquic_set_tls_certificate_chain, picoquic_set_tls_key, picoquic_set_tls_root_certificates,
-    picoquic_stream_data_cb_fn, ptls_iovec_t,
+    picoquic_store_ticket, picoquic_stream_data_cb_fn, ptls_context_t, ptls_iovec_t,
 };
 
 use std::{
     ffi::CString,
-    mem,
+    fs, mem,
     net::SocketAddr,
     os::raw::{c_char, c_void},
     path::PathBuf,
-    ptr,
+    ptr, slice,
     time::{Duration, Instant},
 };
 
@@ -24,7 +42,11 @@ use socket2::SockAddr;
 
 use libc;
 
+#[cfg(feature = "openssl")]
 use openssl::pkey::PKey;
+#[cfg(feature = "openssl")]
+use openssl::x509::store::{X509Store, X509StoreBuilder};
+#[cfg(feature = "openssl")]
 use openssl::x509::X509;
 
 fn create_cstring(path: Option<PathBuf>) -> Result<Option<CString>, Error> {
@@ -50,6 +72,28 @@ fn c_str_or_null(string: &Option<CString>) -> *const c_char {
 pub struct QuicCtx {
     quic: Pointer<picoquic_quic_t>,
     max_delay: Duration,
+    /// The verify certificate handlers, if the verify certificate callback is set up.
+    #[cfg(feature = "openssl")]
+    verify_handlers: Option<Pointer<Handlers>>,
+    /// The root certificates are required to verify certificates by ourself.
+    #[cfg(feature = "openssl")]
+    root_certificate_filename: Option<PathBuf>,
+    #[cfg(feature = "openssl")]
+    root_certificates: Option<(FileFormat, Vec<Vec<u8>>)>,
+    /// Is the default verify certificate handler provided by the user?
+    #[cfg(feature = "openssl")]
+    custom_verifier: bool,
+    /// Picoquic references the directory of the congestion control logs, so we need to keep it.
+    cc_log_dir: Option<CString>,
+    /// Picoquic references the file of the session tickets, so we need to keep it.
+    ticket_file: Option<CString>,
+    /// Picoquic references the congestion algorithm of the `CongestionController`s, so we need
+    /// to keep it.
+    congestion_controller: Option<Box<CustomAlgorithm>>,
+    /// Is the stateless retry enabled for all new connections?
+    stateless_retry: bool,
+    /// Picotls references the TLS contexts of the `SniIdentity`s, so we need to keep them.
+    sni_certificates: Option<Box<SniCertificates>>,
 }
 
 impl QuicCtx {
@@ -62,9 +106,20 @@ impl QuicCtx {
         // The buckets itself are a linked list
         let connection_buckets = 16;
 
+        // The backend needs to be the default of OpenSSL, before picotls uses any crypto.
+        if let Some(ref backend) = config.crypto_backend {
+            crypto_backend::load(backend)?;
+        }
+
         let cert_filename = create_cstring(config.certificate_chain_filename)?;
         let key_filename = create_cstring(config.private_key_filename)?;
+        #[cfg(feature = "openssl")]
+        let root_certificate_filename = config.root_certificate_filename.clone();
         let root_cert_filename = create_cstring(config.root_certificate_filename)?;
+        let ticket_file = match config.session_ticket_store {
+            SessionTicketStore::InMemory => None,
+            SessionTicketStore::File(ref path) => create_cstring(Some(path.clone()))?,
+        };
 
         let reset_seed = config
             .reset_seed
@@ -86,7 +141,7 @@ impl QuicCtx {
                 reset_seed,
                 picoquic_current_time(),
                 ptr::null_mut(),
-                ptr::null(),
+                c_str_or_null(&ticket_file),
                 ptr::null(),
                 0,
             )
@@ -96,9 +151,22 @@ impl QuicCtx {
         let mut quic = QuicCtx {
             quic: Pointer(quic),
             max_delay: Duration::from_secs(10),
+            #[cfg(feature = "openssl")]
+            verify_handlers: None,
+            #[cfg(feature = "openssl")]
+            root_certificate_filename,
+            #[cfg(feature = "openssl")]
+            root_certificates: config.root_certificates.clone(),
+            #[cfg(feature = "openssl")]
+            custom_verifier: config.verify_certificate_handler.is_some(),
+            cc_log_dir: None,
+            ticket_file,
+            congestion_controller: None,
+            stateless_retry: false,
+            sni_certificates: None,
         };
 
-        if config.client_authentication {
+        if config.client_authentication && !config.client_only {
             unsafe {
                 picoquic_set_client_authentication(quic.as_ptr(), 1);
             }
@@ -116,8 +184,78 @@ impl QuicCtx {
             quic.set_tls_root_certificates(certs, format)?;
         }
 
-        if let Some(handler) = config.verify_certificate_handler.take() {
-            verify_certificate::setup_callback(&quic, handler)?;
+        // The certificates are always verified by our callback, so the peer certificates of each
+        // connection are known. Without a handler, they are verified against the root
+        // certificates, like picoquic does. Without the `openssl` feature, the default verifier
+        // of picotls is kept.
+        #[cfg(feature = "openssl")]
+        {
+            let handler = match config.verify_certificate_handler.take() {
+                Some(handler) => handler,
+                None => Box::new(StoreVerifier::new(quic.root_store()?)),
+            };
+            quic.verify_handlers = Some(verify_certificate::setup_callback(&quic, handler)?);
+        }
+
+        if let Some(dir) = config.cc_log_dir {
+            fs::create_dir_all(&dir)?;
+            let dir = create_cstring(Some(dir))?;
+
+            unsafe {
+                picoquic_set_cc_log(quic.as_ptr(), c_str_or_null(&dir));
+            }
+            quic.cc_log_dir = dir;
+        }
+
+        if !config.sni_identities.is_empty() {
+            let mut sni = unsafe { SniCertificates::install(quic.as_ptr()) };
+
+            for identity in config.sni_identities {
+                quic.add_sni_identity(&mut sni, identity)?;
+            }
+            quic.sni_certificates = Some(sni);
+        }
+
+        if let Some(key) = config.retry_token_key {
+            // Picoquic protects the retry tokens with the retry seed.
+            unsafe {
+                (*quic.as_ptr()).retry_seed = key;
+            }
+        }
+        quic.set_stateless_retry(config.stateless_retry);
+
+        if let Some(new_controller) = config.congestion_controller {
+            let alg = CustomAlgorithm::new(new_controller);
+
+            unsafe {
+                picoquic_set_default_congestion_algorithm(quic.as_ptr(), alg.as_ptr());
+            }
+            quic.congestion_controller = Some(alg);
+        } else if let Some(algorithm) = config.congestion_algorithm {
+            unsafe {
+                picoquic_set_default_congestion_algorithm(
+                    quic.as_ptr(),
+                    congestion::algorithm(algorithm),
+                );
+            }
+        } else {
+            // The default algorithm of picoquic records the one-way delay samples as well.
+            unsafe {
+                let default = (*quic.as_ptr()).default_congestion_alg as *mut _;
+                picoquic_set_default_congestion_algorithm(
+                    quic.as_ptr(),
+                    congestion::observed(default),
+                );
+            }
+        }
+
+        if let Some(file) = config.key_log_file {
+            let file = create_cstring(Some(file))?;
+
+            // Picoquic opens the file at once, so the name is not referenced afterwards.
+            unsafe {
+                picoquic_set_key_log_file(quic.as_ptr(), c_str_or_null(&file));
+            }
         }
 
         Ok(quic)
@@ -131,6 +269,19 @@ impl QuicCtx {
         QuicCtx {
             quic: Pointer(ptr::null_mut()),
             max_delay: Duration::from_secs(10),
+            #[cfg(feature = "openssl")]
+            verify_handlers: None,
+            #[cfg(feature = "openssl")]
+            root_certificate_filename: None,
+            #[cfg(feature = "openssl")]
+            root_certificates: None,
+            #[cfg(feature = "openssl")]
+            custom_verifier: false,
+            cc_log_dir: None,
+            ticket_file: None,
+            congestion_controller: None,
+            stateless_retry: false,
+            sni_certificates: None,
         }
     }
 
@@ -142,6 +293,33 @@ impl QuicCtx {
         ConnectionIter::new(*self.quic)
     }
 
+    /// Enables or disables sending a retry packet for each new incoming connection, which forces
+    /// the client to validate its address, before any connection state is allocated.
+    pub fn set_stateless_retry(&mut self, enabled: bool) {
+        self.stateless_retry = enabled;
+        self.set_cookie_mode(enabled);
+    }
+
+    fn set_cookie_mode(&mut self, enabled: bool) {
+        unsafe {
+            picoquic_set_cookie_mode(self.as_ptr(), enabled as i32);
+        }
+    }
+
+    /// Processes the given packet like `incoming_data`, but a new connection is answered with a
+    /// retry packet, even if the stateless retry is disabled.
+    pub fn incoming_data_with_retry(
+        &mut self,
+        buf: &mut [u8],
+        addr_to: SocketAddr,
+        addr_from: SocketAddr,
+        current_time: u64,
+    ) {
+        self.set_cookie_mode(true);
+        self.incoming_data(buf, addr_to, addr_from, current_time);
+        self.set_cookie_mode(self.stateless_retry);
+    }
+
     pub fn incoming_data(
         &mut self,
         buf: &mut [u8],
@@ -170,6 +348,51 @@ impl QuicCtx {
         }
     }
 
+    /// Returns the connection to the given peer address, if one exists.
+    pub fn connection_by_addr(&self, addr: SocketAddr) -> Option<Connection> {
+        let addr = SockAddr::from(addr);
+
+        let cnx =
+            unsafe { picoquic_cnx_by_net(*self.quic, addr.as_ptr() as *mut picoquic::sockaddr) };
+
+        if cnx.is_null() {
+            None
+        } else {
+            Some(Connection::from(cnx))
+        }
+    }
+
+    /// Returns the connection the given packet belongs to. The connection is found by the
+    /// destination connection id of the packet, so it is found after the peer address changed.
+    /// Packets with an unknown connection id, e.g. the first packet of a new connection, are
+    /// matched by the peer address.
+    pub fn connection_by_packet(&self, packet: &[u8], addr: SocketAddr) -> Option<Connection> {
+        let short_id_len = unsafe { (**self.quic).local_cnxid_length as usize };
+
+        destination_connection_id(packet, short_id_len)
+            .and_then(|id| self.connection_by_id(id))
+            .or_else(|| self.connection_by_addr(addr))
+    }
+
+    fn connection_by_id(&self, id: &[u8]) -> Option<Connection> {
+        let mut cnx_id = unsafe { picoquic_null_connection_id };
+
+        if id.is_empty() || id.len() > cnx_id.id.len() {
+            return None;
+        }
+
+        cnx_id.id[..id.len()].copy_from_slice(id);
+        cnx_id.id_len = id.len() as u8;
+
+        let cnx = unsafe { picoquic_cnx_by_id(*self.quic, cnx_id) };
+
+        if cnx.is_null() {
+            None
+        } else {
+            Some(Connection::from(cnx))
+        }
+    }
+
     pub fn stateless_packet_iter(&self) -> StatelessPacketIter {
         StatelessPacketIter::new(*self.quic)
     }
@@ -193,11 +416,205 @@ impl QuicCtx {
         }
     }
 
+    /// Sets the function that selects the application layer protocol of incoming connections.
+    pub fn set_alpn_select_fn(&mut self, select: picoquic_alpn_select_fn) {
+        unsafe {
+            picoquic_set_alpn_select_fn(self.as_ptr(), select);
+        }
+    }
+
+    /// Adds the given session ticket for connections to the given server.
+    pub fn store_session_ticket(
+        &mut self,
+        server_name: &str,
+        alpn: Option<&[u8]>,
+        ticket: &[u8],
+        current_time: u64,
+    ) -> Result<(), Error> {
+        let alpn = alpn.unwrap_or(&[]);
+        // Picoquic copies the ticket, but requires a mutable pointer.
+        let mut ticket = ticket.to_vec();
+
+        let res = unsafe {
+            picoquic_store_ticket(
+                &mut (**self.quic).p_first_ticket,
+                current_time,
+                server_name.as_ptr() as *const c_char,
+                server_name.len() as u16,
+                alpn.as_ptr() as *const c_char,
+                alpn.len() as u16,
+                ticket.as_mut_ptr(),
+                ticket.len() as u32,
+                ptr::null_mut(),
+            )
+        };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(ErrorKind::FFIError.into())
+        }
+    }
+
     /// Returns the current time in micro seconds for Picoquic.
     pub fn get_current_time(&self) -> u64 {
         unsafe { picoquic_current_time() }
     }
 
+    /// Sets the verify certificate handler for the given connection. The handler is used instead
+    /// of the handler of the `Config`.
+    #[cfg(feature = "openssl")]
+    pub fn set_connection_verifier(
+        &mut self,
+        cnx: Connection,
+        handler: Box<dyn VerifyCertificate + Send>,
+    ) -> Result<(), Error> {
+        if let Some(ref handlers) = self.verify_handlers {
+            unsafe {
+                (***handlers).insert(cnx, handler);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets the handler that verifies the certificates of all connections asynchronously, that do
+    /// not have their own handler. The connections with an unfinished verification are tracked
+    /// in `pending`.
+    #[cfg(feature = "openssl")]
+    pub fn set_async_verifier(
+        &mut self,
+        handler: Box<dyn AsyncVerifyCertificate>,
+        pending: PendingVerifications,
+    ) {
+        self.custom_verifier = true;
+
+        if let Some(ref handlers) = self.verify_handlers {
+            unsafe {
+                (***handlers).set_async(handler, pending);
+            }
+        }
+    }
+
+    /// Polls the unfinished asynchronous certificate verifications, see
+    /// `Handlers::poll_verifications`.
+    #[cfg(feature = "openssl")]
+    pub fn poll_certificate_verifications(&mut self) -> Vec<(Connection, bool)> {
+        match self.verify_handlers {
+            Some(ref handlers) => unsafe { (***handlers).poll_verifications() },
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the certificates (DER format) the peer of the given connection presented, the peer
+    /// certificate first.
+    #[cfg(feature = "openssl")]
+    pub fn peer_certificates(&self, cnx: Connection) -> Vec<Vec<u8>> {
+        match self.verify_handlers {
+            Some(ref handlers) => unsafe {
+                (***handlers)
+                    .peer_certificates(cnx)
+                    .cloned()
+                    .unwrap_or_default()
+            },
+            None => Vec::new(),
+        }
+    }
+
+    /// Removes the verify certificate handler and the peer certificates of the given connection.
+    #[cfg(feature = "openssl")]
+    pub fn remove_connection_verifier(&mut self, cnx: Connection) {
+        if let Some(ref handlers) = self.verify_handlers {
+            unsafe {
+                (***handlers).remove(cnx);
+            }
+        }
+    }
+
+    /// Applies the given `TlsConfig` to all subsequent handshakes.
+    pub fn update_tls(&mut self, tls: TlsConfig) -> Result<(), Error> {
+        if let Some(enabled) = tls.client_authentication {
+            unsafe {
+                picoquic_set_client_authentication(self.as_ptr(), enabled as i32);
+            }
+        }
+
+        if let Some(chain) = tls.certificate_chain {
+            self.set_tls_certificate_chain(chain, FileFormat::DER)?;
+        }
+
+        if let Some(key) = tls.private_key {
+            self.set_tls_private_key(key, FileFormat::DER)?;
+        }
+
+        // Without the `openssl` feature, picotls verifies the certificates and picoquic can only
+        // add root certificates, but not remove the old ones. So, they are not updated.
+        #[cfg(feature = "openssl")]
+        if tls.root_certificate_filename.is_some() || tls.root_certificates.is_some() {
+            self.root_certificate_filename = tls.root_certificate_filename;
+            self.root_certificates = tls.root_certificates;
+
+            // Picoquic can only add root certificates, but not remove the old ones. As we verify
+            // the certificates by ourself, the verifier just needs the new root certificates.
+            if !self.custom_verifier {
+                let verifier = Box::new(StoreVerifier::new(self.root_store()?));
+
+                if let Some(ref handlers) = self.verify_handlers {
+                    unsafe { (***handlers).set_default(verifier) }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Builds a `X509Store` with the root certificates.
+    #[cfg(feature = "openssl")]
+    fn root_store(&self) -> Result<X509Store, Error> {
+        root_store(&self.root_certificate_filename, &self.root_certificates)
+    }
+
+    /// Loads the credentials of the given `SniIdentity` into a copy of the TLS context.
+    fn add_sni_identity(
+        &mut self,
+        sni: &mut SniCertificates,
+        identity: SniIdentity,
+    ) -> Result<(), Error> {
+        let tls = TlsConfig::credentials(
+            identity.certificate_chain,
+            identity.private_key,
+            identity.format,
+        )?;
+        let (chain, key) = match (tls.certificate_chain, tls.private_key) {
+            (Some(chain), Some(key)) => (chain, key),
+            _ => bail!("The credentials of the SNI identity are missing"),
+        };
+
+        unsafe {
+            // Picoquic only loads credentials into its own TLS context and would free the
+            // current credentials. These are put aside, until the new credentials are copied.
+            let master = (*self.as_ptr()).tls_master_ctx as *mut ptls_context_t;
+            let certificates = (*master).certificates;
+            let sign_certificate = (*master).sign_certificate;
+            (*master).certificates.list = ptr::null_mut();
+            (*master).certificates.count = 0;
+            (*master).sign_certificate = ptr::null_mut();
+
+            let res = self
+                .set_tls_certificate_chain(chain, FileFormat::DER)
+                .and_then(|_| self.set_tls_private_key(key, FileFormat::DER));
+            let ctx = *master;
+
+            (*master).certificates = certificates;
+            (*master).sign_certificate = sign_certificate;
+            res?;
+
+            sni.add(identity.hostname_pattern, ctx);
+        }
+
+        Ok(())
+    }
+
     /// Sets the tls certificate chain.
     fn set_tls_certificate_chain(
         &mut self,
@@ -234,10 +651,7 @@ impl QuicCtx {
 
     /// Sets the tls private key.
     fn set_tls_private_key(&mut self, key: Vec<u8>, format: FileFormat) -> Result<(), Error> {
-        let mut key = match format {
-            FileFormat::DER => key,
-            FileFormat::PEM => PKey::private_key_from_pem(&key)?.private_key_to_der()?,
-        };
+        let mut key = key_to_der(key, format)?;
 
         let len = key.len();
         let key_ptr = key.as_mut_ptr();
@@ -262,22 +676,141 @@ impl Drop for QuicCtx {
     }
 }
 
-fn make_certs_iovec(
-    certs: Vec<Vec<u8>>,
-    format: FileFormat,
-) -> Result<(*mut ptls_iovec_t, usize), Error> {
-    let certs = match format {
-        FileFormat::DER => certs,
+/// The TLS configuration of a `Config`, that can be applied to a running `QuicCtx`.
+/// All certificates are loaded and checked up-front.
+pub struct TlsConfig {
+    /// The certificate chain in DER format.
+    certificate_chain: Option<Vec<Vec<u8>>>,
+    /// The private key in DER format.
+    private_key: Option<Vec<u8>>,
+    #[cfg(feature = "openssl")]
+    root_certificate_filename: Option<PathBuf>,
+    #[cfg(feature = "openssl")]
+    root_certificates: Option<(FileFormat, Vec<Vec<u8>>)>,
+    client_authentication: Option<bool>,
+}
+
+impl TlsConfig {
+    pub fn new(config: &Config) -> Result<TlsConfig, Error> {
+        let certificate_chain = if let Some((format, ref chain)) = config.certificate_chain {
+            Some(certs_to_der(chain.clone(), format)?)
+        } else if let Some(ref path) = config.certificate_chain_filename {
+            Some(pem::certificates(&fs::read(path)?)?)
+        } else {
+            None
+        };
+
+        let private_key = if let Some((format, ref key)) = config.private_key {
+            Some(key_to_der(key.clone(), format)?)
+        } else if let Some(ref path) = config.private_key_filename {
+            Some(key_to_der(fs::read(path)?, FileFormat::PEM)?)
+        } else {
+            None
+        };
+
+        // Check that the root certificates can be loaded.
+        #[cfg(feature = "openssl")]
+        root_store(&config.root_certificate_filename, &config.root_certificates)?;
+
+        Ok(TlsConfig {
+            certificate_chain,
+            private_key,
+            #[cfg(feature = "openssl")]
+            root_certificate_filename: config.root_certificate_filename.clone(),
+            #[cfg(feature = "openssl")]
+            root_certificates: config.root_certificates.clone(),
+            client_authentication: Some(config.client_authentication),
+        })
+    }
+
+    /// Creates a new instance that only replaces the certificate chain and the private key.
+    pub fn credentials(
+        chain: Vec<Vec<u8>>,
+        private_key: Vec<u8>,
+        format: FileFormat,
+    ) -> Result<TlsConfig, Error> {
+        let chain = certs_to_der(chain, format)?;
+        let private_key = key_to_der(private_key, format)?;
+
+        if chain.is_empty() {
+            bail!("The certificate chain is empty");
+        }
+
+        // Without the `openssl` feature, picotls fails the handshakes with a wrong key.
+        #[cfg(feature = "openssl")]
+        {
+            let public_key = X509::from_der(&chain[0])?.public_key()?;
+
+            if !public_key.public_eq(&PKey::private_key_from_der(&private_key)?) {
+                bail!("The private key does not belong to the certificate");
+            }
+        }
+
+        Ok(TlsConfig {
+            certificate_chain: Some(chain),
+            private_key: Some(private_key),
+            #[cfg(feature = "openssl")]
+            root_certificate_filename: None,
+            #[cfg(feature = "openssl")]
+            root_certificates: None,
+            client_authentication: None,
+        })
+    }
+}
+
+/// Builds a `X509Store` with the given root certificates.
+#[cfg(feature = "openssl")]
+fn root_store(
+    filename: &Option<PathBuf>,
+    certs: &Option<(FileFormat, Vec<Vec<u8>>)>,
+) -> Result<X509Store, Error> {
+    let mut builder = X509StoreBuilder::new()?;
+
+    // Like picotls, the default certificates of OpenSSL are used without root certificates.
+    if filename.is_none() && certs.is_none() {
+        builder.set_default_paths()?;
+    }
+
+    if let Some(ref path) = *filename {
+        for cert in pem::certificates(&fs::read(path)?)? {
+            builder.add_cert(X509::from_der(&cert)?)?;
+        }
+    }
+
+    if let Some((format, ref certs)) = *certs {
+        for cert in certs_to_der(certs.clone(), format)? {
+            builder.add_cert(X509::from_der(&cert)?)?;
+        }
+    }
+
+    Ok(builder.build())
+}
+
+fn key_to_der(key: Vec<u8>, format: FileFormat) -> Result<Vec<u8>, Error> {
+    match format {
+        FileFormat::DER => Ok(key),
+        FileFormat::PEM => pem::private_key(&key),
+    }
+}
+
+fn certs_to_der(certs: Vec<Vec<u8>>, format: FileFormat) -> Result<Vec<Vec<u8>>, Error> {
+    match format {
+        FileFormat::DER => Ok(certs),
         FileFormat::PEM => {
             let mut res = Vec::with_capacity(certs.len());
-            for cert in certs {
-                res.push(X509::from_pem(&cert)?.to_der()?);
+            for data in certs {
+                res.extend(pem::certificates(&data)?);
             }
-            res
+            Ok(res)
         }
-    };
+    }
+}
 
-    let mut certs = certs
+fn make_certs_iovec(
+    certs: Vec<Vec<u8>>,
+    format: FileFormat,
+) -> Result<(*mut ptls_iovec_t, usize), Error> {
+    let mut certs = certs_to_der(certs, format)?
         .into_iter()
         .map(|mut cert| {
             let len = cert.len();
@@ -305,6 +838,34 @@ pub fn socket_addr_from_c(sock_addr: *mut picoquic::sockaddr, sock_len: i32) ->
         .expect("neither ipv4 nor ipv6?")
 }
 
+/// Returns the destination connection id of the given QUIC packet. Packets with a short header
+/// do not carry the length of the connection id, so `short_id_len` is used for them.
+fn destination_connection_id(packet: &[u8], short_id_len: usize) -> Option<&[u8]> {
+    let first = *packet.first()?;
+
+    // The long header carries the version and the length of the connection id.
+    let (start, len) = if first & 0x80 != 0 {
+        (6, *packet.get(5)? as usize)
+    } else {
+        (1, short_id_len)
+    };
+
+    packet.get(start..start + len)
+}
+
+/// Returns the QUIC versions that picoquic implements, in the order of its preference.
+pub fn implemented_versions() -> Vec<u32> {
+    unsafe {
+        slice::from_raw_parts(
+            picoquic::picoquic_supported_versions.as_ptr(),
+            picoquic::picoquic_nb_supported_versions,
+        )
+        .iter()
+        .map(|v| v.version)
+        .collect()
+    }
+}
+
 pub trait MicroSeconds {
     fn from_micro_seconds(micros: u64) -> Self;
     fn as_micro_seconds(&self) -> u64;
@@ -327,6 +888,19 @@ impl MicroSeconds for Duration {
 mod tests {
     use super::*;
 
+    #[test]
+    fn destination_connection_id_of_long_and_short_header() {
+        let long = [0xc3, 0, 0, 0, 1, 4, 1, 2, 3, 4, 8, 9];
+        assert_eq!(Some(&[1, 2, 3, 4][..]), destination_connection_id(&long, 8));
+
+        let short = [0x43, 5, 6, 7, 8, 9];
+        assert_eq!(Some(&[5, 6, 7][..]), destination_connection_id(&short, 3));
+
+        assert_eq!(None, destination_connection_id(&long[..8], 8));
+        assert_eq!(None, destination_connection_id(&short, 8));
+        assert_eq!(None, destination_connection_id(&[], 8));
+    }
+
     #[test]
     fn from_micro_seconds() {
         assert_eq!(
@@ -341,6 +915,20 @@ mod tests {
         assert_eq!(Duration::new(0, 500000), Duration::from_micro_seconds(500));
     }
 
+    #[cfg(feature = "openssl")]
+    #[test]
+    fn pem_can_contain_multiple_certificates() {
+        let ca = include_bytes!("../../tests/certs/ca.crt");
+        let device = include_bytes!("../../tests/certs/device.test.crt");
+        let chain = [&device[..], &ca[..]].concat();
+
+        let der = certs_to_der(vec![chain], FileFormat::PEM).unwrap();
+
+        assert_eq!(2, der.len());
+        assert_eq!(X509::from_pem(device).unwrap().to_der().unwrap(), der[0]);
+        assert_eq!(X509::from_pem(ca).unwrap().to_der().unwrap(), der[1]);
+    }
+
     #[test]
     fn as_micro_seconds() {
         assert_eq!(Duration::from_secs(1).as_micro_seconds(), 1_000_000);
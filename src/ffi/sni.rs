@@ -0,0 +1,106 @@
+use sni::hostname_matches;
+
+use picoquic_sys::picoquic::{
+    picoquic_quic_t, ptls_context_t, ptls_on_client_hello_parameters_t, ptls_on_client_hello_t,
+    ptls_set_context, ptls_t,
+};
+
+use std::{os::raw::c_int, slice, str};
+
+/// The TLS context of a `SniIdentity`, a copy of the TLS context of picoquic with other
+/// credentials.
+struct Identity {
+    hostname_pattern: String,
+    ctx: Box<ptls_context_t>,
+}
+
+/// Selects the TLS context of an incoming connection by the server name(SNI) of the client.
+///
+/// Picotls only knows the pointer to `on_client_hello`, so it needs to be the first field. The
+/// callback casts it back to the `SniCertificates`.
+#[repr(C)]
+pub struct SniCertificates {
+    on_client_hello: ptls_on_client_hello_t,
+    /// The callback of picoquic, that selects the application layer protocol.
+    inner: *mut ptls_on_client_hello_t,
+    /// The TLS context of picoquic, that is used for all server names without identity.
+    master: *mut ptls_context_t,
+    identities: Vec<Identity>,
+}
+
+impl SniCertificates {
+    /// Installs the callback into the TLS context of the given picoquic context.
+    pub unsafe fn install(quic: *mut picoquic_quic_t) -> Box<SniCertificates> {
+        let master = (*quic).tls_master_ctx as *mut ptls_context_t;
+
+        let mut sni = Box::new(SniCertificates {
+            on_client_hello: ptls_on_client_hello_t {
+                cb: Some(on_client_hello),
+            },
+            inner: (*master).on_client_hello,
+            master,
+            identities: Vec::new(),
+        });
+        (*master).on_client_hello = &mut sni.on_client_hello;
+
+        sni
+    }
+
+    /// Adds the TLS context for the given hostname pattern. The patterns are matched in the
+    /// order they were added.
+    pub fn add(&mut self, hostname_pattern: String, ctx: ptls_context_t) {
+        self.identities.push(Identity {
+            hostname_pattern,
+            ctx: Box::new(ctx),
+        });
+    }
+
+    /// Returns the TLS context of the given server name. The context is updated with all
+    /// settings of picoquic, except the credentials.
+    unsafe fn context(&mut self, server_name: &str) -> Option<*mut ptls_context_t> {
+        let master = self.master;
+        let identity = self
+            .identities
+            .iter_mut()
+            .find(|i| hostname_matches(&i.hostname_pattern, server_name))?;
+
+        let certificates = identity.ctx.certificates;
+        let sign_certificate = identity.ctx.sign_certificate;
+        *identity.ctx = *master;
+        identity.ctx.certificates = certificates;
+        identity.ctx.sign_certificate = sign_certificate;
+
+        Some(&mut *identity.ctx as *mut _)
+    }
+}
+
+unsafe extern "C" fn on_client_hello(
+    self_: *mut ptls_on_client_hello_t,
+    tls: *mut ptls_t,
+    params: *mut ptls_on_client_hello_parameters_t,
+) -> c_int {
+    let sni = &mut *(self_ as *mut SniCertificates);
+
+    if !sni.inner.is_null() {
+        if let Some(cb) = (*sni.inner).cb {
+            let ret = cb(sni.inner, tls, params);
+
+            if ret != 0 {
+                return ret;
+            }
+        }
+    }
+
+    let name = (*params).server_name;
+    if name.base.is_null() {
+        return 0;
+    }
+
+    if let Ok(name) = str::from_utf8(slice::from_raw_parts(name.base, name.len)) {
+        if let Some(ctx) = sni.context(name) {
+            ptls_set_context(tls, ctx);
+        }
+    }
+
+    0
+}
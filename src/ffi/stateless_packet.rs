@@ -37,6 +37,27 @@ impl StatelessPacket {
         socket_addr_from_c(addr, socket_len as i32)
     }
 
+    /// Returns the local address the packet should be sent from.
+    /// Returns `None`, if picoquic did not set the local address.
+    pub fn get_local_addr(&self) -> Option<SocketAddr> {
+        let socket_family = unsafe { (*self.packet).addr_local.ss_family };
+
+        let socket_len = if i32::from(socket_family) == libc::AF_INET {
+            mem::size_of::<libc::sockaddr_in>()
+        } else if i32::from(socket_family) == libc::AF_INET6 {
+            mem::size_of::<libc::sockaddr_in6>()
+        } else {
+            return None;
+        };
+
+        let addr = unsafe {
+            mem::transmute::<_, *mut libc::sockaddr_storage>(&mut (*self.packet).addr_local)
+                as *mut picoquic::sockaddr
+        };
+
+        Some(socket_addr_from_c(addr, socket_len as i32))
+    }
+
     pub fn get_data(&self) -> &[u8] {
         unsafe {
             slice::from_raw_parts(
@@ -1,6 +1,9 @@
 use error::*;
-use ffi::{Connection, QuicCtx};
-use verify_certificate::VerifyCertificate;
+use ffi::{Connection, PendingVerifications, Pointer, QuicCtx};
+use verify_certificate::{
+    default_verify_certificate, AsyncVerifyCertificate, VerifyCertificate, VerifyContext,
+};
+use {ConnectionType, Role};
 
 use picoquic_sys::picoquic::{
     picoquic_cnx_t, picoquic_set_verify_certificate_callback, picoquic_verify_sign_cb_fn,
@@ -9,28 +12,176 @@ use picoquic_sys::picoquic::{
     PTLS_ERROR_LIBRARY, PTLS_ERROR_NO_MEMORY,
 };
 
+use std::collections::HashMap;
 use std::mem;
 use std::os::raw::{c_int, c_void};
 use std::slice;
 
+use futures::{
+    Async::{NotReady, Ready},
+    Future,
+};
+
 use openssl::error::ErrorStack;
 use openssl::hash::MessageDigest;
 use openssl::pkey::{Id, PKey, Public};
 use openssl::rsa::Padding;
 use openssl::sign::{RsaPssSaltlen, Verifier};
-use openssl::stack::Stack;
-use openssl::x509::X509;
+use openssl::stack::{Stack, StackRef};
+use openssl::x509::store::X509Store;
+use openssl::x509::{X509Ref, X509};
 
 use openssl_sys::{X509_V_ERR_CERT_HAS_EXPIRED, X509_V_ERR_CERT_REVOKED, X509_V_ERR_OUT_OF_MEM};
 
 pub type PubKey = PKey<Public>;
 
-/// Sets up the verify certificate callback in picoquic
-pub fn setup_callback(quic: &QuicCtx, handler: Box<VerifyCertificate>) -> Result<(), Error> {
+type Verification = Box<dyn Future<Item = bool, Error = ErrorStack>>;
+
+/// The verify certificate handlers of a `QuicCtx`.
+pub struct Handlers {
+    /// Verifies the certificates of all connections that do not have their own handler.
+    default: Box<VerifyCertificate>,
+    /// The handlers of individual connections.
+    connections: HashMap<*mut picoquic_cnx_t, Box<dyn VerifyCertificate + Send>>,
+    /// The certificates (DER format) the peers presented, the peer certificate first.
+    peer_certificates: HashMap<*mut picoquic_cnx_t, Vec<Vec<u8>>>,
+    /// Verifies the certificates of all connections that do not have their own handler, instead
+    /// of the `default` handler.
+    async_handler: Option<Box<dyn AsyncVerifyCertificate>>,
+    /// The unfinished verifications of the `async_handler`.
+    verifications: HashMap<*mut picoquic_cnx_t, Verification>,
+    pending: PendingVerifications,
+}
+
+impl Handlers {
+    /// Sets the handler for the given connection.
+    pub fn insert(&mut self, cnx: Connection, handler: Box<dyn VerifyCertificate + Send>) {
+        self.connections.insert(cnx.as_ptr(), handler);
+    }
+
+    /// Sets the handler for all connections that do not have their own handler.
+    pub fn set_default(&mut self, handler: Box<VerifyCertificate>) {
+        self.default = handler;
+    }
+
+    /// Sets the asynchronous handler for all connections that do not have their own handler.
+    /// The connections with an unfinished verification are tracked in `pending`.
+    pub fn set_async(
+        &mut self,
+        handler: Box<dyn AsyncVerifyCertificate>,
+        pending: PendingVerifications,
+    ) {
+        self.async_handler = Some(handler);
+        self.pending = pending;
+    }
+
+    /// Removes the handler, the peer certificates and the unfinished verification of the given
+    /// connection.
+    pub fn remove(&mut self, cnx: Connection) {
+        self.connections.remove(&cnx.as_ptr());
+        self.peer_certificates.remove(&cnx.as_ptr());
+        self.verifications.remove(&cnx.as_ptr());
+        self.pending.remove(cnx.as_ptr());
+    }
+
+    /// Polls the unfinished verifications. A connection whose certificate could not be verified
+    /// is closed with the TLS alert of the failure.
+    /// Returns the connections with a finished verification and if the certificate was verified.
+    pub fn poll_verifications(&mut self) -> Vec<(Connection, bool)> {
+        let pending = &self.pending;
+        let mut finished = Vec::new();
+
+        self.verifications.retain(|cnx, verification| {
+            let error = match verification.poll() {
+                Ok(NotReady) => return true,
+                Ok(Ready(true)) => None,
+                Ok(Ready(false)) => Some(PTLS_ALERT_CERTIFICATE_UNKNOWN),
+                Err(e) => Some(ssl_error_to_error_code(&e)),
+            };
+
+            let cnx = Connection::from(*cnx);
+            if let Some(error) = error {
+                cnx.close_with_tls_error(error);
+            }
+
+            pending.remove(cnx.as_ptr());
+            finished.push((cnx, error.is_none()));
+            false
+        });
+
+        finished
+    }
+
+    /// Returns the certificates (DER format) the peer of the given connection presented.
+    pub fn peer_certificates(&self, cnx: Connection) -> Option<&Vec<Vec<u8>>> {
+        self.peer_certificates.get(&cnx.as_ptr())
+    }
+
+    /// Verifies the certificate of the given connection. The asynchronous verification is
+    /// started and the certificate is accepted until the verification finished.
+    fn verify(
+        &mut self,
+        cnx: Connection,
+        context: &VerifyContext,
+        cert: &X509Ref,
+        chain: &StackRef<X509>,
+    ) -> Result<bool, ErrorStack> {
+        if let Some(handler) = self.connections.get_mut(&cnx.as_ptr()) {
+            return handler.verify(context, cert, chain);
+        }
+
+        match self.async_handler {
+            Some(ref mut handler) => {
+                let verification = handler.verify(context, cert, chain);
+                self.verifications.insert(cnx.as_ptr(), verification);
+                self.pending.insert(cnx.as_ptr());
+                Ok(true)
+            }
+            None => self.default.verify(context, cert, chain),
+        }
+    }
+}
+
+/// Verifies certificates against a `X509Store` and the server name, like picoquic does without a
+/// custom handler.
+pub struct StoreVerifier {
+    store: X509Store,
+}
+
+impl StoreVerifier {
+    pub fn new(store: X509Store) -> StoreVerifier {
+        StoreVerifier { store }
+    }
+}
+
+impl VerifyCertificate for StoreVerifier {
+    fn verify(
+        &mut self,
+        context: &VerifyContext,
+        cert: &X509Ref,
+        chain: &StackRef<X509>,
+    ) -> Result<bool, ErrorStack> {
+        default_verify_certificate(context, cert, chain, &self.store)
+    }
+}
+
+/// Sets up the verify certificate callback in picoquic.
+/// The returned `Handlers` stay valid until the `QuicCtx` is dropped.
+pub fn setup_callback(
+    quic: &QuicCtx,
+    handler: Box<VerifyCertificate>,
+) -> Result<Pointer<Handlers>, Error> {
     let result;
-    unsafe {
-        let ctx = Box::into_raw(Box::new(handler));
+    let ctx = Box::into_raw(Box::new(Handlers {
+        default: handler,
+        connections: HashMap::new(),
+        peer_certificates: HashMap::new(),
+        async_handler: None,
+        verifications: HashMap::new(),
+        pending: PendingVerifications::default(),
+    }));
 
+    unsafe {
         result = picoquic_set_verify_certificate_callback(
             quic.as_ptr(),
             Some(verify_certificate_callback),
@@ -42,13 +193,13 @@ pub fn setup_callback(quic: &QuicCtx, handler: Box<VerifyCertificate>) -> Result
     if result != 0 {
         Err(ErrorKind::OutOfMemoryError.into())
     } else {
-        Ok(())
+        Ok(Pointer(ctx))
     }
 }
 
 /// Will be called by picoquic to free the handler context
 unsafe extern "C" fn free_ctx(ctx: *mut c_void) {
-    let _ = get_handler(ctx);
+    let _ = get_handlers(ctx);
 }
 
 /// Will be called by picoquic to verify the signed data
@@ -111,10 +262,18 @@ unsafe extern "C" fn verify_certificate_callback(
     verify_sign: *mut picoquic_verify_sign_cb_fn,
     verify_sign_ctx: *mut *mut c_void,
 ) -> c_int {
-    let mut handler = get_handler(ctx);
+    let mut handlers = get_handlers(ctx);
+
+    if num_certs > 0 {
+        let der = slice::from_raw_parts(certs, num_certs)
+            .iter()
+            .map(|cert| slice::from_raw_parts(cert.base, cert.len).to_vec())
+            .collect();
+        handlers.peer_certificates.insert(cnx, der);
+    }
 
     let result = verify_certificate_callback_impl(
-        &mut handler,
+        &mut handlers,
         cnx,
         certs,
         num_certs,
@@ -122,13 +281,13 @@ unsafe extern "C" fn verify_certificate_callback(
         verify_sign_ctx,
     );
 
-    mem::forget(handler);
+    mem::forget(handlers);
 
     result as i32
 }
 
 fn verify_certificate_callback_impl(
-    handler: &mut Box<Box<VerifyCertificate>>,
+    handlers: &mut Handlers,
     cnx: *mut picoquic_cnx_t,
     certs: *mut ptls_iovec_t,
     num_certs: usize,
@@ -146,9 +305,17 @@ fn verify_certificate_callback_impl(
 
     let cnx = Connection::from(cnx);
 
-    let id = cnx.local_id();
+    let context = VerifyContext {
+        connection_id: cnx.local_id(),
+        role: match cnx.con_type() {
+            ConnectionType::Outgoing => Role::Client,
+            ConnectionType::Incoming => Role::Server,
+        },
+        peer_addr: cnx.peer_addr(),
+        server_name: cnx.server_name(),
+    };
 
-    match handler.verify(id, cnx.con_type(), &cert, &chain) {
+    match handlers.verify(cnx, &context, &cert, &chain) {
         Ok(true) => {}
         Ok(false) => {
             return PTLS_ALERT_CERTIFICATE_UNKNOWN;
@@ -171,8 +338,8 @@ fn verify_certificate_callback_impl(
     0
 }
 
-fn get_handler(ptr: *mut c_void) -> Box<Box<VerifyCertificate>> {
-    unsafe { Box::from_raw(ptr as *mut Box<VerifyCertificate>) }
+fn get_handlers(ptr: *mut c_void) -> Box<Handlers> {
+    unsafe { Box::from_raw(ptr as *mut Handlers) }
 }
 
 /// Converts a openssl error to a picotls error
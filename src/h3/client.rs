@@ -0,0 +1,132 @@
+use super::{
+    control::{OpenControlStream, PeerStreams},
+    message::{ReadMessage, Request, Response, SendMessage},
+};
+use connection::{Connection, NewStreamFuture, NewStreamHandle};
+use error::*;
+use stream::Stream;
+
+use futures::{Async::Ready, Future, Poll};
+
+use std::sync::{Arc, Mutex};
+
+/// A HTTP/3 client on top of a `Connection`, see `Client::new`.
+pub struct Client {
+    connection: Connection,
+    new_stream: NewStreamHandle,
+    /// The control stream needs to stay open for the lifetime of the `Connection`.
+    _control: Stream,
+    peer: Arc<Mutex<PeerStreams>>,
+}
+
+impl Client {
+    /// Starts HTTP/3 on the given `Connection`. The `Connection` should negotiate `h3::ALPN`,
+    /// see `Config::set_alpn_protocols`. Resolves to the `Client`, after the control stream is
+    /// opened.
+    pub fn new(connection: Connection) -> NewClient {
+        NewClient {
+            inner: OpenControlStream::new(connection, Vec::new()),
+        }
+    }
+
+    /// Sends a `GET` request for the given absolute URL, e.g. `https://example.com/`.
+    pub fn get(&mut self, url: &str) -> ResponseFuture {
+        match Request::get(url) {
+            Ok(request) => self.send_request(request),
+            Err(err) => ResponseFuture {
+                state: RequestState::Failed(Some(err)),
+                peer: self.peer.clone(),
+            },
+        }
+    }
+
+    /// Sends the given `Request` on a new bidirectional `Stream`. Resolves to the `Response`,
+    /// after the server finished it.
+    pub fn send_request(&mut self, request: Request) -> ResponseFuture {
+        ResponseFuture {
+            state: RequestState::Opening(self.new_stream.new_bidirectional_stream(), request),
+            peer: self.peer.clone(),
+        }
+    }
+
+    /// Returns the underlying `Connection`.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+/// Resolves to a `Client`, see `Client::new`.
+pub struct NewClient {
+    inner: OpenControlStream,
+}
+
+impl Future for NewClient {
+    type Item = Client;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (connection, control) = try_ready!(self.inner.poll());
+
+        Ok(Ready(Client {
+            new_stream: connection.get_new_stream_handle(),
+            peer: Arc::new(Mutex::new(PeerStreams::new(
+                connection.incoming_unidirectional(),
+            ))),
+            _control: control,
+            connection,
+        }))
+    }
+}
+
+enum RequestState {
+    Failed(Option<Error>),
+    Opening(NewStreamFuture, Request),
+    Sending(SendMessage),
+    Reading(ReadMessage),
+}
+
+/// Resolves to the `Response` of a `Request`, see `Client::send_request`.
+pub struct ResponseFuture {
+    state: RequestState,
+    peer: Arc<Mutex<PeerStreams>>,
+}
+
+impl Future for ResponseFuture {
+    type Item = Response;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // The control stream of the server is processed by all requests.
+        let goaway = {
+            let mut peer = self.peer.lock().unwrap();
+            peer.poll()?;
+            peer.goaway()
+        };
+
+        loop {
+            let next = match self.state {
+                RequestState::Failed(ref mut err) => {
+                    return Err(err.take().expect("`ResponseFuture` polled after error"));
+                }
+                RequestState::Opening(ref mut new_stream, ref request) => {
+                    let stream = try_ready!(new_stream.poll());
+
+                    if goaway.map_or(false, |id| stream.id() >= id) {
+                        bail!("server does not accept new requests (`GOAWAY`)");
+                    }
+
+                    RequestState::Sending(SendMessage::new(stream, request.encode()))
+                }
+                RequestState::Sending(ref mut send) => {
+                    RequestState::Reading(ReadMessage::new(try_ready!(send.poll())))
+                }
+                RequestState::Reading(ref mut read) => {
+                    let (_, fields, body) = try_ready!(read.poll());
+                    return Response::decode(fields, body).map(Ready);
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use super::{
+    frame::{self, Frame},
+    ALPN,
+};
+use connection::{Connection, IncomingStreams, NewStreamFuture};
+use error::*;
+use stream::Stream;
+use varint;
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{
+    Async::{NotReady, Ready},
+    AsyncSink, Future, Poll, Sink, Stream as FStream,
+};
+
+use std::mem;
+
+/// The maximum capacity of the QPACK dynamic table.
+const SETTINGS_QPACK_MAX_TABLE_CAPACITY: u64 = 0x01;
+/// The number of streams that can be blocked by the QPACK dynamic table.
+const SETTINGS_QPACK_BLOCKED_STREAMS: u64 = 0x07;
+
+/// The error code to abort reading a unidirectional stream of an unknown type.
+const H3_STREAM_CREATION_ERROR: u64 = 0x0103;
+
+/// Opens the control stream of a `Connection` and sends the `SETTINGS`.
+/// The control stream needs to stay open for the lifetime of the `Connection`.
+pub(crate) struct OpenControlStream {
+    /// The settings of extensions, that are sent in addition to the settings of QPACK.
+    settings: Vec<(u64, u64)>,
+    connection: Option<Connection>,
+    state: State,
+}
+
+enum State {
+    Failed(Option<Error>),
+    Opening(NewStreamFuture),
+    Sending(Option<Stream>, Option<Bytes>),
+}
+
+impl OpenControlStream {
+    pub fn new(mut connection: Connection, settings: Vec<(u64, u64)>) -> OpenControlStream {
+        let state = match connection.negotiated_alpn() {
+            Some(ref alpn) if alpn.as_slice() != ALPN => State::Failed(Some(
+                ::failure::err_msg("the `Connection` did not negotiate HTTP/3").into(),
+            )),
+            _ => State::Opening(connection.new_unidirectional_stream()),
+        };
+
+        OpenControlStream {
+            settings,
+            connection: Some(connection),
+            state,
+        }
+    }
+}
+
+impl Future for OpenControlStream {
+    type Item = (Connection, Stream);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Failed(ref mut err) => {
+                    return Err(err.take().expect("`OpenControlStream` polled after error"))
+                }
+                State::Opening(ref mut new_stream) => {
+                    let stream = try_ready!(new_stream.poll());
+                    State::Sending(Some(stream), Some(encode_settings(&self.settings)))
+                }
+                State::Sending(ref mut stream, ref mut pending) => {
+                    let mut control = stream.take().expect("`OpenControlStream` polled twice");
+
+                    if let Some(data) = pending.take() {
+                        if let AsyncSink::NotReady(data) = control.start_send(data)? {
+                            *pending = Some(data);
+                            *stream = Some(control);
+                            return Ok(NotReady);
+                        }
+                    }
+
+                    if control.poll_complete()?.is_not_ready() {
+                        *stream = Some(control);
+                        return Ok(NotReady);
+                    }
+
+                    let connection = self.connection.take().expect("polled twice");
+                    return Ok(Ready((connection, control)));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+/// Returns the type of the control stream, followed by the `SETTINGS` frame with the given
+/// settings of extensions.
+fn encode_settings(extensions: &[(u64, u64)]) -> Bytes {
+    let mut settings = vec![
+        (SETTINGS_QPACK_MAX_TABLE_CAPACITY, 0),
+        (SETTINGS_QPACK_BLOCKED_STREAMS, 0),
+    ];
+    settings.extend_from_slice(extensions);
+
+    let mut buf = Vec::new();
+    varint::encode(frame::CONTROL_STREAM, &mut buf);
+    Frame::Settings(settings).encode(&mut buf);
+    buf.into()
+}
+
+/// The state of the control stream of the peer.
+#[derive(Default)]
+struct PeerControl {
+    /// Did the peer open its control stream?
+    opened: bool,
+    settings: Option<Vec<(u64, u64)>>,
+    goaway: Option<u64>,
+}
+
+impl PeerControl {
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        match frame {
+            Frame::Settings(settings) => {
+                if self.settings.is_some() {
+                    bail!("peer sent a second `SETTINGS` frame");
+                }
+
+                self.settings = Some(settings);
+            }
+            _ if self.settings.is_none() => {
+                bail!("first frame on the control stream of the peer is not `SETTINGS`");
+            }
+            Frame::GoAway(id) => self.goaway = Some(id),
+            Frame::Data(_) | Frame::Headers(_) => {
+                bail!("peer sent a request frame on its control stream");
+            }
+            Frame::Unknown(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// The result of polling a `PeerStream`.
+enum Polled {
+    /// The stream is still used.
+    Open,
+    /// The stream is not used anymore.
+    Done,
+    /// The stream has an accepted type of an extension, see `PeerStreams::accept_stream_type`.
+    Accepted,
+}
+
+/// A unidirectional stream of the peer.
+struct PeerStream {
+    stream: Stream,
+    /// The type of the stream, after it was received.
+    stype: Option<u64>,
+    buffer: BytesMut,
+}
+
+impl PeerStream {
+    /// Processes the received data of this stream.
+    fn poll(&mut self, control: &mut PeerControl, accepted: &[u64]) -> Result<Polled, Error> {
+        loop {
+            match self.stream.poll()? {
+                Ready(Some(data)) => self.buffer.extend_from_slice(&data),
+                Ready(None) if self.stype == Some(frame::CONTROL_STREAM) => {
+                    bail!("peer closed its control stream");
+                }
+                Ready(None) => return Ok(Polled::Done),
+                NotReady => return Ok(Polled::Open),
+            }
+
+            if self.stype.is_none() {
+                let (stype, len) = match varint::decode(&self.buffer) {
+                    Some(stype) => stype,
+                    None => continue,
+                };
+                self.buffer.advance(len);
+                self.stype = Some(stype);
+
+                match stype {
+                    frame::CONTROL_STREAM if control.opened => {
+                        bail!("peer opened a second control stream");
+                    }
+                    frame::CONTROL_STREAM => control.opened = true,
+                    frame::QPACK_ENCODER_STREAM | frame::QPACK_DECODER_STREAM => {}
+                    _ if accepted.contains(&stype) => return Ok(Polled::Accepted),
+                    // Push streams are not supported, so they are treated as unknown.
+                    _ => {
+                        debug!("stopping peer's unidirectional stream of type {:#x}", stype);
+                        self.stream.stop_sending(H3_STREAM_CREATION_ERROR)?;
+                        return Ok(Polled::Done);
+                    }
+                }
+            }
+
+            if self.stype == Some(frame::CONTROL_STREAM) {
+                while let Some(frame) = Frame::decode(&mut self.buffer)? {
+                    control.handle_frame(frame)?;
+                }
+            } else {
+                // Without a dynamic table, the QPACK streams carry nothing of interest.
+                self.buffer.clear();
+            }
+        }
+    }
+}
+
+/// Processes the unidirectional streams of the peer: its control stream and its QPACK
+/// streams.
+pub(crate) struct PeerStreams {
+    incoming: IncomingStreams,
+    incoming_finished: bool,
+    streams: Vec<PeerStream>,
+    control: PeerControl,
+    /// The stream types of extensions, that are not rejected.
+    accepted_types: Vec<u64>,
+    /// The streams of accepted types with their received data after the stream type.
+    accepted: Vec<(Stream, BytesMut)>,
+}
+
+impl PeerStreams {
+    pub fn new(incoming: IncomingStreams) -> PeerStreams {
+        PeerStreams {
+            incoming,
+            incoming_finished: false,
+            streams: Vec::new(),
+            control: PeerControl::default(),
+            accepted_types: Vec::new(),
+            accepted: Vec::new(),
+        }
+    }
+
+    /// Accepts the unidirectional streams of the given type, instead of stopping them. The
+    /// accepted streams are returned by `take_accepted`.
+    pub fn accept_stream_type(&mut self, stype: u64) {
+        self.accepted_types.push(stype);
+    }
+
+    /// Processes all data the peer sent so far. Needs to be called from within a task, that is
+    /// notified when the peer sends more data.
+    pub fn poll(&mut self) -> Result<(), Error> {
+        while !self.incoming_finished {
+            match self.incoming.poll()? {
+                Ready(Some(stream)) => self.streams.push(PeerStream {
+                    stream,
+                    stype: None,
+                    buffer: BytesMut::new(),
+                }),
+                Ready(None) => self.incoming_finished = true,
+                NotReady => break,
+            }
+        }
+
+        let mut i = 0;
+        while i < self.streams.len() {
+            match self.streams[i].poll(&mut self.control, &self.accepted_types)? {
+                Polled::Open => i += 1,
+                Polled::Done => {
+                    self.streams.remove(i);
+                }
+                Polled::Accepted => {
+                    let stream = self.streams.remove(i);
+                    self.accepted.push((stream.stream, stream.buffer));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the id of the first `Stream` the peer does not process anymore, after it sent a
+    /// `GOAWAY`.
+    pub fn goaway(&self) -> Option<u64> {
+        self.control.goaway
+    }
+
+    /// Returns the settings of the peer, after its `SETTINGS` frame was received.
+    pub fn settings(&self) -> Option<&[(u64, u64)]> {
+        self.control.settings.as_ref().map(|s| s.as_slice())
+    }
+
+    /// Takes the streams of the accepted types, see `accept_stream_type`. The received data
+    /// after the stream type is returned with the stream.
+    pub fn take_accepted(&mut self) -> Vec<(Stream, BytesMut)> {
+        mem::replace(&mut self.accepted, Vec::new())
+    }
+}
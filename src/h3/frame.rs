@@ -0,0 +1,165 @@
+use error::*;
+use varint;
+
+use bytes::{Bytes, BytesMut};
+
+/// The type of the control stream.
+pub const CONTROL_STREAM: u64 = 0x00;
+/// The type of the QPACK encoder stream.
+pub const QPACK_ENCODER_STREAM: u64 = 0x02;
+/// The type of the QPACK decoder stream.
+pub const QPACK_DECODER_STREAM: u64 = 0x03;
+
+const DATA: u64 = 0x00;
+const HEADERS: u64 = 0x01;
+const SETTINGS: u64 = 0x04;
+const GOAWAY: u64 = 0x07;
+
+/// The frame types of HTTP/2 that are reserved in HTTP/3 and must not be sent.
+const RESERVED: [u64; 4] = [0x02, 0x06, 0x08, 0x09];
+
+/// The maximum size of a received frame.
+const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A frame of HTTP/3 (RFC 9114, section 7).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    Data(Bytes),
+    /// A QPACK encoded field section.
+    Headers(Bytes),
+    /// The identifiers and values of the settings.
+    Settings(Vec<(u64, u64)>),
+    /// The id of the first request that the sender of the `GOAWAY` does not process.
+    GoAway(u64),
+    /// A frame of an unknown type, that is ignored.
+    Unknown(u64),
+}
+
+impl Frame {
+    /// Appends the encoded frame to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let mut payload = Vec::new();
+
+        let frame_type = match *self {
+            Frame::Data(ref data) => {
+                payload.extend_from_slice(data);
+                DATA
+            }
+            Frame::Headers(ref fields) => {
+                payload.extend_from_slice(fields);
+                HEADERS
+            }
+            Frame::Settings(ref settings) => {
+                for (id, value) in settings {
+                    varint::encode(*id, &mut payload);
+                    varint::encode(*value, &mut payload);
+                }
+                SETTINGS
+            }
+            Frame::GoAway(id) => {
+                varint::encode(id, &mut payload);
+                GOAWAY
+            }
+            Frame::Unknown(frame_type) => frame_type,
+        };
+
+        varint::encode(frame_type, buf);
+        varint::encode(payload.len() as u64, buf);
+        buf.extend_from_slice(&payload);
+    }
+
+    /// Removes the first complete frame from the given buffer.
+    /// Returns `None`, if the buffer does not contain a complete frame yet.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Frame>, Error> {
+        let (frame_type, type_len) = match varint::decode(buf) {
+            Some(frame_type) => frame_type,
+            None => return Ok(None),
+        };
+        let (len, len_len) = match varint::decode(&buf[type_len..]) {
+            Some(len) => len,
+            None => return Ok(None),
+        };
+
+        if len > MAX_FRAME_SIZE {
+            bail!("HTTP/3 frame with {} bytes exceeds the maximum", len);
+        } else if RESERVED.contains(&frame_type) {
+            bail!("received reserved HTTP/2 frame type {:#x}", frame_type);
+        } else if buf.len() < type_len + len_len + len as usize {
+            return Ok(None);
+        }
+
+        buf.advance(type_len + len_len);
+        let payload = buf.split_to(len as usize).freeze();
+
+        let frame = match frame_type {
+            DATA => Frame::Data(payload),
+            HEADERS => Frame::Headers(payload),
+            SETTINGS => Frame::Settings(decode_settings(&payload)?),
+            GOAWAY => match varint::decode(&payload) {
+                Some((id, _)) => Frame::GoAway(id),
+                None => bail!("received truncated `GOAWAY` frame"),
+            },
+            frame_type => Frame::Unknown(frame_type),
+        };
+
+        Ok(Some(frame))
+    }
+}
+
+/// Decodes the identifiers and values of a `SETTINGS` frame.
+fn decode_settings(mut payload: &[u8]) -> Result<Vec<(u64, u64)>, Error> {
+    let mut settings = Vec::new();
+
+    while !payload.is_empty() {
+        match varint::decode(payload).and_then(|(id, id_len)| {
+            varint::decode(&payload[id_len..]).map(|(value, len)| (id, value, id_len + len))
+        }) {
+            Some((id, value, len)) => {
+                settings.push((id, value));
+                payload = &payload[len..];
+            }
+            None => bail!("received truncated `SETTINGS` frame"),
+        }
+    }
+
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoded_frames_are_decoded() {
+        let frames = vec![
+            Frame::Settings(vec![(0x01, 0), (0x06, 16_384)]),
+            Frame::Headers(Bytes::from(&b"\x00\x00\xd1"[..])),
+            Frame::Data(Bytes::from(&b"hello"[..])),
+            Frame::GoAway(4),
+            Frame::Unknown(0x21),
+        ];
+
+        let mut encoded = Vec::new();
+        frames.iter().for_each(|f| f.encode(&mut encoded));
+
+        // Feed the frames byte by byte, to check that incomplete frames are not decoded.
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in encoded {
+            buf.extend_from_slice(&[byte]);
+
+            if let Some(frame) = Frame::decode(&mut buf).unwrap() {
+                decoded.push(frame);
+            }
+        }
+
+        assert_eq!(frames, decoded);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn reserved_frame_types_are_rejected() {
+        let mut buf = BytesMut::from(&[0x06, 0x00][..]);
+        assert!(Frame::decode(&mut buf).is_err());
+    }
+}
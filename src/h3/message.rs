@@ -0,0 +1,458 @@
+use super::{frame::Frame, qpack};
+use error::*;
+use stream::Stream;
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{
+    Async::{NotReady, Ready},
+    AsyncSink, Future, Poll, Sink, Stream as FStream,
+};
+
+/// The maximum size of the body of a received `Request` or `Response`.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// The header fields of a `Request` or `Response`, without the pseudo-header fields.
+pub type Headers = Vec<(String, String)>;
+
+/// A HTTP/3 request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// The method, e.g. `GET`.
+    pub method: String,
+    /// The scheme of the target URI, e.g. `https`.
+    pub scheme: String,
+    /// The host and the optional port of the target URI.
+    pub authority: String,
+    /// The path and the optional query of the target URI, e.g. `/index.html?lang=en`.
+    pub path: String,
+    /// The protocol of an extended `CONNECT` (RFC 9220), e.g. `webtransport`.
+    pub protocol: Option<String>,
+    pub headers: Headers,
+    pub body: Bytes,
+}
+
+impl Request {
+    /// Creates a new `Request` with the given method for the given absolute URL, e.g.
+    /// `https://example.com/index.html`.
+    pub fn new(method: &str, url: &str) -> Result<Request, Error> {
+        let (scheme, rest) = match url.find("://") {
+            Some(pos) => (&url[..pos], &url[pos + 3..]),
+            None => bail!("URL `{}` has no scheme", url),
+        };
+        let (authority, path) = match rest.find(|c: char| c == '/' || c == '?') {
+            Some(pos) if rest[pos..].starts_with('/') => (&rest[..pos], rest[pos..].to_owned()),
+            Some(pos) => (&rest[..pos], format!("/{}", &rest[pos..])),
+            None => (rest, String::from("/")),
+        };
+
+        if authority.is_empty() {
+            bail!("URL `{}` has no host", url);
+        }
+
+        Ok(Request {
+            method: method.to_owned(),
+            scheme: scheme.to_lowercase(),
+            authority: authority.to_owned(),
+            path,
+            protocol: None,
+            headers: Headers::new(),
+            body: Bytes::new(),
+        })
+    }
+
+    /// Creates a new `GET` `Request` for the given absolute URL.
+    pub fn get(url: &str) -> Result<Request, Error> {
+        Request::new("GET", url)
+    }
+
+    /// Returns the first value of the header field with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub(crate) fn encode(&self) -> Bytes {
+        let mut fields = vec![
+            (String::from(":method"), self.method.clone()),
+            (String::from(":scheme"), self.scheme.clone()),
+            (String::from(":authority"), self.authority.clone()),
+            (String::from(":path"), self.path.clone()),
+        ];
+        if let Some(ref protocol) = self.protocol {
+            fields.push((String::from(":protocol"), protocol.clone()));
+        }
+        fields.extend(self.headers.iter().cloned());
+
+        encode_message(&fields, &self.body)
+    }
+
+    pub(crate) fn decode(fields: Vec<(String, String)>, body: Bytes) -> Result<Request, Error> {
+        let (pseudo, headers) = split_pseudo_headers(fields)?;
+        let mut request = Request {
+            method: String::new(),
+            scheme: String::new(),
+            authority: String::new(),
+            path: String::new(),
+            protocol: None,
+            headers,
+            body,
+        };
+
+        for (name, value) in pseudo {
+            match name.as_str() {
+                ":method" => request.method = value,
+                ":scheme" => request.scheme = value,
+                ":authority" => request.authority = value,
+                ":path" => request.path = value,
+                ":protocol" => request.protocol = Some(value),
+                _ => bail!("request has unknown pseudo-header field `{}`", name),
+            }
+        }
+
+        if request.method.is_empty() || request.scheme.is_empty() || request.path.is_empty() {
+            bail!("request misses mandatory pseudo-header fields");
+        }
+
+        Ok(request)
+    }
+}
+
+/// A HTTP/3 response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// The status code, e.g. `200`.
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Bytes,
+}
+
+impl Response {
+    /// Creates a new `Response` with the given status code and body.
+    pub fn new<B: Into<Bytes>>(status: u16, body: B) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: body.into(),
+        }
+    }
+
+    /// Returns the first value of the header field with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub(crate) fn encode(&self) -> Bytes {
+        let mut fields = vec![(String::from(":status"), self.status.to_string())];
+        fields.extend(self.headers.iter().cloned());
+
+        encode_message(&fields, &self.body)
+    }
+
+    pub(crate) fn decode(fields: Vec<(String, String)>, body: Bytes) -> Result<Response, Error> {
+        let (pseudo, headers) = split_pseudo_headers(fields)?;
+
+        let status = match pseudo.as_slice() {
+            [(name, status)] if name == ":status" => status.parse().ok(),
+            _ => None,
+        };
+
+        match status {
+            Some(status) => Ok(Response {
+                status,
+                headers,
+                body,
+            }),
+            None => bail!("response has invalid pseudo-header fields"),
+        }
+    }
+}
+
+fn find_header<'a>(headers: &'a Headers, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// Splits the given fields into the pseudo-header fields and the header fields. The
+/// pseudo-header fields need to come first.
+fn split_pseudo_headers(fields: Vec<(String, String)>) -> Result<(Headers, Headers), Error> {
+    let pos = fields
+        .iter()
+        .position(|(n, _)| !n.starts_with(':'))
+        .unwrap_or(fields.len());
+    let mut pseudo = fields;
+    let headers = pseudo.split_off(pos);
+
+    if headers.iter().any(|(n, _)| n.starts_with(':')) {
+        bail!("pseudo-header field after a header field");
+    }
+
+    Ok((pseudo, headers))
+}
+
+/// Encodes the given fields and body into a `HEADERS` and a `DATA` frame.
+fn encode_message(fields: &[(String, String)], body: &[u8]) -> Bytes {
+    let mut buf = Vec::with_capacity(body.len() + 64);
+    Frame::Headers(qpack::encode(fields).into()).encode(&mut buf);
+
+    if !body.is_empty() {
+        Frame::Data(Bytes::from(body)).encode(&mut buf);
+    }
+
+    buf.into()
+}
+
+/// Reads a `Request` or `Response` from a `Stream`, until the peer finished the `Stream`.
+/// The header fields of trailers are appended to the header fields.
+pub(crate) struct ReadMessage {
+    stream: Option<Stream>,
+    /// The received data that does not contain a complete frame yet.
+    recv_buffer: BytesMut,
+    fields: Option<Vec<(String, String)>>,
+    body: BytesMut,
+}
+
+impl ReadMessage {
+    pub fn new(stream: Stream) -> ReadMessage {
+        ReadMessage {
+            stream: Some(stream),
+            recv_buffer: BytesMut::new(),
+            fields: None,
+            body: BytesMut::new(),
+        }
+    }
+
+    /// Takes the `Stream` the message is read from, e.g. to reset it after an error.
+    pub fn take_stream(&mut self) -> Option<Stream> {
+        self.stream.take()
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), Error> {
+        match frame {
+            Frame::Headers(fields) => {
+                let fields = qpack::decode(&fields)?;
+
+                match self.fields {
+                    Some(ref mut trailers) => trailers.extend(fields),
+                    None => self.fields = Some(fields),
+                }
+            }
+            Frame::Data(data) => {
+                if self.fields.is_none() {
+                    bail!("received `DATA` frame before the `HEADERS` frame");
+                } else if self.body.len() + data.len() > MAX_BODY_SIZE {
+                    bail!("received body exceeds the maximum of {} bytes", MAX_BODY_SIZE);
+                }
+
+                self.body.extend_from_slice(&data);
+            }
+            Frame::Settings(_) | Frame::GoAway(_) => {
+                bail!("received control frame on a request stream");
+            }
+            Frame::Unknown(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl Future for ReadMessage {
+    type Item = (Stream, Vec<(String, String)>, Bytes);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            while let Some(frame) = Frame::decode(&mut self.recv_buffer)? {
+                self.handle_frame(frame)?;
+            }
+
+            let data = match self.stream {
+                Some(ref mut stream) => try_ready!(stream.poll()),
+                None => panic!("`ReadMessage` polled after completion"),
+            };
+
+            match data {
+                Some(data) => self.recv_buffer.extend_from_slice(&data),
+                None if !self.recv_buffer.is_empty() => {
+                    bail!("`Stream` finished in the middle of a frame");
+                }
+                None => {
+                    let fields = match self.fields.take() {
+                        Some(fields) => fields,
+                        None => bail!("`Stream` finished without a `HEADERS` frame"),
+                    };
+                    let stream = self.stream.take().expect("checked above");
+                    let body = self.body.take().freeze();
+
+                    return Ok(Ready((stream, fields, body)));
+                }
+            }
+        }
+    }
+}
+
+/// Reads the header fields of a `Request` or `Response` from a `Stream`, that stays open after
+/// the header fields, e.g. for an extended `CONNECT`.
+pub(crate) struct ReadHeaders {
+    stream: Option<Stream>,
+    /// The received data that was not decoded yet.
+    recv_buffer: BytesMut,
+}
+
+impl ReadHeaders {
+    /// Creates a new `ReadHeaders` for the given `Stream`, of which `received` was already
+    /// read.
+    pub fn new(stream: Stream, received: BytesMut) -> ReadHeaders {
+        ReadHeaders {
+            stream: Some(stream),
+            recv_buffer: received,
+        }
+    }
+
+    /// Takes the `Stream` the header fields are read from, e.g. to reset it after an error.
+    pub fn take_stream(&mut self) -> Option<Stream> {
+        self.stream.take()
+    }
+}
+
+impl Future for ReadHeaders {
+    /// The `Stream`, the header fields and the received data after the `HEADERS` frame.
+    type Item = (Stream, Vec<(String, String)>, BytesMut);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match Frame::decode(&mut self.recv_buffer)? {
+                Some(Frame::Headers(fields)) => {
+                    let fields = qpack::decode(&fields)?;
+                    let stream = self.stream.take().expect("`ReadHeaders` polled twice");
+                    let rest = self.recv_buffer.take();
+
+                    return Ok(Ready((stream, fields, rest)));
+                }
+                Some(Frame::Unknown(_)) => continue,
+                Some(_) => bail!("received unexpected frame before the `HEADERS` frame"),
+                None => {}
+            }
+
+            let data = match self.stream {
+                Some(ref mut stream) => try_ready!(stream.poll()),
+                None => panic!("`ReadHeaders` polled after completion"),
+            };
+
+            match data {
+                Some(data) => self.recv_buffer.extend_from_slice(&data),
+                None => bail!("`Stream` finished without a `HEADERS` frame"),
+            }
+        }
+    }
+}
+
+/// Sends the given encoded message on the `Stream` and finishes it, unless it was created by
+/// `without_finish`.
+pub(crate) struct SendMessage {
+    stream: Option<Stream>,
+    pending: Option<Bytes>,
+    finish: bool,
+}
+
+impl SendMessage {
+    pub fn new(stream: Stream, message: Bytes) -> SendMessage {
+        SendMessage {
+            stream: Some(stream),
+            pending: Some(message),
+            finish: true,
+        }
+    }
+
+    /// Sends the given encoded message, but keeps the `Stream` open, e.g. for an extended
+    /// `CONNECT`.
+    pub fn without_finish(stream: Stream, message: Bytes) -> SendMessage {
+        SendMessage {
+            finish: false,
+            ..SendMessage::new(stream, message)
+        }
+    }
+}
+
+impl Future for SendMessage {
+    type Item = Stream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut stream = self.stream.take().expect("`SendMessage` polled after completion");
+
+        if let Some(message) = self.pending.take() {
+            if let AsyncSink::NotReady(message) = stream.start_send(message)? {
+                self.pending = Some(message);
+                self.stream = Some(stream);
+                return Ok(NotReady);
+            }
+        }
+
+        if stream.poll_complete()?.is_not_ready() {
+            self.stream = Some(stream);
+            return Ok(NotReady);
+        }
+
+        if self.finish {
+            stream.finish()?;
+        }
+        Ok(Ready(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_is_created_from_url() {
+        let request = Request::get("https://example.com:4433/index.html?lang=en").unwrap();
+        assert_eq!("GET", request.method);
+        assert_eq!("https", request.scheme);
+        assert_eq!("example.com:4433", request.authority);
+        assert_eq!("/index.html?lang=en", request.path);
+
+        assert_eq!("/", Request::get("https://example.com").unwrap().path);
+        assert_eq!("/?q", Request::get("https://example.com?q").unwrap().path);
+        assert!(Request::get("example.com/index.html").is_err());
+        assert!(Request::get("https:///index.html").is_err());
+    }
+
+    #[test]
+    fn pseudo_header_fields_are_decoded() {
+        let fields = |fields: &[(&str, &str)]| {
+            fields
+                .iter()
+                .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+                .collect::<Vec<_>>()
+        };
+
+        let response = Response::decode(
+            fields(&[(":status", "404"), ("content-type", "text/plain")]),
+            Bytes::new(),
+        )
+        .unwrap();
+        assert_eq!(404, response.status);
+        assert_eq!(Some("text/plain"), response.header("Content-Type"));
+
+        assert!(Response::decode(fields(&[("server", "x"), (":status", "200")]), Bytes::new())
+            .is_err());
+        assert!(Request::decode(fields(&[(":method", "GET")]), Bytes::new()).is_err());
+
+        let request = Request::decode(
+            fields(&[
+                (":method", "CONNECT"),
+                (":scheme", "https"),
+                (":authority", "example.com"),
+                (":path", "/chat"),
+                (":protocol", "webtransport"),
+            ]),
+            Bytes::new(),
+        )
+        .unwrap();
+        assert_eq!(Some("webtransport"), request.protocol.as_ref().map(|p| p.as_str()));
+    }
+}
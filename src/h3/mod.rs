@@ -0,0 +1,22 @@
+//! HTTP/3 (RFC 9114) on top of a `Connection`.
+//!
+//! `Client` sends `Request`s on new bidirectional `Stream`s and `Server` yields the received
+//! `Request`s. `serve` drives a `Server` with a service function, that maps each `Request` to
+//! the future of its `Response`. QPACK only uses the static table and server push is not
+//! supported.
+
+mod client;
+pub(crate) mod control;
+pub(crate) mod frame;
+pub(crate) mod message;
+mod qpack;
+mod server;
+mod service;
+
+pub use self::client::{Client, NewClient, ResponseFuture};
+pub use self::message::{Headers, Request, Response};
+pub use self::server::{NewServer, Responder, SendResponse, Server};
+pub use self::service::{serve, Serve};
+
+/// The ALPN of HTTP/3, see `Config::set_alpn_protocols`.
+pub const ALPN: &[u8] = b"h3";
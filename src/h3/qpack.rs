@@ -0,0 +1,446 @@
+//! QPACK (RFC 9204) with the static table only.
+//!
+//! The dynamic table is not supported, its capacity is advertised as `0` in the `SETTINGS`, so
+//! the peer is not allowed to use it. Strings are always encoded without Huffman coding, but
+//! Huffman coded strings of the peer are decoded.
+
+use error::*;
+
+/// The static table of RFC 9204, appendix A.
+const STATIC_TABLE: [(&str, &str); 99] = [
+    (":authority", ""),
+    (":path", "/"),
+    ("age", "0"),
+    ("content-disposition", ""),
+    ("content-length", "0"),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("referer", ""),
+    ("set-cookie", ""),
+    (":method", "CONNECT"),
+    (":method", "DELETE"),
+    (":method", "GET"),
+    (":method", "HEAD"),
+    (":method", "OPTIONS"),
+    (":method", "POST"),
+    (":method", "PUT"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "103"),
+    (":status", "200"),
+    (":status", "304"),
+    (":status", "404"),
+    (":status", "503"),
+    ("accept", "*/*"),
+    ("accept", "application/dns-message"),
+    ("accept-encoding", "gzip, deflate, br"),
+    ("accept-ranges", "bytes"),
+    ("access-control-allow-headers", "cache-control"),
+    ("access-control-allow-headers", "content-type"),
+    ("access-control-allow-origin", "*"),
+    ("cache-control", "max-age=0"),
+    ("cache-control", "max-age=2592000"),
+    ("cache-control", "max-age=604800"),
+    ("cache-control", "no-cache"),
+    ("cache-control", "no-store"),
+    ("cache-control", "public, max-age=31536000"),
+    ("content-encoding", "br"),
+    ("content-encoding", "gzip"),
+    ("content-type", "application/dns-message"),
+    ("content-type", "application/javascript"),
+    ("content-type", "application/json"),
+    ("content-type", "application/x-www-form-urlencoded"),
+    ("content-type", "image/gif"),
+    ("content-type", "image/jpeg"),
+    ("content-type", "image/png"),
+    ("content-type", "text/css"),
+    ("content-type", "text/html; charset=utf-8"),
+    ("content-type", "text/plain"),
+    ("content-type", "text/plain;charset=utf-8"),
+    ("range", "bytes=0-"),
+    ("strict-transport-security", "max-age=31536000"),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains",
+    ),
+    (
+        "strict-transport-security",
+        "max-age=31536000; includesubdomains; preload",
+    ),
+    ("vary", "accept-encoding"),
+    ("vary", "origin"),
+    ("x-content-type-options", "nosniff"),
+    ("x-xss-protection", "1; mode=block"),
+    (":status", "100"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "302"),
+    (":status", "400"),
+    (":status", "403"),
+    (":status", "421"),
+    (":status", "425"),
+    (":status", "500"),
+    ("accept-language", ""),
+    ("access-control-allow-credentials", "FALSE"),
+    ("access-control-allow-credentials", "TRUE"),
+    ("access-control-allow-headers", "*"),
+    ("access-control-allow-methods", "get"),
+    ("access-control-allow-methods", "get, post, options"),
+    ("access-control-allow-methods", "options"),
+    ("access-control-expose-headers", "content-length"),
+    ("access-control-request-headers", "content-type"),
+    ("access-control-request-method", "get"),
+    ("access-control-request-method", "post"),
+    ("alt-svc", "clear"),
+    ("authorization", ""),
+    (
+        "content-security-policy",
+        "script-src 'none'; object-src 'none'; base-uri 'none'",
+    ),
+    ("early-data", "1"),
+    ("expect-ct", ""),
+    ("forwarded", ""),
+    ("if-range", ""),
+    ("origin", ""),
+    ("purpose", "prefetch"),
+    ("server", ""),
+    ("timing-allow-origin", "*"),
+    ("upgrade-insecure-requests", "1"),
+    ("user-agent", ""),
+    ("x-forwarded-for", ""),
+    ("x-frame-options", "deny"),
+    ("x-frame-options", "sameorigin"),
+];
+
+/// Encodes the given fields into a field section, that only references the static table.
+pub fn encode(fields: &[(String, String)]) -> Vec<u8> {
+    // The required insert count and the base are `0`, without a dynamic table.
+    let mut buf = vec![0, 0];
+
+    for (name, value) in fields {
+        let name = name.to_lowercase();
+
+        if let Some(index) = STATIC_TABLE
+            .iter()
+            .position(|&(n, v)| n == name && v == value.as_str())
+        {
+            // Indexed field line, referencing the static table.
+            encode_int(index as u64, 6, 0xc0, &mut buf);
+        } else if let Some(index) = STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+            // Literal field line with a name reference into the static table.
+            encode_int(index as u64, 4, 0x50, &mut buf);
+            encode_string(value.as_bytes(), 7, 0x00, &mut buf);
+        } else {
+            // Literal field line with a literal name.
+            encode_string(name.as_bytes(), 3, 0x20, &mut buf);
+            encode_string(value.as_bytes(), 7, 0x00, &mut buf);
+        }
+    }
+
+    buf
+}
+
+/// Decodes the given field section, that may only reference the static table.
+pub fn decode(mut buf: &[u8]) -> Result<Vec<(String, String)>, Error> {
+    let required_insert_count = decode_int(&mut buf, 8)?;
+    let _delta_base = decode_int(&mut buf, 7)?;
+
+    if required_insert_count != 0 {
+        bail!("QPACK field section references the dynamic table");
+    }
+
+    let mut fields = Vec::new();
+
+    while let Some(&first) = buf.first() {
+        let field = if first & 0x80 != 0 {
+            // Indexed field line.
+            if first & 0x40 == 0 {
+                bail!("QPACK field line references the dynamic table");
+            }
+
+            let (name, value) = static_entry(decode_int(&mut buf, 6)?)?;
+            (name.to_owned(), value.to_owned())
+        } else if first & 0x40 != 0 {
+            // Literal field line with name reference.
+            if first & 0x10 == 0 {
+                bail!("QPACK field line references the dynamic table");
+            }
+
+            let (name, _) = static_entry(decode_int(&mut buf, 4)?)?;
+            (name.to_owned(), decode_string(&mut buf, 7)?)
+        } else if first & 0x20 != 0 {
+            // Literal field line with literal name.
+            let name = decode_string(&mut buf, 3)?;
+            (name, decode_string(&mut buf, 7)?)
+        } else {
+            bail!("QPACK field line references the dynamic table");
+        };
+
+        fields.push(field);
+    }
+
+    Ok(fields)
+}
+
+fn static_entry(index: u64) -> Result<(&'static str, &'static str), Error> {
+    match STATIC_TABLE.get(index as usize) {
+        Some(entry) => Ok(*entry),
+        None => bail!("QPACK static table has no entry {}", index),
+    }
+}
+
+/// Appends the given integer with a prefix of `prefix_bits` bits (RFC 7541, section 5.1).
+/// `flags` are the bits of the first byte in front of the prefix.
+fn encode_int(value: u64, prefix_bits: u8, flags: u8, buf: &mut Vec<u8>) {
+    let max = (1u64 << prefix_bits) - 1;
+
+    if value < max {
+        buf.push(flags | value as u8);
+        return;
+    }
+
+    buf.push(flags | max as u8);
+    let mut value = value - max;
+
+    while value >= 0x80 {
+        buf.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+
+    buf.push(value as u8);
+}
+
+/// Decodes an integer with a prefix of `prefix_bits` bits and removes it from `buf`.
+fn decode_int(buf: &mut &[u8], prefix_bits: u8) -> Result<u64, Error> {
+    let max = (1u64 << prefix_bits) - 1;
+    let mut value = match buf.first() {
+        Some(first) => u64::from(*first) & max,
+        None => bail!("QPACK field section is truncated"),
+    };
+    let mut pos = 1;
+
+    if value == max {
+        let mut shift = 0;
+
+        loop {
+            let byte = match buf.get(pos) {
+                Some(byte) => *byte,
+                None => bail!("QPACK field section is truncated"),
+            };
+            pos += 1;
+
+            if shift > 56 {
+                bail!("QPACK integer is too big");
+            }
+
+            value += u64::from(byte & 0x7f) << shift;
+            shift += 7;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+    }
+
+    *buf = &buf[pos..];
+    Ok(value)
+}
+
+/// Appends the given string, without Huffman coding.
+fn encode_string(data: &[u8], prefix_bits: u8, flags: u8, buf: &mut Vec<u8>) {
+    encode_int(data.len() as u64, prefix_bits, flags, buf);
+    buf.extend_from_slice(data);
+}
+
+/// Decodes a string, whose length has a prefix of `prefix_bits` bits, and removes it from
+/// `buf`. The bit in front of the prefix is the Huffman flag.
+fn decode_string(buf: &mut &[u8], prefix_bits: u8) -> Result<String, Error> {
+    let huffman = buf.first().map_or(false, |b| b & (1 << prefix_bits) != 0);
+    let len = decode_int(buf, prefix_bits)? as usize;
+
+    if buf.len() < len {
+        bail!("QPACK field section is truncated");
+    }
+
+    let data = if huffman {
+        huffman_decode(&buf[..len])?
+    } else {
+        buf[..len].to_vec()
+    };
+
+    let string = match String::from_utf8(data) {
+        Ok(string) => string,
+        Err(_) => bail!("QPACK string is not valid UTF-8"),
+    };
+
+    *buf = &buf[len..];
+    Ok(string)
+}
+
+/// Decodes a Huffman coded string (RFC 7541, appendix B). The code is canonical, so a symbol is
+/// found by the number of codes of each length, without a tree.
+fn huffman_decode(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut res = Vec::with_capacity(data.len() * 8 / 5);
+    // The bits of the current symbol, the first code and the index of the first symbol with the
+    // length of the current symbol.
+    let (mut code, mut first, mut index, mut len) = (0u32, 0u32, 0usize, 0usize);
+    // The bits of the current symbol are all ones, a prefix of EOS, that is used as padding.
+    let mut ones = true;
+
+    for byte in data {
+        for shift in (0..8).rev() {
+            let bit = u32::from(byte >> shift) & 1;
+            code |= bit;
+            ones &= bit == 1;
+            len += 1;
+
+            let count = u32::from(HUFFMAN_LENGTH_COUNTS[len]);
+
+            if code < first + count {
+                match HUFFMAN_SYMBOLS[index + (code - first) as usize] {
+                    HUFFMAN_EOS => bail!("QPACK Huffman coded string contains EOS"),
+                    symbol => res.push(symbol as u8),
+                }
+
+                code = 0;
+                first = 0;
+                index = 0;
+                len = 0;
+                ones = true;
+            } else {
+                index += count as usize;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+        }
+    }
+
+    if len > 7 || !ones {
+        bail!("QPACK Huffman coded string has an invalid padding");
+    }
+
+    Ok(res)
+}
+
+/// The end of string symbol, which must not be part of a string.
+const HUFFMAN_EOS: u16 = 256;
+
+/// The number of Huffman codes of each bit length.
+const HUFFMAN_LENGTH_COUNTS: [u16; 31] = [
+    0, 0, 0, 0, 0, 10, 26, 32, 6, 0, 5, 3, 2, 6, 2, 3, 0, 0, 0, 3, 8, 13, 26, 29, 12, 4, 15, 19, 29,
+    0, 4,
+];
+
+/// The symbols ordered by the bit length of their Huffman code, then by their value.
+const HUFFMAN_SYMBOLS: [u16; 257] = [
+    48, 49, 50, 97, 99, 101, 105, 111, 115, 116, 32, 37, 45, 46, 47, 51, 52, 53, 54, 55, 56, 57, 61,
+    65, 95, 98, 100, 102, 103, 104, 108, 109, 110, 112, 114, 117, 58, 66, 67, 68, 69, 70, 71, 72,
+    73, 74, 75, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 89, 106, 107, 113, 118, 119, 120,
+    121, 122, 38, 42, 44, 59, 88, 90, 33, 34, 40, 41, 63, 39, 43, 124, 35, 62, 0, 36, 64, 91, 93,
+    126, 94, 125, 60, 96, 123, 92, 195, 208, 128, 130, 131, 162, 184, 194, 224, 226, 153, 161, 167,
+    172, 176, 177, 179, 209, 216, 217, 227, 229, 230, 129, 132, 133, 134, 136, 146, 154, 156, 160,
+    163, 164, 169, 170, 173, 178, 181, 185, 186, 187, 189, 190, 196, 198, 228, 232, 233, 1, 135,
+    137, 138, 139, 140, 141, 143, 147, 149, 150, 151, 152, 155, 157, 158, 165, 166, 168, 174, 175,
+    180, 182, 183, 188, 191, 197, 231, 239, 9, 142, 144, 145, 148, 159, 171, 206, 215, 225, 236,
+    237, 199, 207, 234, 235, 192, 193, 200, 201, 202, 205, 210, 213, 218, 219, 238, 240, 242, 243,
+    255, 203, 204, 211, 212, 214, 221, 222, 223, 241, 244, 245, 246, 247, 248, 250, 251, 252, 253,
+    254, 2, 3, 4, 5, 6, 7, 8, 11, 12, 14, 15, 16, 17, 18, 19, 20, 21, 23, 24, 25, 26, 27, 28, 29,
+    30, 31, 127, 220, 249, 10, 13, 22, 256,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(fields: &[(&str, &str)]) -> Vec<(String, String)> {
+        fields
+            .iter()
+            .map(|&(n, v)| (n.to_owned(), v.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn static_table_entries_are_referenced() {
+        assert_eq!(
+            vec![0, 0, 0xd1, 0xc1],
+            encode(&fields(&[(":method", "GET"), (":path", "/")]))
+        );
+
+        // `:path` with another value references the name of entry 1.
+        assert_eq!(
+            vec![0, 0, 0x51, 0x02, b'/', b'a'],
+            encode(&fields(&[(":path", "/a")]))
+        );
+    }
+
+    #[test]
+    fn encoded_fields_are_decoded() {
+        let fields = fields(&[
+            (":status", "200"),
+            ("content-type", "text/plain"),
+            ("content-length", "42"),
+            ("x-custom-header-with-a-long-name", "value"),
+            ("server", ""),
+        ]);
+
+        assert_eq!(fields, decode(&encode(&fields)).unwrap());
+    }
+
+    #[test]
+    fn dynamic_table_is_rejected() {
+        // A required insert count of `1`.
+        assert!(decode(&[0x01, 0x00]).is_err());
+        // An indexed field line, referencing the dynamic table.
+        assert!(decode(&[0x00, 0x00, 0x80]).is_err());
+    }
+
+    #[test]
+    fn huffman_coded_strings_are_decoded() {
+        // `:path` with a Huffman coded value.
+        assert_eq!(
+            fields(&[(":path", "/")]),
+            decode(&[0x00, 0x00, 0x51, 0x81, 0x63]).unwrap()
+        );
+
+        // The examples of RFC 7541, C.4.1 and C.6.1.
+        let www = [0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff];
+        assert_eq!(&b"www.example.com"[..], &huffman_decode(&www).unwrap()[..]);
+
+        let date = [
+            0xd0, 0x7a, 0xbe, 0x94, 0x10, 0x54, 0xd4, 0x44, 0xa8, 0x20, 0x05, 0x95, 0x04, 0x0b,
+            0x81, 0x66, 0xe0, 0x82, 0xa6, 0x2d, 0x1b, 0xff,
+        ];
+        assert_eq!(
+            &b"Mon, 21 Oct 2013 20:13:21 GMT"[..],
+            &huffman_decode(&date).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn invalid_huffman_coded_strings_are_rejected() {
+        // Padding with zero bits.
+        assert!(huffman_decode(&[0x00]).is_err());
+        // Padding that is longer than 7 bits.
+        assert!(huffman_decode(&[0xff]).is_err());
+        // EOS.
+        assert!(huffman_decode(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn integers_with_prefix_are_encoded() {
+        // The example of RFC 7541, C.1.2.
+        let mut buf = Vec::new();
+        encode_int(1337, 5, 0, &mut buf);
+        assert_eq!(vec![0x1f, 0x9a, 0x0a], buf);
+
+        let mut slice = &buf[..];
+        assert_eq!(1337, decode_int(&mut slice, 5).unwrap());
+        assert!(slice.is_empty());
+    }
+}
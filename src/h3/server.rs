@@ -0,0 +1,152 @@
+use super::{
+    control::{OpenControlStream, PeerStreams},
+    message::{ReadMessage, Request, Response, SendMessage},
+};
+use connection::{Connection, IncomingStreams};
+use error::*;
+use stream::Stream;
+
+use futures::{
+    Async::{NotReady, Ready},
+    Future, Poll, Stream as FStream,
+};
+
+/// A HTTP/3 server on top of an incoming `Connection`, see `Server::new`.
+/// The `Server` yields each received `Request` with the `Responder` that sends the `Response`.
+pub struct Server {
+    /// Keeps the `Connection` alive, while the incoming `Stream`s are used.
+    _connection: Connection,
+    incoming: IncomingStreams,
+    incoming_finished: bool,
+    /// The control stream needs to stay open for the lifetime of the `Connection`.
+    _control: Stream,
+    peer: PeerStreams,
+    /// The requests that are not completely received yet.
+    requests: Vec<ReadMessage>,
+}
+
+impl Server {
+    /// Starts HTTP/3 on the given incoming `Connection`. The `Connection` should negotiate
+    /// `h3::ALPN`, see `Config::set_alpn_protocols`. Resolves to the `Server`, after the
+    /// control stream is opened.
+    pub fn new(connection: Connection) -> NewServer {
+        NewServer {
+            inner: OpenControlStream::new(connection, Vec::new()),
+        }
+    }
+
+    /// Polls the received requests. A malformed request resets its `Stream`, but does not fail
+    /// the `Server`.
+    fn poll_requests(&mut self) -> Option<(Request, Responder)> {
+        let mut i = 0;
+
+        while i < self.requests.len() {
+            let (mut stream, res) = match self.requests[i].poll() {
+                Ok(NotReady) => {
+                    i += 1;
+                    continue;
+                }
+                Ok(Ready((stream, fields, body))) => (Some(stream), Request::decode(fields, body)),
+                Err(e) => (self.requests[i].take_stream(), Err(e)),
+            };
+            self.requests.remove(i);
+
+            match res {
+                Ok(request) => {
+                    let stream = stream.expect("finished request has a `Stream`");
+                    return Some((request, Responder::new(stream)));
+                }
+                Err(e) => {
+                    debug!("dropping malformed HTTP/3 request: {}", e);
+
+                    if let Some(ref mut stream) = stream {
+                        let _ = stream.reset();
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl FStream for Server {
+    type Item = (Request, Responder);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        self.peer.poll()?;
+
+        while !self.incoming_finished {
+            match self.incoming.poll()? {
+                Ready(Some(stream)) => self.requests.push(ReadMessage::new(stream)),
+                Ready(None) => self.incoming_finished = true,
+                NotReady => break,
+            }
+        }
+
+        if let Some(request) = self.poll_requests() {
+            Ok(Ready(Some(request)))
+        } else if self.incoming_finished && self.requests.is_empty() {
+            Ok(Ready(None))
+        } else {
+            Ok(NotReady)
+        }
+    }
+}
+
+/// Resolves to a `Server`, see `Server::new`.
+pub struct NewServer {
+    inner: OpenControlStream,
+}
+
+impl Future for NewServer {
+    type Item = Server;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (connection, control) = try_ready!(self.inner.poll());
+
+        Ok(Ready(Server {
+            incoming: connection.incoming_bidirectional(),
+            incoming_finished: false,
+            _control: control,
+            peer: PeerStreams::new(connection.incoming_unidirectional()),
+            requests: Vec::new(),
+            _connection: connection,
+        }))
+    }
+}
+
+/// Sends the `Response` to a `Request`, that was received by a `Server`.
+pub struct Responder {
+    stream: Stream,
+}
+
+impl Responder {
+    pub(crate) fn new(stream: Stream) -> Responder {
+        Responder { stream }
+    }
+
+    /// Sends the given `Response` and finishes the `Stream` of the `Request`.
+    pub fn send(self, response: Response) -> SendResponse {
+        SendResponse {
+            inner: SendMessage::new(self.stream, response.encode()),
+        }
+    }
+}
+
+/// Resolves, after the `Response` was handed to the `Stream`, see `Responder::send`.
+pub struct SendResponse {
+    inner: SendMessage,
+}
+
+impl Future for SendResponse {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        try_ready!(self.inner.poll());
+        Ok(Ready(()))
+    }
+}
@@ -0,0 +1,145 @@
+use super::{
+    message::{Request, Response},
+    server::{NewServer, Responder, SendResponse, Server},
+};
+use connection::Connection;
+use error::*;
+
+use futures::{
+    Async::{NotReady, Ready},
+    Future, IntoFuture, Poll, Stream as FStream,
+};
+
+/// Serves the HTTP/3 requests of the given incoming `Connection` with a service function, like
+/// the services of hyper. `service` is called with each received `Request` and returns the
+/// future of its `Response`. If this future fails, the `Request` is answered with status `500`.
+///
+/// The returned future resolves, after the peer closed the `Connection` and all `Response`s
+/// were sent. The `Connection` should negotiate `h3::ALPN`, see `Config::set_alpn_protocols`.
+pub fn serve<F, R>(connection: Connection, service: F) -> Serve<F, R>
+where
+    F: FnMut(Request) -> R,
+    R: IntoFuture<Item = Response, Error = Error>,
+{
+    Serve {
+        new_server: Some(Server::new(connection)),
+        server: None,
+        service,
+        responses: Vec::new(),
+    }
+}
+
+/// Drives a `Server` with a service function, see `serve`.
+pub struct Serve<F, R: IntoFuture> {
+    new_server: Option<NewServer>,
+    server: Option<Server>,
+    service: F,
+    /// The `Response`s that are not completely handed to their `Stream`s yet.
+    responses: Vec<HandleRequest<R::Future>>,
+}
+
+impl<F, R> Serve<F, R>
+where
+    R: IntoFuture<Item = Response, Error = Error>,
+{
+    /// Polls the `Response`s. A `Response` that could not be sent does not fail the `Serve`.
+    fn poll_responses(&mut self) {
+        let mut i = 0;
+
+        while i < self.responses.len() {
+            match self.responses[i].poll() {
+                Ok(NotReady) => i += 1,
+                Ok(Ready(())) => {
+                    self.responses.swap_remove(i);
+                }
+                Err(e) => {
+                    debug!("could not send HTTP/3 response: {}", e);
+                    self.responses.swap_remove(i);
+                }
+            }
+        }
+    }
+}
+
+impl<F, R> Future for Serve<F, R>
+where
+    F: FnMut(Request) -> R,
+    R: IntoFuture<Item = Response, Error = Error>,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(mut new_server) = self.new_server.take() {
+            match new_server.poll()? {
+                Ready(server) => self.server = Some(server),
+                NotReady => {
+                    self.new_server = Some(new_server);
+                    return Ok(NotReady);
+                }
+            }
+        }
+
+        while let Some(poll) = self.server.as_mut().map(|s| s.poll()) {
+            match poll? {
+                Ready(Some((request, responder))) => {
+                    let response = (self.service)(request).into_future();
+                    self.responses.push(HandleRequest {
+                        state: RequestState::Calling(response, Some(responder)),
+                    });
+                }
+                Ready(None) => self.server = None,
+                NotReady => break,
+            }
+        }
+
+        self.poll_responses();
+
+        if self.server.is_none() && self.responses.is_empty() {
+            Ok(Ready(()))
+        } else {
+            Ok(NotReady)
+        }
+    }
+}
+
+enum RequestState<R> {
+    Calling(R, Option<Responder>),
+    Sending(SendResponse),
+}
+
+/// Waits for the `Response` of the service and sends it.
+struct HandleRequest<R> {
+    state: RequestState<R>,
+}
+
+impl<R> Future for HandleRequest<R>
+where
+    R: Future<Item = Response, Error = Error>,
+{
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                RequestState::Calling(ref mut response, ref mut responder) => {
+                    let response = match response.poll() {
+                        Ok(NotReady) => return Ok(NotReady),
+                        Ok(Ready(response)) => response,
+                        Err(e) => {
+                            debug!("HTTP/3 service failed: {}", e);
+                            Response::new(500, Vec::new())
+                        }
+                    };
+
+                    let responder = responder.take().expect("`Response` is only sent once");
+                    RequestState::Sending(responder.send(response))
+                }
+                RequestState::Sending(ref mut send) => return send.poll(),
+            };
+
+            self.state = next;
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use connection::Type as ConnectionType;
+use error::*;
+use ffi::{self, QuicCtx};
+
+#[cfg(feature = "openssl")]
+use openssl::hash::{hash, MessageDigest};
+
+use std::{collections::HashSet, net::SocketAddr, time::SystemTime};
+
+/// The outcome of a handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakeOutcome {
+    /// The handshake finished and the `Connection` is established.
+    Accepted,
+    /// The `Connection` was closed, before the handshake finished.
+    Failed {
+        /// The description of the error that closed the `Connection`, if there was any.
+        error: Option<String>,
+        /// The TLS alert that failed the handshake.
+        tls_alert: Option<TlsAlert>,
+    },
+}
+
+/// A record about a single handshake, that is given to the `HandshakeAudit` handler.
+#[derive(Debug, Clone)]
+pub struct HandshakeRecord {
+    /// The time point the outcome of the handshake was known.
+    pub time: SystemTime,
+    /// Was the `Connection` created by the peer or locally?
+    pub connection_type: ConnectionType,
+    /// The address of the peer.
+    pub peer_addr: SocketAddr,
+    /// The local address of the `Connection`.
+    pub local_addr: SocketAddr,
+    /// The server name(SNI) of the `Connection`.
+    pub server_name: Option<String>,
+    /// The negotiated application layer protocol.
+    pub alpn: Option<String>,
+    /// The SHA-256 fingerprints of the certificates the peer presented, the peer certificate
+    /// first. Always empty without the `openssl` feature.
+    pub peer_certificate_fingerprints: Vec<Vec<u8>>,
+    /// The outcome of the handshake.
+    pub outcome: HandshakeOutcome,
+}
+
+/// The `HandshakeAudit` trait is used to record the outcome of each handshake of a `Context`.
+pub trait HandshakeAudit: Send {
+    /// Will be called once per `Connection`, when its handshake finished or failed.
+    fn record(&mut self, record: HandshakeRecord);
+}
+
+impl<F> HandshakeAudit for F
+where
+    F: FnMut(HandshakeRecord) + Send,
+{
+    fn record(&mut self, record: HandshakeRecord) {
+        self(record)
+    }
+}
+
+/// Tracks the handshakes of all connections and records their outcome.
+pub struct HandshakeAuditor {
+    handler: Box<dyn HandshakeAudit>,
+    /// The connections that finished their handshake.
+    established: HashSet<usize>,
+}
+
+impl HandshakeAuditor {
+    pub fn new(handler: Box<dyn HandshakeAudit>) -> HandshakeAuditor {
+        HandshakeAuditor {
+            handler,
+            established: HashSet::new(),
+        }
+    }
+
+    /// Records the connection as accepted, when its handshake just finished.
+    pub fn check_connection(&mut self, quic: &QuicCtx, cnx: ffi::Connection) {
+        if cnx.is_ready() && self.established.insert(cnx.as_ptr() as usize) {
+            self.record(quic, cnx, HandshakeOutcome::Accepted);
+        }
+    }
+
+    /// Records the connection as failed, if it is disconnected before the handshake finished.
+    /// Needs to be called, before the connection is deleted.
+    pub fn on_disconnected(&mut self, quic: &QuicCtx, cnx: ffi::Connection) {
+        if self.established.remove(&(cnx.as_ptr() as usize)) {
+            return;
+        }
+
+        let error = cnx.error(false).map(|err| err());
+        let tls_alert = match error.as_ref().map(Error::kind) {
+            Some(ErrorKind::TLSAlert(alert)) => Some(*alert),
+            _ => None,
+        };
+
+        let outcome = HandshakeOutcome::Failed {
+            error: error.map(|e| e.to_string()),
+            tls_alert,
+        };
+        self.record(quic, cnx, outcome);
+    }
+
+    fn record(&mut self, quic: &QuicCtx, cnx: ffi::Connection, outcome: HandshakeOutcome) {
+        self.handler.record(HandshakeRecord {
+            time: SystemTime::now(),
+            connection_type: cnx.con_type(),
+            peer_addr: cnx.peer_addr(),
+            local_addr: cnx.local_addr(),
+            server_name: cnx.server_name(),
+            alpn: cnx.alpn(),
+            peer_certificate_fingerprints: peer_certificate_fingerprints(quic, cnx),
+            outcome,
+        });
+    }
+}
+
+#[cfg(feature = "openssl")]
+fn peer_certificate_fingerprints(quic: &QuicCtx, cnx: ffi::Connection) -> Vec<Vec<u8>> {
+    quic.peer_certificates(cnx)
+        .iter()
+        .filter_map(|cert| hash(MessageDigest::sha256(), cert).ok())
+        .map(|digest| digest.to_vec())
+        .collect()
+}
+
+/// The peer certificates are only known with our verify certificate callback.
+#[cfg(not(feature = "openssl"))]
+fn peer_certificate_fingerprints(_: &QuicCtx, _: ffi::Connection) -> Vec<Vec<u8>> {
+    Vec::new()
+}
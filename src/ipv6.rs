@@ -0,0 +1,64 @@
+//! IPv6 socket options that are not covered by `std` and `socket2`.
+
+#[cfg(target_os = "linux")]
+use socket_options::set_option;
+
+#[cfg(target_os = "linux")]
+use std::{io, net::SocketAddrV6, os::unix::io::RawFd};
+
+/// The highest flow label, flow labels are 20 bit values.
+pub const MAX_FLOW_LABEL: u32 = 0xF_FFFF;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use libc::c_int;
+
+    // Taken from `linux/in6.h`.
+    pub const IPV6_FLOWLABEL_MGR: c_int = 32;
+    pub const IPV6_FLOWINFO_SEND: c_int = 33;
+    pub const IPV6_FL_A_GET: u8 = 0;
+    pub const IPV6_FL_S_ANY: u8 = 255;
+    pub const IPV6_FL_F_CREATE: u16 = 1;
+
+    #[repr(C)]
+    #[allow(non_camel_case_types)]
+    pub struct in6_flowlabel_req {
+        pub flr_dst: [u8; 16],
+        pub flr_label: u32,
+        pub flr_action: u8,
+        pub flr_share: u8,
+        pub flr_flags: u16,
+        pub flr_expires: u16,
+        pub flr_linger: u16,
+        pub flr_pad: u32,
+    }
+}
+
+/// Sets the traffic class of all packets that are sent by the given IPv6 socket.
+#[cfg(target_os = "linux")]
+pub fn set_traffic_class(fd: RawFd, traffic_class: u8) -> io::Result<()> {
+    let traffic_class = libc::c_int::from(traffic_class);
+    unsafe { set_option(fd, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, &traffic_class) }
+}
+
+/// Leases the flow label for packets to the given peer from the kernel and enables sending
+/// packets with flow labels on the given IPv6 socket. Afterwards, a packet gets the flow label
+/// that is set in the `flowinfo` of its target address.
+#[cfg(target_os = "linux")]
+pub fn register_flow_label(fd: RawFd, peer: &SocketAddrV6, flow_label: u32) -> io::Result<()> {
+    let req = sys::in6_flowlabel_req {
+        flr_dst: peer.ip().octets(),
+        flr_label: flow_label.to_be(),
+        flr_action: sys::IPV6_FL_A_GET,
+        flr_share: sys::IPV6_FL_S_ANY,
+        flr_flags: sys::IPV6_FL_F_CREATE,
+        flr_expires: 0,
+        flr_linger: 0,
+        flr_pad: 0,
+    };
+
+    unsafe {
+        set_option(fd, libc::IPPROTO_IPV6, sys::IPV6_FLOWLABEL_MGR, &req)?;
+        set_option(fd, libc::IPPROTO_IPV6, sys::IPV6_FLOWINFO_SEND, &(1 as libc::c_int))
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+/// The interval after which the packet protection keys of a `Connection` are updated, see
+/// `Config::set_key_update_interval`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyUpdateInterval {
+    /// Update the keys, after the given number of bytes were protected with them.
+    Bytes(u64),
+    /// Update the keys, after they were used for the given time.
+    Duration(Duration),
+}
+
+/// Decides when a connection updates its packet protection keys.
+///
+/// A key update is due, when the application requested it or the `KeyUpdateInterval` elapsed
+/// since the last key update, regardless of which side initiated the last update.
+pub struct KeyUpdateScheduler {
+    interval: Option<KeyUpdateInterval>,
+    /// The time and the number of protected bytes at the last key update.
+    last_update: (Instant, u64),
+    /// Was a key update requested by the application, that is not done yet?
+    requested: bool,
+}
+
+impl KeyUpdateScheduler {
+    pub fn new(interval: Option<KeyUpdateInterval>, now: Instant) -> KeyUpdateScheduler {
+        KeyUpdateScheduler {
+            interval,
+            last_update: (now, 0),
+            requested: false,
+        }
+    }
+
+    /// Requests a key update, see `Connection::initiate_key_update`.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Is a key update due, with the given total number of protected bytes?
+    pub fn is_due(&self, now: Instant, protected_bytes: u64) -> bool {
+        let (time, bytes) = self.last_update;
+
+        self.requested
+            || match self.interval {
+                Some(KeyUpdateInterval::Bytes(max)) => {
+                    protected_bytes.saturating_sub(bytes) >= max
+                }
+                Some(KeyUpdateInterval::Duration(max)) => now.duration_since(time) >= max,
+                None => false,
+            }
+    }
+
+    /// Needs to be called after each key update, that was initiated by us or by the peer.
+    pub fn on_key_update(&mut self, now: Instant, protected_bytes: u64, initiated_by_peer: bool) {
+        self.last_update = (now, protected_bytes);
+
+        if !initiated_by_peer {
+            self.requested = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_update_is_due_after_the_interval() {
+        let now = Instant::now();
+        let mut scheduler = KeyUpdateScheduler::new(Some(KeyUpdateInterval::Bytes(1000)), now);
+
+        assert!(!scheduler.is_due(now, 999));
+        assert!(scheduler.is_due(now, 1000));
+
+        scheduler.on_key_update(now, 1000, false);
+        assert!(!scheduler.is_due(now, 1999));
+
+        // A key update of the peer restarts the interval as well.
+        scheduler.on_key_update(now, 1500, true);
+        assert!(!scheduler.is_due(now, 2000));
+        assert!(scheduler.is_due(now, 2500));
+
+        let mut scheduler =
+            KeyUpdateScheduler::new(Some(KeyUpdateInterval::Duration(Duration::from_secs(5))), now);
+        assert!(!scheduler.is_due(now + Duration::from_secs(4), 0));
+        assert!(scheduler.is_due(now + Duration::from_secs(5), 0));
+
+        scheduler.on_key_update(now + Duration::from_secs(5), 0, false);
+        assert!(!scheduler.is_due(now + Duration::from_secs(9), 0));
+    }
+
+    #[test]
+    fn requested_key_update_is_due_until_it_is_done() {
+        let now = Instant::now();
+        let mut scheduler = KeyUpdateScheduler::new(None, now);
+
+        assert!(!scheduler.is_due(now, u64::max_value()));
+
+        scheduler.request();
+        assert!(scheduler.is_due(now, 0));
+
+        scheduler.on_key_update(now, 0, true);
+        assert!(scheduler.is_due(now, 0));
+
+        scheduler.on_key_update(now, 0, false);
+        assert!(!scheduler.is_due(now, 0));
+    }
+}
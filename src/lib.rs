@@ -21,6 +21,13 @@ Building is currently only tested on Linux. To build the project, you just need
 run `cargo build`.
 `picoquic-sys` will also build the `picoquic` c-library for you (hopefully).
 
+The default `openssl` feature enables the certificate verification handlers
+(`Config::set_verify_certificate_handler` etc.), `Connection::peer_certificates` and the
+certificate fingerprints of a `HandshakeRecord`, which use the `openssl` crate. Without the
+feature, the certificates are verified by picotls against the root certificates. picoquic and
+picotls still link OpenSSL for the TLS handshake and the packet protection, so the OpenSSL
+libraries are always needed.
+
 ## Example
 
 ### Client
@@ -29,6 +36,8 @@ extern crate bytes;
 extern crate futures;
 extern crate picoquic;
 extern crate tokio;
+#[cfg(feature = "std-future")]
+extern crate tokio1;
 
 use picoquic::{Config, Context};
 
@@ -72,6 +81,8 @@ extern crate bytes;
 extern crate futures;
 extern crate picoquic;
 extern crate tokio;
+#[cfg(feature = "std-future")]
+extern crate tokio1;
 
 use picoquic::{Config, Context};
 
@@ -147,39 +158,146 @@ Unless you explicitly state otherwise, any contribution intentionally submitted
 for inclusion in the work by you, as defined in the Apache-2.0 license, shall be
 dual licensed as above, without any additional terms or conditions.
 */
+#[cfg(feature = "bincode-codec")]
+extern crate bincode;
 extern crate bytes;
 extern crate failure;
 #[macro_use]
 extern crate failure_derive;
 #[macro_use]
 extern crate futures;
+#[cfg(feature = "std-future")]
+extern crate futures03;
 extern crate libc;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "openssl")]
 extern crate openssl;
 extern crate openssl_sys;
 extern crate parking_lot;
 extern crate picoquic_sys;
+#[cfg(any(feature = "bincode-codec", feature = "cbor-codec"))]
+extern crate serde;
+#[cfg(feature = "cbor-codec")]
+extern crate serde_cbor;
 extern crate socket2;
 extern crate tokio;
+#[cfg(feature = "std-future")]
+extern crate tokio1;
+#[cfg(feature = "tracing")]
+extern crate tracing01;
 
+// Declared first, so `span_event!` is available in all other modules.
+#[macro_use]
+mod trace;
+
+mod admission;
+mod amplification;
+#[cfg(feature = "bench")]
+mod bench;
+mod blackhole;
+mod careful_resume;
 mod config;
+mod congestion;
 mod connection;
 mod context;
 mod context_inner;
+mod crypto_backend;
+mod datagram;
+mod driver_thread;
+mod ecn;
 #[macro_use]
 mod error;
+mod event_monitor;
 mod ffi;
+pub mod h3;
+mod handshake_audit;
+mod ipv6;
+mod key_update;
+pub mod masque;
+mod message_stream;
+mod metrics;
+mod mtu_discovery;
+mod packet_observer;
+mod pem;
+mod priority;
+mod qlog;
+mod rate_limit;
+mod receive_window;
+mod recv_pool;
+mod registry;
+mod runtime;
+#[cfg(feature = "self-signed")]
+mod self_signed;
+mod sni;
+mod socket_options;
+mod stats;
+#[cfg(feature = "std-future")]
+mod std_future;
 mod stream;
+mod stream_credit;
+mod transfer;
+mod transport;
+#[cfg(any(feature = "bincode-codec", feature = "cbor-codec"))]
+mod typed_stream;
 mod unbounded_with_error;
+mod varint;
+#[cfg(feature = "openssl")]
 mod verify_certificate;
+mod version;
+pub mod webtransport;
 
-pub use self::config::{Config, FileFormat, Role};
+pub use self::admission::{
+    AcceptDecision, AcceptFilter, AdmitConnection, IncomingConnectionInfo, PauseMode,
+};
+#[cfg(feature = "bench")]
+pub use self::bench::{Bench, HandshakeRate, Latency, Throughput};
+pub use self::careful_resume::PathParameters;
+pub use self::config::{
+    Config, CongestionAlgorithm, ConnectionConfig, FileFormat, MtuDiscovery, Role,
+    SessionTicketStore,
+};
+pub use self::congestion::{CongestionController, NewCongestionController, PathInfo};
 pub use self::connection::{
-    Connection, Id as ConnectionId, NewStreamFuture, NewStreamHandle, Type as ConnectionType,
+    CloseFuture, Connection, ConnectionIds, Event as ConnectionEvent, Events as ConnectionEvents,
+    HandshakeCompleted, Id as ConnectionId, IncomingStreams, NewStreamFuture, NewStreamHandle,
+    PingFuture, TlsInfo, TransportParameters, Type as ConnectionType,
 };
-pub use self::context::Context;
+pub use self::context::{Context, ContextBuilder, ContextDriver, ListenerControl, ShutdownFuture};
 pub use self::context_inner::{NewConnectionFuture, NewConnectionHandle};
-pub use self::error::{Error, ErrorKind};
-pub use self::stream::{Stream, Type as SType};
-pub use self::verify_certificate::{default_verify_certificate, VerifyCertificate};
+pub use self::crypto_backend::CryptoBackend;
+pub use self::datagram::Datagrams;
+pub use self::ecn::Ecn;
+pub use self::error::{Error, ErrorKind, TlsAlert};
+pub use self::handshake_audit::{HandshakeAudit, HandshakeOutcome, HandshakeRecord};
+pub use self::key_update::KeyUpdateInterval;
+pub use self::message_stream::MessageStream;
+pub use self::metrics::{Metrics, MetricsSink, RttHistogram};
+pub use self::packet_observer::{
+    LongPacketType, PacketDirection, PacketHeader, PacketMetadata, PacketObserver,
+};
+pub use self::priority::Priority;
+pub use self::rate_limit::{HandshakeRateLimit, RateLimitAction};
+pub use self::registry::ConnectionInfo;
+pub use self::runtime::{Socket, Spawn, ThreadTimer, Timer};
+pub use self::sni::SniIdentity;
+pub use self::stats::{
+    ConnectionStats, CryptoStats, EcnCounts, PacketNumberSpaceStats, PathStats,
+};
+#[cfg(feature = "std-future")]
+pub use self::std_future::{TokioTimer, TokioUdpSocket};
+pub use self::stream::{Id as StreamId, Info as StreamInfo, Stream, Type as SType};
+pub use self::transfer::{transfer, Progress as TransferProgress, Transfer};
+pub use self::transport::{InMemory as InMemoryTransport, LinkConditions};
+#[cfg(feature = "bincode-codec")]
+pub use self::typed_stream::Bincode;
+#[cfg(feature = "cbor-codec")]
+pub use self::typed_stream::Cbor;
+#[cfg(any(feature = "bincode-codec", feature = "cbor-codec"))]
+pub use self::typed_stream::{Codec, TypedStream};
+#[cfg(feature = "openssl")]
+pub use self::verify_certificate::{
+    default_verify_certificate, AsyncVerifyCertificate, PinnedVerifier, VerifyCertificate,
+    VerifyContext,
+};
+pub use self::version::QuicVersion;
@@ -0,0 +1,163 @@
+use super::{
+    decode_datagram, encode_datagram, settings, supports_connect_udp, target_path, PROTOCOL,
+};
+use connection::Connection;
+use datagram::DatagramSender;
+use error::*;
+use h3::Request;
+use runtime::Socket;
+use webtransport::{client::ExtendedConnect, session::Handle};
+
+use futures::{
+    Async::{NotReady, Ready},
+    Future, Poll,
+};
+
+use std::{io, net::SocketAddr};
+
+/// A tunnel of UDP packets through a proxy to a single target, see `UdpTunnel::connect`.
+///
+/// The `UdpTunnel` is a `Socket`, that sends and receives the packets of the target. An inner
+/// `Connection` needs a smaller MTU than the outer `Connection`, the packets that exceed
+/// `max_packet_size` are dropped.
+pub struct UdpTunnel {
+    id: u64,
+    target: SocketAddr,
+    local_addr: SocketAddr,
+    datagrams: DatagramSender,
+    handle: Handle,
+}
+
+impl UdpTunnel {
+    /// Establishes a new `UdpTunnel` through the proxy of the given `Connection` to the given
+    /// target. `proxy` is the authority of the proxy, e.g. `proxy.example.com:443`.
+    pub fn connect(connection: Connection, proxy: &str, target: SocketAddr) -> Connect {
+        let url = format!(
+            "https://{}{}",
+            proxy,
+            target_path(&target.ip().to_string(), target.port())
+        );
+        let request = Request::new("CONNECT", &url).map(|mut request| {
+            request
+                .headers
+                .push(("capsule-protocol".into(), "?1".into()));
+            request
+        });
+
+        Connect {
+            target,
+            local_addr: connection.local_addr(),
+            inner: ExtendedConnect::new(
+                connection,
+                request,
+                PROTOCOL,
+                settings(),
+                supports_connect_udp,
+            ),
+        }
+    }
+
+    /// Returns the address of the target.
+    pub fn target(&self) -> SocketAddr {
+        self.target
+    }
+
+    /// Returns the maximum size of a tunneled packet, limited by the maximum datagram size of the
+    /// outer `Connection`.
+    pub fn max_packet_size(&self) -> usize {
+        let overhead = encode_datagram(self.id, &[]).len();
+        self.datagrams.max_size().saturating_sub(overhead)
+    }
+}
+
+/// Converts an `Error` of the outer `Connection` into an `io::Error` of the `Socket`.
+fn io_error(err: Error) -> io::Error {
+    let kind = match *err.kind() {
+        ErrorKind::SessionClosed { .. } | ErrorKind::Disconnected => io::ErrorKind::NotConnected,
+        _ => io::ErrorKind::Other,
+    };
+
+    io::Error::new(kind, err.to_string())
+}
+
+impl Socket for UdpTunnel {
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        let id = self.id;
+
+        loop {
+            let datagram = match self.handle.poll(|dispatcher| dispatcher.take_datagram(id)) {
+                Ok(Ready(datagram)) => datagram,
+                Ok(NotReady) => return Ok(NotReady),
+                Err(err) => return Err(io_error(err)),
+            };
+
+            if let Some(packet) = decode_datagram(datagram) {
+                let len = packet.len().min(buf.len());
+                buf[..len].copy_from_slice(&packet[..len]);
+
+                return Ok(Ready((len, self.target)));
+            }
+        }
+    }
+
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        if *target != self.target {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the tunnel only reaches {}", self.target),
+            ));
+        }
+
+        match self.datagrams.send(encode_datagram(self.id, buf)) {
+            Ok(()) => {}
+            // Like a link that drops the packets exceeding its MTU.
+            Err(ref err) if matches!(*err.kind(), ErrorKind::DatagramTooLarge(..)) => {
+                debug!("dropping tunneled packet of {} bytes: {}", buf.len(), err);
+            }
+            Err(err) => return Err(io_error(err)),
+        }
+
+        Ok(Ready(buf.len()))
+    }
+
+    fn poll_write_ready(&mut self) -> Poll<(), io::Error> {
+        Ok(Ready(()))
+    }
+
+    /// Returns the local address of the outer `Connection`.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+impl Drop for UdpTunnel {
+    fn drop(&mut self) {
+        // Dropping the `CONNECT` stream closes the tunnel.
+        self.handle.lock().remove_session(self.id);
+    }
+}
+
+/// Resolves to a `UdpTunnel`, after the proxy accepted it, see `UdpTunnel::connect`.
+pub struct Connect {
+    target: SocketAddr,
+    local_addr: SocketAddr,
+    inner: ExtendedConnect,
+}
+
+impl Future for Connect {
+    type Item = UdpTunnel;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (id, handle) = try_ready!(self.inner.poll());
+        let datagrams = handle.lock().datagram_sender();
+
+        Ok(Ready(UdpTunnel {
+            id,
+            target: self.target,
+            local_addr: self.local_addr,
+            datagrams,
+            handle,
+        }))
+    }
+}
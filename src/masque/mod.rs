@@ -0,0 +1,120 @@
+//! Proxying of UDP in HTTP/3 (CONNECT-UDP, RFC 9298) on top of a `Connection`.
+//!
+//! A `UdpTunnel` is established by an extended `CONNECT` to a proxy, see `UdpTunnel::connect`,
+//! and the `Proxy` relays the UDP packets of the tunnels to their targets. A `UdpTunnel` is a
+//! `Socket`, so the `Connection`s of a `Context` created by `Context::with_io` are tunneled
+//! through the outer `Connection`. The packets are carried in datagrams, so both peers need
+//! `Config::enable_datagrams` and the `Connection` needs to negotiate `h3::ALPN`.
+
+mod client;
+mod server;
+
+pub use self::client::{Connect, UdpTunnel};
+pub use self::server::{NewProxy, Proxy, Relay, TunnelRequest};
+
+use varint;
+use webtransport::{SETTINGS_ENABLE_CONNECT_PROTOCOL, SETTINGS_H3_DATAGRAM};
+
+use bytes::Bytes;
+
+/// The protocol of the extended `CONNECT` request, that establishes a `UdpTunnel`.
+const PROTOCOL: &str = "connect-udp";
+
+/// The path of the default URI template, followed by the target host and port.
+const WELL_KNOWN_PATH: &str = "/.well-known/masque/udp/";
+
+/// The context id of the datagrams that carry UDP packets.
+const UDP_PAYLOAD_CONTEXT: u64 = 0;
+
+/// Returns the settings of CONNECT-UDP, that are sent on the control stream.
+fn settings() -> Vec<(u64, u64)> {
+    vec![
+        (SETTINGS_ENABLE_CONNECT_PROTOCOL, 1),
+        (SETTINGS_H3_DATAGRAM, 1),
+    ]
+}
+
+/// Returns `true`, if the given settings of a proxy announce support for CONNECT-UDP.
+fn supports_connect_udp(settings: &[(u64, u64)]) -> bool {
+    let value = |id| settings.iter().find(|s| s.0 == id).map_or(0, |s| s.1);
+
+    value(SETTINGS_ENABLE_CONNECT_PROTOCOL) == 1 && value(SETTINGS_H3_DATAGRAM) == 1
+}
+
+/// Returns the path of the default URI template for the given target. The colons of IPv6
+/// addresses are percent-encoded.
+fn target_path(host: &str, port: u16) -> String {
+    format!("{}{}/{}/", WELL_KNOWN_PATH, host.replace(':', "%3A"), port)
+}
+
+/// Parses the target host and port from the path of a request.
+fn parse_target_path(path: &str) -> Option<(String, u16)> {
+    if !path.starts_with(WELL_KNOWN_PATH) {
+        return None;
+    }
+
+    let mut parts = path[WELL_KNOWN_PATH.len()..]
+        .trim_end_matches('/')
+        .splitn(2, '/');
+    let host = parts.next()?.replace("%3A", ":").replace("%3a", ":");
+    let port = parts.next()?.parse().ok()?;
+
+    if host.is_empty() {
+        None
+    } else {
+        Some((host, port))
+    }
+}
+
+/// Encodes the given UDP packet into a datagram of the given tunnel.
+fn encode_datagram(tunnel: u64, packet: &[u8]) -> Bytes {
+    let mut datagram = Vec::with_capacity(packet.len() + 9);
+    varint::encode(tunnel / 4, &mut datagram);
+    varint::encode(UDP_PAYLOAD_CONTEXT, &mut datagram);
+    datagram.extend_from_slice(packet);
+    datagram.into()
+}
+
+/// Returns the UDP packet of a datagram, of which the quarter stream id was already removed.
+/// Returns `None`, if the datagram carries another context.
+fn decode_datagram(mut datagram: Bytes) -> Option<Bytes> {
+    match varint::decode(&datagram)? {
+        (UDP_PAYLOAD_CONTEXT, len) => {
+            datagram.advance(len);
+            Some(datagram)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_is_parsed_from_path() {
+        let path = target_path("192.0.2.6", 443);
+        assert_eq!("/.well-known/masque/udp/192.0.2.6/443/", path);
+        assert_eq!(Some(("192.0.2.6".into(), 443)), parse_target_path(&path));
+
+        let path = target_path("2001:db8::42", 53);
+        assert_eq!("/.well-known/masque/udp/2001%3Adb8%3A%3A42/53/", path);
+        assert_eq!(Some(("2001:db8::42".into(), 53)), parse_target_path(&path));
+
+        assert_eq!(None, parse_target_path("/.well-known/masque/udp/example.com/"));
+        assert_eq!(None, parse_target_path("/.well-known/masque/udp//443/"));
+        assert_eq!(None, parse_target_path("/index.html"));
+    }
+
+    #[test]
+    fn udp_packets_are_decoded_from_datagrams() {
+        let datagram = encode_datagram(8, b"packet");
+        // The quarter stream id is removed by the `Dispatcher`.
+        assert_eq!(
+            Some(Bytes::from(&b"packet"[..])),
+            decode_datagram(datagram.slice_from(1))
+        );
+
+        assert_eq!(None, decode_datagram(Bytes::from(&b"\x01packet"[..])));
+    }
+}
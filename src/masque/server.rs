@@ -0,0 +1,312 @@
+use super::{decode_datagram, encode_datagram, parse_target_path, settings, PROTOCOL};
+use connection::Connection;
+use datagram::DatagramSender;
+use error::*;
+use h3::{
+    control::OpenControlStream,
+    message::SendMessage,
+    Request, Responder, Response, SendResponse,
+};
+use stream::Stream;
+use webtransport::{
+    session::{Dispatcher, Handle},
+    H3_REQUEST_REJECTED,
+};
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{
+    Async::{NotReady, Ready},
+    Future, Poll, Stream as FStream,
+};
+
+use tokio::net::UdpSocket;
+
+use std::net::SocketAddr;
+
+/// The maximum size of a UDP packet, that is received from a target.
+const MAX_PACKET_SIZE: usize = 65_535;
+
+/// A CONNECT-UDP proxy on top of an incoming `Connection`, see `Proxy::new`.
+/// The `Proxy` yields the `TunnelRequest`s of the client, other HTTP/3 requests are rejected.
+pub struct Proxy {
+    handle: Handle,
+}
+
+impl Proxy {
+    /// Starts a proxy on the given incoming `Connection`. The `Connection` should negotiate
+    /// `h3::ALPN`. Resolves to the `Proxy`, after the control stream is opened.
+    pub fn new(connection: Connection) -> NewProxy {
+        NewProxy {
+            inner: OpenControlStream::new(connection, settings()),
+        }
+    }
+}
+
+impl FStream for Proxy {
+    type Item = TunnelRequest;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let received = try_ready!(self.handle.poll(|dispatcher| {
+                match dispatcher.take_request() {
+                    Some(request) => Ok(Some(Some(request))),
+                    None if dispatcher.requests_finished() => Ok(Some(None)),
+                    None => Ok(None),
+                }
+            }));
+
+            let (mut stream, request, received) = match received {
+                Some((stream, fields, received)) => {
+                    (stream, Request::decode(fields, Bytes::new()), received)
+                }
+                None => return Ok(Ready(None)),
+            };
+
+            let target = match request {
+                Ok(ref request)
+                    if request.method == "CONNECT"
+                        && request.protocol.as_ref().map(|p| p.as_str()) == Some(PROTOCOL) =>
+                {
+                    parse_target_path(&request.path)
+                }
+                _ => None,
+            };
+
+            match (request, target) {
+                (Ok(request), Some((host, port))) => {
+                    let id = stream.id();
+                    self.handle.lock().add_session(id);
+
+                    return Ok(Ready(Some(TunnelRequest {
+                        id,
+                        request,
+                        host,
+                        port,
+                        stream: Some(stream),
+                        received,
+                        handle: self.handle.clone(),
+                    })));
+                }
+                _ => {
+                    debug!("rejecting request on stream {}, it is no CONNECT-UDP", stream.id());
+                    let _ = stream.stop_sending(H3_REQUEST_REJECTED);
+                    let _ = stream.reset();
+                }
+            }
+        }
+    }
+}
+
+/// Resolves to a `Proxy`, see `Proxy::new`.
+pub struct NewProxy {
+    inner: OpenControlStream,
+}
+
+impl Future for NewProxy {
+    type Item = Proxy;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (connection, control) = try_ready!(self.inner.poll());
+
+        Ok(Ready(Proxy {
+            handle: Dispatcher::new(connection, control, true),
+        }))
+    }
+}
+
+/// A request of a client to tunnel UDP packets to a target, that needs to be accepted or
+/// rejected. Dropping the `TunnelRequest` rejects it, by resetting its `Stream`.
+pub struct TunnelRequest {
+    id: u64,
+    request: Request,
+    host: String,
+    port: u16,
+    /// `None`, after the `TunnelRequest` was accepted or rejected.
+    stream: Option<Stream>,
+    /// The data that was received on the `Stream` after the header fields.
+    received: BytesMut,
+    handle: Handle,
+}
+
+impl TunnelRequest {
+    /// Returns the extended `CONNECT` request.
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Returns the host of the target, a domain name or an IP address.
+    pub fn target_host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the UDP port of the target.
+    pub fn target_port(&self) -> u16 {
+        self.port
+    }
+
+    /// Accepts the tunnel with the status `200` and relays its packets to and from the given
+    /// address of the target. The caller resolves `target_host` and decides if the target may be
+    /// reached.
+    pub fn accept(mut self, target: SocketAddr) -> Relay {
+        let stream = self.stream.take().expect("`TunnelRequest` is not answered");
+        let bind_addr = if target.is_ipv4() {
+            SocketAddr::from(([0, 0, 0, 0], 0))
+        } else {
+            SocketAddr::from(([0u16; 8], 0))
+        };
+
+        let mut response = Response::new(200, Bytes::new());
+        response
+            .headers
+            .push(("capsule-protocol".into(), "?1".into()));
+
+        // A failed bind drops the `Stream`, which resets it.
+        let (accepting, socket, error) = match UdpSocket::bind(&bind_addr) {
+            Ok(socket) => {
+                let send = SendMessage::without_finish(stream, response.encode());
+                (Some(send), Some(socket), None)
+            }
+            Err(err) => (None, None, Some(err.into())),
+        };
+
+        Relay {
+            id: self.id,
+            target,
+            accepting,
+            received: Some(self.received.take()),
+            error,
+            socket,
+            pending: None,
+            datagrams: self.handle.lock().datagram_sender(),
+            handle: self.handle.clone(),
+            buffer: vec![0; MAX_PACKET_SIZE],
+        }
+    }
+
+    /// Rejects the tunnel with the given status, e.g. `403`.
+    pub fn reject(mut self, status: u16) -> SendResponse {
+        let stream = self.stream.take().expect("`TunnelRequest` is not answered");
+        self.handle.lock().remove_session(self.id);
+
+        Responder::new(stream).send(Response::new(status, Bytes::new()))
+    }
+}
+
+impl Drop for TunnelRequest {
+    fn drop(&mut self) {
+        if self.stream.is_some() {
+            self.handle.lock().remove_session(self.id);
+        }
+    }
+}
+
+/// Relays the packets of an accepted tunnel, see `TunnelRequest::accept`.
+/// Resolves, after the client closed the tunnel.
+pub struct Relay {
+    id: u64,
+    target: SocketAddr,
+    /// Sends the response, until the tunnel is established.
+    accepting: Option<SendMessage>,
+    /// The data that was received on the `CONNECT` stream, until the tunnel is established.
+    received: Option<BytesMut>,
+    /// The error of binding the socket.
+    error: Option<Error>,
+    socket: Option<UdpSocket>,
+    /// A packet for the target, that the socket did not accept yet.
+    pending: Option<Bytes>,
+    datagrams: DatagramSender,
+    handle: Handle,
+    buffer: Vec<u8>,
+}
+
+impl Relay {
+    /// Relays the packets of the client to the target.
+    /// Resolves, after the client closed the tunnel.
+    fn poll_client(&mut self) -> Poll<(), Error> {
+        let id = self.id;
+        let socket = self.socket.as_mut().expect("socket is bound");
+
+        loop {
+            if let Some(packet) = self.pending.take() {
+                match socket.poll_send_to(&packet, &self.target) {
+                    Ok(Ready(_)) => {}
+                    Ok(NotReady) => {
+                        self.pending = Some(packet);
+                        return Ok(NotReady);
+                    }
+                    Err(err) => debug!("failed to relay packet to {}: {}", self.target, err),
+                }
+            }
+
+            match self.handle.poll(|dispatcher| dispatcher.take_datagram(id)) {
+                Ok(Ready(datagram)) => self.pending = decode_datagram(datagram),
+                Ok(NotReady) => return Ok(NotReady),
+                Err(ref err) if matches!(*err.kind(), ErrorKind::SessionClosed { .. }) => {
+                    return Ok(Ready(()));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Relays the packets of the target to the client.
+    fn poll_target(&mut self) -> Result<(), Error> {
+        let socket = self.socket.as_mut().expect("socket is bound");
+
+        loop {
+            let (len, from) = match socket.poll_recv_from(&mut self.buffer)? {
+                Ready(packet) => packet,
+                NotReady => return Ok(()),
+            };
+
+            if from != self.target {
+                debug!("dropping packet of {}, that is not the target", from);
+                continue;
+            }
+
+            match self
+                .datagrams
+                .send(encode_datagram(self.id, &self.buffer[..len]))
+            {
+                Ok(()) => {}
+                Err(ref err) if matches!(*err.kind(), ErrorKind::DatagramTooLarge(..)) => {
+                    debug!("dropping packet of {} bytes of the target: {}", len, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl Future for Relay {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        if let Some(ref mut accepting) = self.accepting {
+            let stream = try_ready!(accepting.poll());
+            let received = self.received.take().expect("tunnel is established once");
+            self.handle
+                .lock()
+                .establish_session(self.id, stream, received);
+        }
+        self.accepting = None;
+
+        self.poll_target()?;
+        self.poll_client()
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        // Dropping the `CONNECT` stream closes the tunnel.
+        self.handle.lock().remove_session(self.id);
+    }
+}
@@ -0,0 +1,223 @@
+use error::*;
+use stream::Stream;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+
+use futures::{
+    Async::{NotReady, Ready},
+    AsyncSink, Poll, Sink, StartSend, Stream as FStream,
+};
+
+use std::io::Cursor;
+
+/// The number of bytes used by the length prefix of each message.
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// The default maximum size of a sent or received message.
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// A `MessageStream` sends and receives whole messages over a `Stream`, see `Stream::framed`.
+/// A `Stream` does not preserve the boundaries of the written data, so each message is prefixed
+/// with its length, as 32 bit big endian integer. The peer needs to use the same framing.
+pub struct MessageStream {
+    stream: Stream,
+    /// The received data that does not contain a complete message yet.
+    recv_buffer: BytesMut,
+    /// A framed message that the `Stream` did not accept yet.
+    pending: Option<Bytes>,
+    max_message_size: usize,
+}
+
+impl MessageStream {
+    /// Creates a new `MessageStream` on top of the given `Stream`.
+    pub fn new(stream: Stream) -> MessageStream {
+        MessageStream {
+            stream,
+            recv_buffer: BytesMut::new(),
+            pending: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+
+    /// Sets the maximum size of a sent or received message. Sending or receiving a bigger
+    /// message results in an error. The length prefix limits the size to at most 4GiB - 1.
+    /// Default: 16MiB
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Returns a reference to the underlying `Stream`.
+    pub fn get_ref(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying `Stream`.
+    pub fn get_mut(&mut self) -> &mut Stream {
+        &mut self.stream
+    }
+
+    /// Consumes the `MessageStream` and returns the underlying `Stream`.
+    /// Any buffered data, that does not form a complete message, is discarded.
+    pub fn into_inner(self) -> Stream {
+        self.stream
+    }
+
+    /// Tries to send the pending framed message. While a message is pending, `start_send`
+    /// does not accept a new message.
+    pub(crate) fn send_pending(&mut self) -> Poll<(), Error> {
+        if let Some(data) = self.pending.take() {
+            if let AsyncSink::NotReady(data) = self.stream.start_send(data)? {
+                self.pending = Some(data);
+                return Ok(NotReady);
+            }
+        }
+
+        Ok(Ready(()))
+    }
+}
+
+impl FStream for MessageStream {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(msg) = decode_frame(&mut self.recv_buffer, self.max_message_size)? {
+                return Ok(Ready(Some(msg)));
+            }
+
+            match try_ready!(self.stream.poll()) {
+                Some(data) => self.recv_buffer.extend_from_slice(&data),
+                None if self.recv_buffer.is_empty() => return Ok(Ready(None)),
+                None => {
+                    bail!("`Stream` finished in the middle of a message");
+                }
+            }
+        }
+    }
+}
+
+impl Sink for MessageStream {
+    type SinkItem = BytesMut;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.send_pending()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        check_message_size(item.len(), self.max_message_size)?;
+        self.pending = Some(encode_frame(&item));
+        self.send_pending()?;
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.send_pending());
+        self.stream.poll_complete()
+    }
+}
+
+/// Checks that a message with the given length can be sent, the peer would reject a bigger
+/// message and a length that does not fit into the length prefix would corrupt the framing.
+fn check_message_size(len: usize, max_message_size: usize) -> Result<(), Error> {
+    if len > max_message_size {
+        bail!(
+            "message with {} bytes exceeds the maximum of {} bytes",
+            len,
+            max_message_size
+        );
+    } else if len as u64 > u64::from(u32::max_value()) {
+        bail!("message with {} bytes does not fit into the length prefix", len);
+    }
+
+    Ok(())
+}
+
+/// Prefixes the given message with its length.
+fn encode_frame(msg: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(LENGTH_PREFIX_SIZE + msg.len());
+    frame.put_u32_be(msg.len() as u32);
+    frame.put_slice(msg);
+    frame.freeze()
+}
+
+/// Removes the first complete message from the given buffer.
+///
+/// # Returns
+/// Some(_) is the message without its length prefix. None intends that the buffer does not
+/// contain a complete message yet.
+fn decode_frame(
+    buffer: &mut BytesMut,
+    max_message_size: usize,
+) -> Result<Option<BytesMut>, Error> {
+    if buffer.len() < LENGTH_PREFIX_SIZE {
+        return Ok(None);
+    }
+
+    let len = Cursor::new(&buffer[..LENGTH_PREFIX_SIZE]).get_u32_be() as usize;
+
+    if len > max_message_size {
+        bail!(
+            "received message with {} bytes exceeds the maximum of {} bytes",
+            len,
+            max_message_size
+        );
+    }
+
+    if buffer.len() < LENGTH_PREFIX_SIZE + len {
+        return Ok(None);
+    }
+
+    buffer.advance(LENGTH_PREFIX_SIZE);
+    Ok(Some(buffer.split_to(len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encoded_frames() {
+        let mut buffer = BytesMut::new();
+        buffer.extend_from_slice(&encode_frame(b"hello"));
+        buffer.extend_from_slice(&encode_frame(b""));
+        buffer.extend_from_slice(&encode_frame(b"world"));
+
+        assert_eq!(&b"hello"[..], &decode_frame(&mut buffer, 10).unwrap().unwrap()[..]);
+        assert_eq!(&b""[..], &decode_frame(&mut buffer, 10).unwrap().unwrap()[..]);
+        assert_eq!(&b"world"[..], &decode_frame(&mut buffer, 10).unwrap().unwrap()[..]);
+        assert!(decode_frame(&mut buffer, 10).unwrap().is_none());
+    }
+
+    #[test]
+    fn decode_incomplete_frame() {
+        let frame = encode_frame(b"hello");
+        let mut buffer = BytesMut::new();
+
+        for byte in frame.iter().take(frame.len() - 1) {
+            buffer.extend_from_slice(&[*byte]);
+            assert!(decode_frame(&mut buffer, 10).unwrap().is_none());
+        }
+
+        buffer.extend_from_slice(&frame[frame.len() - 1..]);
+        assert_eq!(&b"hello"[..], &decode_frame(&mut buffer, 10).unwrap().unwrap()[..]);
+    }
+
+    #[test]
+    fn decode_frame_exceeding_maximum_size() {
+        let mut buffer = BytesMut::from(&encode_frame(b"hello world")[..]);
+
+        assert!(decode_frame(&mut buffer, 10).is_err());
+    }
+
+    #[test]
+    fn message_sizes_are_checked_before_sending() {
+        assert!(check_message_size(10, 10).is_ok());
+        assert!(check_message_size(11, 10).is_err());
+        assert!(check_message_size(u32::max_value() as usize, usize::max_value()).is_ok());
+
+        #[cfg(target_pointer_width = "64")]
+        assert!(check_message_size(u32::max_value() as usize + 1, usize::max_value()).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+/// The upper bounds of the buckets of the `RttHistogram` in milliseconds.
+const RTT_BUCKET_BOUNDS: [u64; 10] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500];
+
+/// The distribution of the smoothed round trip times of the open `Connection`s.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RttHistogram {
+    /// The inclusive upper bounds of the buckets, in ascending order.
+    pub bounds: Vec<Duration>,
+    /// The number of round trip times per bucket. The last entry counts the round trip times
+    /// above the last bound, so there is one entry more than there are `bounds`.
+    pub counts: Vec<u64>,
+    /// The sum of all round trip times.
+    pub sum: Duration,
+}
+
+impl RttHistogram {
+    fn new() -> RttHistogram {
+        RttHistogram {
+            bounds: RTT_BUCKET_BOUNDS
+                .iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect(),
+            counts: vec![0; RTT_BUCKET_BOUNDS.len() + 1],
+            sum: Duration::from_secs(0),
+        }
+    }
+
+    fn record(&mut self, rtt: Duration) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| rtt <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += rtt;
+    }
+
+    /// Returns the number of recorded round trip times.
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+/// The metrics of a `Context`, that are reported to the `MetricsSink`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metrics {
+    /// The number of open `Connection`s, including the ones in the handshake.
+    pub connections_open: usize,
+    /// The number of handshakes that finished since the last report.
+    pub handshakes: u64,
+    /// The number of handshakes that finished per second since the last report.
+    pub handshakes_per_second: f64,
+    /// The number of UDP payload bytes that were sent, since the `Context` was created.
+    pub bytes_sent: u64,
+    /// The number of UDP payload bytes that were received, since the `Context` was created.
+    pub bytes_received: u64,
+    /// The number of UDP packets that were sent, since the `Context` was created.
+    pub packets_sent: u64,
+    /// The number of UDP packets that were received, since the `Context` was created.
+    pub packets_received: u64,
+    /// The number of QUIC packets that were lost, since the `Context` was created.
+    pub packets_lost: u64,
+    /// The smoothed round trip times of the primary paths of the open `Connection`s.
+    pub rtt: RttHistogram,
+}
+
+/// The `MetricsSink` trait receives the `Metrics` of a `Context` in a regular interval, e.g.
+/// to export them to prometheus or statsd.
+pub trait MetricsSink: Send {
+    /// Will be called once per interval, see `Config::set_metrics_sink`.
+    fn report(&mut self, metrics: &Metrics);
+}
+
+impl<F> MetricsSink for F
+where
+    F: FnMut(&Metrics) + Send,
+{
+    fn report(&mut self, metrics: &Metrics) {
+        self(metrics)
+    }
+}
+
+/// The state of an open connection at the time of a report.
+pub struct ConnectionSample {
+    pub packets_lost: u64,
+    pub rtt: Duration,
+}
+
+/// Collects the metrics of a `Context` and reports them to the `MetricsSink`.
+pub struct MetricsReporter {
+    sink: Box<dyn MetricsSink>,
+    interval: Duration,
+    last_report: Instant,
+    /// The connections that finished their handshake, the key is the address of the connection.
+    established: HashSet<usize>,
+    handshakes: u64,
+    bytes_sent: u64,
+    bytes_received: u64,
+    packets_sent: u64,
+    packets_received: u64,
+    /// The lost packets of the connections that are already closed.
+    closed_packets_lost: u64,
+}
+
+impl MetricsReporter {
+    pub fn new(sink: Box<dyn MetricsSink>, interval: Duration, now: Instant) -> MetricsReporter {
+        MetricsReporter {
+            sink,
+            interval,
+            last_report: now,
+            established: HashSet::new(),
+            handshakes: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            packets_sent: 0,
+            packets_received: 0,
+            closed_packets_lost: 0,
+        }
+    }
+
+    pub fn on_packet_sent(&mut self, len: usize) {
+        self.packets_sent += 1;
+        self.bytes_sent += len as u64;
+    }
+
+    pub fn on_packet_received(&mut self, len: usize) {
+        self.packets_received += 1;
+        self.bytes_received += len as u64;
+    }
+
+    /// Counts the handshake of the given connection, when it just finished.
+    pub fn check_connection(&mut self, key: usize, is_ready: bool) {
+        if is_ready && self.established.insert(key) {
+            self.handshakes += 1;
+        }
+    }
+
+    /// Needs to be called, before the connection is deleted.
+    pub fn on_disconnected(&mut self, key: usize, packets_lost: u64) {
+        self.established.remove(&key);
+        self.closed_packets_lost += packets_lost;
+    }
+
+    /// Returns the time of the next report.
+    pub fn next_report(&self) -> Instant {
+        self.last_report + self.interval
+    }
+
+    /// Reports the metrics to the `MetricsSink`, if the interval elapsed.
+    /// The `connections` are all open connections.
+    pub fn report<I>(&mut self, connections: I, now: Instant)
+    where
+        I: Iterator<Item = ConnectionSample>,
+    {
+        if now < self.next_report() {
+            return;
+        }
+
+        let mut rtt = RttHistogram::new();
+        let mut connections_open = 0;
+        let mut packets_lost = self.closed_packets_lost;
+
+        for connection in connections {
+            connections_open += 1;
+            packets_lost += connection.packets_lost;
+            rtt.record(connection.rtt);
+        }
+
+        let elapsed = now.duration_since(self.last_report);
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+        self.sink.report(&Metrics {
+            connections_open,
+            handshakes: self.handshakes,
+            handshakes_per_second: self.handshakes as f64 / elapsed,
+            bytes_sent: self.bytes_sent,
+            bytes_received: self.bytes_received,
+            packets_sent: self.packets_sent,
+            packets_received: self.packets_received,
+            packets_lost,
+            rtt,
+        });
+
+        self.handshakes = 0;
+        self.last_report = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    fn reporter(now: Instant) -> (MetricsReporter, Arc<Mutex<Vec<Metrics>>>) {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let reports = reports.clone();
+            move |m: &Metrics| reports.lock().unwrap().push(m.clone())
+        };
+
+        (
+            MetricsReporter::new(Box::new(sink), Duration::from_secs(2), now),
+            reports,
+        )
+    }
+
+    #[test]
+    fn rtts_are_sorted_into_buckets() {
+        let mut histogram = RttHistogram::new();
+        histogram.record(Duration::from_millis(1));
+        histogram.record(Duration::from_millis(30));
+        histogram.record(Duration::from_secs(10));
+
+        assert_eq!(3, histogram.count());
+        assert_eq!(1, histogram.counts[0]);
+        assert_eq!(1, histogram.counts[4]);
+        assert_eq!(1, histogram.counts[RTT_BUCKET_BOUNDS.len()]);
+        assert_eq!(Duration::from_millis(10_031), histogram.sum);
+    }
+
+    #[test]
+    fn metrics_are_reported_once_per_interval() {
+        let now = Instant::now();
+        let (mut reporter, reports) = reporter(now);
+
+        reporter.on_packet_sent(100);
+        reporter.on_packet_received(50);
+        reporter.check_connection(1, false);
+        reporter.check_connection(1, true);
+        reporter.check_connection(1, true);
+        reporter.check_connection(2, true);
+        reporter.on_disconnected(2, 3);
+
+        reporter.report(Vec::new().into_iter(), now + Duration::from_secs(1));
+        assert!(reports.lock().unwrap().is_empty());
+
+        let sample = ConnectionSample {
+            packets_lost: 2,
+            rtt: Duration::from_millis(20),
+        };
+        reporter.report(vec![sample].into_iter(), now + Duration::from_secs(2));
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(1, reports.len());
+        assert_eq!(1, reports[0].connections_open);
+        assert_eq!(2, reports[0].handshakes);
+        assert!((reports[0].handshakes_per_second - 1.0).abs() < 1e-9);
+        assert_eq!((100, 1), (reports[0].bytes_sent, reports[0].packets_sent));
+        assert_eq!((50, 1), (reports[0].bytes_received, reports[0].packets_received));
+        assert_eq!(5, reports[0].packets_lost);
+        assert_eq!(1, reports[0].rtt.count());
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::{Duration, Instant};
+
+/// The time a probed MTU needs to survive, before the next size is probed. This needs to be
+/// longer than the probation of the `BlackholeDetector`, which clamps down failed probes.
+const CONFIRM_DELAY: Duration = Duration::from_secs(2);
+
+/// Probes the path MTU of a connection with a list of packet sizes.
+///
+/// A probe raises the MTU of the path to the next bigger size. If the packets of that size get
+/// lost, the `BlackholeDetector` clamps the MTU down again and the probe failed.
+pub struct MtuProber {
+    /// The probe sizes in ascending order.
+    sizes: Vec<usize>,
+    interval: Duration,
+    /// The MTU of the running probe.
+    probing: Option<usize>,
+    next_probe: Option<Instant>,
+}
+
+impl MtuProber {
+    pub fn new(sizes: Vec<usize>, interval: Duration) -> MtuProber {
+        MtuProber {
+            sizes,
+            interval,
+            probing: None,
+            next_probe: None,
+        }
+    }
+
+    /// Checks if the next probe should be started.
+    ///
+    /// # Returns
+    /// Some(_) is the MTU that should be probed. None intends that the MTU should not be changed.
+    pub fn poll(&mut self, mtu: usize, now: Instant) -> Option<usize> {
+        match self.next_probe {
+            Some(next) if next > now => return None,
+            _ => {}
+        }
+
+        if let Some(probe) = self.probing.take() {
+            if mtu < probe {
+                // The probe failed
+                self.next_probe = Some(now + self.interval);
+                return None;
+            }
+        }
+
+        match self.sizes.iter().find(|s| **s > mtu) {
+            Some(size) => {
+                self.probing = Some(*size);
+                self.next_probe = Some(now + CONFIRM_DELAY);
+                Some(*size)
+            }
+            None => {
+                self.next_probe = Some(now + self.interval);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes_all_sizes_in_ascending_order() {
+        let now = Instant::now();
+        let mut prober = MtuProber::new(vec![1400, 4000, 9000], Duration::from_secs(600));
+
+        assert_eq!(Some(1400), prober.poll(1200, now));
+        assert_eq!(None, prober.poll(1400, now));
+        let now = now + CONFIRM_DELAY;
+        assert_eq!(Some(4000), prober.poll(1400, now));
+        let now = now + CONFIRM_DELAY;
+        assert_eq!(Some(9000), prober.poll(4000, now));
+        let now = now + CONFIRM_DELAY;
+        assert_eq!(None, prober.poll(9000, now));
+    }
+
+    #[test]
+    fn failed_probe_restarts_after_interval() {
+        let now = Instant::now();
+        let interval = Duration::from_secs(600);
+        let mut prober = MtuProber::new(vec![1400, 9000], interval);
+
+        assert_eq!(Some(1400), prober.poll(1200, now));
+        let now = now + CONFIRM_DELAY;
+        assert_eq!(Some(9000), prober.poll(1400, now));
+        // The `BlackholeDetector` clamped the MTU down
+        let now = now + CONFIRM_DELAY;
+        assert_eq!(None, prober.poll(1400, now));
+        assert_eq!(None, prober.poll(1400, now + CONFIRM_DELAY));
+        assert_eq!(Some(9000), prober.poll(1400, now + interval));
+    }
+}
@@ -0,0 +1,192 @@
+use connection::Id as ConnectionId;
+
+use std::net::SocketAddr;
+
+/// The direction of a packet that is given to the `PacketObserver`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+/// The type of a QUIC packet with a long header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LongPacketType {
+    Initial,
+    ZeroRtt,
+    Handshake,
+    Retry,
+}
+
+/// The header of the first QUIC packet in a UDP datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PacketHeader {
+    /// A packet with a long header, used by the handshake.
+    Long {
+        packet_type: LongPacketType,
+        version: u32,
+        /// The destination connection id.
+        dcid: Vec<u8>,
+        /// The source connection id.
+        scid: Vec<u8>,
+    },
+    /// A version negotiation packet.
+    VersionNegotiation {
+        /// The destination connection id.
+        dcid: Vec<u8>,
+        /// The source connection id.
+        scid: Vec<u8>,
+    },
+    /// A packet with a short header, used after the handshake. The connection id is not part of
+    /// the header, because its length is only known to the connection.
+    Short { spin_bit: bool },
+}
+
+impl PacketHeader {
+    /// Parses the unprotected part of the header of the given QUIC packet.
+    /// Returns `None`, if the packet is too short to be a QUIC packet.
+    pub fn parse(packet: &[u8]) -> Option<PacketHeader> {
+        let first = *packet.first()?;
+
+        if !is_long_header(packet) {
+            return Some(PacketHeader::Short {
+                spin_bit: first & 0x20 != 0,
+            });
+        }
+
+        let version = packet.get(1..5)?;
+        let version = version
+            .iter()
+            .fold(0u32, |version, b| version << 8 | u32::from(*b));
+
+        let dcid_len = *packet.get(5)? as usize;
+        let dcid = packet.get(6..6 + dcid_len)?.to_vec();
+        let scid_len = *packet.get(6 + dcid_len)? as usize;
+        let scid_start = 7 + dcid_len;
+        let scid = packet.get(scid_start..scid_start + scid_len)?.to_vec();
+
+        if version == 0 {
+            return Some(PacketHeader::VersionNegotiation { dcid, scid });
+        }
+
+        let packet_type = match (first >> 4) & 0x03 {
+            0 => LongPacketType::Initial,
+            1 => LongPacketType::ZeroRtt,
+            2 => LongPacketType::Handshake,
+            _ => LongPacketType::Retry,
+        };
+
+        Some(PacketHeader::Long {
+            packet_type,
+            version,
+            dcid,
+            scid,
+        })
+    }
+}
+
+/// The decoded metadata of a packet that is given to the `PacketObserver`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketMetadata {
+    /// The header of the first QUIC packet in the datagram, the datagram may contain more
+    /// coalesced packets. `None`, if the header could not be parsed.
+    pub header: Option<PacketHeader>,
+    /// The local id of the `Connection` the packet belongs to. `None` for packets of unknown
+    /// peers and for stateless packets, e.g. version negotiation, retry or stateless reset.
+    pub connection: Option<ConnectionId>,
+}
+
+impl PacketMetadata {
+    pub(crate) fn new(packet: &[u8], connection: Option<ConnectionId>) -> PacketMetadata {
+        PacketMetadata {
+            header: PacketHeader::parse(packet),
+            connection,
+        }
+    }
+}
+
+/// The `PacketObserver` trait is used to inspect every UDP datagram that a `Context` sends or
+/// receives, e.g. to write a pcap file.
+pub trait PacketObserver: Send {
+    /// Will be called for every datagram with its encrypted payload, before a received datagram
+    /// is processed and after a datagram was sent.
+    fn observe(
+        &mut self,
+        direction: PacketDirection,
+        remote_addr: SocketAddr,
+        data: &[u8],
+        metadata: &PacketMetadata,
+    );
+}
+
+impl<F> PacketObserver for F
+where
+    F: FnMut(PacketDirection, SocketAddr, &[u8], &PacketMetadata) + Send,
+{
+    fn observe(
+        &mut self,
+        direction: PacketDirection,
+        remote_addr: SocketAddr,
+        data: &[u8],
+        metadata: &PacketMetadata,
+    ) {
+        self(direction, remote_addr, data, metadata)
+    }
+}
+
+/// Is the given QUIC packet a long header packet, which are used by the handshake?
+pub fn is_long_header(packet: &[u8]) -> bool {
+    packet.first().map(|b| b & 0x80 != 0).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_header_is_parsed() {
+        let packet = [0xc3, 0, 0, 0, 1, 2, 0xaa, 0xbb, 1, 0xcc, 0x00];
+
+        assert_eq!(
+            Some(PacketHeader::Long {
+                packet_type: LongPacketType::Initial,
+                version: 1,
+                dcid: vec![0xaa, 0xbb],
+                scid: vec![0xcc],
+            }),
+            PacketHeader::parse(&packet)
+        );
+
+        let mut handshake = packet;
+        handshake[0] = 0xe3;
+        match PacketHeader::parse(&handshake) {
+            Some(PacketHeader::Long { packet_type, .. }) => {
+                assert_eq!(LongPacketType::Handshake, packet_type)
+            }
+            header => panic!("unexpected header: {:?}", header),
+        }
+    }
+
+    #[test]
+    fn version_negotiation_and_short_headers_are_parsed() {
+        let packet = [0x80, 0, 0, 0, 0, 1, 0xaa, 0, 0, 0, 0, 1];
+        assert_eq!(
+            Some(PacketHeader::VersionNegotiation {
+                dcid: vec![0xaa],
+                scid: Vec::new(),
+            }),
+            PacketHeader::parse(&packet)
+        );
+
+        assert_eq!(
+            Some(PacketHeader::Short { spin_bit: true }),
+            PacketHeader::parse(&[0x60, 1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn truncated_packets_are_not_parsed() {
+        assert_eq!(None, PacketHeader::parse(&[]));
+        assert_eq!(None, PacketHeader::parse(&[0xc3, 0, 0, 0, 1, 8, 0xaa]));
+    }
+}
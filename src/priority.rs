@@ -0,0 +1,115 @@
+use std::fmt;
+
+/// The highest(least urgent) urgency of a `Priority`.
+const MAX_URGENCY: u8 = 7;
+
+/// The priority of a `Stream`, as defined by the extensible priorities of RFC 9218.
+///
+/// The priority can be exchanged with the peer as `Priority` header field or `PRIORITY_UPDATE`
+/// frame of an application protocol, by using the `Display` implementation and `parse`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Priority {
+    /// The urgency from `0`(most urgent) to `7`(least urgent).
+    pub urgency: u8,
+    /// Can the data of the `Stream` be processed incrementally by the peer?
+    pub incremental: bool,
+}
+
+impl Priority {
+    /// Creates a new `Priority`. The urgency is clamped to `7`.
+    pub fn new(urgency: u8, incremental: bool) -> Priority {
+        Priority {
+            urgency: urgency.min(MAX_URGENCY),
+            incremental,
+        }
+    }
+
+    /// Parses the value of a `Priority` header field, e.g. `u=1, i`.
+    /// Missing or invalid parameters take the default value and unknown parameters are ignored,
+    /// as required by RFC 9218.
+    pub fn parse(value: &str) -> Priority {
+        let mut priority = Priority::default();
+
+        for member in value.split(',') {
+            let mut parts = member.trim().splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().map(str::trim);
+
+            match (key, value) {
+                ("u", Some(value)) => {
+                    if let Some(urgency) = value.parse::<u8>().ok().filter(|u| *u <= MAX_URGENCY) {
+                        priority.urgency = urgency;
+                    }
+                }
+                ("i", None) | ("i", Some("?1")) => priority.incremental = true,
+                ("i", Some("?0")) => priority.incremental = false,
+                _ => {}
+            }
+        }
+
+        priority
+    }
+
+    /// Converts the priority into the stream priority of picoquic. Picoquic serves lower values
+    /// first and `Stream`s with the same odd value in FIFO order, otherwise round-robin.
+    pub(crate) fn to_picoquic(self) -> u8 {
+        (self.urgency << 1) | if self.incremental { 0 } else { 1 }
+    }
+}
+
+impl Default for Priority {
+    fn default() -> Priority {
+        Priority {
+            urgency: 3,
+            incremental: false,
+        }
+    }
+}
+
+impl fmt::Display for Priority {
+    /// Formats the priority as value of a `Priority` header field.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "u={}", self.urgency)?;
+
+        if self.incremental {
+            write!(f, ", i")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_priority_header_field() {
+        assert_eq!(Priority::new(1, true), Priority::parse("u=1, i"));
+        assert_eq!(Priority::new(5, false), Priority::parse("u=5"));
+        assert_eq!(Priority::new(3, true), Priority::parse("i=?1"));
+        assert_eq!(Priority::new(0, false), Priority::parse("u=0, i=?0"));
+        assert_eq!(Priority::default(), Priority::parse(""));
+        assert_eq!(Priority::default(), Priority::parse("u=9, foo=bar"));
+    }
+
+    #[test]
+    fn format_priority_header_field() {
+        assert_eq!("u=3", Priority::default().to_string());
+        assert_eq!("u=0, i", Priority::new(0, true).to_string());
+        assert_eq!(Priority::new(6, true), Priority::parse(&Priority::new(6, true).to_string()));
+    }
+
+    #[test]
+    fn picoquic_stream_priority() {
+        assert_eq!(7, Priority::default().to_picoquic());
+        assert_eq!(0, Priority::new(0, true).to_picoquic());
+        assert_eq!(15, Priority::new(7, false).to_picoquic());
+        assert!(Priority::new(1, false).to_picoquic() < Priority::new(2, true).to_picoquic());
+    }
+
+    #[test]
+    fn urgency_is_clamped() {
+        assert_eq!(7, Priority::new(42, false).urgency);
+    }
+}
@@ -0,0 +1,463 @@
+//! Writes the events of a `Connection` as JSON qlog trace, see `Config::enable_qlog`.
+//!
+//! Picoquic does not report single packets to us, so the packet events are derived from the
+//! statistics of the `Connection`, each time its `Context` is polled. The packet events carry
+//! the packet numbers, but not the frames of the packets.
+
+use connection;
+use stats::{ConnectionStats, PathStats};
+use stream;
+
+use std::{
+    collections::HashMap,
+    fmt::Write as FmtWrite,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// The packet number spaces of QUIC.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Space {
+    Initial,
+    Handshake,
+    /// The 0-RTT and 1-RTT packets.
+    ApplicationData,
+}
+
+impl Space {
+    fn name(self) -> &'static str {
+        match self {
+            Space::Initial => "initial",
+            Space::Handshake => "handshake",
+            Space::ApplicationData => "application_data",
+        }
+    }
+
+    fn packet_type(self) -> &'static str {
+        match self {
+            Space::Initial => "initial",
+            Space::Handshake => "handshake",
+            Space::ApplicationData => "1RTT",
+        }
+    }
+}
+
+/// The congestion control metrics of the primary path.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Metrics {
+    congestion_window: u64,
+    bytes_in_flight: u64,
+    smoothed_rtt: Duration,
+    min_rtt: Duration,
+    pacing_rate: u64,
+}
+
+impl Metrics {
+    fn from_path(path: &PathStats) -> Metrics {
+        Metrics {
+            congestion_window: path.congestion_window,
+            bytes_in_flight: path.bytes_in_flight,
+            smoothed_rtt: path.smoothed_rtt,
+            min_rtt: path.min_rtt,
+            pacing_rate: path.pacing_rate,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    PacketSent {
+        space: Space,
+        packet_number: u64,
+    },
+    PacketReceived {
+        space: Space,
+        packet_number: u64,
+    },
+    PacketsAcked {
+        space: Space,
+        largest_acknowledged: u64,
+    },
+    PacketLost,
+    MetricsUpdated(Metrics),
+    /// Data of a `Stream` was received from or sent to the peer.
+    StreamDataMoved {
+        stream_id: stream::Id,
+        offset: u64,
+        length: u64,
+        fin: bool,
+        sent: bool,
+    },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match *self {
+            Event::PacketSent { .. } => "transport:packet_sent",
+            Event::PacketReceived { .. } => "transport:packet_received",
+            Event::PacketsAcked { .. } => "recovery:packets_acked",
+            Event::PacketLost => "recovery:packet_lost",
+            Event::MetricsUpdated(_) => "recovery:metrics_updated",
+            Event::StreamDataMoved { .. } => "transport:stream_data_moved",
+        }
+    }
+
+    /// Returns the data of this event as JSON object.
+    fn data(&self) -> String {
+        match *self {
+            Event::PacketSent {
+                space,
+                packet_number,
+            }
+            | Event::PacketReceived {
+                space,
+                packet_number,
+            } => format!(
+                "{{\"header\":{{\"packet_type\":\"{}\",\"packet_number\":{}}}}}",
+                space.packet_type(),
+                packet_number
+            ),
+            Event::PacketsAcked {
+                space,
+                largest_acknowledged,
+            } => format!(
+                "{{\"packet_number_space\":\"{}\",\"largest_acknowledged\":{}}}",
+                space.name(),
+                largest_acknowledged
+            ),
+            Event::PacketLost => "{}".to_owned(),
+            Event::MetricsUpdated(ref metrics) => format!(
+                "{{\"congestion_window\":{},\"bytes_in_flight\":{},\"smoothed_rtt\":{:.3},\
+                 \"min_rtt\":{:.3},\"pacing_rate\":{}}}",
+                metrics.congestion_window,
+                metrics.bytes_in_flight,
+                millis(metrics.smoothed_rtt),
+                millis(metrics.min_rtt),
+                // qlog expects the pacing rate in bits per second.
+                metrics.pacing_rate * 8
+            ),
+            Event::StreamDataMoved {
+                stream_id,
+                offset,
+                length,
+                fin,
+                sent,
+            } => {
+                let to = if sent { "network" } else { "application" };
+
+                let mut data = format!(
+                    "{{\"stream_id\":{},\"offset\":{},\"length\":{},\"from\":\"transport\",\
+                     \"to\":\"{}\"",
+                    stream_id, offset, length, to
+                );
+
+                if fin {
+                    data.push_str(",\"fin\":true");
+                }
+
+                data.push('}');
+                data
+            }
+        }
+    }
+}
+
+/// Returns the given duration in milliseconds, as qlog expects it.
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
+/// Returns the events that happened between the two statistics of a `Connection`.
+fn stats_events(old: &ConnectionStats, new: &ConnectionStats) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    let spaces = [
+        (Space::Initial, &old.initial, &new.initial),
+        (Space::Handshake, &old.handshake, &new.handshake),
+        (Space::ApplicationData, &old.application, &new.application),
+    ];
+
+    for &(space, old_space, new_space) in spaces.iter() {
+        events.extend(
+            (old_space.packets_sent..new_space.packets_sent).map(|packet_number| {
+                Event::PacketSent {
+                    space,
+                    packet_number,
+                }
+            }),
+        );
+
+        match new_space.largest_received {
+            Some(packet_number) if new_space.largest_received > old_space.largest_received => {
+                events.push(Event::PacketReceived {
+                    space,
+                    packet_number,
+                })
+            }
+            _ => {}
+        }
+
+        match new_space.largest_acked {
+            Some(largest_acknowledged) if new_space.largest_acked > old_space.largest_acked => {
+                events.push(Event::PacketsAcked {
+                    space,
+                    largest_acknowledged,
+                })
+            }
+            _ => {}
+        }
+    }
+
+    let lost = new.packets_lost.saturating_sub(old.packets_lost);
+    events.extend((0..lost).map(|_| Event::PacketLost));
+
+    let metrics = new.primary_path().map(Metrics::from_path);
+    if metrics.is_some() && metrics != old.primary_path().map(Metrics::from_path) {
+        events.extend(metrics.map(Event::MetricsUpdated));
+    }
+
+    events
+}
+
+/// Writes the qlog trace of one `Connection`.
+/// The trace is completed, when the writer is dropped.
+pub(crate) struct QlogWriter {
+    file: File,
+    start: Instant,
+    /// The statistics of the last update.
+    stats: ConnectionStats,
+    /// The number of bytes that were received on each `Stream`.
+    recv_offsets: HashMap<stream::Id, u64>,
+    /// The number of bytes that were sent on each `Stream`.
+    sent_offsets: HashMap<stream::Id, u64>,
+    /// The events that were not written yet.
+    pending: String,
+    /// Was an event recorded already? The events are separated by commas.
+    has_events: bool,
+}
+
+impl QlogWriter {
+    /// Creates the trace of the given `Connection` in the given directory.
+    pub fn create(dir: &Path, id: connection::Id, is_client: bool) -> io::Result<QlogWriter> {
+        let vantage_point = if is_client { "client" } else { "server" };
+
+        fs::create_dir_all(dir)?;
+        let mut file = File::create(dir.join(format!("{:016x}.{}.qlog", id, vantage_point)))?;
+
+        let reference_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(millis)
+            .unwrap_or(0.0);
+
+        write!(
+            file,
+            "{{\"qlog_version\":\"0.3\",\"qlog_format\":\"JSON\",\"title\":\"picoquic-rs\",\
+             \"traces\":[{{\"vantage_point\":{{\"type\":\"{}\"}},\"common_fields\":\
+             {{\"group_id\":\"{:016x}\",\"reference_time\":{:.3}}},\"events\":[",
+            vantage_point, id, reference_time
+        )?;
+
+        Ok(QlogWriter {
+            file,
+            start: Instant::now(),
+            stats: ConnectionStats::default(),
+            recv_offsets: HashMap::new(),
+            sent_offsets: HashMap::new(),
+            pending: String::new(),
+            has_events: false,
+        })
+    }
+
+    fn push(&mut self, event: &Event) {
+        if self.has_events {
+            self.pending.push(',');
+        }
+        self.has_events = true;
+
+        let _ = write!(
+            self.pending,
+            "\n{{\"time\":{:.3},\"name\":\"{}\",\"data\":{}}}",
+            millis(self.start.elapsed()),
+            event.name(),
+            event.data()
+        );
+    }
+
+    /// Records the packet and congestion control events since the last statistics.
+    pub fn on_stats(&mut self, stats: &ConnectionStats) {
+        for event in stats_events(&self.stats, stats) {
+            self.push(&event);
+        }
+
+        self.stats = stats.clone();
+    }
+
+    /// Records data that was received on the given `Stream`.
+    pub fn on_stream_data_received(&mut self, id: stream::Id, len: usize, fin: bool) {
+        let offset = {
+            let offset = self.recv_offsets.entry(id).or_insert(0);
+            let old = *offset;
+            *offset += len as u64;
+            old
+        };
+
+        self.push(&Event::StreamDataMoved {
+            stream_id: id,
+            offset,
+            length: len as u64,
+            fin,
+            sent: false,
+        });
+    }
+
+    /// Records the data that was sent on the given `Stream`s, since the last call. The sent
+    /// offset of each open `Stream` needs to be given, `Stream`s that are not given are
+    /// forgotten.
+    pub fn on_stream_data_sent<I: Iterator<Item = (stream::Id, u64)>>(&mut self, streams: I) {
+        let mut sent_offsets = HashMap::new();
+
+        for (id, sent) in streams {
+            let offset = self.sent_offsets.get(&id).cloned().unwrap_or(0);
+
+            if sent > offset {
+                self.push(&Event::StreamDataMoved {
+                    stream_id: id,
+                    offset,
+                    length: sent - offset,
+                    fin: false,
+                    sent: true,
+                });
+            }
+
+            sent_offsets.insert(id, sent);
+        }
+
+        self.recv_offsets.retain(|id, _| sent_offsets.contains_key(id));
+        self.sent_offsets = sent_offsets;
+    }
+
+    /// Writes the recorded events to the trace.
+    pub fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.file.write_all(self.pending.as_bytes()) {
+            warn!("could not write qlog trace: {:?}", e);
+        }
+
+        self.pending.clear();
+    }
+}
+
+impl Drop for QlogWriter {
+    fn drop(&mut self) {
+        self.flush();
+
+        if let Err(e) = self.file.write_all(b"\n]}]}\n") {
+            warn!("could not complete qlog trace: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use stats::PacketNumberSpaceStats;
+
+    fn path_stats(congestion_window: u64) -> PathStats {
+        PathStats {
+            peer_addr: ([127, 0, 0, 1], 4433).into(),
+            smoothed_rtt: Duration::from_millis(10),
+            rtt_variance: Duration::from_millis(1),
+            min_rtt: Duration::from_millis(5),
+            congestion_window,
+            bytes_in_flight: 0,
+            mtu: 1440,
+            pacing_rate: 1000,
+            one_way_delay: None,
+        }
+    }
+
+    #[test]
+    fn stats_changes_are_recorded_as_events() {
+        let old = ConnectionStats {
+            application: PacketNumberSpaceStats {
+                packets_sent: 2,
+                largest_acked: Some(0),
+                largest_received: Some(3),
+            },
+            paths: vec![path_stats(10000)],
+            ..Default::default()
+        };
+
+        let new = ConnectionStats {
+            application: PacketNumberSpaceStats {
+                packets_sent: 4,
+                largest_acked: Some(1),
+                largest_received: Some(3),
+            },
+            paths: vec![path_stats(12000)],
+            packets_lost: 1,
+            ..Default::default()
+        };
+
+        let events = stats_events(&old, &new);
+
+        assert_eq!(
+            vec![
+                Event::PacketSent {
+                    space: Space::ApplicationData,
+                    packet_number: 2,
+                },
+                Event::PacketSent {
+                    space: Space::ApplicationData,
+                    packet_number: 3,
+                },
+                Event::PacketsAcked {
+                    space: Space::ApplicationData,
+                    largest_acknowledged: 1,
+                },
+                Event::PacketLost,
+                Event::MetricsUpdated(Metrics::from_path(&path_stats(12000))),
+            ],
+            events
+        );
+        assert!(stats_events(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn events_are_serialized_as_qlog() {
+        let sent = Event::PacketSent {
+            space: Space::Initial,
+            packet_number: 0,
+        };
+        assert_eq!("transport:packet_sent", sent.name());
+        assert_eq!(
+            "{\"header\":{\"packet_type\":\"initial\",\"packet_number\":0}}",
+            sent.data()
+        );
+
+        let stream = Event::StreamDataMoved {
+            stream_id: 4,
+            offset: 100,
+            length: 5,
+            fin: true,
+            sent: false,
+        };
+        assert_eq!(
+            "{\"stream_id\":4,\"offset\":100,\"length\":5,\"from\":\"transport\",\
+             \"to\":\"application\",\"fin\":true}",
+            stream.data()
+        );
+
+        let metrics = Event::MetricsUpdated(Metrics::from_path(&path_stats(12000)));
+        assert_eq!(
+            "{\"congestion_window\":12000,\"bytes_in_flight\":0,\"smoothed_rtt\":10.000,\
+             \"min_rtt\":5.000,\"pacing_rate\":8000}",
+            metrics.data()
+        );
+    }
+}
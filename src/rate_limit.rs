@@ -0,0 +1,155 @@
+use std::{collections::HashMap, net::IpAddr};
+
+/// How often buckets that are full again are removed, in microseconds.
+const PRUNE_INTERVAL: u64 = 1_000_000;
+
+/// What happens with a new handshake, that exceeds the `HandshakeRateLimit` of its source IP.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitAction {
+    /// The handshake is answered with a stateless retry. Clients that own their address repeat
+    /// the handshake with the retry token and are accepted.
+    Retry,
+    /// The packet is silently dropped.
+    Drop,
+}
+
+/// The rate limit of new incoming handshakes per source IP.
+///
+/// Each source IP has a token bucket that holds up to `burst` handshakes and is refilled with
+/// `per_second` handshakes per second.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HandshakeRateLimit {
+    /// The number of handshakes per second.
+    pub per_second: f64,
+    /// The number of handshakes that may be started at once.
+    pub burst: u32,
+    /// What happens with handshakes that exceed the limit.
+    pub action: RateLimitAction,
+}
+
+struct Bucket {
+    tokens: f64,
+    /// The time of the last refill in microseconds.
+    updated: u64,
+}
+
+/// Enforces the `HandshakeRateLimit` per source IP with a token bucket.
+///
+/// Buckets that would be full are equal to no bucket, so these are removed regularly. This keeps
+/// the memory bounded by the number of source IPs that started handshakes recently.
+pub struct HandshakeRateLimiter {
+    limit: Option<HandshakeRateLimit>,
+    buckets: HashMap<IpAddr, Bucket>,
+    /// The time of the last pruning in microseconds.
+    pruned: u64,
+}
+
+impl HandshakeRateLimiter {
+    pub fn new(limit: Option<HandshakeRateLimit>) -> HandshakeRateLimiter {
+        HandshakeRateLimiter {
+            limit,
+            buckets: HashMap::new(),
+            pruned: 0,
+        }
+    }
+
+    /// Sets the limit, that is used for all further handshakes.
+    pub fn set_limit(&mut self, limit: Option<HandshakeRateLimit>) {
+        self.limit = limit;
+
+        if limit.is_none() {
+            self.buckets.clear();
+        }
+    }
+
+    /// Needs to be called for each new handshake of the given source IP.
+    ///
+    /// # Returns
+    /// None, if the handshake is within the limit. Otherwise, the action that is taken for the
+    /// handshake.
+    pub fn on_new_handshake(&mut self, ip: IpAddr, now: u64) -> Option<RateLimitAction> {
+        let limit = self.limit?;
+        let burst = f64::from(limit.burst);
+
+        if now.saturating_sub(self.pruned) >= PRUNE_INTERVAL {
+            self.pruned = now;
+            self.buckets.retain(|_, b| b.tokens + refill(&limit, b.updated, now) < burst);
+        }
+
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = (bucket.tokens + refill(&limit, bucket.updated, now)).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            debug!("handshake of {} exceeds the handshake rate limit", ip);
+            Some(limit.action)
+        }
+    }
+}
+
+/// Returns the number of tokens that were refilled between `since` and `now`.
+fn refill(limit: &HandshakeRateLimit, since: u64, now: u64) -> f64 {
+    now.saturating_sub(since) as f64 / 1_000_000f64 * limit.per_second
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(per_second: f64, burst: u32) -> HandshakeRateLimiter {
+        HandshakeRateLimiter::new(Some(HandshakeRateLimit {
+            per_second,
+            burst,
+            action: RateLimitAction::Drop,
+        }))
+    }
+
+    #[test]
+    fn handshakes_above_burst_are_limited() {
+        let mut limiter = limiter(1.0, 2);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        assert_eq!(None, limiter.on_new_handshake(ip, 0));
+        assert_eq!(None, limiter.on_new_handshake(ip, 0));
+        assert_eq!(Some(RateLimitAction::Drop), limiter.on_new_handshake(ip, 0));
+
+        // Other source IPs have their own bucket.
+        assert_eq!(None, limiter.on_new_handshake(IpAddr::from([127, 0, 0, 2]), 0));
+    }
+
+    #[test]
+    fn bucket_is_refilled_over_time() {
+        let mut limiter = limiter(2.0, 1);
+        let ip = IpAddr::from([127, 0, 0, 1]);
+
+        assert_eq!(None, limiter.on_new_handshake(ip, 0));
+        assert_eq!(Some(RateLimitAction::Drop), limiter.on_new_handshake(ip, 100_000));
+        assert_eq!(None, limiter.on_new_handshake(ip, 600_000));
+    }
+
+    #[test]
+    fn full_buckets_are_pruned() {
+        let mut limiter = limiter(1.0, 1);
+
+        assert_eq!(None, limiter.on_new_handshake(IpAddr::from([127, 0, 0, 1]), 0));
+        assert_eq!(1, limiter.buckets.len());
+
+        assert_eq!(None, limiter.on_new_handshake(IpAddr::from([127, 0, 0, 2]), 2_000_000));
+        assert_eq!(1, limiter.buckets.len());
+    }
+
+    #[test]
+    fn without_limit_all_handshakes_are_accepted() {
+        let mut limiter = HandshakeRateLimiter::new(None);
+
+        for _ in 0..100 {
+            assert_eq!(None, limiter.on_new_handshake(IpAddr::from([127, 0, 0, 1]), 0));
+        }
+    }
+}
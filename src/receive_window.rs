@@ -0,0 +1,125 @@
+use std::{
+    cmp,
+    time::{Duration, Instant},
+};
+
+/// Grows the receive window of a connection, based on the observed bandwidth-delay product
+/// (similar to the receive buffer auto tuning of TCP).
+///
+/// The amount of data that is received in one round trip time, is the bandwidth-delay product
+/// of the path. If the peer sends more than half of the receive window in one round trip time,
+/// it is probably limited by the flow control and the window is increased.
+pub struct ReceiveWindowTuner {
+    /// The current receive window.
+    window: u64,
+    /// The maximum the receive window is allowed to grow to.
+    max_window: u64,
+    /// The number of bytes received in the current measurement.
+    received: u64,
+    /// The time point at which the current measurement started.
+    measurement_start: Option<Instant>,
+}
+
+impl ReceiveWindowTuner {
+    pub fn new(window: u64, max_window: u64) -> ReceiveWindowTuner {
+        ReceiveWindowTuner {
+            window,
+            max_window,
+            received: 0,
+            measurement_start: None,
+        }
+    }
+
+    /// Needs to be called for all data that is received by the connection.
+    pub fn on_data_received(&mut self, len: usize) {
+        self.received += len as u64;
+    }
+
+    /// Finishes the current measurement, if it took at least one round trip time.
+    ///
+    /// # Returns
+    /// Some(_) is the new receive window. None intends that the receive window should not be
+    /// changed.
+    pub fn poll(&mut self, rtt: Duration, now: Instant) -> Option<u64> {
+        let start = match self.measurement_start {
+            Some(start) => start,
+            None => {
+                self.start_measurement(now);
+                return None;
+            }
+        };
+
+        if rtt == Duration::from_secs(0) || now.duration_since(start) < rtt {
+            return None;
+        }
+
+        let bdp = self.received;
+        self.start_measurement(now);
+
+        if bdp * 2 > self.window && self.window < self.max_window {
+            self.window = cmp::min(self.max_window, cmp::max(self.window * 2, bdp * 2));
+            Some(self.window)
+        } else {
+            None
+        }
+    }
+
+    fn start_measurement(&mut self, now: Instant) {
+        self.received = 0;
+        self.measurement_start = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_grows_when_peer_is_limited() {
+        let rtt = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut tuner = ReceiveWindowTuner::new(1000, 100_000);
+
+        assert_eq!(None, tuner.poll(rtt, now));
+        tuner.on_data_received(900);
+        assert_eq!(Some(2000), tuner.poll(rtt, now + rtt));
+        tuner.on_data_received(1800);
+        assert_eq!(Some(4000), tuner.poll(rtt, now + rtt * 2));
+    }
+
+    #[test]
+    fn window_does_not_grow_when_peer_is_not_limited() {
+        let rtt = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut tuner = ReceiveWindowTuner::new(1000, 100_000);
+
+        assert_eq!(None, tuner.poll(rtt, now));
+        tuner.on_data_received(400);
+        assert_eq!(None, tuner.poll(rtt, now + rtt));
+    }
+
+    #[test]
+    fn window_does_not_grow_before_one_rtt_passed() {
+        let rtt = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut tuner = ReceiveWindowTuner::new(1000, 100_000);
+
+        assert_eq!(None, tuner.poll(rtt, now));
+        tuner.on_data_received(900);
+        assert_eq!(None, tuner.poll(rtt, now + rtt / 2));
+        assert_eq!(Some(2000), tuner.poll(rtt, now + rtt));
+    }
+
+    #[test]
+    fn window_does_not_grow_above_maximum() {
+        let rtt = Duration::from_millis(100);
+        let now = Instant::now();
+        let mut tuner = ReceiveWindowTuner::new(1000, 1500);
+
+        assert_eq!(None, tuner.poll(rtt, now));
+        tuner.on_data_received(900);
+        assert_eq!(Some(1500), tuner.poll(rtt, now + rtt));
+        tuner.on_data_received(1500);
+        assert_eq!(None, tuner.poll(rtt, now + rtt * 2));
+    }
+}
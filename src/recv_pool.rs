@@ -0,0 +1,83 @@
+use bytes::BytesMut;
+
+/// Copies the received data of a connection into big chunks, instead of allocating a buffer for
+/// each stream frame.
+///
+/// The data is handed out as slices of the current chunk. A new chunk is only allocated, if the
+/// current chunk is full. If all slices of the current chunk were dropped, its memory is reused
+/// for the next chunk.
+pub struct RecvPool {
+    /// The size of the chunks, `None` allocates a buffer for each stream frame.
+    chunk_size: Option<usize>,
+    /// The unused part of the current chunk.
+    chunk: BytesMut,
+}
+
+impl RecvPool {
+    pub fn new(chunk_size: Option<usize>) -> RecvPool {
+        RecvPool {
+            chunk_size,
+            chunk: BytesMut::new(),
+        }
+    }
+
+    /// Copies the given data into the pool.
+    pub fn copy(&mut self, data: &[u8]) -> BytesMut {
+        let chunk_size = match self.chunk_size {
+            // Data that does not fit into a chunk gets its own buffer.
+            Some(chunk_size) if data.len() <= chunk_size => chunk_size,
+            _ => return BytesMut::from(data),
+        };
+
+        if self.chunk.capacity() < data.len() {
+            // Reclaims the memory of the chunk, if no slice of it is alive anymore.
+            self.chunk.reserve(chunk_size);
+        }
+
+        self.chunk.extend_from_slice(data);
+        self.chunk.split_to(data.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_is_copied_into_chunks() {
+        let mut pool = RecvPool::new(Some(1024));
+
+        let first = pool.copy(&[1; 100]);
+        let second = pool.copy(&[2; 200]);
+        let big = pool.copy(&[3; 2048]);
+
+        assert_eq!(&[1; 100][..], &first[..]);
+        assert_eq!(&[2; 200][..], &second[..]);
+        assert_eq!(&[3; 2048][..], &big[..]);
+        // Both slices are part of the same chunk.
+        assert_eq!(unsafe { first.as_ptr().add(100) }, second.as_ptr());
+    }
+
+    #[test]
+    fn chunk_is_reused_after_all_slices_are_dropped() {
+        let mut pool = RecvPool::new(Some(1024));
+
+        let first = pool.copy(&[1; 600]);
+        let ptr = first.as_ptr();
+        drop(first);
+
+        // The chunk has not enough space left, so its memory is reclaimed.
+        let second = pool.copy(&[2; 600]);
+        assert_eq!(ptr, second.as_ptr());
+        assert_eq!(&[2; 600][..], &second[..]);
+    }
+
+    #[test]
+    fn without_chunk_size_each_data_is_allocated() {
+        let mut pool = RecvPool::new(None);
+
+        let data = pool.copy(b"hello");
+        assert_eq!(&b"hello"[..], &data[..]);
+        assert!(pool.chunk.is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use connection::{ConnectionIds, Id, Type};
+use ffi;
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+/// Information about a live `Connection` of a `Context`, see `Context::get_connection`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConnectionInfo {
+    /// The id of the `Connection`, see `Connection::id`.
+    pub id: Id,
+    /// Was the `Connection` created by the peer or locally?
+    pub connection_type: Type,
+    /// The current address of the peer.
+    pub peer_addr: SocketAddr,
+    /// The local address of the `Connection`.
+    pub local_addr: SocketAddr,
+    /// The current QUIC connection ids.
+    pub connection_ids: ConnectionIds,
+}
+
+/// The live connections of a context, shared between the `ContextInner` and the `Context`.
+#[derive(Clone, Default)]
+pub struct ConnectionRegistry {
+    /// The key is the address of the connection.
+    connections: Arc<Mutex<HashMap<usize, ConnectionInfo>>>,
+}
+
+impl ConnectionRegistry {
+    /// Updates the information about the given connection. The id is taken, when the connection
+    /// is seen the first time, like the id of its `Connection`.
+    pub fn update(&self, key: usize, con: ffi::Connection) {
+        let mut connections = self.connections.lock().unwrap();
+        let id = connections
+            .get(&key)
+            .map(|info| info.id)
+            .unwrap_or_else(|| con.local_id());
+
+        connections.insert(
+            key,
+            ConnectionInfo {
+                id,
+                connection_type: con.con_type(),
+                peer_addr: con.peer_addr(),
+                local_addr: con.path_local_addr().unwrap_or_else(|| con.local_addr()),
+                connection_ids: con.connection_ids(),
+            },
+        );
+    }
+
+    /// Needs to be called, before the connection is deleted.
+    pub fn remove(&self, key: usize) {
+        self.connections.lock().unwrap().remove(&key);
+    }
+
+    pub fn clear(&self) {
+        self.connections.lock().unwrap().clear();
+    }
+
+    /// Returns the information about the live connection with the given id.
+    pub fn get(&self, id: Id) -> Option<ConnectionInfo> {
+        self.connections
+            .lock()
+            .unwrap()
+            .values()
+            .find(|info| info.id == id)
+            .cloned()
+    }
+}
@@ -0,0 +1,344 @@
+use context::ContextDriver;
+#[cfg(target_os = "linux")]
+use ecn;
+use ecn::Ecn;
+use error::*;
+#[cfg(target_os = "linux")]
+use ipv6;
+use socket_options;
+
+use failure;
+
+use futures::{
+    task::{self, Task},
+    Async, Future, Poll,
+};
+
+use std::{
+    io,
+    net::{SocketAddr, SocketAddrV6},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+
+use tokio::{net::UdpSocket, runtime::TaskExecutor, timer::Delay};
+
+/// A UDP socket that is used by a `Context` to send and receive packets.
+///
+/// All functions need to be non-blocking. If a function returns `NotReady`, the current task
+/// needs to be notified when the socket is ready again.
+pub trait Socket: Send {
+    /// Receives a single packet into the given buffer.
+    /// Returns the number of bytes received and the address of the sender.
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error>;
+
+    /// Sends the given data as a single packet to the given target.
+    /// Returns the number of bytes sent.
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error>;
+
+    /// Checks if the socket is ready to send a packet.
+    fn poll_write_ready(&mut self) -> Poll<(), io::Error>;
+
+    /// Returns the local address the socket is bound to.
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Sets the traffic class of all IPv6 packets sent by this socket.
+    /// The default implementation does not support this option.
+    fn set_ipv6_traffic_class(&mut self, _traffic_class: u8) -> io::Result<()> {
+        Err(socket_options::unsupported("traffic class"))
+    }
+
+    /// Prepares the socket to send packets with the given flow label to the given peer.
+    /// The flow label is given as `flowinfo` of the target address to `poll_send_to`.
+    /// The default implementation does not support this option.
+    fn register_ipv6_flow_label(&mut self, _peer: &SocketAddrV6, _label: u32) -> io::Result<()> {
+        Err(socket_options::unsupported("flow label"))
+    }
+
+    /// Sets the size of the send buffer of this socket in bytes.
+    /// The default implementation does not support this option.
+    fn set_send_buffer_size(&mut self, _size: usize) -> io::Result<()> {
+        Err(socket_options::unsupported("send buffer size"))
+    }
+
+    /// Sets the size of the receive buffer of this socket in bytes.
+    /// The default implementation does not support this option.
+    fn set_recv_buffer_size(&mut self, _size: usize) -> io::Result<()> {
+        Err(socket_options::unsupported("receive buffer size"))
+    }
+
+    /// Sets the TOS(DSCP and ECN bits) of all IPv4 packets sent by this socket.
+    /// The default implementation does not support this option.
+    fn set_tos(&mut self, _tos: u8) -> io::Result<()> {
+        Err(socket_options::unsupported("TOS"))
+    }
+
+    /// Sets the TTL(hop limit for IPv6) of all packets sent by this socket.
+    /// The default implementation does not support this option.
+    fn set_ttl(&mut self, _ttl: u32) -> io::Result<()> {
+        Err(socket_options::unsupported("TTL"))
+    }
+
+    /// Enables ECN. All packets sent by this socket are marked as ECN capable(`Ecn::Ect0`) and
+    /// `poll_recv_from_with_ecn` returns the codepoints of the received packets.
+    /// The default implementation does not support this option.
+    fn enable_ecn(&mut self) -> io::Result<()> {
+        Err(socket_options::unsupported("ECN"))
+    }
+
+    /// Receives a single packet into the given buffer, like `poll_recv_from`.
+    /// Returns the number of bytes received, the address of the sender and the ECN codepoint of
+    /// the packet. The default implementation returns `Ecn::NotEct` for all packets.
+    fn poll_recv_from_with_ecn(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Poll<(usize, SocketAddr, Ecn), io::Error> {
+        let (len, addr) = try_ready!(self.poll_recv_from(buf));
+        Ok(Async::Ready((len, addr, Ecn::NotEct)))
+    }
+}
+
+impl Socket for UdpSocket {
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        UdpSocket::poll_recv_from(self, buf)
+    }
+
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        UdpSocket::poll_send_to(self, buf, target)
+    }
+
+    fn poll_write_ready(&mut self) -> Poll<(), io::Error> {
+        UdpSocket::poll_write_ready(self).map(|r| r.map(|_| ()))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        UdpSocket::local_addr(self)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_ipv6_traffic_class(&mut self, traffic_class: u8) -> io::Result<()> {
+        ipv6::set_traffic_class(self.as_raw_fd(), traffic_class)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn register_ipv6_flow_label(&mut self, peer: &SocketAddrV6, label: u32) -> io::Result<()> {
+        ipv6::register_flow_label(self.as_raw_fd(), peer, label)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_send_buffer_size(&mut self, size: usize) -> io::Result<()> {
+        socket_options::set_send_buffer_size(self.as_raw_fd(), size)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_recv_buffer_size(&mut self, size: usize) -> io::Result<()> {
+        socket_options::set_recv_buffer_size(self.as_raw_fd(), size)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_tos(&mut self, tos: u8) -> io::Result<()> {
+        socket_options::set_tos(self.as_raw_fd(), tos)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_ttl(&mut self, ttl: u32) -> io::Result<()> {
+        let is_ipv6 = UdpSocket::local_addr(self)?.is_ipv6();
+        socket_options::set_ttl(self.as_raw_fd(), ttl, is_ipv6)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn enable_ecn(&mut self) -> io::Result<()> {
+        let is_ipv6 = UdpSocket::local_addr(self)?.is_ipv6();
+        ecn::enable(self.as_raw_fd(), is_ipv6)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn poll_recv_from_with_ecn(
+        &mut self,
+        buf: &mut [u8],
+    ) -> Poll<(usize, SocketAddr, Ecn), io::Error> {
+        match ecn::recv_from(self.as_raw_fd(), buf) {
+            Ok(res) => Ok(Async::Ready(res)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                // Registers the current task to be notified, when the socket is readable again.
+                // A packet that arrived in the meantime is returned without its codepoint.
+                let (len, addr) = try_ready!(UdpSocket::poll_recv_from(self, buf));
+                Ok(Async::Ready((len, addr, Ecn::NotEct)))
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// A timer that is used by a `Context` to wake up picoquic, to handle resends, timeouts, etc.
+///
+/// If `poll` returns `NotReady`, the current task needs to be notified when the timer fires.
+pub trait Timer: Send {
+    /// Resets the timer to fire at the given time point.
+    fn reset(&mut self, at: Instant);
+
+    /// Checks if the timer fired.
+    fn poll(&mut self) -> Poll<(), Error>;
+}
+
+impl Timer for Delay {
+    fn reset(&mut self, at: Instant) {
+        Delay::reset(self, at)
+    }
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        Future::poll(self).map_err(|e| failure::Error::from(e).into())
+    }
+}
+
+impl<T: Timer + ?Sized> Timer for Box<T> {
+    fn reset(&mut self, at: Instant) {
+        (**self).reset(at)
+    }
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        (**self).poll()
+    }
+}
+
+#[derive(Default)]
+struct ThreadTimerState {
+    /// The time point the timer fires at, `None` after it fired.
+    at: Option<Instant>,
+    fired: bool,
+    task: Option<Task>,
+    /// The `ThreadTimer` was dropped and the thread needs to stop.
+    dropped: bool,
+}
+
+/// A `Timer` that waits on its own thread, so it does not require a runtime.
+pub struct ThreadTimer {
+    state: Arc<(Mutex<ThreadTimerState>, Condvar)>,
+}
+
+impl ThreadTimer {
+    /// Creates a new `ThreadTimer` and starts its thread. The timer does not fire, before it
+    /// was reset.
+    pub fn new() -> ThreadTimer {
+        let state = Arc::new((Mutex::new(ThreadTimerState::default()), Condvar::new()));
+        let thread_state = state.clone();
+
+        thread::spawn(move || {
+            let (ref lock, ref cvar) = *thread_state;
+            let mut state = lock.lock().unwrap();
+
+            while !state.dropped {
+                let now = Instant::now();
+                let at = state.at;
+
+                match at {
+                    Some(at) if at <= now => {
+                        state.at = None;
+                        state.fired = true;
+
+                        if let Some(task) = state.task.take() {
+                            task.notify();
+                        }
+                    }
+                    Some(at) => state = cvar.wait_timeout(state, at - now).unwrap().0,
+                    None => state = cvar.wait(state).unwrap(),
+                }
+            }
+        });
+
+        ThreadTimer { state }
+    }
+
+    fn with_state<R>(&self, f: impl FnOnce(&mut ThreadTimerState) -> R) -> R {
+        let (ref lock, ref cvar) = *self.state;
+        let res = f(&mut lock.lock().unwrap());
+        cvar.notify_one();
+        res
+    }
+}
+
+impl Default for ThreadTimer {
+    fn default() -> ThreadTimer {
+        ThreadTimer::new()
+    }
+}
+
+impl Timer for ThreadTimer {
+    fn reset(&mut self, at: Instant) {
+        self.with_state(|state| {
+            state.at = Some(at);
+            state.fired = false;
+        })
+    }
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        let (ref lock, _) = *self.state;
+        let mut state = lock.lock().unwrap();
+
+        if state.fired {
+            Ok(Async::Ready(()))
+        } else {
+            state.task = Some(task::current());
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for ThreadTimer {
+    fn drop(&mut self) {
+        self.with_state(|state| state.dropped = true)
+    }
+}
+
+/// Spawns the `ContextDriver` of a `Context` on an executor, see `Context::new_with_spawner`.
+///
+/// This makes it possible to use a `Context` without a tokio runtime, e.g. on a custom
+/// executor. The sockets of the `Context` use the background reactor of tokio then.
+pub trait Spawn {
+    /// Spawns the given `ContextDriver`, the driver never finishes.
+    fn spawn(&self, driver: ContextDriver) -> Result<(), Error>;
+
+    /// Creates the `Timer` of the `Context`.
+    /// The default implementation creates a `ThreadTimer`, that does not require a runtime.
+    fn timer(&self) -> Box<dyn Timer> {
+        Box::new(ThreadTimer::new())
+    }
+}
+
+impl Spawn for TaskExecutor {
+    fn spawn(&self, driver: ContextDriver) -> Result<(), Error> {
+        TaskExecutor::spawn(self, driver);
+        Ok(())
+    }
+
+    fn timer(&self) -> Box<dyn Timer> {
+        Box::new(Delay::new(Instant::now() + Duration::from_secs(10)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::future;
+
+    #[test]
+    fn thread_timer_fires_after_reset() {
+        let mut timer = ThreadTimer::new();
+        let start = Instant::now();
+        timer.reset(start + Duration::from_millis(50));
+
+        future::poll_fn(|| timer.poll()).wait().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        timer.reset(Instant::now() + Duration::from_secs(60));
+        let not_ready = future::lazy(|| Ok::<_, ()>(timer.poll().unwrap().is_not_ready()))
+            .wait()
+            .unwrap();
+        assert!(not_ready);
+    }
+}
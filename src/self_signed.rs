@@ -0,0 +1,80 @@
+use config::{Config, FileFormat};
+use error::*;
+
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    rsa::Rsa,
+    x509::{extension::SubjectAlternativeName, X509NameBuilder, X509},
+};
+
+/// The validity of a generated certificate in days.
+const VALIDITY_DAYS: u32 = 365;
+
+/// Generates a key pair and a self-signed certificate for the given common name. The common name
+/// is also the DNS subject alternative name of the certificate.
+pub fn generate(common_name: &str) -> Result<(X509, PKey<Private>), Error> {
+    let key = PKey::from_rsa(Rsa::generate(2048)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(64, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial.to_asn1_integer()?)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&Asn1Time::days_from_now(0)?)?;
+    builder.set_not_after(&Asn1Time::days_from_now(VALIDITY_DAYS)?)?;
+
+    let alt_name = SubjectAlternativeName::new()
+        .dns(common_name)
+        .build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(alt_name)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((builder.build(), key))
+}
+
+/// Generates a self-signed certificate and sets it as certificate chain of the given `Config`.
+/// Returns the generated certificate.
+pub fn apply(config: &mut Config, common_name: &str) -> Result<X509, Error> {
+    let (cert, key) = generate(common_name)?;
+
+    config.set_certificate_chain(vec![cert.to_der()?], FileFormat::DER);
+    config.set_private_key(key.private_key_to_der()?, FileFormat::DER);
+
+    Ok(cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_certificate_is_self_signed() {
+        let (cert, key) = generate("p2p.test").unwrap();
+
+        assert!(cert.verify(&key).unwrap());
+        assert!(cert.public_key().unwrap().public_eq(&key));
+
+        let common_name = cert
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .unwrap()
+            .data()
+            .as_utf8()
+            .unwrap();
+        assert_eq!("p2p.test", &common_name.to_string());
+    }
+}
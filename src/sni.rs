@@ -0,0 +1,53 @@
+use config::FileFormat;
+
+/// A certificate chain and private key, that the server presents to clients which request a
+/// matching server name(SNI). See `Config::add_sni_identity`.
+#[derive(Clone)]
+pub struct SniIdentity {
+    /// The server name, e.g. `example.com`, or a wildcard, e.g. `*.example.com`, that matches
+    /// exactly one additional label.
+    pub hostname_pattern: String,
+    /// The format of the `certificate_chain` and the `private_key`.
+    pub format: FileFormat,
+    /// The certificate chain, the own certificate first.
+    pub certificate_chain: Vec<Vec<u8>>,
+    pub private_key: Vec<u8>,
+}
+
+/// Returns if the given server name matches the given hostname pattern.
+/// Names are compared case-insensitive, a trailing dot is ignored.
+pub fn hostname_matches(pattern: &str, server_name: &str) -> bool {
+    let pattern = pattern.trim_end_matches('.');
+    let server_name = server_name.trim_end_matches('.');
+
+    if pattern.starts_with("*.") {
+        match server_name.find('.') {
+            // The wildcard matches exactly one non-empty label.
+            Some(pos) if pos > 0 => server_name[pos..].eq_ignore_ascii_case(&pattern[1..]),
+            _ => false,
+        }
+    } else {
+        pattern.eq_ignore_ascii_case(server_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_pattern_matches_case_insensitive() {
+        assert!(hostname_matches("example.com", "example.com"));
+        assert!(hostname_matches("example.com", "EXAMPLE.com."));
+        assert!(!hostname_matches("example.com", "www.example.com"));
+    }
+
+    #[test]
+    fn wildcard_pattern_matches_one_label() {
+        assert!(hostname_matches("*.example.com", "www.example.com"));
+        assert!(hostname_matches("*.example.com", "API.Example.Com"));
+        assert!(!hostname_matches("*.example.com", "example.com"));
+        assert!(!hostname_matches("*.example.com", "a.b.example.com"));
+        assert!(!hostname_matches("*.example.com", ".example.com"));
+    }
+}
@@ -0,0 +1,136 @@
+//! Socket options for tuning the UDP sockets of a `Context`, that are not covered by tokio.
+
+use std::io;
+
+#[cfg(target_os = "linux")]
+use std::{
+    mem::{self, ManuallyDrop},
+    os::unix::io::{FromRawFd, RawFd},
+};
+
+#[cfg(target_os = "linux")]
+use socket2::Socket;
+
+/// The error for options that are not supported by a socket or platform.
+pub fn unsupported(option: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("setting the {} is not supported", option),
+    )
+}
+
+/// Sets the given option of the given socket to `value`.
+#[cfg(target_os = "linux")]
+pub unsafe fn set_option<T>(
+    fd: RawFd,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: &T,
+) -> io::Result<()> {
+    let res = libc::setsockopt(
+        fd,
+        level,
+        name,
+        value as *const T as *const libc::c_void,
+        mem::size_of::<T>() as libc::socklen_t,
+    );
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Calls `f` with the given socket as `socket2::Socket`, without closing the socket afterwards.
+#[cfg(target_os = "linux")]
+fn with_socket2<F>(fd: RawFd, f: F) -> io::Result<()>
+where
+    F: FnOnce(&Socket) -> io::Result<()>,
+{
+    let socket = ManuallyDrop::new(unsafe { Socket::from_raw_fd(fd) });
+    f(&socket)
+}
+
+/// Converts the given value into an integer option, values that are too big are rejected.
+#[cfg(target_os = "linux")]
+fn to_c_int<T: Copy + Into<u64>>(value: T, option: &str) -> io::Result<libc::c_int> {
+    let value = value.into();
+
+    if value > libc::c_int::max_value() as u64 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the {} is too big: {}", option, value),
+        ))
+    } else {
+        Ok(value as libc::c_int)
+    }
+}
+
+/// Sets the size of the send buffer(`SO_SNDBUF`) of the given socket in bytes.
+/// Linux doubles the value for its bookkeeping and caps it at `net.core.wmem_max`.
+#[cfg(target_os = "linux")]
+pub fn set_send_buffer_size(fd: RawFd, size: usize) -> io::Result<()> {
+    let size = to_c_int(size as u64, "send buffer size")?;
+    with_socket2(fd, |s| s.set_send_buffer_size(size as usize))
+}
+
+/// Sets the size of the receive buffer(`SO_RCVBUF`) of the given socket in bytes.
+/// Linux doubles the value for its bookkeeping and caps it at `net.core.rmem_max`.
+#[cfg(target_os = "linux")]
+pub fn set_recv_buffer_size(fd: RawFd, size: usize) -> io::Result<()> {
+    let size = to_c_int(size as u64, "receive buffer size")?;
+    with_socket2(fd, |s| s.set_recv_buffer_size(size as usize))
+}
+
+/// Sets the TOS(DSCP and ECN bits) of all IPv4 packets that are sent by the given socket. This
+/// includes the IPv4 packets of a dual stack IPv6 socket.
+#[cfg(target_os = "linux")]
+pub fn set_tos(fd: RawFd, tos: u8) -> io::Result<()> {
+    unsafe { set_option(fd, libc::IPPROTO_IP, libc::IP_TOS, &libc::c_int::from(tos)) }
+}
+
+/// Sets the TTL of all packets that are sent by the given socket, the hop limit for IPv6.
+#[cfg(target_os = "linux")]
+pub fn set_ttl(fd: RawFd, ttl: u32, is_ipv6: bool) -> io::Result<()> {
+    let ttl = to_c_int(ttl, "TTL")? as u32;
+
+    with_socket2(fd, |s| {
+        if is_ipv6 {
+            s.set_unicast_hops_v6(ttl)?;
+            // Dual stack sockets also send IPv4 packets, IPv6 only sockets reject the option.
+            let _ = s.set_ttl(ttl);
+            Ok(())
+        } else {
+            s.set_ttl(ttl)
+        }
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    use std::{net::UdpSocket, os::unix::io::AsRawFd};
+
+    #[test]
+    fn options_are_set() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let fd = socket.as_raw_fd();
+
+        set_send_buffer_size(fd, 64 * 1024).unwrap();
+        set_recv_buffer_size(fd, 64 * 1024).unwrap();
+        set_tos(fd, 0xb8).unwrap();
+        set_ttl(fd, 42, false).unwrap();
+
+        assert_eq!(42, socket.ttl().unwrap());
+    }
+
+    #[test]
+    fn too_big_values_are_rejected() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let err = set_send_buffer_size(socket.as_raw_fd(), usize::max_value()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
@@ -0,0 +1,191 @@
+use ecn::Ecn;
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// The transport statistics of a `Connection`, see `Connection::stats`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// The statistics of the `Initial` packet number space.
+    pub initial: PacketNumberSpaceStats,
+    /// The statistics of the `Handshake` packet number space.
+    pub handshake: PacketNumberSpaceStats,
+    /// The statistics of the application data packet number space(0-RTT and 1-RTT).
+    pub application: PacketNumberSpaceStats,
+    /// The statistics of each path, the primary path first.
+    pub paths: Vec<PathStats>,
+    /// The total number of packets that were sent, in all packet number spaces.
+    pub packets_sent: u64,
+    /// The total number of packets that were received.
+    pub packets_received: u64,
+    /// The number of packets that were declared lost and were not acknowledged later on.
+    pub packets_lost: u64,
+    /// The total number of packets that were declared lost and retransmitted.
+    pub retransmissions: u64,
+    /// The number of packets that were retransmitted, but the original packet was acknowledged
+    /// later on.
+    pub spurious_retransmissions: u64,
+    /// The crypto throughput of the `Connection`.
+    pub crypto: CryptoStats,
+    /// The ECN codepoints of the received packets, see `Config::enable_ecn`.
+    pub ecn_received: EcnCounts,
+}
+
+/// The statistics of one packet number space of a `Connection`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PacketNumberSpaceStats {
+    /// The number of packets that were sent in this packet number space.
+    pub packets_sent: u64,
+    /// The highest packet number that was acknowledged by the peer.
+    pub largest_acked: Option<u64>,
+    /// The highest packet number that was received from the peer.
+    pub largest_received: Option<u64>,
+}
+
+/// The statistics of one path of a `Connection`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathStats {
+    /// The address of the peer on this path.
+    pub peer_addr: SocketAddr,
+    /// The smoothed round trip time.
+    pub smoothed_rtt: Duration,
+    /// The variance of the round trip time.
+    pub rtt_variance: Duration,
+    /// The minimum round trip time that was observed.
+    pub min_rtt: Duration,
+    /// The congestion window in bytes.
+    pub congestion_window: u64,
+    /// The number of bytes that were sent, but are not yet acknowledged or declared lost.
+    pub bytes_in_flight: u64,
+    /// The maximum packet size.
+    pub mtu: usize,
+    /// The pacing rate of the congestion control in bytes per second.
+    pub pacing_rate: u64,
+    /// The latest one-way delay sample, `None` if the peer does not send time stamps, see
+    /// `Config::enable_time_stamps`. The clocks of both sides are not synchronized, so only the
+    /// variation of the samples is meaningful.
+    pub one_way_delay: Option<Duration>,
+}
+
+impl ConnectionStats {
+    /// Returns the statistics of the primary path, which carries the `Stream`s.
+    pub fn primary_path(&self) -> Option<&PathStats> {
+        self.paths.first()
+    }
+}
+
+/// The throughput of the packet protection of a `Connection`, see `Config::set_crypto_backend`.
+///
+/// The time is measured around sending and receiving each packet in picoquic, so it contains
+/// the packet processing of picoquic besides encrypting and decrypting the packet.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CryptoStats {
+    /// The number of bytes that were protected and sent.
+    pub protected_bytes: u64,
+    /// The time that was spent to protect packets.
+    pub protect_time: Duration,
+    /// The number of bytes that were received and unprotected.
+    pub unprotected_bytes: u64,
+    /// The time that was spent to unprotect packets.
+    pub unprotect_time: Duration,
+}
+
+impl CryptoStats {
+    /// Returns the bytes per second that are protected.
+    /// Returns `None`, if no packet was protected yet.
+    pub fn protect_throughput(&self) -> Option<f64> {
+        throughput(self.protected_bytes, self.protect_time)
+    }
+
+    /// Returns the bytes per second that are unprotected.
+    /// Returns `None`, if no packet was unprotected yet.
+    pub fn unprotect_throughput(&self) -> Option<f64> {
+        throughput(self.unprotected_bytes, self.unprotect_time)
+    }
+}
+
+/// The number of packets per ECN codepoint.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EcnCounts {
+    pub not_ect: u64,
+    pub ect0: u64,
+    pub ect1: u64,
+    /// The packets that were marked by a router, as it experienced congestion.
+    pub ce: u64,
+}
+
+fn throughput(bytes: u64, time: Duration) -> Option<f64> {
+    let secs = time.as_secs() as f64 + f64::from(time.subsec_nanos()) / 1_000_000_000.0;
+
+    if secs > 0.0 {
+        Some(bytes as f64 / secs)
+    } else {
+        None
+    }
+}
+
+/// Collects the `CryptoStats` of a `Connection` on the driver side.
+#[derive(Clone, Default)]
+pub struct CryptoMeter(Arc<Mutex<CryptoStats>>);
+
+impl CryptoMeter {
+    pub fn on_protected(&self, bytes: usize, time: Duration) {
+        let mut stats = self.0.lock().unwrap();
+        stats.protected_bytes += bytes as u64;
+        stats.protect_time += time;
+    }
+
+    pub fn on_unprotected(&self, bytes: usize, time: Duration) {
+        let mut stats = self.0.lock().unwrap();
+        stats.unprotected_bytes += bytes as u64;
+        stats.unprotect_time += time;
+    }
+
+    pub fn get(&self) -> CryptoStats {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Counts the ECN codepoints of the packets that a `Connection` received, on the driver side.
+#[derive(Clone, Default)]
+pub struct EcnMeter(Arc<Mutex<EcnCounts>>);
+
+impl EcnMeter {
+    pub fn on_received(&self, ecn: Ecn) {
+        let mut counts = self.0.lock().unwrap();
+
+        match ecn {
+            Ecn::NotEct => counts.not_ect += 1,
+            Ecn::Ect0 => counts.ect0 += 1,
+            Ecn::Ect1 => counts.ect1 += 1,
+            Ecn::Ce => counts.ce += 1,
+        }
+    }
+
+    pub fn get(&self) -> EcnCounts {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crypto_throughput() {
+        let meter = CryptoMeter::default();
+        assert_eq!(None, meter.get().protect_throughput());
+
+        meter.on_protected(1000, Duration::from_millis(1));
+        meter.on_protected(1000, Duration::from_millis(1));
+        meter.on_unprotected(500, Duration::from_millis(2));
+
+        let stats = meter.get();
+        assert_eq!(2000, stats.protected_bytes);
+        assert_eq!(Some(1_000_000.0), stats.protect_throughput());
+        assert_eq!(Some(250_000.0), stats.unprotect_throughput());
+    }
+}
@@ -0,0 +1,272 @@
+use config::Config;
+use connection::{CloseFuture, Connection, Events, IncomingStreams, NewStreamFuture};
+use context::{Context, ContextDriver};
+use context_inner::NewConnectionFuture;
+use datagram::Datagrams;
+use error::*;
+use runtime::{Socket, Timer};
+use stream::Stream;
+
+use bytes::Bytes;
+
+use futures::{
+    future,
+    task::{self as task01, Task},
+    Async, AsyncSink, Future, Poll, Sink, Stream as FStream,
+};
+
+use futures03::{
+    compat::Compat01As03,
+    task::{noop_waker_ref, waker, ArcWake},
+    Sink as Sink03, Stream as Stream03,
+};
+
+use tokio1::{self, io::ReadBuf, net};
+
+use std::{
+    future::Future as StdFuture,
+    io,
+    net::{SocketAddr, UdpSocket},
+    pin::Pin,
+    sync::Arc,
+    task,
+    time::Instant,
+};
+
+/// Polls the given futures 0.1 operation with the `std` task `Context`. The current task is
+/// notified by the waker of the `Context`.
+fn poll_01<T, F>(cx: &mut task::Context, f: F) -> task::Poll<Result<T, Error>>
+where
+    F: FnMut() -> Poll<T, Error>,
+{
+    StdFuture::poll(Pin::new(&mut Compat01As03::new(future::poll_fn(f))), cx)
+}
+
+/// Wakes a futures 0.1 `Task`.
+struct TaskWaker(Task);
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.notify();
+    }
+}
+
+/// Polls the given `std` operation in the current futures 0.1 task.
+fn poll_std<T, F>(f: F) -> Async<T>
+where
+    F: FnOnce(&mut task::Context) -> task::Poll<T>,
+{
+    let waker = waker(Arc::new(TaskWaker(task01::current())));
+
+    match f(&mut task::Context::from_waker(&waker)) {
+        task::Poll::Ready(value) => Async::Ready(value),
+        task::Poll::Pending => Async::NotReady,
+    }
+}
+
+fn into_poll_01<T>(res: Async<io::Result<T>>) -> Poll<T, io::Error> {
+    match res {
+        Async::Ready(res) => res.map(Async::Ready),
+        Async::NotReady => Ok(Async::NotReady),
+    }
+}
+
+macro_rules! impl_std_future {
+    ($($type:ty),*) => {
+        $(
+            impl StdFuture for $type {
+                type Output = Result<<$type as Future>::Item, <$type as Future>::Error>;
+
+                fn poll(
+                    self: Pin<&mut Self>,
+                    cx: &mut task::Context,
+                ) -> task::Poll<Self::Output> {
+                    let this = self.get_mut();
+                    StdFuture::poll(Pin::new(&mut Compat01As03::new(this)), cx)
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! impl_std_stream {
+    ($($type:ty),*) => {
+        $(
+            impl Stream03 for $type {
+                type Item = Result<<$type as FStream>::Item, Error>;
+
+                fn poll_next(
+                    self: Pin<&mut Self>,
+                    cx: &mut task::Context,
+                ) -> task::Poll<Option<Self::Item>> {
+                    let this = self.get_mut();
+                    poll_01(cx, || FStream::poll(&mut *this)).map(Result::transpose)
+                }
+            }
+        )*
+    };
+}
+
+impl_std_future!(NewConnectionFuture, NewStreamFuture, CloseFuture);
+impl_std_stream!(Context, Connection, IncomingStreams, Stream, Events, Datagrams);
+
+impl StdFuture for ContextDriver {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<()> {
+        let this = self.get_mut();
+        StdFuture::poll(Pin::new(&mut Compat01As03::new(this)), cx).map(|_| ())
+    }
+}
+
+impl Sink03<Bytes> for Stream {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        poll_01(cx, || this.poll_send_watermark())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Error> {
+        let this = self.get_mut();
+        let mut item = Some(item);
+
+        // `poll_ready` checked the `SendWatermark` before, so nobody needs to be woken up, if
+        // `start_send` registers the current task.
+        let mut cx = task::Context::from_waker(noop_waker_ref());
+        let res = poll_01(&mut cx, || {
+            let item = item.take().expect("item is only sent once");
+            Sink::start_send(&mut *this, item).map(|res| match res {
+                AsyncSink::Ready => Async::Ready(None),
+                AsyncSink::NotReady(item) => Async::Ready(Some(item)),
+            })
+        });
+
+        match res {
+            task::Poll::Ready(Ok(None)) => Ok(()),
+            task::Poll::Ready(Ok(Some(item))) => Err(ErrorKind::SendError(item).into()),
+            task::Poll::Ready(Err(e)) => Err(e),
+            task::Poll::Pending => unreachable!("`start_send` never returns `NotReady`"),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        poll_01(cx, || Sink::poll_complete(&mut *this))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut task::Context) -> task::Poll<Result<(), Error>> {
+        let this = self.get_mut();
+        poll_01(cx, || Sink::close(&mut *this))
+    }
+}
+
+impl Sink03<Bytes> for Datagrams {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut task::Context) -> task::Poll<Result<(), Error>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Error> {
+        Sink::start_send(self.get_mut(), item).map(|_| ())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut task::Context) -> task::Poll<Result<(), Error>> {
+        task::Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut task::Context) -> task::Poll<Result<(), Error>> {
+        task::Poll::Ready(Ok(()))
+    }
+}
+
+/// A tokio 1.x `UdpSocket` that can be used as `Socket` of a `Context`.
+pub struct TokioUdpSocket(net::UdpSocket);
+
+impl TokioUdpSocket {
+    /// Creates a new `TokioUdpSocket` from the given socket.
+    pub fn new(socket: net::UdpSocket) -> TokioUdpSocket {
+        TokioUdpSocket(socket)
+    }
+
+    /// Binds a new socket to the given address.
+    /// This function needs to be called in the context of a tokio 1.x runtime.
+    pub fn bind(addr: &SocketAddr) -> io::Result<TokioUdpSocket> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+
+        net::UdpSocket::from_std(socket).map(TokioUdpSocket)
+    }
+}
+
+impl Socket for TokioUdpSocket {
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        let socket = &self.0;
+
+        into_poll_01(poll_std(|cx| {
+            let mut buf = ReadBuf::new(buf);
+            socket
+                .poll_recv_from(cx, &mut buf)
+                .map(|res| res.map(|addr| (buf.filled().len(), addr)))
+        }))
+    }
+
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        let socket = &self.0;
+        into_poll_01(poll_std(|cx| socket.poll_send_to(cx, buf, *target)))
+    }
+
+    fn poll_write_ready(&mut self) -> Poll<(), io::Error> {
+        let socket = &self.0;
+        into_poll_01(poll_std(|cx| socket.poll_send_ready(cx)))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.0.local_addr()
+    }
+}
+
+/// A tokio 1.x timer that can be used as `Timer` of a `Context`.
+pub struct TokioTimer(Pin<Box<tokio1::time::Sleep>>);
+
+impl TokioTimer {
+    /// Creates a new `TokioTimer` that fires at once.
+    /// This function needs to be called in the context of a tokio 1.x runtime.
+    pub fn new() -> TokioTimer {
+        TokioTimer(Box::pin(tokio1::time::sleep_until(tokio1::time::Instant::now())))
+    }
+}
+
+impl Default for TokioTimer {
+    fn default() -> TokioTimer {
+        TokioTimer::new()
+    }
+}
+
+impl Timer for TokioTimer {
+    fn reset(&mut self, at: Instant) {
+        self.0.as_mut().reset(tokio1::time::Instant::from_std(at));
+    }
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        let sleep = &mut self.0;
+        Ok(poll_std(|cx| StdFuture::poll(sleep.as_mut(), cx)))
+    }
+}
+
+impl Context {
+    /// Creates a new `Context` on the current tokio 1.x runtime. The `Context` binds to the
+    /// given address and is driven by a task that is spawned with `tokio::spawn`.
+    ///
+    /// All types of this crate implement the `std` `Future`, `Stream` and `Sink` traits with the
+    /// `std-future` feature, so they can be used with async/await.
+    pub fn new_tokio1(listen_address: &SocketAddr, config: Config) -> Result<Context, Error> {
+        let socket = TokioUdpSocket::bind(listen_address).context(ErrorKind::NetworkError)?;
+        let (context, driver) = Context::with_io(socket, TokioTimer::new(), config)?;
+
+        tokio1::spawn(driver);
+
+        Ok(context)
+    }
+}
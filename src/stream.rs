@@ -1,20 +1,43 @@
+use config::Role;
+use driver_thread::DriverThread;
 use error::*;
 use ffi;
+use message_stream::MessageStream;
 use picoquic_sys::picoquic::{
-    self, picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_reset_stream,
+    self, picoquic_add_to_stream, picoquic_call_back_event_t, picoquic_mark_active_stream,
+    picoquic_provide_stream_data_buffer, picoquic_reset_stream, picoquic_set_stream_priority,
     picoquic_stop_sending,
 };
+use priority::Priority;
+use recv_pool::RecvPool;
+use trace::Span;
 use unbounded_with_error::{unbounded_with_error, Receiver, Sender};
 
 use bytes::{Bytes, BytesMut};
 
 use futures::{
     sync::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    task::AtomicTask,
     Async::{NotReady, Ready},
-    Future, Poll, Sink, StartSend, Stream as FStream,
+    AsyncSink, Future, Poll, Sink, StartSend, Stream as FStream,
 };
 
-use std::{net::SocketAddr, ptr};
+use std::{
+    cmp,
+    collections::VecDeque,
+    fmt,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    net::SocketAddr,
+    ops::Range,
+    os::raw::c_void,
+    ptr, slice,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
 
 pub type Id = u64;
 
@@ -24,26 +47,186 @@ pub type Id = u64;
 enum Message {
     /// Close the `Stream`.
     Close,
+    /// The peer finished sending on the `Stream`.
+    Fin,
     /// Recv data.
     RecvData(BytesMut),
     /// Send data.
     SendData(Bytes),
+    /// Send the given range of the file.
+    SendFile(File, Range<u64>),
+    /// Send the FIN bit after all data that was sent before.
+    Finish,
+    /// Drop all data that is not yet handed to picoquic and reset the `Stream`, if requested.
+    ClearSendQueue { reset: bool },
+    /// Set the maximum number of bytes of small writes that are coalesced.
+    SetWriteCoalescing(Option<usize>),
+    /// Set the priority of the `Stream`.
+    SetPriority(Priority),
+    /// Grant the peer to send up to the given offset.
+    SetMaxStreamData(u64),
+    /// Ask the peer to stop sending, with the given error code.
+    StopSending(u64),
     Error(Error),
     /// Reset the `Stream`.
     Reset,
+    /// The peer reset the `Stream` with the given error code.
+    ResetReceived(u64),
+}
+
+/// The send progress of a `Stream`, shared between the `Stream` and its `Context`.
+#[derive(Debug, Default)]
+struct SendProgress {
+    /// The number of bytes picoquic sent.
+    sent: AtomicUsize,
+    /// The number of bytes picoquic can send, before the flow control of the peer blocks it.
+    capacity: AtomicUsize,
+    /// Is the sending side of the `Stream` closed?
+    closed: AtomicBool,
+    /// The error code of the `STOP_SENDING`, if the peer stopped the sending side.
+    stopped_by_peer: Mutex<Option<u64>>,
+    /// The task that waits for picoquic to send data.
+    task: AtomicTask,
+}
+
+/// The state for handing data from the `Stream` directly to picoquic, while the `Stream` is used
+/// on the driver thread (see `DriverThread`). Shared between the `Stream` and its `Context`.
+struct DirectSend {
+    /// The driver of the `Context` this `Stream` belongs to.
+    driver: Option<Arc<DriverThread>>,
+    cnx: ffi::Connection,
+    /// Can data be handed directly to picoquic? This is only the case, while the `Context` has
+    /// no data queued and the `Stream` and its connection are open.
+    enabled: AtomicBool,
+    /// The number of messages that wait to be processed by the `Context`. To keep the order,
+    /// data is only handed directly to picoquic, if no message is waiting.
+    pending_msgs: AtomicUsize,
+    /// Was any data handed directly to picoquic?
+    data_sent: AtomicBool,
+    /// Data that is kept in the `Stream` is never handed directly to picoquic.
+    send_path: SendPath,
+}
+
+impl DirectSend {
+    fn new(cnx: ffi::Connection, send_path: SendPath) -> DirectSend {
+        DirectSend {
+            driver: DriverThread::current(),
+            cnx,
+            enabled: AtomicBool::new(false),
+            pending_msgs: AtomicUsize::new(0),
+            data_sent: AtomicBool::new(false),
+            send_path,
+        }
+    }
+
+    /// Hands the given data directly to picoquic, if this is called on the driver thread.
+    ///
+    /// # Returns
+    /// false, if the data needs to be sent via the `Context`.
+    fn send(&self, id: Id, data: &[u8]) -> bool {
+        let driver = match self.driver {
+            Some(ref driver) if !data.is_empty() && !self.send_path.is_kept(data.len()) => driver,
+            _ => return false,
+        };
+
+        driver
+            .run(|| {
+                if !self.enabled.load(Ordering::Relaxed)
+                    || self.pending_msgs.load(Ordering::Relaxed) != 0
+                {
+                    return false;
+                }
+
+                // If picoquic fails to add the data, the `Context` runs into the same error and
+                // handles it.
+                let res = unsafe {
+                    picoquic_add_to_stream(self.cnx.as_ptr(), id, data.as_ptr(), data.len(), 0)
+                };
+
+                if res == 0 {
+                    self.data_sent.store(true, Ordering::Relaxed);
+                }
+
+                res == 0
+            })
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Debug for DirectSend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DirectSend")
+            .field("enabled", &self.enabled)
+            .field("pending_msgs", &self.pending_msgs)
+            .field("data_sent", &self.data_sent)
+            .finish()
+    }
+}
+
+/// The limits for the data of a `Stream` that was not yet sent by picoquic. While a limit is
+/// reached, the `Sink` of the `Stream` is not ready.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct SendWatermark {
+    /// The maximum `send_backlog` in bytes.
+    pub bytes: Option<u64>,
+    /// The maximum number of messages that wait to be processed by the `Context`.
+    pub messages: Option<usize>,
+}
+
+/// Decides which data of a `Stream` is kept in the `Stream`, until picoquic requests it via
+/// `prepare_to_send`. All other data is copied into the send queue of picoquic at once.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub(crate) struct SendPath {
+    /// Keep all data, see `Config::enable_callback_driven_send`.
+    pub callback_driven: bool,
+    /// Keep the writes with at least this number of bytes, see
+    /// `Config::set_zero_copy_send_threshold`.
+    pub zero_copy_threshold: Option<usize>,
+    /// Hand each write separately to picoquic, see `Config::coalesce_stream_writes`.
+    pub preserve_write_boundaries: bool,
+}
+
+impl SendPath {
+    /// Returns if the given number of bytes are kept in the `Stream`, instead of being copied.
+    fn is_kept(&self, len: usize) -> bool {
+        !self.preserve_write_boundaries
+            && (self.callback_driven
+                || self.zero_copy_threshold.map(|min| len >= min).unwrap_or(false))
+    }
 }
 
 /// A `Stream` can either be unidirectional or bidirectional.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Type {
     Unidirectional,
     Bidirectional,
 }
 
+/// Information about a `Stream`.
+#[derive(Copy, Clone, Debug)]
+pub struct Info {
+    /// The id of the `Stream`.
+    pub id: Id,
+    /// The `Type` of the `Stream`.
+    pub stype: Type,
+    /// The side of the `Connection` that initiated the `Stream`.
+    pub initiator: Role,
+    /// Was the `Stream` initiated by the local side of the `Connection`?
+    pub locally_initiated: bool,
+    /// The time point at which the `Stream` was created.
+    pub created: Instant,
+    /// Did the peer finish sending on this `Stream`?
+    pub fin_received: bool,
+    /// Did the peer reset this `Stream`?
+    pub reset_received: bool,
+}
+
 /// A `Stream` is part of a `Connection`. A `Connection` can consists of multiple `Stream`s.
 /// Each `Stream` is a new channel over the `Connection` to the Peer. All traffic of a `Stream`
 /// is always unique for each `Stream`.
 /// The `Stream` needs to be polled, to get notified about a new `Message`.
+/// Data that is sent on the thread that drives the `Context`, is handed directly to picoquic,
+/// instead of being passed through a channel to the driver.
 #[derive(Debug)]
 pub struct Stream {
     recv_msg: UnboundedReceiver<Message>,
@@ -52,6 +235,20 @@ pub struct Stream {
     peer_addr: SocketAddr,
     local_addr: SocketAddr,
     stream_reset: bool,
+    fin_received: bool,
+    /// Did the local side reset this `Stream`?
+    reset_sent: bool,
+    /// Did the local side finish sending on this `Stream`?
+    fin_sent: bool,
+    /// Is the `Connection` this `Stream` belongs to, a client connection?
+    is_client_con: bool,
+    created: Instant,
+    /// The number of bytes that were handed to this `Stream` for sending.
+    queued: u64,
+    send_progress: Arc<SendProgress>,
+    direct_send: Arc<DirectSend>,
+    priority: Priority,
+    send_watermark: SendWatermark,
 }
 
 impl Stream {
@@ -60,11 +257,22 @@ impl Stream {
         cnx: ffi::Connection,
         local_addr: SocketAddr,
         is_client_con: bool,
+        send_path: SendPath,
+        write_coalescing: Option<usize>,
+        send_watermark: SendWatermark,
     ) -> (Stream, Context) {
         let (recv_msg, recv_send) = unbounded();
         let (send_msg, send_recv) = unbounded_with_error();
 
-        let ctx = Context::new(recv_msg, send_recv, id, cnx, is_client_con);
+        let ctx = Context::new(
+            recv_msg,
+            send_recv,
+            id,
+            cnx,
+            is_client_con,
+            send_path,
+            write_coalescing,
+        );
         let stream = Stream {
             recv_msg: recv_send,
             send_msg,
@@ -72,6 +280,16 @@ impl Stream {
             peer_addr: cnx.peer_addr(),
             local_addr,
             stream_reset: false,
+            fin_received: false,
+            reset_sent: false,
+            fin_sent: false,
+            is_client_con,
+            created: Instant::now(),
+            queued: 0,
+            send_progress: ctx.send_progress.clone(),
+            direct_send: ctx.direct_send.clone(),
+            priority: Priority::default(),
+            send_watermark,
         };
 
         (stream, ctx)
@@ -86,6 +304,30 @@ impl Stream {
         }
     }
 
+    /// Returns the id of this `Stream`.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns the `Info` about this `Stream`.
+    pub fn info(&self) -> Info {
+        let initiator = if is_client_initiated(self.id) {
+            Role::Client
+        } else {
+            Role::Server
+        };
+
+        Info {
+            id: self.id,
+            stype: self.get_type(),
+            initiator,
+            locally_initiated: (initiator == Role::Client) == self.is_client_con,
+            created: self.created,
+            fin_received: self.fin_received,
+            reset_received: self.stream_reset,
+        }
+    }
+
     /// Returns the address of the `Connection`'s peer.
     pub fn peer_addr(&self) -> SocketAddr {
         self.peer_addr
@@ -97,16 +339,232 @@ impl Stream {
     }
 
     /// Resets this stream.
+    /// All further sends on this `Stream` fail with `ErrorKind::StreamClosed`.
     pub fn reset(&mut self) -> Result<(), Error> {
-        self.send_msg
-            .unbounded_send(Message::Reset)
-            .map_err(|e| e.into_error(|_| ErrorKind::Unknown.into()))
+        self.reset_sent = true;
+        self.send_message(Message::Reset)
+    }
+
+    /// Finishes the sending side of this `Stream`. The FIN bit is sent after all data that was
+    /// sent before, so the peer sees the end of the `Stream`, while this side can still receive
+    /// data. All further sends on this `Stream` fail with `ErrorKind::StreamClosed`.
+    /// Without calling `finish`, the FIN bit is sent when the `Stream` is dropped.
+    ///
+    /// Fails with `ErrorKind::ReceiveOnlyStream`, if this is an incoming unidirectional `Stream`
+    /// and with `ErrorKind::StreamClosed`, if the sending side is already finished or reset.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        if !is_send_allowed(self.id, self.is_client_con) {
+            return Err(ErrorKind::ReceiveOnlyStream.into());
+        } else if self.reset_sent || self.fin_sent {
+            return Err(ErrorKind::StreamClosed.into());
+        }
+
+        self.send_message(Message::Finish)?;
+        self.fin_sent = true;
+        Ok(())
+    }
+
+    /// Returns if the sending side of this `Stream` was finished with `finish`.
+    pub fn is_finished(&self) -> bool {
+        self.fin_sent
     }
 
     /// Returns if this stream received a reset.
+    /// A reset by the peer is reported by `poll` as `ErrorKind::StreamReset`, while a `FIN` ends
+    /// the stream normally with `Ok(Ready(None))`.
     pub fn is_reset(&self) -> bool {
         self.stream_reset
     }
+
+    /// Sends the given `range` of the file.
+    /// The file content is not loaded into memory up-front, it is read directly into the send
+    /// buffer of picoquic, when picoquic is ready to send it.
+    /// A `range` that ends behind the end of the file is shortened to the length of the file.
+    /// If the file could not be read, the `Stream` is reset and the error is returned by
+    /// `poll`.
+    /// Fails with `ErrorKind::ReceiveOnlyStream`, if this is an incoming unidirectional `Stream`.
+    pub fn send_file(&mut self, file: File, range: Range<u64>) -> Result<(), Error> {
+        if !is_send_allowed(self.id, self.is_client_con) {
+            return Err(ErrorKind::ReceiveOnlyStream.into());
+        } else if self.reset_sent || self.fin_sent {
+            return Err(ErrorKind::StreamClosed.into());
+        }
+
+        // A range beyond the end of the file would never be sent and block the `send_backlog`.
+        let end = cmp::min(range.end, file.metadata()?.len());
+        let range = range.start..end;
+        let len = range.end.saturating_sub(range.start);
+
+        self.send_message(Message::SendFile(file, range))?;
+        self.queued += len;
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes that were handed to this `Stream`, but were not yet sent by
+    /// picoquic. Picoquic sends the data, when the flow control and the congestion control of
+    /// the `Connection` allow it.
+    pub fn send_backlog(&self) -> u64 {
+        self.queued.saturating_sub(self.send_progress.sent.load(Ordering::Relaxed) as u64)
+    }
+
+    /// Returns the number of bytes that picoquic sent on this `Stream`.
+    pub fn bytes_sent(&self) -> u64 {
+        self.send_progress.sent.load(Ordering::Relaxed) as u64
+    }
+
+    /// Returns the number of bytes picoquic can send on this `Stream`, before it is blocked by
+    /// the flow control of the peer. This is the minimum of the credit the peer granted for this
+    /// `Stream` and for the whole `Connection`. While the capacity is `0`, the peer needs to
+    /// grant more credit, before the `send_backlog` shrinks, see `Event::StreamDataBlocked`.
+    /// The capacity is updated each time the `Context` processes this `Stream`.
+    pub fn send_capacity(&self) -> u64 {
+        self.send_progress.capacity.load(Ordering::Relaxed) as u64
+    }
+
+    /// Grants the peer to send up to `max` bytes in total on this `Stream`, e.g. to raise the
+    /// receive window of a `Stream` that transfers a lot of data. Picoquic grants new credit
+    /// itself, when the application consumed the received data. The limit can not be decreased,
+    /// a `max` that is not bigger than the current limit is ignored.
+    pub fn set_max_stream_data(&mut self, max: u64) -> Result<(), Error> {
+        self.send_message(Message::SetMaxStreamData(max))
+    }
+
+    /// Checks if the `send_backlog` is at most `max` bytes.
+    /// If the backlog is bigger, the current task is notified, when picoquic sent more data.
+    /// Fails with `ErrorKind::StreamClosed`, if the sending side of this `Stream` is closed.
+    pub fn poll_send_backlog(&mut self, max: u64) -> Poll<(), Error> {
+        if self.send_backlog() <= max {
+            return Ok(Ready(()));
+        }
+
+        self.send_progress.task.register();
+
+        if self.send_backlog() <= max {
+            Ok(Ready(()))
+        } else if self.reset_sent || self.send_progress.closed.load(Ordering::Relaxed) {
+            Err(self.send_closed_error())
+        } else {
+            Ok(NotReady)
+        }
+    }
+
+    /// Is one of the limits of the `SendWatermark` reached?
+    fn is_send_watermark_reached(&self) -> bool {
+        let bytes = self.send_watermark.bytes.map_or(false, |max| self.send_backlog() >= max);
+        let messages = self.send_watermark.messages.map_or(false, |max| {
+            self.direct_send.pending_msgs.load(Ordering::Relaxed) >= max
+        });
+
+        bytes || messages
+    }
+
+    /// Checks if the `SendWatermark` allows to send more data.
+    /// If not, the current task is notified, when picoquic sent more data or the `Context`
+    /// processed more messages.
+    pub(crate) fn poll_send_watermark(&mut self) -> Poll<(), Error> {
+        if !self.is_send_watermark_reached() {
+            return Ok(Ready(()));
+        }
+
+        self.send_progress.task.register();
+
+        if !self.is_send_watermark_reached() {
+            Ok(Ready(()))
+        } else if self.reset_sent || self.send_progress.closed.load(Ordering::Relaxed) {
+            Err(self.send_closed_error())
+        } else {
+            Ok(NotReady)
+        }
+    }
+
+    /// Returns the error for sending on the closed sending side of this `Stream`.
+    fn send_closed_error(&self) -> Error {
+        match *self.send_progress.stopped_by_peer.lock().unwrap() {
+            Some(code) if !self.reset_sent => ErrorKind::StopSending(code).into(),
+            _ => ErrorKind::StreamClosed.into(),
+        }
+    }
+
+    /// Asks the peer with a `STOP_SENDING` frame to stop sending on this `Stream`, e.g. to abort
+    /// a download. The sending side of this `Stream` stays open. The peer is expected to reset
+    /// its sending side, which `poll` reports as `ErrorKind::StreamReset`. Does nothing on the
+    /// sending side of an unidirectional `Stream`.
+    pub fn stop_sending(&mut self, error_code: u64) -> Result<(), Error> {
+        self.send_message(Message::StopSending(error_code))
+    }
+
+    /// Drops all data of this `Stream` that was not yet handed to picoquic.
+    /// With the callback driven send path (see `Config::enable_callback_driven_send`), this is
+    /// all data that was not yet requested by picoquic. Otherwise, only the data of big writes
+    /// (see `Config::set_zero_copy_send_threshold`) and the data that is queued behind them or
+    /// behind a file (see `send_file`) can be dropped, as all other data is directly copied into
+    /// picoquic.
+    ///
+    /// reset - Also reset this `Stream`.
+    pub fn clear_send_queue(&mut self, reset: bool) -> Result<(), Error> {
+        self.send_message(Message::ClearSendQueue { reset })
+    }
+
+    /// Sets the maximum number of bytes of small writes that are coalesced, before they are
+    /// handed to picoquic. `None` disables the coalescing, for latency critical `Stream`s.
+    /// See `Config::enable_write_coalescing`.
+    pub fn set_write_coalescing(&mut self, max_bytes: Option<usize>) -> Result<(), Error> {
+        self.send_message(Message::SetWriteCoalescing(max_bytes))
+    }
+
+    /// Sets the priority of this `Stream`.
+    ///
+    /// Picoquic sends the data of `Stream`s with a more urgent priority first. `Stream`s with
+    /// the same urgency are served round-robin, if they are `incremental`, otherwise one after
+    /// the other. While `Stream`s with a more urgent priority have queued data, the queued data
+    /// of `Stream`s with a less urgent priority is not requested at all. Data is queued in the
+    /// `Stream`, with the callback driven send path (see `Config::enable_callback_driven_send`),
+    /// for big writes (see `Config::set_zero_copy_send_threshold`) and for files (see
+    /// `send_file`).
+    ///
+    /// The default priority is set by `Config::set_default_stream_priority`.
+    ///
+    /// A priority signal that was received from the peer (e.g. a `Priority` header field) can be
+    /// applied with `Priority::parse`.
+    pub fn set_priority(&mut self, priority: Priority) -> Result<(), Error> {
+        self.send_message(Message::SetPriority(priority))?;
+        self.priority = priority;
+        Ok(())
+    }
+
+    /// Sets the urgency of this `Stream` from `0`(most urgent) to `7`(least urgent) and keeps
+    /// `incremental`, see `set_priority`.
+    pub fn set_urgency(&mut self, urgency: u8) -> Result<(), Error> {
+        let priority = Priority::new(urgency, self.priority.incremental);
+        self.set_priority(priority)
+    }
+
+    /// Returns the priority of this `Stream`.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Sets the priority of a new `Stream`, the `Context` is updated by the caller.
+    pub(crate) fn set_initial_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// Turns this `Stream` into a `MessageStream`, that sends and receives whole messages,
+    /// instead of a sequence of bytes. The peer needs to frame its messages the same way.
+    pub fn framed(self) -> MessageStream {
+        MessageStream::new(self)
+    }
+
+    /// Sends the given message to the `Context`.
+    fn send_message(&mut self, msg: Message) -> Result<(), Error> {
+        self.direct_send.pending_msgs.fetch_add(1, Ordering::Relaxed);
+
+        self.send_msg.unbounded_send(msg).map_err(|e| {
+            self.direct_send.pending_msgs.fetch_sub(1, Ordering::Relaxed);
+            e.into_error(|_| ErrorKind::Unknown.into())
+        })
+    }
 }
 
 impl FStream for Stream {
@@ -120,12 +578,30 @@ impl FStream for Stream {
             .map_err(|_| Error::from(ErrorKind::Unknown)))
         {
             Some(Message::Close) | None => Ok(Ready(None)),
+            Some(Message::Fin) => {
+                self.fin_received = true;
+                Ok(Ready(None))
+            }
             Some(Message::RecvData(d)) => Ok(Ready(Some(d))),
             Some(Message::SendData(_)) => panic!("`SendData` message in `Stream` poll!"),
+            Some(Message::SendFile(..)) => panic!("`SendFile` message in `Stream` poll!"),
+            Some(Message::Finish) => panic!("`Finish` message in `Stream` poll!"),
+            Some(Message::ClearSendQueue { .. }) => {
+                panic!("`ClearSendQueue` message in `Stream` poll!")
+            }
+            Some(Message::SetWriteCoalescing(_)) => {
+                panic!("`SetWriteCoalescing` message in `Stream` poll!")
+            }
+            Some(Message::SetPriority(_)) => panic!("`SetPriority` message in `Stream` poll!"),
+            Some(Message::SetMaxStreamData(_)) => {
+                panic!("`SetMaxStreamData` message in `Stream` poll!")
+            }
+            Some(Message::StopSending(_)) => panic!("`StopSending` message in `Stream` poll!"),
             Some(Message::Error(err)) => Err(err),
-            Some(Message::Reset) => {
+            Some(Message::Reset) => panic!("`Reset` message in `Stream` poll!"),
+            Some(Message::ResetReceived(code)) => {
                 self.stream_reset = true;
-                Ok(Ready(None))
+                Err(ErrorKind::StreamReset(code).into())
             }
         }
     }
@@ -143,13 +619,44 @@ impl Sink for Stream {
             }
         }
 
-        self.send_msg
+        if !is_send_allowed(self.id, self.is_client_con) {
+            return Err(ErrorKind::ReceiveOnlyStream.into());
+        } else if self.reset_sent || self.fin_sent {
+            return Err(ErrorKind::StreamClosed.into());
+        }
+
+        if self.poll_send_watermark()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let len = item.len() as u64;
+
+        // On the driver thread, the data can be handed directly to picoquic.
+        if self.direct_send.send(self.id, &item) {
+            self.queued += len;
+            return Ok(AsyncSink::Ready);
+        }
+
+        self.direct_send.pending_msgs.fetch_add(1, Ordering::Relaxed);
+
+        let res = self
+            .send_msg
             .start_send(Message::SendData(item))
-            .map_err(|e| e.into_error(|item| ErrorKind::SendError(extract_data(item)).into()))
-            .map(|r| r.map(extract_data))
+            .map_err(|e| e.into_error(|item| ErrorKind::SendError(extract_data(item)).into()));
+
+        match res {
+            Ok(AsyncSink::Ready) => self.queued += len,
+            _ => {
+                self.direct_send.pending_msgs.fetch_sub(1, Ordering::Relaxed);
+            }
+        }
+
+        Ok(res?.map(extract_data))
     }
 
     fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_send_watermark());
+
         self.send_msg
             .poll_complete()
             .map_err(|_| ErrorKind::Unknown.into())
@@ -158,7 +665,26 @@ impl Sink for Stream {
 
 impl Drop for Stream {
     fn drop(&mut self) {
-        let _ = self.send_msg.start_send(Message::Close);
+        let _ = self.send_message(Message::Close);
+    }
+}
+
+/// Data that waits in the send queue of a `Stream`.
+enum SendData {
+    Data(Bytes),
+    File { file: File, remaining: u64 },
+}
+
+impl SendData {
+    fn len(&self) -> u64 {
+        match *self {
+            SendData::Data(ref data) => data.len() as u64,
+            SendData::File { remaining, .. } => remaining,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -172,7 +698,42 @@ pub(crate) struct Context {
     is_client_con: bool,
     /// Did this stream send any data?
     data_send: bool,
+    /// Was the FIN bit handed to picoquic or is it pending in `fin_pending`?
+    fin_sent: bool,
     stop_sending: bool,
+    /// Decides which data picoquic requests via `prepare_to_send`, instead of getting it
+    /// up-front.
+    send_path: SendPath,
+    /// The data that waits for being requested by picoquic.
+    send_queue: VecDeque<SendData>,
+    /// The number of bytes in `send_queue`.
+    send_queue_len: u64,
+    /// Send the FIN bit with the last data of `send_queue`.
+    fin_pending: bool,
+    send_progress: Arc<SendProgress>,
+    direct_send: Arc<DirectSend>,
+    /// Is the connection this Stream belongs to, closed?
+    connection_closed: bool,
+    /// The maximum number of bytes of small writes that are coalesced in `coalesced`.
+    write_coalescing: Option<usize>,
+    /// The small writes that wait for picoquic to send all previous data.
+    coalesced: BytesMut,
+    /// The writes that wait to be handed separately to picoquic, after picoquic sent all
+    /// previous data. See `SendPath::preserve_write_boundaries`.
+    separate_writes: VecDeque<Bytes>,
+    /// Send the FIN bit with the last data of `separate_writes`.
+    fin_after_writes: bool,
+    /// The number of bytes that were added to the send queue of picoquic.
+    added_to_stream: u64,
+    /// The flow control limit of the peer, that was reported as blocking.
+    data_blocked_at: Option<u64>,
+    priority: Priority,
+    /// Is this `Stream` allowed to send its queued data? See `set_scheduled`.
+    scheduled: bool,
+    /// Is this `Stream` marked as active in picoquic?
+    active: bool,
+    /// The span of this `Stream`, a child of the span of its connection.
+    span: Span,
 }
 
 impl Context {
@@ -182,12 +743,17 @@ impl Context {
         id: Id,
         cnx: ffi::Connection,
         is_client_con: bool,
+        send_path: SendPath,
+        write_coalescing: Option<usize>,
     ) -> Context {
         // We need to poll this once, so the current `Task` is registered to be woken up, when
         // new data should be send.
         let _ = send_msg.poll();
 
-        Context {
+        let span = Span::stream(id);
+        span_event!(span, debug, "stream({}) opened", id);
+
+        let ctx = Context {
             recv_msg,
             send_msg,
             id,
@@ -195,23 +761,56 @@ impl Context {
             cnx,
             is_client_con,
             data_send: false,
+            fin_sent: false,
             stop_sending: false,
-        }
+            send_path,
+            send_queue: VecDeque::new(),
+            send_queue_len: 0,
+            fin_pending: false,
+            send_progress: Arc::new(SendProgress::default()),
+            direct_send: Arc::new(DirectSend::new(cnx, send_path)),
+            connection_closed: false,
+            write_coalescing,
+            coalesced: BytesMut::new(),
+            separate_writes: VecDeque::new(),
+            fin_after_writes: false,
+            added_to_stream: 0,
+            data_blocked_at: None,
+            priority: Priority::default(),
+            scheduled: true,
+            active: false,
+            span,
+        };
+
+        ctx.update_direct_send();
+        ctx
     }
 
     fn reset(&mut self) {
-        self.send_msg.close();
+        span_event!(self.span, debug, "stream({}) reset", self.id);
+        self.close_send_side();
+        self.clear_send_queue();
         unsafe {
             picoquic_reset_stream(self.cnx.as_ptr(), self.id, 0);
         }
     }
 
-    pub fn recv_data(&mut self, data: &[u8], event: picoquic_call_back_event_t) {
+    pub fn recv_data(
+        &mut self,
+        data: &[u8],
+        event: picoquic_call_back_event_t,
+        pool: &mut RecvPool,
+    ) {
         if !data.is_empty() {
             if self.finished {
-                error!("stream({}) received data after being finished!", self.id);
+                span_event!(
+                    self.span,
+                    error,
+                    "stream({}) received data after being finished!",
+                    self.id
+                );
             } else {
-                let data = BytesMut::from(data);
+                let data = pool.copy(data);
 
                 let _ = self.recv_msg.unbounded_send(Message::RecvData(data));
             }
@@ -219,41 +818,438 @@ impl Context {
 
         if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stream_reset {
             self.finished = true;
-            let _ = self.recv_msg.unbounded_send(Message::Reset);
+            let code = self.cnx.remote_stream_error(self.id);
+            span_event!(self.span, debug, "stream({}) reset by peer: {}", self.id, code);
+            let _ = self.recv_msg.unbounded_send(Message::ResetReceived(code));
         } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stop_sending {
+            let code = self.cnx.remote_stop_sending_error(self.id);
+            span_event!(self.span, debug, "stream({}) stopped by peer: {}", self.id, code);
             self.stop_sending = true;
-            self.send_msg.close();
+
+            // A sending side that we closed before, keeps failing with `StreamClosed`.
+            if !self.send_progress.closed.load(Ordering::Relaxed) {
+                *self.send_progress.stopped_by_peer.lock().unwrap() = Some(code);
+                self.close_send_side_with(move || ErrorKind::StopSending(code).into());
+            }
+            self.clear_send_queue();
         } else if event == picoquic::picoquic_call_back_event_t_picoquic_callback_stream_fin {
-            let _ = self.recv_msg.unbounded_send(Message::Close);
+            span_event!(self.span, debug, "stream({}) finished by peer", self.id);
+            let _ = self.recv_msg.unbounded_send(Message::Fin);
             self.finished = true;
         }
+
+        self.update_direct_send();
     }
 
     /// Handle a connection error.
     pub fn handle_connection_error(&mut self, err: impl ErrorFn<Output = Error>) {
+        span_event!(self.span, debug, "stream({}) failed: {}", self.id, err());
+        self.connection_closed = true;
+        self.update_direct_send();
         let _ = self.recv_msg.unbounded_send(Message::Error(err()));
         self.send_msg.propagate_error(err)
     }
 
     /// Handle connection close.
     pub fn handle_connection_close(&mut self) {
+        self.connection_closed = true;
+        self.update_direct_send();
         let _ = self.recv_msg.unbounded_send(Message::Close);
     }
 
-    fn send_data(&mut self, data: Bytes) {
+    /// Enables the `Stream` to hand data directly to picoquic, if nothing is queued that would
+    /// need to be sent before.
+    fn update_direct_send(&self) {
+        let enabled = !self.send_path.callback_driven
+            && !self.send_path.preserve_write_boundaries
+            && self.write_coalescing.is_none()
+            && !self.connection_closed
+            && !self.stop_sending
+            && !self.fin_sent
+            && self.send_queue.is_empty()
+            && !self.send_progress.closed.load(Ordering::Relaxed);
+
+        self.direct_send.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn send_data(&mut self, data: Bytes) {
         if is_unidirectional(self.id) && !self.is_unidirectional_send_allowed() {
-            //TODO: maybe we should do more than just printing
-            error!("tried to send data to incoming unidirectional stream!");
+            // `Stream` already rejects the data, this should never happen.
+            span_event!(self.span, error, "tried to send data to incoming unidirectional stream!");
         } else if !self.stop_sending {
             self.data_send = self.data_send || !data.is_empty();
-            unsafe {
-                // TODO handle the result
-                picoquic_add_to_stream(self.cnx.as_ptr(), self.id, data.as_ptr(), data.len(), 0);
+
+            // If there is still queued data (e.g. a file), we need to queue the data as well,
+            // to keep the order. Big writes are kept, so picoquic copies them only once into the
+            // packets.
+            if self.send_path.preserve_write_boundaries && self.send_queue.is_empty() {
+                self.separate_writes.push_back(data);
+                self.flush_separate_writes_if_idle();
+            } else if self.send_path.is_kept(data.len()) || !self.send_queue.is_empty() {
+                self.flush_coalesced();
+                self.queue_data(SendData::Data(data));
+            } else {
+                match self.write_coalescing {
+                    Some(max) if data.len() < max => {
+                        self.coalesced.extend_from_slice(&data);
+
+                        if self.coalesced.len() >= max {
+                            self.flush_coalesced();
+                        }
+                    }
+                    _ => {
+                        self.flush_coalesced();
+                        self.add_to_stream(&data, false);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hands the coalesced writes to picoquic.
+    fn flush_coalesced(&mut self) {
+        if self.coalesced.is_empty() {
+            return;
+        }
+
+        let data = self.coalesced.take().freeze();
+
+        if self.send_path.callback_driven || !self.send_queue.is_empty() {
+            self.queue_data(SendData::Data(data));
+        } else {
+            self.add_to_stream(&data, false);
+        }
+    }
+
+    /// Hands the coalesced writes to picoquic, if picoquic sent all previous data of this
+    /// `Stream`.
+    fn flush_coalesced_if_idle(&mut self) {
+        if self.coalesced.is_empty() {
+            return;
+        }
+
+        let idle = self
+            .cnx
+            .stream_sent_offset(self.id)
+            .map(|sent| sent >= self.added_to_stream)
+            .unwrap_or(true);
+
+        if idle {
+            self.flush_coalesced();
+        }
+    }
+
+    /// Hands the next of the `separate_writes` to picoquic, if picoquic sent all previous data
+    /// of this `Stream`.
+    fn flush_separate_writes_if_idle(&mut self) {
+        while self.scheduled && !self.separate_writes.is_empty() {
+            let idle = self
+                .cnx
+                .stream_sent_offset(self.id)
+                .map(|sent| sent >= self.added_to_stream)
+                .unwrap_or(true);
+
+            if !idle {
+                return;
+            }
+
+            let data = self.separate_writes.pop_front().expect("checked that it is not empty");
+            let fin = self.fin_after_writes && self.separate_writes.is_empty();
+            self.add_to_stream(&data, fin);
+        }
+    }
+
+    /// Moves the `separate_writes` into the `send_queue`, so the data that is queued next is
+    /// sent after them.
+    fn queue_separate_writes(&mut self) {
+        while let Some(data) = self.separate_writes.pop_front() {
+            self.queue_data(SendData::Data(data));
+        }
+
+        if self.fin_after_writes {
+            self.fin_after_writes = false;
+            self.fin_pending = true;
+        }
+    }
+
+    fn send_file(&mut self, mut file: File, range: Range<u64>) {
+        if is_unidirectional(self.id) && !self.is_unidirectional_send_allowed() {
+            // `Stream` already rejects the file, this should never happen.
+            span_event!(
+                self.span,
+                error,
+                "tried to send a file to incoming unidirectional stream!"
+            );
+        } else if !self.stop_sending {
+            self.flush_coalesced();
+            self.queue_separate_writes();
+
+            if let Err(e) = file.seek(SeekFrom::Start(range.start)) {
+                let _ = self.recv_msg.unbounded_send(Message::Error(e.into()));
+                // The data that was queued after the file should not reach the peer without it.
+                self.reset();
+                return;
+            }
+
+            let remaining = range.end.saturating_sub(range.start);
+            self.data_send = self.data_send || remaining > 0;
+            self.queue_data(SendData::File { file, remaining });
+        }
+    }
+
+    /// Closes the channel for sending data, further sends on the `Stream` fail with
+    /// `ErrorKind::StreamClosed`.
+    fn close_send_side(&mut self) {
+        self.close_send_side_with(|| ErrorKind::StreamClosed.into());
+    }
+
+    /// Closes the channel for sending data, further sends on the `Stream` fail with the given
+    /// error.
+    fn close_send_side_with(&mut self, err: impl ErrorFn<Output = Error>) {
+        self.send_msg.propagate_error(err);
+        self.send_msg.close();
+        self.send_progress.closed.store(true, Ordering::Relaxed);
+        self.send_progress.task.notify();
+    }
+
+    /// Updates the number of bytes picoquic sent and notifies the `Stream`, if it changed.
+    fn update_send_progress(&self) {
+        let sent = match self.cnx.stream_sent_offset(self.id) {
+            Some(sent) => sent,
+            None => return,
+        };
+
+        if let Some(limit) = self.cnx.stream_send_limit(self.id) {
+            let (data_sent, max_data) = self.cnx.data_sent_and_limit();
+            let capacity = cmp::min(
+                limit.saturating_sub(sent),
+                max_data.saturating_sub(data_sent),
+            );
+            self.send_progress.capacity.store(capacity as usize, Ordering::Relaxed);
+        }
+
+        if self.send_progress.sent.swap(sent as usize, Ordering::Relaxed) != sent as usize {
+            self.send_progress.task.notify();
+        }
+    }
+
+    /// Returns the flow control limit of this `Stream`, if picoquic has data to send that is
+    /// blocked by it. Each limit is only returned once.
+    pub fn check_data_blocked(&mut self) -> Option<u64> {
+        let (sent, limit) = match (
+            self.cnx.stream_sent_offset(self.id),
+            self.cnx.stream_send_limit(self.id),
+        ) {
+            (Some(sent), Some(limit)) => (sent, limit),
+            _ => return None,
+        };
+
+        let has_data = self.cnx.stream_has_queued_data(self.id)
+            || !self.coalesced.is_empty()
+            || !self.separate_writes.is_empty()
+            || !self.send_queue.is_empty();
+
+        if !has_data || sent < limit {
+            self.data_blocked_at = None;
+            None
+        } else if self.data_blocked_at != Some(limit) {
+            self.data_blocked_at = Some(limit);
+            Some(limit)
+        } else {
+            None
+        }
+    }
+
+    /// Hands the given data directly to picoquic.
+    /// If picoquic fails to add the data, the error is propagated to the `Stream` and the
+    /// `Stream` is reset, as the peer would not receive all the data.
+    fn add_to_stream(&mut self, data: &[u8], fin: bool) {
+        let data_ptr = if data.is_empty() {
+            ptr::null()
+        } else {
+            data.as_ptr()
+        };
+
+        let res = unsafe {
+            picoquic_add_to_stream(self.cnx.as_ptr(), self.id, data_ptr, data.len(), fin as i32)
+        };
+
+        if res == 0 {
+            self.added_to_stream += data.len() as u64;
+        } else {
+            span_event!(
+                self.span,
+                error,
+                "stream({}) could not add data to picoquic: {}",
+                self.id,
+                res
+            );
+            let _ = self
+                .recv_msg
+                .unbounded_send(Message::Error(ErrorKind::AddToStreamError(res).into()));
+            self.send_msg.propagate_error(move || ErrorKind::AddToStreamError(res).into());
+            self.reset();
+        }
+    }
+
+    /// Queues the given data, until picoquic requests it via `prepare_to_send`.
+    fn queue_data(&mut self, data: SendData) {
+        if data.is_empty() {
+            return;
+        }
+
+        self.send_queue_len += data.len();
+        self.send_queue.push_back(data);
+        self.update_active();
+    }
+
+    fn clear_send_queue(&mut self) {
+        self.send_queue.clear();
+        self.send_queue_len = 0;
+        self.fin_pending = false;
+        self.coalesced.clear();
+        self.separate_writes.clear();
+        self.fin_after_writes = false;
+        self.update_active();
+    }
+
+    /// Returns the priority of this `Stream`.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Sets the priority of this `Stream` in picoquic.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+
+        let res = unsafe {
+            picoquic_set_stream_priority(self.cnx.as_ptr(), self.id, priority.to_picoquic())
+        };
+
+        if res != 0 {
+            span_event!(self.span, error, "stream({}) could not set priority: {}", self.id, res);
+        }
+    }
+
+    /// Returns if this `Stream` has data queued, that waits to be requested by picoquic.
+    pub fn has_queued_data(&self) -> bool {
+        !self.send_queue.is_empty()
+    }
+
+    /// Allows or forbids picoquic to request the queued data of this `Stream`. The `Stream`s of
+    /// a connection with a less urgent priority are not scheduled, while more urgent `Stream`s
+    /// have queued data.
+    pub fn set_scheduled(&mut self, scheduled: bool) {
+        self.scheduled = scheduled;
+        self.update_active();
+    }
+
+    /// Marks this `Stream` as active in picoquic, if it has queued data and is scheduled.
+    fn update_active(&mut self) {
+        let active = self.scheduled && !self.send_queue.is_empty();
+
+        if active != self.active {
+            self.set_active(active);
+        }
+    }
+
+    /// Marks this `Stream` as (in)active, picoquic only calls `prepare_to_send` for active
+    /// `Stream`s.
+    fn set_active(&mut self, active: bool) {
+        let res = unsafe { picoquic_mark_active_stream(self.cnx.as_ptr(), self.id, active as i32) };
+
+        if res == 0 {
+            self.active = active;
+        } else {
+            span_event!(
+                self.span,
+                error,
+                "stream({}) could not be marked as active: {}",
+                self.id,
+                res
+            );
+        }
+    }
+
+    /// Picoquic is ready to send up to `max_len` bytes of this `Stream`.
+    /// The data is copied from the `send_queue` into the buffer provided by picoquic. Files are
+    /// read directly into this buffer.
+    pub fn prepare_to_send(&mut self, context: *mut c_void, max_len: usize) {
+        let len = cmp::min(max_len as u64, self.send_queue_len) as usize;
+        let is_last = len as u64 == self.send_queue_len;
+        let is_fin = is_last && self.fin_pending;
+
+        let buffer = unsafe {
+            picoquic_provide_stream_data_buffer(context, len, is_fin as i32, !is_last as i32)
+        };
+
+        if buffer.is_null() {
+            span_event!(
+                self.span,
+                error,
+                "stream({}) did not get a buffer from picoquic!",
+                self.id
+            );
+            return;
+        }
+
+        let buffer = unsafe { slice::from_raw_parts_mut(buffer, len) };
+        let mut written = 0;
+
+        while written < len {
+            let res = match self.send_queue.front_mut() {
+                Some(&mut SendData::Data(ref mut data)) => {
+                    let chunk = data.split_to(cmp::min(data.len(), len - written));
+                    buffer[written..written + chunk.len()].copy_from_slice(&chunk);
+                    Ok(chunk.len())
+                }
+                Some(&mut SendData::File {
+                    ref mut file,
+                    ref mut remaining,
+                }) => {
+                    let read = cmp::min(*remaining, (len - written) as u64) as usize;
+                    *remaining -= read as u64;
+                    file.read_exact(&mut buffer[written..written + read])
+                        .map(|_| read)
+                }
+                None => unreachable!("`send_queue_len` matches the data in `send_queue`"),
+            };
+
+            match res {
+                Ok(n) => written += n,
+                Err(e) => {
+                    span_event!(
+                        self.span,
+                        error,
+                        "stream({}) could not read the file to send: {:?}",
+                        self.id,
+                        e
+                    );
+                    let _ = self.recv_msg.unbounded_send(Message::Error(e.into()));
+                    // The peer should not process the invalid data that we gave to picoquic.
+                    self.reset();
+                    return;
+                }
             }
+
+            if self.send_queue.front().map(SendData::is_empty).unwrap_or(false) {
+                self.send_queue.pop_front();
+            }
+        }
+
+        self.send_queue_len -= len as u64;
+        // Picoquic marks the `Stream` as inactive, after it got the last data.
+        self.active = !is_last;
+
+        if is_fin {
+            self.fin_pending = false;
         }
+
+        self.update_direct_send();
     }
 
     fn close(&mut self) {
+        span_event!(self.span, debug, "stream({}) closed", self.id);
         self.finished = true;
         self.stop_sending = true;
         self.send_msg.close();
@@ -264,57 +1260,139 @@ impl Context {
             }
         }
 
-        if self.data_send {
-            unsafe {
-                picoquic_add_to_stream(self.cnx.as_ptr(), self.id, ptr::null(), 0, 1);
-            }
+        if self.fin_sent {
+            // The sending side is already finished.
+        } else if self.data_send || self.direct_send.data_sent.load(Ordering::Relaxed) {
+            self.send_fin();
         } else {
             self.reset();
         }
     }
 
-    /// Returns if this Stream is the sending side of an unidirectional Stream.
-    fn is_unidirectional_send_allowed(&self) -> bool {
-        if self.is_client_initiated() {
-            self.is_client_con
-        } else {
-            !self.is_client_con
+    /// Asks the peer to stop sending, requested by `Stream::stop_sending`.
+    fn request_stop_sending(&mut self, code: u64) {
+        if self.finished || is_unidirectional(self.id) && self.is_unidirectional_send_allowed() {
+            return;
+        }
+
+        span_event!(self.span, debug, "stream({}) stop sending: {}", self.id, code);
+        unsafe {
+            picoquic_stop_sending(self.cnx.as_ptr(), self.id, code as _);
         }
     }
 
-    /// Is the Stream initiated by the client?
-    fn is_client_initiated(&self) -> bool {
-        self.id & 1 == 0
+    /// Finishes the sending side, requested by `Stream::finish`.
+    fn finish(&mut self) {
+        if self.fin_sent || self.stop_sending {
+            return;
+        }
+
+        self.send_fin();
+        self.update_direct_send();
     }
-}
 
-fn is_unidirectional(id: Id) -> bool {
-    id & 2 != 0
-}
+    /// Sends the FIN bit after all queued data.
+    fn send_fin(&mut self) {
+        self.fin_sent = true;
+        self.flush_coalesced();
 
-impl Future for Context {
-    type Item = ();
-    type Error = Error;
+        if !self.separate_writes.is_empty() {
+            // The FIN bit will be send with the last separate write.
+            self.fin_after_writes = true;
+        } else if self.send_queue.is_empty() {
+            self.add_to_stream(&[], true);
+        } else {
+            // The FIN bit will be send in `prepare_to_send` with the last queued data.
+            self.fin_pending = true;
+        }
+    }
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+    /// Returns if this Stream is the sending side of an unidirectional Stream.
+    fn is_unidirectional_send_allowed(&self) -> bool {
+        is_unidirectional_send_allowed(self.id, self.is_client_con)
+    }
+
+    /// Processes the messages of the `Stream`.
+    fn poll_messages(&mut self) -> Poll<(), Error> {
         loop {
-            match try_ready!(self.send_msg.poll()) {
+            let msg = try_ready!(self.send_msg.poll());
+
+            if msg.is_some() {
+                self.direct_send.pending_msgs.fetch_sub(1, Ordering::Relaxed);
+            }
+
+            match msg {
                 Some(Message::Reset) => {
                     self.reset();
                 }
                 Some(Message::Close) => {
                     self.close();
-                    return Ok(Ready(()));
+
+                    // Wait until picoquic requested all queued data.
+                    if self.send_queue.is_empty() && self.separate_writes.is_empty() {
+                        return Ok(Ready(()));
+                    }
                 }
                 Some(Message::SendData(data)) => {
                     self.send_data(data);
                 }
+                Some(Message::SendFile(file, range)) => {
+                    self.send_file(file, range);
+                }
+                Some(Message::Finish) => {
+                    self.finish();
+                }
+                Some(Message::ClearSendQueue { reset }) => {
+                    let fin_pending = self.fin_pending;
+                    self.clear_send_queue();
+
+                    if reset {
+                        self.reset();
+                    } else if fin_pending {
+                        // The `Stream` was finished, the FIN bit is still required.
+                        self.add_to_stream(&[], true);
+                    }
+                }
+                Some(Message::SetPriority(priority)) => {
+                    self.set_priority(priority);
+                }
+                Some(Message::SetMaxStreamData(max)) => {
+                    if let Err(e) = self.cnx.set_stream_max_data(self.id, max) {
+                        span_event!(
+                            self.span,
+                            error,
+                            "stream({}) could not grant credit: {:?}",
+                            self.id,
+                            e
+                        );
+                    }
+                }
+                Some(Message::StopSending(code)) => {
+                    self.request_stop_sending(code);
+                }
+                Some(Message::SetWriteCoalescing(max)) => {
+                    self.write_coalescing = max;
+
+                    if max.is_none() {
+                        self.flush_coalesced();
+                    }
+                }
                 Some(Message::RecvData(_)) => {
                     panic!("`RecvData` message in `Context` future!");
                 }
+                Some(Message::Fin) => {
+                    panic!("`Fin` message in `Context` future!");
+                }
+                Some(Message::ResetReceived(_)) => {
+                    panic!("`ResetReceived` message in `Context` future!");
+                }
                 Some(Message::Error(_)) => {}
                 None => {
-                    if self.finished && self.stop_sending {
+                    if self.finished
+                        && self.stop_sending
+                        && self.send_queue.is_empty()
+                        && self.separate_writes.is_empty()
+                    {
                         return Ok(Ready(()));
                     } else {
                         return Ok(NotReady);
@@ -324,3 +1402,51 @@ impl Future for Context {
         }
     }
 }
+
+fn is_unidirectional(id: Id) -> bool {
+    id & 2 != 0
+}
+
+/// Returns if the Stream with the given id is the sending side of an unidirectional Stream.
+fn is_unidirectional_send_allowed(id: Id, is_client_con: bool) -> bool {
+    is_client_initiated(id) == is_client_con
+}
+
+/// Returns if a Stream with the given id can send data.
+fn is_send_allowed(id: Id, is_client_con: bool) -> bool {
+    !is_unidirectional(id) || is_unidirectional_send_allowed(id, is_client_con)
+}
+
+/// Is the Stream initiated by the client?
+pub(crate) fn is_client_initiated(id: Id) -> bool {
+    id & 1 == 0
+}
+
+impl Future for Context {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.update_send_progress();
+
+        let pending_msgs = self.direct_send.pending_msgs.load(Ordering::Relaxed);
+        let res = self.poll_messages();
+
+        // The `Stream` could wait for the `SendWatermark`.
+        if self.direct_send.pending_msgs.load(Ordering::Relaxed) != pending_msgs {
+            self.send_progress.task.notify();
+        }
+
+        self.flush_coalesced_if_idle();
+        self.flush_separate_writes_if_idle();
+        self.update_direct_send();
+        res
+    }
+}
+
+impl Drop for Context {
+    fn drop(&mut self) {
+        // The `Stream` must not call into picoquic anymore, the connection could be deleted.
+        self.direct_send.enabled.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,95 @@
+use std::mem;
+
+/// Withholds the stream credit of a connection, while the application lags behind with
+/// accepting incoming streams.
+///
+/// Picoquic grants the peer new stream credit, when streams are closed. While more than
+/// `max_pending` incoming streams wait to be accepted by the application, the credit is frozen.
+/// The credit that picoquic granted in the meantime is given back to the peer, when the
+/// application caught up.
+pub struct StreamCreditGate {
+    max_pending: usize,
+    /// The credit at the time point the gate was closed.
+    held: Option<u64>,
+    /// The credit that was withheld, while the gate was closed.
+    withheld: u64,
+}
+
+impl StreamCreditGate {
+    pub fn new(max_pending: usize) -> StreamCreditGate {
+        StreamCreditGate {
+            max_pending,
+            held: None,
+            withheld: 0,
+        }
+    }
+
+    /// Checks if the credit needs to be withheld or given back.
+    ///
+    /// pending - The number of incoming streams that wait to be accepted by the application.
+    /// credit - The current credit picoquic computed for the peer.
+    ///
+    /// # Returns
+    /// Some(_) is the credit that needs to be set. None intends that the credit should not be
+    /// changed.
+    pub fn poll(&mut self, pending: usize, credit: u64) -> Option<u64> {
+        if pending >= self.max_pending {
+            match self.held {
+                Some(held) if credit > held => {
+                    self.withheld += credit - held;
+                    Some(held)
+                }
+                Some(_) => None,
+                None => {
+                    debug!("application lags behind with accepting streams, withholding credit");
+                    self.held = Some(credit);
+                    None
+                }
+            }
+        } else if self.held.take().is_some() {
+            let withheld = mem::replace(&mut self.withheld, 0);
+
+            if withheld > 0 {
+                Some(credit + withheld)
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credit_is_not_changed_while_application_keeps_up() {
+        let mut gate = StreamCreditGate::new(2);
+
+        assert_eq!(None, gate.poll(0, 4));
+        assert_eq!(None, gate.poll(1, 8));
+    }
+
+    #[test]
+    fn credit_is_withheld_and_given_back() {
+        let mut gate = StreamCreditGate::new(2);
+
+        assert_eq!(None, gate.poll(2, 4));
+        assert_eq!(Some(4), gate.poll(2, 8));
+        assert_eq!(None, gate.poll(3, 4));
+        assert_eq!(Some(4), gate.poll(3, 12));
+        assert_eq!(Some(16), gate.poll(1, 4));
+        assert_eq!(None, gate.poll(0, 16));
+    }
+
+    #[test]
+    fn credit_is_kept_if_nothing_was_withheld() {
+        let mut gate = StreamCreditGate::new(1);
+
+        assert_eq!(None, gate.poll(1, 4));
+        assert_eq!(None, gate.poll(1, 4));
+        assert_eq!(None, gate.poll(0, 8));
+    }
+}
@@ -0,0 +1,128 @@
+//! The spans of the context, connection and stream lifecycles.
+//!
+//! With the `tracing` feature, each `Context`, `Connection` and `Stream` has a span and the
+//! events of `span_event!` are recorded in the span. Without the feature, the spans are empty and
+//! the events are logged with the `log` crate.
+
+/// Records an event in the given `Span`, logs the event without the `tracing` feature.
+///
+/// `span_event!(span, debug, "stream({}) opened", id)`
+macro_rules! span_event {
+    ($span:expr, error, $($arg:tt)+) => { span_event_emit!($span, ERROR, Error, $($arg)+) };
+    ($span:expr, warn, $($arg:tt)+) => { span_event_emit!($span, WARN, Warn, $($arg)+) };
+    ($span:expr, info, $($arg:tt)+) => { span_event_emit!($span, INFO, Info, $($arg)+) };
+    ($span:expr, debug, $($arg:tt)+) => { span_event_emit!($span, DEBUG, Debug, $($arg)+) };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! span_event_emit {
+    ($span:expr, $tracing_level:ident, $log_level:ident, $($arg:tt)+) => {
+        ::tracing01::event!(
+            parent: $span.inner(),
+            ::tracing01::Level::$tracing_level,
+            $($arg)+
+        )
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span_event_emit {
+    ($span:expr, $tracing_level:ident, $log_level:ident, $($arg:tt)+) => {{
+        let _ = &$span;
+        log!(::log::Level::$log_level, $($arg)+)
+    }};
+}
+
+#[cfg(feature = "tracing")]
+mod imp {
+    use tracing01::{span::Entered as TracingEntered, Span as TracingSpan};
+
+    use std::net::SocketAddr;
+
+    /// The span of a `Context`, `Connection` or `Stream`.
+    #[derive(Clone)]
+    pub struct Span {
+        span: TracingSpan,
+    }
+
+    /// Leaves the `Span` on drop.
+    pub struct Entered<'a> {
+        _entered: TracingEntered<'a>,
+    }
+
+    impl Span {
+        /// The span of a `Context` with the given local addresses.
+        pub fn context(local_addrs: &[SocketAddr]) -> Span {
+            Span {
+                span: ::tracing01::info_span!("context", local_addrs = ?local_addrs),
+            }
+        }
+
+        /// The span of a connection, a child of the current span.
+        pub fn connection(id: u64, peer_addr: SocketAddr, is_client: bool) -> Span {
+            let cid = format!("{:016x}", id);
+
+            Span {
+                span: ::tracing01::info_span!(
+                    "connection",
+                    cid = %cid,
+                    peer_addr = %peer_addr,
+                    client = is_client
+                ),
+            }
+        }
+
+        /// The span of a stream, a child of the current span.
+        pub fn stream(id: u64) -> Span {
+            Span {
+                span: ::tracing01::debug_span!("stream", id = id),
+            }
+        }
+
+        /// Enters the span, events of the current span are recorded in it until the returned
+        /// guard is dropped.
+        pub fn enter(&self) -> Entered {
+            Entered {
+                _entered: self.span.enter(),
+            }
+        }
+
+        pub fn inner(&self) -> &TracingSpan {
+            &self.span
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+mod imp {
+    use std::{marker::PhantomData, net::SocketAddr};
+
+    /// The span of a `Context`, `Connection` or `Stream`, empty without the `tracing` feature.
+    #[derive(Clone)]
+    pub struct Span;
+
+    /// Leaves the `Span` on drop.
+    pub struct Entered<'a> {
+        _span: PhantomData<&'a Span>,
+    }
+
+    impl Span {
+        pub fn context(_local_addrs: &[SocketAddr]) -> Span {
+            Span
+        }
+
+        pub fn connection(_id: u64, _peer_addr: SocketAddr, _is_client: bool) -> Span {
+            Span
+        }
+
+        pub fn stream(_id: u64) -> Span {
+            Span
+        }
+
+        pub fn enter(&self) -> Entered {
+            Entered { _span: PhantomData }
+        }
+    }
+}
+
+pub use self::imp::Span;
@@ -0,0 +1,149 @@
+use error::*;
+use stream::Stream;
+
+use bytes::Bytes;
+
+use futures::{
+    Async::{NotReady, Ready},
+    AsyncSink, Future, Poll, Sink, Stream as FStream,
+};
+
+/// The default maximum number of bytes that are handed to the `Stream`, but not yet sent by
+/// picoquic.
+const DEFAULT_MAX_BACKLOG: u64 = 256 * 1024;
+
+/// The progress of a `Transfer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// The number of bytes that were handed to the `Stream`.
+    pub queued: u64,
+    /// The number of bytes picoquic sent.
+    pub sent: u64,
+}
+
+/// Pumps all chunks of the given `source` into the given `Stream`.
+///
+/// The chunks are handed to the `Stream`, while at most `max_backlog` bytes are waiting to be
+/// sent by picoquic. So, the transfer proceeds at the speed that the flow control and the
+/// congestion control of the `Connection` allow.
+///
+/// The returned `Transfer` resolves to the `Stream` and the number of transferred bytes, after
+/// picoquic sent all data.
+pub fn transfer<S>(stream: Stream, source: S) -> Transfer<S>
+where
+    S: FStream<Item = Bytes, Error = Error>,
+{
+    Transfer {
+        stream: Some(stream),
+        source: Some(source),
+        pending: None,
+        max_backlog: DEFAULT_MAX_BACKLOG,
+        queued: 0,
+        last_progress: None,
+        on_progress: None,
+    }
+}
+
+/// The future created by `transfer`.
+pub struct Transfer<S> {
+    stream: Option<Stream>,
+    /// The source of the chunks, `None` after all chunks were taken.
+    source: Option<S>,
+    /// The chunk that waits to be handed to the `Stream`.
+    pending: Option<Bytes>,
+    max_backlog: u64,
+    queued: u64,
+    last_progress: Option<Progress>,
+    on_progress: Option<Box<dyn FnMut(Progress) + Send>>,
+}
+
+impl<S> Transfer<S>
+where
+    S: FStream<Item = Bytes, Error = Error>,
+{
+    /// Sets the maximum number of bytes that are handed to the `Stream`, but not yet sent by
+    /// picoquic.
+    /// Default: 256 KiB
+    pub fn max_backlog(mut self, max: u64) -> Transfer<S> {
+        self.max_backlog = max;
+        self
+    }
+
+    /// Sets the function that is called, when the transfer made progress.
+    pub fn on_progress<F>(mut self, on_progress: F) -> Transfer<S>
+    where
+        F: FnMut(Progress) + Send + 'static,
+    {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    fn stream(&mut self) -> &mut Stream {
+        self.stream.as_mut().expect("`Transfer` polled after completion")
+    }
+
+    fn report_progress(&mut self) {
+        let progress = Progress {
+            queued: self.queued,
+            sent: self.stream().bytes_sent(),
+        };
+
+        if self.last_progress != Some(progress) {
+            self.last_progress = Some(progress);
+
+            if let Some(ref mut on_progress) = self.on_progress {
+                on_progress(progress);
+            }
+        }
+    }
+}
+
+impl<S> Future for Transfer<S>
+where
+    S: FStream<Item = Bytes, Error = Error>,
+{
+    type Item = (Stream, u64);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.report_progress();
+
+            if let Some(chunk) = self.pending.take() {
+                let max_backlog = self.max_backlog;
+                let len = chunk.len() as u64;
+
+                if self.stream().poll_send_backlog(max_backlog)?.is_not_ready() {
+                    self.pending = Some(chunk);
+                    return Ok(NotReady);
+                }
+
+                if let AsyncSink::NotReady(chunk) = self.stream().start_send(chunk)? {
+                    self.pending = Some(chunk);
+                    return Ok(NotReady);
+                }
+
+                self.queued += len;
+            }
+
+            let next = match self.source {
+                Some(ref mut source) => try_ready!(source.poll()),
+                None => None,
+            };
+
+            match next {
+                Some(chunk) => self.pending = Some(chunk),
+                None => {
+                    self.source = None;
+
+                    try_ready!(self.stream().poll_complete());
+                    try_ready!(self.stream().poll_send_backlog(0));
+                    self.report_progress();
+
+                    let stream = self.stream.take().expect("`Transfer` polled after completion");
+                    return Ok(Ready((stream, self.queued)));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use runtime::Socket;
+
+use futures::{
+    task::{self, Task},
+    Async, Future, Poll,
+};
+
+use std::{
+    collections::VecDeque,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::timer::Delay;
+
+/// The conditions of the link between the two sockets of an `InMemoryTransport` pair.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkConditions {
+    /// The time a packet needs to arrive at the peer.
+    pub latency: Duration,
+    /// The probability between `0.0` and `1.0` that a sent packet is dropped.
+    pub loss: f64,
+    /// The seed for dropping packets. The same seed drops the same packets, when the same packets
+    /// are sent.
+    pub seed: u64,
+}
+
+impl Default for LinkConditions {
+    fn default() -> LinkConditions {
+        LinkConditions {
+            latency: Duration::from_millis(0),
+            loss: 0.0,
+            seed: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+struct Packet {
+    at: Instant,
+    data: Vec<u8>,
+    from: SocketAddr,
+}
+
+#[derive(Default)]
+struct Queue {
+    packets: VecDeque<Packet>,
+    /// The task that waits for a packet.
+    task: Option<Task>,
+}
+
+/// A `Socket` that is connected in memory to exactly one other socket, without using the network.
+///
+/// A pair is created by `InMemoryTransport::pair` and can be given to `Context::with_io`. Packets
+/// to any other address than the peer are dropped. The `LinkConditions` simulate latency and loss
+/// in both directions, to test the behaviour of a `Context` deterministically.
+pub struct InMemory {
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+    incoming: Arc<Mutex<Queue>>,
+    outgoing: Arc<Mutex<Queue>>,
+    conditions: LinkConditions,
+    rng: u64,
+    delay: Option<Delay>,
+}
+
+impl InMemory {
+    /// Creates two sockets with the given addresses that are connected to each other.
+    pub fn pair(
+        first: SocketAddr,
+        second: SocketAddr,
+        conditions: LinkConditions,
+    ) -> (InMemory, InMemory) {
+        let to_first = Arc::new(Mutex::new(Queue::default()));
+        let to_second = Arc::new(Mutex::new(Queue::default()));
+
+        let first_socket = InMemory {
+            local_addr: first,
+            peer_addr: second,
+            incoming: to_first.clone(),
+            outgoing: to_second.clone(),
+            conditions,
+            rng: conditions.seed.max(1),
+            delay: None,
+        };
+
+        let second_socket = InMemory {
+            local_addr: second,
+            peer_addr: first,
+            incoming: to_second,
+            outgoing: to_first,
+            conditions,
+            rng: (!conditions.seed).max(1),
+            delay: None,
+        };
+
+        (first_socket, second_socket)
+    }
+
+    /// Returns the address of the connected socket.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer_addr
+    }
+
+    fn is_lost(&mut self) -> bool {
+        self.conditions.loss > 0.0 && next_random(&mut self.rng) < self.conditions.loss
+    }
+}
+
+/// Returns a random number between `0.0` and `1.0`, using xorshift64.
+fn next_random(state: &mut u64) -> f64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+
+    (*state >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Compares the ip and port, the `flowinfo` and `scope_id` of IPv6 addresses are ignored.
+fn is_same_addr(left: &SocketAddr, right: &SocketAddr) -> bool {
+    left.ip() == right.ip() && left.port() == right.port()
+}
+
+impl Socket for InMemory {
+    fn poll_recv_from(&mut self, buf: &mut [u8]) -> Poll<(usize, SocketAddr), io::Error> {
+        loop {
+            let at = {
+                let mut incoming = self.incoming.lock().unwrap();
+
+                let at = match incoming.packets.front() {
+                    Some(packet) => packet.at,
+                    None => {
+                        incoming.task = Some(task::current());
+                        return Ok(Async::NotReady);
+                    }
+                };
+
+                if at <= Instant::now() {
+                    let packet = incoming.packets.pop_front().expect("front packet exists");
+                    let len = packet.data.len().min(buf.len());
+                    buf[..len].copy_from_slice(&packet.data[..len]);
+                    return Ok(Async::Ready((len, packet.from)));
+                }
+
+                at
+            };
+
+            let delay = self.delay.get_or_insert_with(|| Delay::new(at));
+            delay.reset(at);
+            try_ready!(delay.poll().map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+        }
+    }
+
+    fn poll_send_to(&mut self, buf: &[u8], target: &SocketAddr) -> Poll<usize, io::Error> {
+        if !is_same_addr(target, &self.peer_addr) || self.is_lost() {
+            return Ok(Async::Ready(buf.len()));
+        }
+
+        let mut outgoing = self.outgoing.lock().unwrap();
+        outgoing.packets.push_back(Packet {
+            at: Instant::now() + self.conditions.latency,
+            data: buf.to_vec(),
+            from: self.local_addr,
+        });
+
+        if let Some(task) = outgoing.task.take() {
+            task.notify();
+        }
+
+        Ok(Async::Ready(buf.len()))
+    }
+
+    fn poll_write_ready(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_numbers_are_deterministic_and_in_range() {
+        let mut first = 42;
+        let mut second = 42;
+
+        for _ in 0..1000 {
+            let value = next_random(&mut first);
+            assert!((0.0..1.0).contains(&value));
+            assert_eq!(value, next_random(&mut second));
+        }
+    }
+
+    #[test]
+    fn loss_rate_matches_conditions() {
+        let conditions = LinkConditions {
+            loss: 0.25,
+            ..LinkConditions::default()
+        };
+        let (mut socket, _) = InMemory::pair(
+            ([10, 0, 0, 1], 1).into(),
+            ([10, 0, 0, 2], 1).into(),
+            conditions,
+        );
+
+        let lost = (0..10_000).filter(|_| socket.is_lost()).count();
+        assert!(lost > 2_000 && lost < 3_000, "lost {} packets", lost);
+    }
+}
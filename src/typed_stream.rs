@@ -0,0 +1,138 @@
+use error::*;
+use message_stream::MessageStream;
+use stream::Stream;
+
+use bytes::BytesMut;
+
+use futures::{Async::Ready, AsyncSink, Poll, Sink, StartSend, Stream as FStream};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use failure;
+
+use std::marker::PhantomData;
+
+/// A `Codec` serializes and deserializes the messages of a `TypedStream`.
+pub trait Codec {
+    /// Serializes the given message.
+    fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>, Error>;
+
+    /// Deserializes a message from the given data.
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error>;
+}
+
+/// A `Codec` that uses bincode.
+#[cfg(feature = "bincode-codec")]
+pub struct Bincode;
+
+#[cfg(feature = "bincode-codec")]
+impl Codec for Bincode {
+    fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>, Error> {
+        ::bincode::serialize(msg).map_err(|e| failure::Error::from(e).into())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        ::bincode::deserialize(data).map_err(|e| failure::Error::from(e).into())
+    }
+}
+
+/// A `Codec` that uses CBOR.
+#[cfg(feature = "cbor-codec")]
+pub struct Cbor;
+
+#[cfg(feature = "cbor-codec")]
+impl Codec for Cbor {
+    fn encode<T: Serialize>(msg: &T) -> Result<Vec<u8>, Error> {
+        ::serde_cbor::to_vec(msg).map_err(|e| failure::Error::from(e).into())
+    }
+
+    fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, Error> {
+        ::serde_cbor::from_slice(data).map_err(|e| failure::Error::from(e).into())
+    }
+}
+
+/// A `TypedStream` sends and receives whole messages of type `T` over a `Stream`.
+/// Each message is serialized with the `Codec` `C` and framed by a `MessageStream`.
+pub struct TypedStream<T, C> {
+    messages: MessageStream,
+    _marker: PhantomData<(T, C)>,
+}
+
+impl<T, C> TypedStream<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// Creates a new `TypedStream` on top of the given `Stream`.
+    pub fn new(stream: Stream) -> TypedStream<T, C> {
+        TypedStream {
+            messages: MessageStream::new(stream),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sets the maximum size of a received message. Receiving a bigger message results in an
+    /// error.
+    /// Default: 16MiB
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.messages.set_max_message_size(size);
+    }
+
+    /// Returns a reference to the underlying `Stream`.
+    pub fn get_ref(&self) -> &Stream {
+        self.messages.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying `Stream`.
+    pub fn get_mut(&mut self) -> &mut Stream {
+        self.messages.get_mut()
+    }
+
+    /// Consumes the `TypedStream` and returns the underlying `Stream`.
+    /// Any buffered data, that does not form a complete message, is discarded.
+    pub fn into_inner(self) -> Stream {
+        self.messages.into_inner()
+    }
+}
+
+impl<T, C> FStream for TypedStream<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    type Item = T;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match try_ready!(self.messages.poll()) {
+            Some(msg) => C::decode(&msg).map(|m| Ready(Some(m))),
+            None => Ok(Ready(None)),
+        }
+    }
+}
+
+impl<T, C> Sink for TypedStream<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    type SinkItem = T;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        // The message is only encoded, after the `MessageStream` accepts it.
+        if self.messages.send_pending()?.is_not_ready() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+
+        let msg = BytesMut::from(C::encode(&item)?);
+        match self.messages.start_send(msg)? {
+            AsyncSink::Ready => Ok(AsyncSink::Ready),
+            AsyncSink::NotReady(_) => unreachable!("no message is pending"),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.messages.poll_complete()
+    }
+}
@@ -32,9 +32,13 @@ impl ErrorState {
         self.inner.read().as_ref().map(|v| v())
     }
 
-    /// Set the stored error function.
+    /// Set the stored error function, if no error function was stored before.
     pub fn set_error<T: ErrorFn>(&mut self, err_fn: T) {
-        *self.inner.write() = Some(Box::new(err_fn));
+        let mut inner = self.inner.write();
+
+        if inner.is_none() {
+            *inner = Some(Box::new(err_fn));
+        }
     }
 }
 
@@ -0,0 +1,75 @@
+//! The variable-length integer encoding of QUIC (RFC 9000, section 16).
+
+/// The biggest value that can be encoded.
+pub const MAX_VALUE: u64 = (1 << 62) - 1;
+
+/// Appends the given value to `buf`, with the shortest encoding.
+///
+/// # Panics
+/// If the value is bigger than `MAX_VALUE`.
+pub fn encode(value: u64, buf: &mut Vec<u8>) {
+    assert!(value <= MAX_VALUE, "{} is too big for a varint", value);
+
+    let (len, prefix) = match value {
+        0..=0x3f => (1, 0x00),
+        0x40..=0x3fff => (2, 0x40),
+        0x4000..=0x3fff_ffff => (4, 0x80),
+        _ => (8, 0xc0),
+    };
+
+    let bytes = value.to_be_bytes();
+    let start = buf.len();
+    buf.extend_from_slice(&bytes[8 - len..]);
+    buf[start] |= prefix;
+}
+
+/// Decodes the varint at the start of `buf`.
+/// Returns the value and the number of bytes it occupied, or `None` if `buf` is too short.
+pub fn decode(buf: &[u8]) -> Option<(u64, usize)> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = buf.get(..len)?;
+
+    let value = bytes[1..]
+        .iter()
+        .fold(u64::from(first & 0x3f), |value, b| value << 8 | u64::from(*b));
+    Some((value, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(value: u64) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode(value, &mut buf);
+        buf
+    }
+
+    #[test]
+    fn values_are_encoded_with_the_shortest_length() {
+        // The examples of RFC 9000, appendix A.1.
+        assert_eq!(vec![0x25], encoded(37));
+        assert_eq!(vec![0x7b, 0xbd], encoded(15_293));
+        assert_eq!(vec![0x9d, 0x7f, 0x3e, 0x7d], encoded(494_878_333));
+        assert_eq!(
+            vec![0xc2, 0x19, 0x7c, 0x5e, 0xff, 0x14, 0xe8, 0x8c],
+            encoded(151_288_809_941_952_652)
+        );
+    }
+
+    #[test]
+    fn encoded_values_are_decoded() {
+        for value in &[0, 37, 15_293, 494_878_333, MAX_VALUE] {
+            let mut buf = encoded(*value);
+            let len = buf.len();
+            buf.push(0xff);
+
+            assert_eq!(Some((*value, len)), decode(&buf));
+            assert_eq!(None, decode(&buf[..len - 1]));
+        }
+
+        // A value does not need to use the shortest encoding.
+        assert_eq!(Some((37, 2)), decode(&[0x40, 0x25]));
+    }
+}
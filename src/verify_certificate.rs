@@ -1,4 +1,12 @@
-use {ConnectionId, ConnectionType};
+use sni::hostname_matches;
+use {ConnectionId, Role};
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+};
+
+use futures::Future;
 
 pub use openssl::{
     error::ErrorStack,
@@ -7,6 +15,20 @@ pub use openssl::{
     *,
 };
 
+/// Information about the `Connection` a certificate is verified for.
+#[derive(Debug, Clone)]
+pub struct VerifyContext {
+    /// The id of the `Connection`.
+    pub connection_id: ConnectionId,
+    /// The role of the local side of the `Connection`. A client verifies the certificate of the
+    /// server and vice versa.
+    pub role: Role,
+    /// The address of the peer that presented the certificate.
+    pub peer_addr: SocketAddr,
+    /// The server name(SNI) of the `Connection`.
+    pub server_name: Option<String>,
+}
+
 /// The `VerifyCertificate` trait is used by the verify certificate handler, to verify a
 /// certificate.
 pub trait VerifyCertificate {
@@ -18,20 +40,121 @@ pub trait VerifyCertificate {
     /// a `Err(ErrorStack)` is expected.
     fn verify(
         &mut self,
-        connection_id: ConnectionId,
-        connection_type: ConnectionType,
+        context: &VerifyContext,
         cert: &X509Ref,
         chain: &StackRef<X509>,
     ) -> Result<bool, ErrorStack>;
 }
 
+/// The asynchronous variant of `VerifyCertificate`, for verifications that can not finish
+/// instantly, e.g. because they require an OCSP or revocation lookup.
+pub trait AsyncVerifyCertificate {
+    /// Will be called to verify the given certificate and certificates chain.
+    ///
+    /// # Result
+    ///
+    /// The returned future should resolve to `true`, if the certificate could be verified.
+    /// The future is polled by the `Context`, so it must not block.
+    fn verify(
+        &mut self,
+        context: &VerifyContext,
+        cert: &X509Ref,
+        chain: &StackRef<X509>,
+    ) -> Box<dyn Future<Item = bool, Error = ErrorStack>>;
+}
+
+/// Verifies certificates by the SHA-256 hash of their public key(the DER encoded
+/// SubjectPublicKeyInfo), instead of a chain of trust. This is useful for peer to peer
+/// deployments, where there is no certificate authority.
+///
+/// Only the certificate of the peer needs to match one of the pins, the chain and the validity
+/// period are not checked.
+#[derive(Debug, Clone, Default)]
+pub struct PinnedVerifier {
+    pins: HashSet<[u8; 32]>,
+}
+
+impl PinnedVerifier {
+    /// Creates a new instance that accepts the certificates with the given SPKI pins.
+    pub fn new<I: IntoIterator<Item = [u8; 32]>>(pins: I) -> PinnedVerifier {
+        PinnedVerifier {
+            pins: pins.into_iter().collect(),
+        }
+    }
+
+    /// Accepts the certificates with the given SPKI pin as well.
+    pub fn add_pin(&mut self, pin: [u8; 32]) {
+        self.pins.insert(pin);
+    }
+
+    /// Returns the SPKI pin of the given certificate.
+    pub fn spki_pin(cert: &X509Ref) -> Result<[u8; 32], ErrorStack> {
+        let spki = cert.public_key()?.public_key_to_der()?;
+        Ok(sha::sha256(&spki))
+    }
+}
+
+impl VerifyCertificate for PinnedVerifier {
+    fn verify(
+        &mut self,
+        _: &VerifyContext,
+        cert: &X509Ref,
+        _: &StackRef<X509>,
+    ) -> Result<bool, ErrorStack> {
+        Ok(self.pins.contains(&PinnedVerifier::spki_pin(cert)?))
+    }
+}
+
 /// Provides a default implementation for verifying a certificate and certificates chain against
-/// a `X509Store` with trusted certificates.
+/// a `X509Store` with trusted certificates. A client also checks, that the certificate was issued
+/// for the server name(SNI) of the `Connection`.
 pub fn default_verify_certificate(
+    context: &VerifyContext,
     cert: &X509Ref,
     chain: &StackRef<X509>,
     store: &X509StoreRef,
 ) -> Result<bool, ErrorStack> {
-    let mut context = X509StoreContext::new()?;
-    context.init(store, cert, chain, |c| c.verify_cert())
+    let mut store_context = X509StoreContext::new()?;
+
+    if !store_context.init(store, cert, chain, |c| c.verify_cert())? {
+        return Ok(false);
+    }
+
+    match (context.role, context.server_name.as_ref()) {
+        (Role::Client, Some(server_name)) => Ok(matches_server_name(cert, server_name)),
+        _ => Ok(true),
+    }
+}
+
+/// Checks the server name against the IP addresses or the DNS names of the subject alternative
+/// names. Like OpenSSL, the common name is only checked, if there are no DNS names.
+fn matches_server_name(cert: &X509Ref, server_name: &str) -> bool {
+    let alt_names = cert.subject_alt_names();
+    let alt_names = alt_names.iter().flat_map(|names| names.iter());
+
+    if let Ok(ip) = server_name.parse::<IpAddr>() {
+        let ip = match ip {
+            IpAddr::V4(ip) => ip.octets().to_vec(),
+            IpAddr::V6(ip) => ip.octets().to_vec(),
+        };
+
+        return alt_names
+            .filter_map(|name| name.ipaddress())
+            .any(|addr| addr == &ip[..]);
+    }
+
+    let dns_names = alt_names
+        .filter_map(|name| name.dnsname())
+        .collect::<Vec<_>>();
+
+    if !dns_names.is_empty() {
+        return dns_names
+            .iter()
+            .any(|name| hostname_matches(name, server_name));
+    }
+
+    cert.subject_name()
+        .entries_by_nid(nid::Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().as_utf8().ok())
+        .any(|name| hostname_matches(&name, server_name))
 }
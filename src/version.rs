@@ -0,0 +1,148 @@
+use error::*;
+use ffi;
+
+use std::fmt;
+
+/// The version of the QUIC protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuicVersion {
+    /// The final version of RFC 9000, `0x00000001`.
+    V1,
+    /// An IETF draft version, e.g. `Draft(29)` for `0xff00001d`.
+    Draft(u8),
+    /// Any other version, e.g. a version of another vendor.
+    Other(u32),
+}
+
+/// The prefix of the IETF draft versions.
+const DRAFT_PREFIX: u32 = 0xff00_0000;
+
+impl QuicVersion {
+    /// Returns the version number, as it is sent on the wire.
+    pub fn value(self) -> u32 {
+        match self {
+            QuicVersion::V1 => 1,
+            QuicVersion::Draft(draft) => DRAFT_PREFIX | u32::from(draft),
+            QuicVersion::Other(version) => version,
+        }
+    }
+
+    /// Returns all versions that picoquic implements, in the order of its preference.
+    pub fn implemented() -> Vec<QuicVersion> {
+        ffi::implemented_versions()
+            .into_iter()
+            .map(QuicVersion::from)
+            .collect()
+    }
+
+    /// Is this version implemented by picoquic?
+    pub fn is_implemented(self) -> bool {
+        ffi::implemented_versions().contains(&self.value())
+    }
+}
+
+impl From<u32> for QuicVersion {
+    fn from(version: u32) -> QuicVersion {
+        match version {
+            1 => QuicVersion::V1,
+            v if v & 0xffff_ff00 == DRAFT_PREFIX => QuicVersion::Draft(v as u8),
+            v => QuicVersion::Other(v),
+        }
+    }
+}
+
+impl fmt::Display for QuicVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QuicVersion::V1 => write!(f, "v1"),
+            QuicVersion::Draft(draft) => write!(f, "draft-{}", draft),
+            QuicVersion::Other(version) => write!(f, "{:#010x}", version),
+        }
+    }
+}
+
+/// Returns the most preferred of the `supported` versions, that is in the `implemented`
+/// versions. `None` selects the default version of picoquic, if no versions are configured.
+pub fn select_version(
+    supported: &[QuicVersion],
+    implemented: &[QuicVersion],
+) -> Result<Option<QuicVersion>, Error> {
+    if supported.is_empty() {
+        return Ok(None);
+    }
+
+    supported
+        .iter()
+        .find(|v| implemented.contains(v))
+        .cloned()
+        .map(Some)
+        .ok_or_else(|| ErrorKind::UnsupportedVersions(supported.to_vec()).into())
+}
+
+/// Is the given version implemented by picoquic and one of the `supported` versions?
+/// All implemented versions are supported, if no versions are configured.
+pub fn is_supported(version: QuicVersion, supported: &[QuicVersion]) -> bool {
+    version.is_implemented() && (supported.is_empty() || supported.contains(&version))
+}
+
+/// Returns the versions the peer offered in the given version negotiation packet.
+/// Returns `None`, if the packet is not a version negotiation packet.
+pub fn offered_versions(packet: &[u8]) -> Option<Vec<QuicVersion>> {
+    // A version negotiation packet has a long header with version `0`.
+    if packet.first()? & 0x80 == 0 || packet.get(1..5)? != &[0, 0, 0, 0][..] {
+        return None;
+    }
+
+    let dcid_len = *packet.get(5)? as usize;
+    let scid_len = *packet.get(6 + dcid_len)? as usize;
+    let versions = packet.get(7 + dcid_len + scid_len..)?;
+
+    Some(
+        versions
+            .chunks(4)
+            .filter(|v| v.len() == 4)
+            .map(|v| {
+                let version = v.iter().fold(0u32, |version, b| version << 8 | u32::from(*b));
+                QuicVersion::from(version)
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn versions_are_converted_from_and_to_wire_format() {
+        assert_eq!(QuicVersion::V1, QuicVersion::from(1));
+        assert_eq!(QuicVersion::Draft(29), QuicVersion::from(0xff00_001d));
+        assert_eq!(QuicVersion::Other(0x1a2a_3a4a), QuicVersion::from(0x1a2a_3a4a));
+        assert_eq!(0xff00_001d, QuicVersion::Draft(29).value());
+        assert_eq!("draft-29", QuicVersion::Draft(29).to_string());
+    }
+
+    #[test]
+    fn most_preferred_implemented_version_is_selected() {
+        let implemented = [QuicVersion::Draft(29), QuicVersion::Draft(27)];
+
+        assert_eq!(None, select_version(&[], &implemented).unwrap());
+        assert_eq!(
+            Some(QuicVersion::Draft(27)),
+            select_version(&[QuicVersion::V1, QuicVersion::Draft(27)], &implemented).unwrap()
+        );
+        assert!(select_version(&[QuicVersion::V1], &implemented).is_err());
+    }
+
+    #[test]
+    fn offered_versions_are_parsed_from_version_negotiation() {
+        let packet = [0x80, 0, 0, 0, 0, 1, 0xaa, 0, 0, 0, 0, 1, 0xff, 0, 0, 0x1d];
+        assert_eq!(
+            Some(vec![QuicVersion::V1, QuicVersion::Draft(29)]),
+            offered_versions(&packet)
+        );
+
+        assert_eq!(None, offered_versions(&[0xc3, 0, 0, 0, 1, 0]));
+        assert_eq!(None, offered_versions(&[0x40, 0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,255 @@
+use super::{
+    session::{Dispatcher, Handle, Session},
+    settings, supports_webtransport, PROTOCOL,
+};
+use connection::{Connection, NewStreamFuture};
+use error::*;
+use h3::{
+    control::OpenControlStream,
+    message::{ReadHeaders, SendMessage},
+    Request, Response,
+};
+use stream::Stream;
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{Async::Ready, Future, Poll};
+
+/// A WebTransport client on top of a `Connection`, see `Client::new`.
+/// The `Client` establishes any number of `Session`s on the same `Connection`.
+pub struct Client {
+    handle: Handle,
+}
+
+impl Client {
+    /// Starts WebTransport on the given `Connection`. The `Connection` should negotiate
+    /// `h3::ALPN`. Resolves to the `Client`, after the control stream is opened.
+    pub fn new(connection: Connection) -> NewClient {
+        let settings = settings(&connection);
+
+        NewClient {
+            inner: OpenControlStream::new(connection, settings),
+        }
+    }
+
+    /// Establishes a new `Session` with the given absolute URL, e.g.
+    /// `https://example.com/chat`.
+    pub fn connect(&mut self, url: &str) -> Connect {
+        Connect {
+            inner: ExtendedConnect::with_handle(
+                self.handle.clone(),
+                Request::new("CONNECT", url),
+                PROTOCOL,
+                supports_webtransport,
+            ),
+        }
+    }
+}
+
+/// Resolves to a `Client`, see `Client::new`.
+pub struct NewClient {
+    inner: OpenControlStream,
+}
+
+impl Future for NewClient {
+    type Item = Client;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (connection, control) = try_ready!(self.inner.poll());
+
+        Ok(Ready(Client {
+            handle: Dispatcher::new(connection, control, false),
+        }))
+    }
+}
+
+impl Session {
+    /// Establishes a new `Session` on the given `Connection` with the given absolute URL, e.g.
+    /// `https://example.com/chat`. The `Connection` should negotiate `h3::ALPN`.
+    /// This is the only `Session` of the `Connection`, use a `Client` to establish more.
+    pub fn connect(connection: Connection, url: &str) -> Connect {
+        let settings = settings(&connection);
+
+        Connect {
+            inner: ExtendedConnect::new(
+                connection,
+                Request::new("CONNECT", url),
+                PROTOCOL,
+                settings,
+                supports_webtransport,
+            ),
+        }
+    }
+}
+
+/// Resolves to a `Session`, after the server accepted it, see `Client::connect`.
+pub struct Connect {
+    inner: ExtendedConnect,
+}
+
+impl Future for Connect {
+    type Item = Session;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (id, handle) = try_ready!(self.inner.poll());
+        Ok(Ready(Session::new(id, handle)))
+    }
+}
+
+enum State {
+    Failed(Option<Error>),
+    OpenControl(OpenControlStream),
+    Opening(NewStreamFuture),
+    /// The extended `CONNECT` may only be sent after the `SETTINGS` of the server were received.
+    WaitForSettings(Option<Stream>),
+    Sending(SendMessage),
+    Reading(ReadHeaders),
+}
+
+/// Sends an extended `CONNECT` (RFC 9220) on a `Connection` and resolves to the id of the
+/// established session with the `Dispatcher` of the `Connection`, after the server accepted it.
+pub(crate) struct ExtendedConnect {
+    request: Option<Request>,
+    /// Checks that the `SETTINGS` of the server support the protocol of the `request`.
+    is_supported: fn(&[(u64, u64)]) -> bool,
+    handle: Option<Handle>,
+    /// The id of the session, while it is not established.
+    session: Option<u64>,
+    state: State,
+}
+
+impl ExtendedConnect {
+    /// Creates a new `ExtendedConnect` of the given protocol. The given settings are sent to
+    /// the server, in addition to the settings of HTTP/3.
+    pub fn new(
+        connection: Connection,
+        request: Result<Request, Error>,
+        protocol: &str,
+        settings: Vec<(u64, u64)>,
+        is_supported: fn(&[(u64, u64)]) -> bool,
+    ) -> ExtendedConnect {
+        ExtendedConnect::build(request, protocol, is_supported, None, || {
+            State::OpenControl(OpenControlStream::new(connection, settings))
+        })
+    }
+
+    /// Creates a new `ExtendedConnect` of the given protocol, on a `Connection` that already
+    /// has a `Dispatcher`.
+    pub fn with_handle(
+        handle: Handle,
+        request: Result<Request, Error>,
+        protocol: &str,
+        is_supported: fn(&[(u64, u64)]) -> bool,
+    ) -> ExtendedConnect {
+        let dispatcher = handle.clone();
+
+        ExtendedConnect::build(request, protocol, is_supported, Some(handle), move || {
+            let new_stream = dispatcher.lock().new_stream_handle();
+            State::Opening(new_stream.new_bidirectional_stream())
+        })
+    }
+
+    /// The `state` is only created, if the `request` is valid.
+    fn build<F: FnOnce() -> State>(
+        request: Result<Request, Error>,
+        protocol: &str,
+        is_supported: fn(&[(u64, u64)]) -> bool,
+        handle: Option<Handle>,
+        state: F,
+    ) -> ExtendedConnect {
+        let (request, state) = match request {
+            Ok(mut request) => {
+                request.protocol = Some(protocol.to_owned());
+                (Some(request), state())
+            }
+            Err(err) => (None, State::Failed(Some(err))),
+        };
+
+        ExtendedConnect {
+            request,
+            is_supported,
+            handle,
+            session: None,
+            state,
+        }
+    }
+}
+
+impl Future for ExtendedConnect {
+    type Item = (u64, Handle);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let next = match self.state {
+                State::Failed(ref mut err) => {
+                    return Err(err.take().expect("`ExtendedConnect` polled after error"));
+                }
+                State::OpenControl(ref mut open) => {
+                    let (connection, control) = try_ready!(open.poll());
+                    let handle = Dispatcher::new(connection, control, false);
+                    let new_stream = handle
+                        .lock()
+                        .new_stream_handle()
+                        .new_bidirectional_stream();
+
+                    self.handle = Some(handle);
+                    State::Opening(new_stream)
+                }
+                State::Opening(ref mut new_stream) => {
+                    State::WaitForSettings(Some(try_ready!(new_stream.poll())))
+                }
+                State::WaitForSettings(ref mut stream) => {
+                    let is_supported = self.is_supported;
+                    let handle = self.handle.as_ref().expect("`Dispatcher` is created");
+                    let supported = try_ready!(handle.poll(|dispatcher| {
+                        Ok(dispatcher.peer_settings().map(is_supported))
+                    }));
+
+                    let stream = stream.take().expect("`ExtendedConnect` polled after completion");
+                    let request = self.request.take().expect("request is valid");
+
+                    if !supported {
+                        bail!("server does not support the protocol {:?}", request.protocol);
+                    }
+
+                    handle.lock().add_session(stream.id());
+                    self.session = Some(stream.id());
+                    State::Sending(SendMessage::without_finish(stream, request.encode()))
+                }
+                State::Sending(ref mut send) => {
+                    State::Reading(ReadHeaders::new(try_ready!(send.poll()), BytesMut::new()))
+                }
+                State::Reading(ref mut read) => {
+                    let (stream, fields, received) = try_ready!(read.poll());
+                    let response = Response::decode(fields, Bytes::new())?;
+
+                    if response.status < 200 || response.status >= 300 {
+                        bail!(
+                            "server rejected the extended `CONNECT` with status {}",
+                            response.status
+                        );
+                    }
+
+                    let handle = self.handle.clone().expect("`Dispatcher` is created");
+                    let id = self.session.take().expect("session is added");
+                    handle.lock().establish_session(id, stream, received);
+
+                    return Ok(Ready((id, handle)));
+                }
+            };
+
+            self.state = next;
+        }
+    }
+}
+
+impl Drop for ExtendedConnect {
+    fn drop(&mut self) {
+        if let (Some(handle), Some(id)) = (self.handle.as_ref(), self.session) {
+            handle.lock().remove_session(id);
+        }
+    }
+}
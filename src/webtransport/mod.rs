@@ -0,0 +1,89 @@
+//! WebTransport over HTTP/3 (draft-ietf-webtrans-http3) on top of a `Connection`.
+//!
+//! A `Session` is established by an extended `CONNECT` request, see `Client` and `Server`. The
+//! `SessionStream`s and datagrams of a `Session` carry the id of the `Session`, so many
+//! `Session`s can share one `Connection`. The `Connection` needs to negotiate `h3::ALPN` and
+//! datagrams require `Config::enable_datagrams` on both peers.
+
+pub(crate) mod client;
+mod server;
+pub(crate) mod session;
+
+pub use self::client::{Client, Connect, NewClient};
+pub use self::server::{AcceptSession, NewServer, Server, SessionRequest};
+pub use self::session::{
+    AcceptStream, CloseSession, OpenStream, RecvDatagram, Session, SessionStream,
+};
+
+use connection::Connection;
+
+/// The protocol of the extended `CONNECT` request, that establishes a `Session`.
+const PROTOCOL: &str = "webtransport";
+
+pub(crate) const SETTINGS_ENABLE_CONNECT_PROTOCOL: u64 = 0x08;
+pub(crate) const SETTINGS_H3_DATAGRAM: u64 = 0x33;
+/// The setting of the older drafts, that are still implemented by browsers.
+const SETTINGS_ENABLE_WEBTRANSPORT: u64 = 0x2b60_3742;
+const SETTINGS_WEBTRANSPORT_MAX_SESSIONS: u64 = 0xc671_706a;
+
+/// Starts a bidirectional `Stream` of a `Session`, followed by the id of the `Session`.
+const WEBTRANSPORT_STREAM: u64 = 0x41;
+/// The stream type of a unidirectional `Stream` of a `Session`.
+const WEBTRANSPORT_UNI_STREAM: u64 = 0x54;
+
+/// The capsule type that closes a `Session`.
+const CLOSE_WEBTRANSPORT_SESSION: u64 = 0x2843;
+
+/// The error code to reject a `Stream` of an unknown `Session`.
+const WEBTRANSPORT_BUFFERED_STREAM_REJECTED: u64 = 0x3994_bd84;
+/// The error code to reject a request that is not processed.
+pub(crate) const H3_REQUEST_REJECTED: u64 = 0x010b;
+
+/// The maximum number of `Session`s a `Server` accepts per `Connection`.
+const MAX_SESSIONS: u64 = 16;
+
+/// Returns the settings of WebTransport, that are sent on the control stream.
+fn settings(connection: &Connection) -> Vec<(u64, u64)> {
+    let mut settings = vec![
+        (SETTINGS_ENABLE_CONNECT_PROTOCOL, 1),
+        (SETTINGS_ENABLE_WEBTRANSPORT, 1),
+        (SETTINGS_WEBTRANSPORT_MAX_SESSIONS, MAX_SESSIONS),
+    ];
+
+    if connection.max_datagram_size() > 0 {
+        settings.push((SETTINGS_H3_DATAGRAM, 1));
+    }
+
+    settings
+}
+
+/// Returns `true`, if the given settings of a server announce support for WebTransport.
+fn supports_webtransport(settings: &[(u64, u64)]) -> bool {
+    let value = |id| settings.iter().find(|s| s.0 == id).map_or(0, |s| s.1);
+
+    value(SETTINGS_ENABLE_CONNECT_PROTOCOL) == 1
+        && (value(SETTINGS_WEBTRANSPORT_MAX_SESSIONS) > 0
+            || value(SETTINGS_ENABLE_WEBTRANSPORT) == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webtransport_support_is_detected_from_settings() {
+        assert!(supports_webtransport(&[
+            (SETTINGS_ENABLE_CONNECT_PROTOCOL, 1),
+            (SETTINGS_WEBTRANSPORT_MAX_SESSIONS, 1),
+        ]));
+        assert!(supports_webtransport(&[
+            (SETTINGS_ENABLE_WEBTRANSPORT, 1),
+            (SETTINGS_ENABLE_CONNECT_PROTOCOL, 1),
+        ]));
+        assert!(!supports_webtransport(&[(SETTINGS_WEBTRANSPORT_MAX_SESSIONS, 1)]));
+        assert!(!supports_webtransport(&[
+            (SETTINGS_ENABLE_CONNECT_PROTOCOL, 1),
+            (SETTINGS_WEBTRANSPORT_MAX_SESSIONS, 0),
+        ]));
+    }
+}
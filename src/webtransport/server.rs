@@ -0,0 +1,195 @@
+use super::{
+    session::{Dispatcher, Handle, Session},
+    settings, H3_REQUEST_REJECTED, MAX_SESSIONS, PROTOCOL,
+};
+use connection::Connection;
+use error::*;
+use h3::{
+    control::OpenControlStream,
+    message::SendMessage,
+    Request, Responder, Response, SendResponse,
+};
+use stream::Stream;
+
+use bytes::{Bytes, BytesMut};
+
+use futures::{Async::Ready, Future, Poll, Stream as FStream};
+
+/// A WebTransport server on top of an incoming `Connection`, see `Server::new`.
+/// The `Server` yields the `SessionRequest`s of the client, other HTTP/3 requests are rejected.
+/// The requests of the client are rejected as well, while it has `MAX_SESSIONS` `Session`s.
+pub struct Server {
+    handle: Handle,
+}
+
+impl Server {
+    /// Starts WebTransport on the given incoming `Connection`. The `Connection` should
+    /// negotiate `h3::ALPN`. Resolves to the `Server`, after the control stream is opened.
+    pub fn new(connection: Connection) -> NewServer {
+        let settings = settings(&connection);
+
+        NewServer {
+            inner: OpenControlStream::new(connection, settings),
+        }
+    }
+}
+
+/// Returns `true`, if the given `Request` is an extended `CONNECT` for WebTransport.
+fn is_session_request(request: &Request) -> bool {
+    request.method == "CONNECT" && request.protocol.as_ref().map(|p| p.as_str()) == Some(PROTOCOL)
+}
+
+impl FStream for Server {
+    type Item = SessionRequest;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            let received = try_ready!(self.handle.poll(|dispatcher| {
+                match dispatcher.take_request() {
+                    Some(request) => Ok(Some(Some(request))),
+                    None if dispatcher.requests_finished() => Ok(Some(None)),
+                    None => Ok(None),
+                }
+            }));
+
+            let (mut stream, request, received) = match received {
+                Some((stream, fields, received)) => {
+                    (stream, Request::decode(fields, Bytes::new()), received)
+                }
+                None => return Ok(Ready(None)),
+            };
+
+            match request {
+                Ok(ref request)
+                    if is_session_request(request)
+                        && self.handle.lock().session_count() >= MAX_SESSIONS as usize =>
+                {
+                    debug!("rejecting session on stream {}, too many sessions", stream.id());
+                    let _ = stream.stop_sending(H3_REQUEST_REJECTED);
+                    let _ = stream.reset();
+                }
+                Ok(request) if is_session_request(&request) => {
+                    let id = stream.id();
+                    self.handle.lock().add_session(id);
+
+                    return Ok(Ready(Some(SessionRequest {
+                        id,
+                        request,
+                        stream: Some(stream),
+                        received,
+                        handle: self.handle.clone(),
+                    })));
+                }
+                _ => {
+                    debug!("rejecting request on stream {}, it is no WebTransport", stream.id());
+                    let _ = stream.stop_sending(H3_REQUEST_REJECTED);
+                    let _ = stream.reset();
+                }
+            }
+        }
+    }
+}
+
+/// Resolves to a `Server`, see `Server::new`.
+pub struct NewServer {
+    inner: OpenControlStream,
+}
+
+impl Future for NewServer {
+    type Item = Server;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (connection, control) = try_ready!(self.inner.poll());
+
+        Ok(Ready(Server {
+            handle: Dispatcher::new(connection, control, true),
+        }))
+    }
+}
+
+/// A request of a client to establish a `Session`, that needs to be accepted or rejected.
+/// Dropping the `SessionRequest` rejects it, by resetting its `Stream`.
+pub struct SessionRequest {
+    id: u64,
+    request: Request,
+    /// `None`, after the `SessionRequest` was accepted or rejected.
+    stream: Option<Stream>,
+    /// The data that was received on the `Stream` after the header fields.
+    received: BytesMut,
+    handle: Handle,
+}
+
+impl SessionRequest {
+    /// Returns the extended `CONNECT` request, e.g. to check its `path`.
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// Accepts the `Session` with the status `200`.
+    pub fn accept(mut self) -> AcceptSession {
+        let stream = self.stream.take().expect("`SessionRequest` is not answered");
+
+        let mut response = Response::new(200, Bytes::new());
+        // Required by browsers, that implement an older draft.
+        response
+            .headers
+            .push(("sec-webtransport-http3-draft".into(), "draft02".into()));
+
+        AcceptSession {
+            id: self.id,
+            inner: SendMessage::without_finish(stream, response.encode()),
+            received: Some(self.received.take()),
+            handle: self.handle.clone(),
+        }
+    }
+
+    /// Rejects the `Session` with the given status, e.g. `404`.
+    pub fn reject(mut self, status: u16) -> SendResponse {
+        let stream = self.stream.take().expect("`SessionRequest` is not answered");
+        self.handle.lock().remove_session(self.id);
+
+        Responder::new(stream).send(Response::new(status, Bytes::new()))
+    }
+}
+
+impl Drop for SessionRequest {
+    fn drop(&mut self) {
+        if self.stream.is_some() {
+            self.handle.lock().remove_session(self.id);
+        }
+    }
+}
+
+/// Resolves to the `Session`, after the response was sent, see `SessionRequest::accept`.
+pub struct AcceptSession {
+    id: u64,
+    inner: SendMessage,
+    /// `None`, after the `Session` was established.
+    received: Option<BytesMut>,
+    handle: Handle,
+}
+
+impl Future for AcceptSession {
+    type Item = Session;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let stream = try_ready!(self.inner.poll());
+        let received = self.received.take().expect("`AcceptSession` polled after completion");
+        self.handle
+            .lock()
+            .establish_session(self.id, stream, received);
+
+        Ok(Ready(Session::new(self.id, self.handle.clone())))
+    }
+}
+
+impl Drop for AcceptSession {
+    fn drop(&mut self) {
+        if self.received.is_some() {
+            self.handle.lock().remove_session(self.id);
+        }
+    }
+}
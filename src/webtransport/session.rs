@@ -0,0 +1,855 @@
+use super::{
+    CLOSE_WEBTRANSPORT_SESSION, H3_REQUEST_REJECTED, WEBTRANSPORT_BUFFERED_STREAM_REJECTED,
+    WEBTRANSPORT_STREAM, WEBTRANSPORT_UNI_STREAM,
+};
+use connection::{Connection, IncomingStreams, NewStreamFuture, NewStreamHandle};
+use datagram::{DatagramSender, Datagrams};
+use error::*;
+use h3::{
+    control::PeerStreams,
+    frame::Frame,
+    message::{ReadHeaders, SendMessage},
+};
+use stream::{self, Stream};
+use varint;
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+use futures::{
+    task::{self, Task},
+    Async::{NotReady, Ready},
+    AsyncSink, Future, Poll, Sink, StartSend, Stream as FStream,
+};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+/// The maximum number of received `Stream`s, that are buffered for a `Session`.
+const MAX_BUFFERED_STREAMS: usize = 256;
+/// The maximum number of received datagrams, that are buffered for a `Session`.
+const MAX_BUFFERED_DATAGRAMS: usize = 1024;
+/// The maximum size of a received capsule.
+const MAX_CAPSULE_SIZE: u64 = 64 * 1024;
+
+/// The state of a `Session`, as seen by the `Dispatcher`.
+#[derive(Default)]
+struct SessionState {
+    /// The `CONNECT` stream, after the `Session` was established.
+    connect: Option<Stream>,
+    /// The received data of the `CONNECT` stream, that does not contain a complete frame yet.
+    recv_buffer: BytesMut,
+    /// The payload of the received `DATA` frames, that does not contain a complete capsule yet.
+    capsules: BytesMut,
+    streams: VecDeque<SessionStream>,
+    datagrams: VecDeque<Bytes>,
+    /// The error code and reason, after the `Session` was closed.
+    closed: Option<(u32, String)>,
+}
+
+impl SessionState {
+    /// Processes the received data of the `CONNECT` stream.
+    /// Returns `true`, if the `Session` was closed.
+    fn poll_connect(&mut self) -> bool {
+        if self.closed.is_some() {
+            return false;
+        }
+
+        match self.read_capsules() {
+            Ok(Some(closed)) => self.closed = Some(closed),
+            Ok(None) => return false,
+            Err(e) => {
+                debug!("closing WebTransport session after an error: {}", e);
+                self.closed = Some((0, String::new()));
+            }
+        }
+
+        true
+    }
+
+    /// Reads the capsules of the `CONNECT` stream, until the `CLOSE_WEBTRANSPORT_SESSION`
+    /// capsule is received. A finished `CONNECT` stream closes the `Session` without an error
+    /// code.
+    fn read_capsules(&mut self) -> Result<Option<(u32, String)>, Error> {
+        loop {
+            let data = match self.connect {
+                Some(ref mut connect) => match connect.poll()? {
+                    Ready(data) => data,
+                    NotReady => return Ok(None),
+                },
+                None => return Ok(None),
+            };
+
+            match data {
+                Some(data) => self.recv_buffer.extend_from_slice(&data),
+                None => return Ok(Some((0, String::new()))),
+            }
+
+            while let Some(frame) = Frame::decode(&mut self.recv_buffer)? {
+                match frame {
+                    Frame::Data(data) => self.capsules.extend_from_slice(&data),
+                    Frame::Unknown(_) => {}
+                    _ => bail!("received unexpected frame on the `CONNECT` stream"),
+                }
+            }
+
+            while let Some((ctype, payload)) = decode_capsule(&mut self.capsules)? {
+                if ctype == CLOSE_WEBTRANSPORT_SESSION && payload.len() >= 4 {
+                    let code = payload[..4]
+                        .iter()
+                        .fold(0, |code, b| (code << 8) | u32::from(*b));
+                    let reason = String::from_utf8_lossy(&payload[4..]).into_owned();
+
+                    return Ok(Some((code, reason)));
+                }
+            }
+        }
+    }
+
+    fn error_if_closed(&self) -> Result<(), Error> {
+        match self.closed {
+            Some((code, ref reason)) => Err(ErrorKind::SessionClosed {
+                code,
+                reason: reason.clone(),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Removes the first complete capsule (RFC 9297) from the given buffer.
+/// Returns `None`, if the buffer does not contain a complete capsule yet.
+fn decode_capsule(buf: &mut BytesMut) -> Result<Option<(u64, Bytes)>, Error> {
+    let (ctype, type_len) = match varint::decode(buf) {
+        Some(ctype) => ctype,
+        None => return Ok(None),
+    };
+    let (len, len_len) = match varint::decode(&buf[type_len..]) {
+        Some(len) => len,
+        None => return Ok(None),
+    };
+
+    if len > MAX_CAPSULE_SIZE {
+        bail!("capsule with {} bytes exceeds the maximum", len);
+    } else if buf.len() < type_len + len_len + len as usize {
+        return Ok(None);
+    }
+
+    buf.advance(type_len + len_len);
+    Ok(Some((ctype, buf.split_to(len as usize).freeze())))
+}
+
+/// Returns the `CLOSE_WEBTRANSPORT_SESSION` capsule in a `DATA` frame.
+fn encode_close(code: u32, reason: &str) -> Bytes {
+    let mut payload = Vec::with_capacity(4 + reason.len());
+    payload.put_u32_be(code);
+    payload.extend_from_slice(reason.as_bytes());
+
+    let mut capsule = Vec::new();
+    varint::encode(CLOSE_WEBTRANSPORT_SESSION, &mut capsule);
+    varint::encode(payload.len() as u64, &mut capsule);
+    capsule.extend_from_slice(&payload);
+
+    let mut buf = Vec::new();
+    Frame::Data(capsule.into()).encode(&mut buf);
+    buf.into()
+}
+
+/// The prefix of a received `Stream`.
+enum Prefix {
+    Incomplete,
+    Session(u64),
+    /// A bidirectional `Stream` that carries a request instead.
+    Request,
+    /// The `Stream` was finished or reset before the prefix was received.
+    Closed,
+}
+
+/// A received `Stream`, of which the `Session` is not known yet.
+struct PendingStream {
+    stream: Stream,
+    buffer: BytesMut,
+    /// Does the `Stream` start with `WEBTRANSPORT_STREAM`? Only bidirectional `Stream`s do,
+    /// the stream type of unidirectional `Stream`s is already read by `PeerStreams`.
+    signal: bool,
+}
+
+impl PendingStream {
+    fn poll(&mut self) -> Prefix {
+        loop {
+            match self.parse() {
+                Prefix::Incomplete => {}
+                prefix => return prefix,
+            }
+
+            match self.stream.poll() {
+                Ok(Ready(Some(data))) => self.buffer.extend_from_slice(&data),
+                Ok(NotReady) => return Prefix::Incomplete,
+                Ok(Ready(None)) | Err(_) => return Prefix::Closed,
+            }
+        }
+    }
+
+    fn parse(&mut self) -> Prefix {
+        let mut len = 0;
+
+        if self.signal {
+            match varint::decode(&self.buffer) {
+                Some((WEBTRANSPORT_STREAM, signal_len)) => len = signal_len,
+                Some(_) => return Prefix::Request,
+                None => return Prefix::Incomplete,
+            }
+        }
+
+        match varint::decode(&self.buffer[len..]) {
+            Some((session, session_len)) => {
+                self.buffer.advance(len + session_len);
+                Prefix::Session(session)
+            }
+            None => Prefix::Incomplete,
+        }
+    }
+}
+
+/// Distributes the `Stream`s and datagrams of a `Connection` to its `Session`s. The sessions of
+/// other extended `CONNECT` protocols, e.g. the tunnels of `masque`, use it as well.
+///
+/// The `Dispatcher` is polled by all futures of the `Session`s, the first one that is polled
+/// processes everything that was received and wakes the others.
+pub(crate) struct Dispatcher {
+    /// Keeps the `Connection` alive, while the incoming `Stream`s are used.
+    _connection: Connection,
+    /// The control stream needs to stay open for the lifetime of the `Connection`.
+    _control: Stream,
+    new_stream: NewStreamHandle,
+    datagram_sender: DatagramSender,
+    is_server: bool,
+    peer: PeerStreams,
+    incoming: IncomingStreams,
+    incoming_finished: bool,
+    /// `None`, if the datagrams were taken from the `Connection` before.
+    datagrams: Option<Datagrams>,
+    pending: Vec<PendingStream>,
+    /// The requests that are not completely received yet, only used by servers.
+    requests: Vec<ReadHeaders>,
+    /// The received requests with the received data after their header fields.
+    received_requests: VecDeque<(Stream, Vec<(String, String)>, BytesMut)>,
+    sessions: HashMap<u64, SessionState>,
+    /// The tasks that wait for the `Dispatcher`.
+    waiting: Vec<Task>,
+}
+
+impl Dispatcher {
+    pub fn new(mut connection: Connection, control: Stream, is_server: bool) -> Handle {
+        let mut peer = PeerStreams::new(connection.incoming_unidirectional());
+        peer.accept_stream_type(WEBTRANSPORT_UNI_STREAM);
+
+        let dispatcher = Dispatcher {
+            new_stream: connection.get_new_stream_handle(),
+            datagram_sender: connection.datagram_sender(),
+            is_server,
+            peer,
+            incoming: connection.incoming_bidirectional(),
+            incoming_finished: false,
+            datagrams: connection.incoming_datagrams(),
+            pending: Vec::new(),
+            requests: Vec::new(),
+            received_requests: VecDeque::new(),
+            sessions: HashMap::new(),
+            waiting: Vec::new(),
+            _connection: connection,
+            _control: control,
+        };
+
+        Handle(Arc::new(Mutex::new(dispatcher)))
+    }
+
+    /// Returns the settings of the peer, after its `SETTINGS` frame was received.
+    pub fn peer_settings(&self) -> Option<&[(u64, u64)]> {
+        self.peer.settings()
+    }
+
+    pub fn new_stream_handle(&self) -> NewStreamHandle {
+        self.new_stream.clone()
+    }
+
+    pub fn datagram_sender(&self) -> DatagramSender {
+        self.datagram_sender.clone()
+    }
+
+    /// Adds a `Session`, that is not established yet. The `Stream`s and datagrams of the
+    /// `Session` are buffered, until it is established or removed.
+    pub fn add_session(&mut self, id: u64) {
+        self.sessions.insert(id, SessionState::default());
+    }
+
+    /// Establishes a `Session` with its `CONNECT` stream and the data that was received on the
+    /// `CONNECT` stream after the header fields.
+    pub fn establish_session(&mut self, id: u64, connect: Stream, received: BytesMut) {
+        let session = self.sessions.entry(id).or_insert_with(SessionState::default);
+        session.connect = Some(connect);
+        session.recv_buffer = received;
+    }
+
+    /// Returns the number of `Session`s, including the ones that are not established yet.
+    pub fn session_count(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Removes a `Session` and returns its `CONNECT` stream.
+    pub fn remove_session(&mut self, id: u64) -> Option<Stream> {
+        self.sessions.remove(&id).and_then(|s| s.connect)
+    }
+
+    /// Returns the next received datagram of the given session, without the quarter stream id.
+    /// Fails with `ErrorKind::SessionClosed`, after the session was closed.
+    pub fn take_datagram(&mut self, id: u64) -> Result<Option<Bytes>, Error> {
+        match self.sessions.get_mut(&id) {
+            Some(session) => match session.datagrams.pop_front() {
+                Some(datagram) => Ok(Some(datagram)),
+                None => session.error_if_closed().map(|_| None),
+            },
+            None => Err(ErrorKind::SessionClosed {
+                code: 0,
+                reason: String::new(),
+            }
+            .into()),
+        }
+    }
+
+    /// Returns the next received request.
+    pub fn take_request(&mut self) -> Option<(Stream, Vec<(String, String)>, BytesMut)> {
+        self.received_requests.pop_front()
+    }
+
+    /// Returns `true`, if the peer will not open any more requests.
+    pub fn requests_finished(&self) -> bool {
+        self.incoming_finished
+            && self.pending.is_empty()
+            && self.requests.is_empty()
+            && self.received_requests.is_empty()
+    }
+
+    /// Processes everything that was received so far and wakes the waiting tasks, if anything
+    /// changed.
+    fn poll(&mut self) -> Result<(), Error> {
+        self.peer.poll()?;
+
+        for (stream, buffer) in self.peer.take_accepted() {
+            self.pending.push(PendingStream {
+                stream,
+                buffer,
+                signal: false,
+            });
+        }
+
+        while !self.incoming_finished {
+            match self.incoming.poll()? {
+                Ready(Some(stream)) => self.pending.push(PendingStream {
+                    stream,
+                    buffer: BytesMut::new(),
+                    signal: true,
+                }),
+                Ready(None) => self.incoming_finished = true,
+                NotReady => break,
+            }
+        }
+
+        let mut progress = self.poll_pending();
+        progress |= self.poll_requests();
+        progress |= self.poll_datagrams();
+
+        for session in self.sessions.values_mut() {
+            progress |= session.poll_connect();
+        }
+
+        if progress {
+            self.waiting.drain(..).for_each(|t| t.notify());
+        }
+
+        Ok(())
+    }
+
+    fn poll_pending(&mut self) -> bool {
+        let mut progress = false;
+        let mut i = 0;
+
+        while i < self.pending.len() {
+            let prefix = self.pending[i].poll();
+
+            if let Prefix::Incomplete = prefix {
+                i += 1;
+                continue;
+            }
+
+            let PendingStream { mut stream, buffer, .. } = self.pending.remove(i);
+
+            match prefix {
+                Prefix::Incomplete | Prefix::Closed => {}
+                Prefix::Request if self.is_server => {
+                    self.requests.push(ReadHeaders::new(stream, buffer));
+                }
+                Prefix::Request => {
+                    debug!("rejecting request of the server on stream {}", stream.id());
+                    let _ = stream.stop_sending(H3_REQUEST_REJECTED);
+                    let _ = stream.reset();
+                }
+                Prefix::Session(id) => {
+                    progress |= self.dispatch_stream(id, SessionStream::new(stream, buffer));
+                }
+            }
+        }
+
+        progress
+    }
+
+    /// Hands the given `Stream` to its `Session`. Returns `true`, if the `Session` accepted it.
+    fn dispatch_stream(&mut self, id: u64, mut stream: SessionStream) -> bool {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            if session.closed.is_none() && session.streams.len() < MAX_BUFFERED_STREAMS {
+                session.streams.push_back(stream);
+                return true;
+            }
+        }
+
+        debug!("rejecting stream {} of session {}", stream.id(), id);
+        let _ = stream.stop_sending(WEBTRANSPORT_BUFFERED_STREAM_REJECTED);
+
+        if stream.get_type() == stream::Type::Bidirectional {
+            let _ = stream.reset();
+        }
+        false
+    }
+
+    fn poll_requests(&mut self) -> bool {
+        let mut progress = false;
+        let mut i = 0;
+
+        while i < self.requests.len() {
+            match self.requests[i].poll() {
+                Ok(NotReady) => i += 1,
+                Ok(Ready(request)) => {
+                    self.requests.remove(i);
+                    self.received_requests.push_back(request);
+                    progress = true;
+                }
+                Err(e) => {
+                    debug!("dropping malformed HTTP/3 request: {}", e);
+
+                    if let Some(mut stream) = self.requests.remove(i).take_stream() {
+                        let _ = stream.reset();
+                    }
+                }
+            }
+        }
+
+        progress
+    }
+
+    fn poll_datagrams(&mut self) -> bool {
+        let mut progress = false;
+
+        loop {
+            let mut datagram = match self.datagrams.as_mut().map(|d| d.poll()) {
+                Some(Ok(Ready(Some(datagram)))) => datagram,
+                Some(Ok(NotReady)) | None => return progress,
+                Some(Ok(Ready(None))) | Some(Err(_)) => {
+                    self.datagrams = None;
+                    return progress;
+                }
+            };
+
+            // Datagrams start with the quarter stream id of the `CONNECT` stream.
+            let (quarter_id, len) = match varint::decode(&datagram) {
+                Some(quarter_id) => quarter_id,
+                None => continue,
+            };
+            datagram.advance(len);
+
+            match self.sessions.get_mut(&(quarter_id * 4)) {
+                Some(session) => {
+                    if session.datagrams.len() < MAX_BUFFERED_DATAGRAMS {
+                        session.datagrams.push_back(datagram.freeze());
+                        progress = true;
+                    }
+                }
+                None => debug!("dropping datagram of unknown session {}", quarter_id * 4),
+            }
+        }
+    }
+
+    /// Registers the current task to be woken, when the `Dispatcher` made progress.
+    fn wait(&mut self) {
+        if !self.waiting.iter().any(|t| t.will_notify_current()) {
+            self.waiting.push(task::current());
+        }
+    }
+}
+
+/// A shared handle to the `Dispatcher` of a `Connection`.
+///
+/// The `Stream`s of the `Connection` only wake the task that polled them last, so a dropped
+/// handle wakes all waiting tasks, to let one of them poll the `Dispatcher` again.
+#[derive(Clone)]
+pub(crate) struct Handle(Arc<Mutex<Dispatcher>>);
+
+impl Handle {
+    pub fn lock(&self) -> MutexGuard<Dispatcher> {
+        self.0.lock().unwrap()
+    }
+
+    /// Polls the `Dispatcher` and calls `f` with it. If `f` returns `None`, the current task is
+    /// woken, when the `Dispatcher` made progress.
+    pub fn poll<R, F>(&self, f: F) -> Poll<R, Error>
+    where
+        F: FnOnce(&mut Dispatcher) -> Result<Option<R>, Error>,
+    {
+        let mut dispatcher = self.lock();
+        dispatcher.poll()?;
+
+        match f(&mut *dispatcher)? {
+            Some(res) => Ok(Ready(res)),
+            None => {
+                dispatcher.wait();
+                Ok(NotReady)
+            }
+        }
+    }
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Ok(mut dispatcher) = self.0.lock() {
+            dispatcher.waiting.drain(..).for_each(|t| t.notify());
+        }
+    }
+}
+
+/// A WebTransport session, see `Session::connect` and `Server`.
+///
+/// Dropping the `Session` closes it without an error code, see `close`.
+pub struct Session {
+    id: u64,
+    new_stream: NewStreamHandle,
+    datagrams: DatagramSender,
+    handle: Handle,
+}
+
+impl Session {
+    pub(crate) fn new(id: u64, handle: Handle) -> Session {
+        let (new_stream, datagrams) = {
+            let dispatcher = handle.lock();
+            (dispatcher.new_stream_handle(), dispatcher.datagram_sender())
+        };
+
+        Session {
+            id,
+            new_stream,
+            datagrams,
+            handle,
+        }
+    }
+
+    /// Returns the id of this `Session`, the id of its `CONNECT` stream.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Opens a new bidirectional `SessionStream`.
+    pub fn open_stream(&mut self) -> OpenStream {
+        OpenStream::new(self.new_stream.new_bidirectional_stream(), WEBTRANSPORT_STREAM, self.id)
+    }
+
+    /// Opens a new unidirectional `SessionStream`.
+    pub fn open_uni_stream(&mut self) -> OpenStream {
+        OpenStream::new(
+            self.new_stream.new_unidirectional_stream(),
+            WEBTRANSPORT_UNI_STREAM,
+            self.id,
+        )
+    }
+
+    /// Accepts the next bidirectional or unidirectional `SessionStream` the peer opened.
+    /// Fails with `ErrorKind::SessionClosed`, after the `Session` was closed.
+    pub fn accept_stream(&self) -> AcceptStream {
+        AcceptStream {
+            id: self.id,
+            handle: self.handle.clone(),
+        }
+    }
+
+    /// Sends the given data in an unreliable datagram.
+    ///
+    /// Fails with `ErrorKind::DatagramsUnsupported`, if one of the peers did not enable
+    /// datagrams(`Config::enable_datagrams`), and with `ErrorKind::DatagramTooLarge`, if the
+    /// data exceeds `max_datagram_size`.
+    pub fn send_datagram(&self, data: &[u8]) -> Result<(), Error> {
+        let max = self.max_datagram_size();
+        if max > 0 && data.len() > max {
+            return Err(ErrorKind::DatagramTooLarge(data.len(), max).into());
+        }
+
+        let mut datagram = Vec::with_capacity(data.len() + 8);
+        varint::encode(self.id / 4, &mut datagram);
+        datagram.extend_from_slice(data);
+
+        self.datagrams.send(datagram.into())
+    }
+
+    /// Returns the maximum payload of a datagram, see `Connection::max_datagram_size`.
+    pub fn max_datagram_size(&self) -> usize {
+        let mut prefix = Vec::new();
+        varint::encode(self.id / 4, &mut prefix);
+
+        self.datagrams.max_size().saturating_sub(prefix.len())
+    }
+
+    /// Receives the next datagram of this `Session`.
+    /// Fails with `ErrorKind::SessionClosed`, after the `Session` was closed.
+    pub fn recv_datagram(&self) -> RecvDatagram {
+        RecvDatagram {
+            id: self.id,
+            handle: self.handle.clone(),
+        }
+    }
+
+    /// Closes this `Session` with the given error code and reason.
+    pub fn close(self, code: u32, reason: &str) -> CloseSession {
+        let inner = match self.handle.lock().remove_session(self.id) {
+            Some(connect) => Some(SendMessage::new(connect, encode_close(code, reason))),
+            None => None,
+        };
+
+        CloseSession { inner }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Dropping the `CONNECT` stream closes the `Session`.
+        self.handle.lock().remove_session(self.id);
+    }
+}
+
+/// Resolves to a new `SessionStream`, after the prefix of the `Session` was sent.
+pub struct OpenStream {
+    new_stream: NewStreamFuture,
+    prefix: Option<Bytes>,
+    stream: Option<Stream>,
+}
+
+impl OpenStream {
+    fn new(new_stream: NewStreamFuture, signal: u64, session: u64) -> OpenStream {
+        let mut prefix = Vec::new();
+        varint::encode(signal, &mut prefix);
+        varint::encode(session, &mut prefix);
+
+        OpenStream {
+            new_stream,
+            prefix: Some(prefix.into()),
+            stream: None,
+        }
+    }
+}
+
+impl Future for OpenStream {
+    type Item = SessionStream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => try_ready!(self.new_stream.poll()),
+        };
+
+        if let Some(prefix) = self.prefix.take() {
+            if let AsyncSink::NotReady(prefix) = stream.start_send(prefix)? {
+                self.prefix = Some(prefix);
+                self.stream = Some(stream);
+                return Ok(NotReady);
+            }
+        }
+
+        if stream.poll_complete()?.is_not_ready() {
+            self.stream = Some(stream);
+            return Ok(NotReady);
+        }
+
+        Ok(Ready(SessionStream::new(stream, BytesMut::new())))
+    }
+}
+
+/// Resolves to the next `SessionStream` the peer opened, see `Session::accept_stream`.
+pub struct AcceptStream {
+    id: u64,
+    handle: Handle,
+}
+
+impl Future for AcceptStream {
+    type Item = SessionStream;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let id = self.id;
+
+        self.handle.poll(|dispatcher| match dispatcher.sessions.get_mut(&id) {
+            Some(session) => match session.streams.pop_front() {
+                Some(stream) => Ok(Some(stream)),
+                None => session.error_if_closed().map(|_| None),
+            },
+            None => Err(ErrorKind::SessionClosed {
+                code: 0,
+                reason: String::new(),
+            }
+            .into()),
+        })
+    }
+}
+
+/// Resolves to the next datagram of a `Session`, see `Session::recv_datagram`.
+pub struct RecvDatagram {
+    id: u64,
+    handle: Handle,
+}
+
+impl Future for RecvDatagram {
+    type Item = Bytes;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let id = self.id;
+        self.handle.poll(|dispatcher| dispatcher.take_datagram(id))
+    }
+}
+
+/// Resolves, after the `CLOSE_WEBTRANSPORT_SESSION` capsule was sent, see `Session::close`.
+pub struct CloseSession {
+    /// `None`, if the `Session` was already closed.
+    inner: Option<SendMessage>,
+}
+
+impl Future for CloseSession {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut inner) = self.inner {
+            try_ready!(inner.poll());
+        }
+
+        Ok(Ready(()))
+    }
+}
+
+/// A `Stream` of a `Session`. The prefix that associates the `Stream` with its `Session` is
+/// not part of the received data.
+pub struct SessionStream {
+    stream: Stream,
+    /// The data that was received together with the prefix.
+    buffered: Option<BytesMut>,
+}
+
+impl SessionStream {
+    fn new(stream: Stream, buffered: BytesMut) -> SessionStream {
+        SessionStream {
+            stream,
+            buffered: Some(buffered).filter(|b| !b.is_empty()),
+        }
+    }
+
+    /// Returns the id of the underlying `Stream`.
+    pub fn id(&self) -> stream::Id {
+        self.stream.id()
+    }
+
+    /// Returns the type of the underlying `Stream`.
+    pub fn get_type(&self) -> stream::Type {
+        self.stream.get_type()
+    }
+
+    /// Returns a reference to the underlying `Stream`.
+    pub fn get_ref(&self) -> &Stream {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying `Stream`.
+    /// Data received by the underlying `Stream` bypasses the buffered data of this
+    /// `SessionStream`.
+    pub fn get_mut(&mut self) -> &mut Stream {
+        &mut self.stream
+    }
+
+    /// Finishes sending, see `Stream::finish`.
+    pub fn finish(&mut self) -> Result<(), Error> {
+        self.stream.finish()
+    }
+
+    /// Resets the `Stream`, see `Stream::reset`.
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.stream.reset()
+    }
+
+    /// Asks the peer to stop sending, see `Stream::stop_sending`.
+    pub fn stop_sending(&mut self, error_code: u64) -> Result<(), Error> {
+        self.stream.stop_sending(error_code)
+    }
+}
+
+impl FStream for SessionStream {
+    type Item = BytesMut;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        match self.buffered.take() {
+            Some(data) => Ok(Ready(Some(data))),
+            None => self.stream.poll(),
+        }
+    }
+}
+
+impl Sink for SessionStream {
+    type SinkItem = Bytes;
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.stream.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.stream.poll_complete()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_capsule_is_decoded() {
+        let mut buf = BytesMut::new();
+        match Frame::decode(&mut BytesMut::from(&encode_close(7, "bye")[..])).unwrap() {
+            Some(Frame::Data(data)) => buf.extend_from_slice(&data),
+            frame => panic!("unexpected frame {:?}", frame),
+        }
+
+        // An incomplete capsule is not decoded.
+        let mut incomplete = BytesMut::from(&buf[..buf.len() - 1]);
+        assert_eq!(None, decode_capsule(&mut incomplete).unwrap());
+
+        let (ctype, payload) = decode_capsule(&mut buf).unwrap().unwrap();
+        assert_eq!(CLOSE_WEBTRANSPORT_SESSION, ctype);
+        assert_eq!(&b"\x00\x00\x00\x07bye"[..], &payload[..]);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn capsule_exceeding_maximum_size_is_rejected() {
+        let mut buf = Vec::new();
+        varint::encode(CLOSE_WEBTRANSPORT_SESSION, &mut buf);
+        varint::encode(1 << 40, &mut buf);
+
+        assert!(decode_capsule(&mut BytesMut::from(&buf[..])).is_err());
+    }
+}
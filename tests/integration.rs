@@ -1,38 +1,59 @@
 extern crate bytes;
 extern crate futures;
+#[cfg(feature = "std-future")]
+extern crate futures03;
 extern crate openssl;
 extern crate picoquic;
 extern crate timebomb;
 extern crate tokio;
+#[cfg(feature = "std-future")]
+extern crate tokio1;
 
 use picoquic::{
-    default_verify_certificate, Config, Connection, ConnectionId, ConnectionType, Context, Error,
-    ErrorKind, FileFormat, NewStreamFuture, NewStreamHandle, SType, Stream, VerifyCertificate,
+    default_verify_certificate, h3, masque, webtransport, AcceptDecision, AsyncVerifyCertificate,
+    Config, CongestionAlgorithm, CongestionController, Connection, ConnectionConfig,
+    ConnectionEvent, ConnectionType, Context, ContextBuilder, ContextDriver, CryptoBackend, Error,
+    ErrorKind, FileFormat, HandshakeOutcome, HandshakeRateLimit, HandshakeRecord,
+    InMemoryTransport, IncomingConnectionInfo, LinkConditions, LongPacketType, Metrics,
+    NewStreamFuture, NewStreamHandle, PacketDirection, PacketHeader, PacketMetadata, PathInfo,
+    PauseMode, PinnedVerifier, Priority, QuicVersion, RateLimitAction, Role, SType, Socket, Spawn,
+    Stream, TransferProgress, VerifyCertificate, VerifyContext,
 };
 
 use std::{
-    fmt,
+    env, fmt,
+    fs::{self, File},
     net::SocketAddr,
     sync::{
         atomic::{AtomicUsize, Ordering},
-        mpsc::channel,
-        Arc,
+        mpsc::{channel, Receiver},
+        Arc, Mutex,
     },
     thread,
     time::{Duration, Instant},
 };
 
+#[cfg(feature = "bench")]
+use picoquic::Bench;
+#[cfg(feature = "bincode-codec")]
+use picoquic::{Bincode, TypedStream};
+
 use futures::{sync::mpsc::unbounded, Future, Sink, Stream as FStream};
 
 use bytes::{Bytes, BytesMut};
 
 use openssl::{
     error::ErrorStack,
+    pkey::PKey,
     stack::StackRef,
     x509::{store::X509StoreBuilder, X509Ref, X509},
 };
 
-use tokio::{runtime::Runtime, timer::Interval};
+use tokio::{
+    net::UdpSocket,
+    runtime::{current_thread, Runtime},
+    timer::{Delay, Interval, Timeout},
+};
 
 const TEST_SERVER_NAME: &str = "picoquic.test";
 
@@ -141,6 +162,13 @@ fn client_connects_creates_stream_and_sends_data<F, T, C>(
     let stream = evt_loop.block_on(new_stream).expect("creates stream");
     check_type(&stream);
 
+    let info = stream.info();
+    assert_eq!(info.id, stream.id());
+    assert_eq!(info.initiator, Role::Client);
+    assert!(info.locally_initiated);
+    assert!(!info.fin_received);
+    assert!(!info.reset_received);
+
     assert_eq!(
         stream.local_addr(),
         ([0, 0, 0, 0], context.local_addr().port()).into()
@@ -238,21 +266,83 @@ fn empty_stream_reset_and_no_more_send_on_drop_inner() {
         .block_on(stream.send(Bytes::from(send_data)))
         .unwrap();
 
-    let (result, stream) = evt_loop
-        .block_on(stream.into_future().map_err(|(e, _)| e))
-        .unwrap();
+    let (err, stream) = match evt_loop.block_on(stream.into_future()) {
+        Err((err, stream)) => (err, stream),
+        Ok(_) => panic!("expected the stream to be reset"),
+    };
 
-    assert_eq!(result, None);
+    assert!(is_stream_reset(&err));
     assert!(stream.is_reset());
     // Send data in a loop, as `stop_sending` is processed after `reset` and to prevent race
     // conditions, we need to try sending multiple times.
-    assert!(evt_loop
-        .block_on(futures::lazy(move || stream.send_all(
-            Interval::new(Instant::now(), Duration::from_millis(100))
-                .map_err(|_| Error::from(ErrorKind::Unknown))
-                .map(|_| Bytes::from("error"))
-        )))
-        .is_err());
+    let err = evt_loop
+        .block_on(futures::lazy(move || {
+            stream.send_all(
+                Interval::new(Instant::now(), Duration::from_millis(100))
+                    .map_err(|_| Error::from(ErrorKind::Unknown))
+                    .map(|_| Bytes::from("error")),
+            )
+        }))
+        .err()
+        .expect("sending fails");
+    assert!(is_stopped_by_peer(&err));
+}
+
+fn is_stopped_by_peer(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::StopSending(0) => true,
+        _ => false,
+    }
+}
+
+fn is_stream_reset(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::StreamReset(_) => true,
+        _ => false,
+    }
+}
+
+fn is_stream_closed(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::StreamClosed => true,
+        _ => false,
+    }
+}
+
+#[test]
+fn send_after_reset_fails_with_stream_closed() {
+    timebomb::timeout_ms(send_after_reset_fails_with_stream_closed_inner, 10000);
+}
+
+fn send_after_reset_fails_with_stream_closed_inner() {
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            tokio::spawn(c.for_each(move |s| s.for_each(|_| Ok(()))).map_err(|_| ()));
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .block_on(
+            con.new_bidirectional_stream()
+                .and_then(|s| s.send(Bytes::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    stream.reset().expect("resets stream");
+
+    let err = evt_loop
+        .block_on(stream.send(Bytes::from("after reset")))
+        .err()
+        .expect("sending fails");
+    assert!(is_stream_closed(&err));
 }
 
 #[test]
@@ -304,6 +394,114 @@ fn none_empty_stream_set_fin_bit_on_drop_inner() {
     assert!(!stream.is_reset());
 }
 
+#[test]
+fn finished_stream_rejects_sends_and_still_receives() {
+    timebomb::timeout_ms(finished_stream_rejects_sends_and_still_receives_inner, 10000);
+}
+
+fn finished_stream_rejects_sends_and_still_receives_inner() {
+    // The server answers, after the client finished sending.
+    let addr = start_server_thread_with_default_config(|c| {
+        c.for_each(|c| {
+            tokio::spawn(
+                c.for_each(|s| {
+                    let (send, recv) = s.split();
+                    tokio::spawn(
+                        recv.concat2()
+                            .and_then(move |data| send.send(data.freeze()))
+                            .map(|_| ())
+                            .map_err(|_| ()),
+                    );
+                    Ok(())
+                })
+                .map_err(|_| ()),
+            );
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let mut stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    stream.finish().expect("finishes stream");
+    assert!(stream.is_finished());
+    assert!(is_stream_closed(&stream.finish().unwrap_err()));
+
+    let err = stream.start_send(Bytes::from("too late")).unwrap_err();
+    assert!(is_stream_closed(&err));
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+}
+
+#[test]
+fn send_on_incoming_unidirectional_stream_fails() {
+    timebomb::timeout_ms(send_on_incoming_unidirectional_stream_fails_inner, 10000);
+}
+
+fn send_on_incoming_unidirectional_stream_fails_inner() {
+    let (send, recv) = unbounded();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    s.send(Bytes::from("hello client")).then(move |r| {
+                        let _ = send.unbounded_send(r.err().map(|e| is_receive_only(&e)));
+                        Ok(())
+                    })
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let _stream = evt_loop
+        .block_on(
+            con.new_unidirectional_stream()
+                .and_then(|s| s.send(Bytes::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    assert_eq!(
+        Some(true),
+        evt_loop.block_on(recv.into_future()).unwrap().0.unwrap()
+    );
+}
+
+fn is_receive_only(err: &Error) -> bool {
+    match err.kind() {
+        ErrorKind::ReceiveOnlyStream => true,
+        _ => false,
+    }
+}
+
 fn start_server_that_sends_received_data_back<C>(create_config: C) -> SocketAddr
 where
     C: 'static + Send + FnOnce() -> Config,
@@ -449,6 +647,70 @@ fn open_bi_stream_to_server_and_server_creates_new_bi_stream_to_answer() {
     });
 }
 
+#[test]
+fn incoming_streams_are_split_by_type() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send_bi = send.clone();
+            let send_uni = send.clone();
+
+            let bidirectional = c.incoming_bidirectional().for_each(move |s| {
+                let _ = send_bi.send((SType::Bidirectional, s.get_type()));
+                Ok(())
+            });
+            let unidirectional = c.incoming_unidirectional().for_each(move |s| {
+                let _ = send_uni.send((SType::Unidirectional, s.get_type()));
+                Ok(())
+            });
+
+            tokio::spawn(
+                bidirectional
+                    .join(unidirectional)
+                    .map(move |_| drop(c))
+                    .map_err(|_| ()),
+            );
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut streams = Vec::new();
+    for uni in &[true, false, true] {
+        let stream = if *uni {
+            con.new_unidirectional_stream()
+        } else {
+            con.new_bidirectional_stream()
+        };
+        let stream = evt_loop.block_on(stream).expect("creates stream");
+        streams.push(
+            evt_loop
+                .block_on(stream.send(Bytes::from("hello server")))
+                .expect("sends data"),
+        );
+    }
+
+    let mut received = (0..3)
+        .map(|_| recv.recv_timeout(Duration::from_secs(5)).expect("receives stream"))
+        .collect::<Vec<_>>();
+    received.sort_by_key(|(expected, _)| *expected == SType::Bidirectional);
+
+    assert_eq!(
+        vec![
+            (SType::Unidirectional, SType::Unidirectional),
+            (SType::Unidirectional, SType::Unidirectional),
+            (SType::Bidirectional, SType::Bidirectional),
+        ],
+        received
+    );
+}
+
 #[derive(Clone)]
 struct VerifyCertificateImpl {
     counter: Arc<AtomicUsize>,
@@ -473,11 +735,15 @@ impl VerifyCertificateImpl {
 impl VerifyCertificate for VerifyCertificateImpl {
     fn verify(
         &mut self,
-        _: ConnectionId,
-        _: ConnectionType,
+        context: &VerifyContext,
         cert: &X509Ref,
         chain: &StackRef<X509>,
     ) -> Result<bool, ErrorStack> {
+        assert!(context.peer_addr.ip().is_loopback());
+        if context.role == Role::Client {
+            assert_eq!(Some(TEST_SERVER_NAME), context.server_name.as_ref().map(|s| &s[..]));
+        }
+
         let ca_cert = include_bytes!("certs/ca.crt");
         let ca_cert = X509::from_pem(ca_cert)?;
 
@@ -485,7 +751,7 @@ impl VerifyCertificate for VerifyCertificateImpl {
         store_bldr.add_cert(ca_cert).unwrap();
         let store = store_bldr.build();
 
-        let res = default_verify_certificate(cert, chain, &store);
+        let res = default_verify_certificate(context, cert, chain, &store);
 
         self.increment();
 
@@ -493,6 +759,30 @@ impl VerifyCertificate for VerifyCertificateImpl {
     }
 }
 
+/// Verifies the certificate with the `VerifyCertificateImpl` after a delay, like a revocation
+/// lookup would do.
+struct DelayedVerifyCertificate {
+    inner: VerifyCertificateImpl,
+    accept: bool,
+}
+
+impl AsyncVerifyCertificate for DelayedVerifyCertificate {
+    fn verify(
+        &mut self,
+        context: &VerifyContext,
+        cert: &X509Ref,
+        chain: &StackRef<X509>,
+    ) -> Box<dyn Future<Item = bool, Error = ErrorStack>> {
+        let accept = self.accept;
+        let res = self
+            .inner
+            .verify(context, cert, chain)
+            .map(|verified| verified && accept);
+
+        Box::new(Delay::new(Instant::now() + Duration::from_millis(200)).then(move |_| res))
+    }
+}
+
 fn verify_certificate_callback_is_called_and_certificate_is_verified(
     client_cert: String,
     client_key: String,
@@ -560,16 +850,3658 @@ fn verify_certificate_callback_is_called_and_certificate_verification_fails() {
 }
 
 #[test]
-fn set_certificate_and_key_from_memory() {
-    client_connects_creates_bidirectional_stream_and_sends_data_impl(get_test_config(), || {
-        let cert = include_bytes!("certs/device.test.crt");
-        let key = include_bytes!("certs/device.key");
+fn mutual_tls_exposes_peer_certificates() {
+    let device_cert = X509::from_pem(include_bytes!("certs/device.test.crt"))
+        .and_then(|c| c.to_der())
+        .unwrap();
+    let (send, recv) = channel();
 
-        let mut config = get_test_config();
-        config.certificate_chain_filename = None;
-        config.private_key_filename = None;
-        config.set_certificate_chain(vec![cert.to_vec()], FileFormat::PEM);
-        config.set_private_key(key.to_vec(), FileFormat::PEM);
-        config
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_verify_certificate_handler(VerifyCertificateImpl::new());
+            config.enable_client_authentication();
+            config
+        },
+        move |c| {
+            c.for_each(move |c| {
+                let send = send.clone();
+                tokio::spawn(
+                    c.into_future()
+                        .map(move |(_, c)| {
+                            let certs = c
+                                .peer_certificates()
+                                .iter()
+                                .map(|c| c.to_der().unwrap())
+                                .collect::<Vec<_>>();
+                            let _ = send.send(certs);
+                        })
+                        .map_err(|_| ()),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let mut client_config = get_test_config();
+    client_config.set_verify_certificate_handler(VerifyCertificateImpl::new());
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(client_config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let server_certs = con.peer_certificates();
+    assert_eq!(device_cert, server_certs[0].to_der().unwrap());
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let _stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    let client_certs = recv
+        .recv_timeout(Duration::from_secs(10))
+        .expect("server reports the client certificates");
+    assert_eq!(device_cert, client_certs[0]);
+}
+
+#[test]
+fn server_presents_certificate_of_sni_identity() {
+    let device_cert = X509::from_pem(include_bytes!("certs/device.test.crt")).unwrap();
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            // The clients do not trust the default certificate of the server.
+            config.set_certificate_chain_filename(format!(
+                "{}device.invalid.crt",
+                get_test_certs_path()
+            ));
+            config.add_sni_identity(
+                "*.test",
+                vec![include_bytes!("certs/device.test.crt").to_vec()],
+                include_bytes!("certs/device.key").to_vec(),
+                FileFormat::PEM,
+            );
+            config
+        },
+        move |c| {
+            c.for_each(move |c| {
+                let _ = send.send(c.sni().map(String::from));
+                tokio::spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(
+        device_cert.to_der().unwrap(),
+        con.peer_certificates()[0].to_der().unwrap()
+    );
+    assert_eq!(Some(TEST_SERVER_NAME), con.sni());
+    assert_eq!(
+        Some(TEST_SERVER_NAME.to_string()),
+        recv.recv_timeout(Duration::from_secs(10)).expect("server reports the SNI")
+    );
+}
+
+#[test]
+fn handshake_completed_resolves_on_client_and_server() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(c.handshake_completed().then(move |res| {
+                let _ = send.send((res.is_ok(), c.is_handshake_complete()));
+                c.for_each(|_| Ok(())).map_err(|_| ())
+            }));
+            Ok(())
+        })
     });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    evt_loop
+        .block_on(con.handshake_completed())
+        .expect("handshake completes");
+    assert!(con.is_handshake_complete());
+
+    assert_eq!(
+        (true, true),
+        recv.recv_timeout(Duration::from_secs(10)).expect("server reports the handshake")
+    );
+}
+
+#[test]
+fn connection_exposes_peer_certificate_without_verify_handler() {
+    let device_cert = X509::from_pem(include_bytes!("certs/device.test.crt"))
+        .and_then(|c| c.to_der())
+        .unwrap();
+    let addr = start_server_thread_with_default_config(|c| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let cert = con.peer_certificate().expect("server presented a certificate");
+    assert_eq!(device_cert, cert.to_der().unwrap());
+    assert!(!con.peer_certificates().is_empty());
+}
+
+#[test]
+fn certificate_for_other_server_name_is_rejected() {
+    let addr = start_server_thread_with_default_config(|c| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    // The certificate is issued by the trusted CA, but for `TEST_SERVER_NAME`.
+    let err = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), "other.test"))
+        .err()
+        .expect("connection fails");
+
+    match err.kind() {
+        ErrorKind::TLSAlert(alert) => {
+            assert_eq!(46, alert.code);
+            assert!(alert.sent);
+        }
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn async_verify_certificate_handler_delays_connection() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let verifier = VerifyCertificateImpl::new();
+    let mut config = get_test_config();
+    config.set_async_verify_certificate_handler(DelayedVerifyCertificate {
+        inner: verifier.clone(),
+        accept: true,
+    });
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let start = Instant::now();
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert_eq!(1, verifier.get());
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    let answer = evt_loop
+        .block_on(
+            stream
+                .into_future()
+                .map(|(m, _)| m.unwrap())
+                .map_err(|(e, _)| e),
+        )
+        .unwrap();
+    assert_eq!(&b"hello server"[..], &answer[..]);
+}
+
+#[test]
+fn async_verify_certificate_handler_rejects_connection() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut config = get_test_config();
+    config.set_async_verify_certificate_handler(DelayedVerifyCertificate {
+        inner: VerifyCertificateImpl::new(),
+        accept: false,
+    });
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let err = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .err()
+        .expect("connection fails");
+
+    match err.kind() {
+        ErrorKind::TLSAlert(alert) => {
+            assert_eq!(46, alert.code);
+            assert!(alert.sent);
+        }
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+fn spki_pin(pem: &[u8]) -> [u8; 32] {
+    PinnedVerifier::spki_pin(&X509::from_pem(pem).unwrap()).unwrap()
+}
+
+#[test]
+fn pinned_verifier_accepts_pinned_certificate() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let pin = spki_pin(include_bytes!("certs/device.test.crt"));
+
+    let mut config = Config::new();
+    config.set_verify_certificate_handler(PinnedVerifier::new(vec![pin]));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    assert!(evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .is_ok());
+}
+
+#[test]
+fn pinned_verifier_rejects_other_certificates() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let pin = spki_pin(include_bytes!("certs/ca.crt"));
+
+    let mut config = Config::new();
+    config.set_verify_certificate_handler(PinnedVerifier::new(vec![pin]));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    assert!(evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .is_err());
+}
+
+#[cfg(feature = "self-signed")]
+#[test]
+fn client_connects_to_pinned_self_signed_server() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = Config::new();
+            config.generate_self_signed(TEST_SERVER_NAME);
+            config
+        },
+        move |c| {
+            let _ = send.send(c.self_signed_certificate().cloned());
+            c.for_each(|_| Ok(()))
+        },
+    );
+
+    let cert = recv
+        .recv()
+        .unwrap()
+        .expect("server generated a certificate");
+    let pin = PinnedVerifier::spki_pin(&cert).unwrap();
+
+    let mut config = Config::new();
+    config.set_verify_certificate_handler(PinnedVerifier::new(vec![pin]));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(cert.to_der().unwrap(), con.peer_certificate().unwrap().to_der().unwrap());
+}
+
+#[test]
+fn connection_uses_own_verify_certificate_handler() {
+    let send_data = "hello server";
+    let call_counter = VerifyCertificateImpl::new();
+
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut config = ConnectionConfig::new();
+    config.set_verify_certificate_handler(call_counter.clone());
+
+    let mut con = evt_loop
+        .block_on(context.new_connection_with_config(
+            ([127, 0, 0, 1], addr.port()).into(),
+            TEST_SERVER_NAME,
+            config,
+        ))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    evt_loop
+        .block_on(stream.send(Bytes::from(send_data)))
+        .unwrap();
+
+    assert_eq!(call_counter.get(), 1);
+
+    // A second connection without an own handler uses the verification of the `Context`
+    evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(call_counter.get(), 1);
+}
+
+#[test]
+fn set_certificate_and_key_from_memory() {
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(get_test_config(), || {
+        let cert = include_bytes!("certs/device.test.crt");
+        let key = include_bytes!("certs/device.key");
+
+        let mut config = get_test_config();
+        config.certificate_chain_filename = None;
+        config.private_key_filename = None;
+        config.set_certificate_chain(vec![cert.to_vec()], FileFormat::PEM);
+        config.set_private_key(key.to_vec(), FileFormat::PEM);
+        config
+    });
+}
+
+#[test]
+fn set_certificate_chain_and_key_from_der() {
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(get_test_config(), || {
+        let cert = X509::from_pem(include_bytes!("certs/device.test.crt")).unwrap();
+        let key = PKey::private_key_from_pem(include_bytes!("certs/device.key")).unwrap();
+
+        let mut config = get_test_config();
+        config.certificate_chain_filename = None;
+        config.private_key_filename = None;
+        config.set_certificate_chain_from_der(vec![cert.to_der().unwrap()]);
+        config.set_private_key_from_der(key.private_key_to_der().unwrap());
+        config
+    });
+}
+
+#[test]
+fn set_certificate_chain_from_pem_bundle() {
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(get_test_config(), || {
+        let chain = [
+            &include_bytes!("certs/device.test.crt")[..],
+            &include_bytes!("certs/ca.crt")[..],
+        ]
+        .concat();
+
+        let mut config = get_test_config();
+        config.certificate_chain_filename = None;
+        config.private_key_filename = None;
+        config.set_certificate_chain_from_pem(chain);
+        config.set_private_key_from_pem(include_bytes!("certs/device.key").to_vec());
+        config
+    });
+}
+
+#[test]
+fn client_and_server_use_callback_driven_send() {
+    let mut client_config = get_test_config();
+    client_config.enable_callback_driven_send();
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        let mut config = get_test_config();
+        config.enable_callback_driven_send();
+        config
+    });
+}
+
+#[test]
+fn client_and_server_use_different_recv_buffer_chunk_sizes() {
+    let mut client_config = get_test_config();
+    client_config.set_recv_buffer_chunk_size(Some(16));
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        let mut config = get_test_config();
+        config.set_recv_buffer_chunk_size(None);
+        config
+    });
+}
+
+#[test]
+fn big_writes_without_copy_keep_the_order_of_all_writes() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    s.concat2().map(move |data| {
+                        let _ = send.send(data.to_vec());
+                    })
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let mut config = get_test_config();
+    config.set_zero_copy_send_threshold(Some(1024));
+    config.enable_write_coalescing(512);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .block_on(con.new_unidirectional_stream())
+        .expect("creates stream");
+
+    let mut expected = Vec::new();
+    for i in 0..64u32 {
+        // Mix small writes that are copied or coalesced with big writes that are kept.
+        let len = if i % 3 == 0 { 256 * 1024 } else { 100 };
+        let data = vec![i as u8; len];
+        expected.extend_from_slice(&data);
+
+        stream = evt_loop.block_on(stream.send(Bytes::from(data))).unwrap();
+    }
+    drop(stream);
+
+    assert_eq!(
+        expected,
+        recv.recv_timeout(Duration::from_secs(10)).expect("receives stream")
+    );
+}
+
+#[test]
+fn urgent_stream_is_sent_before_bulk_stream() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    s.concat2().map(move |data| {
+                        let _ = send.send(data.len());
+                    })
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let mut config = get_test_config();
+    config.enable_callback_driven_send();
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut bulk = evt_loop
+        .block_on(con.new_unidirectional_stream())
+        .expect("creates stream");
+    let mut urgent = evt_loop
+        .block_on(con.new_unidirectional_stream())
+        .expect("creates stream");
+
+    bulk.set_priority(Priority::new(7, true)).unwrap();
+    urgent.set_priority(Priority::parse("u=0")).unwrap();
+    assert_eq!(Priority::new(0, false), urgent.priority());
+
+    let bulk = evt_loop
+        .block_on(bulk.send(Bytes::from(vec![1u8; 4 * 1024 * 1024])))
+        .unwrap();
+    let urgent = evt_loop
+        .block_on(urgent.send(Bytes::from("urgent")))
+        .unwrap();
+    drop(urgent);
+    drop(bulk);
+
+    let timeout = Duration::from_secs(10);
+    assert_eq!(6, recv.recv_timeout(timeout).expect("receives urgent stream"));
+    assert_eq!(4 * 1024 * 1024, recv.recv_timeout(timeout).expect("receives bulk stream"));
+}
+
+#[test]
+fn new_streams_use_default_stream_priority() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut config = get_test_config();
+    config.set_default_stream_priority(Priority::new(1, true));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    assert_eq!(Priority::new(1, true), stream.priority());
+
+    stream.set_urgency(5).unwrap();
+    assert_eq!(Priority::new(5, true), stream.priority());
+
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+}
+
+#[test]
+fn stream_send_waits_for_send_backlog_below_watermark() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    s.concat2().map(move |data| {
+                        let _ = send.send(data.len());
+                    })
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let max_backlog = 64 * 1024;
+    let mut config = get_test_config();
+    config.set_max_send_backlog(max_backlog);
+    config.set_max_pending_send_messages(4);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .block_on(con.new_unidirectional_stream())
+        .expect("creates stream");
+
+    for _ in 0..32 {
+        stream = evt_loop
+            .block_on(stream.send(Bytes::from(vec![1u8; 32 * 1024])))
+            .unwrap();
+        assert!(stream.send_backlog() < max_backlog);
+    }
+    drop(stream);
+
+    assert_eq!(
+        32 * 32 * 1024,
+        recv.recv_timeout(Duration::from_secs(10)).expect("receives stream")
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn client_and_server_use_socket_options() {
+    let mut client_config = get_test_config();
+    client_config.set_socket_send_buffer_size(1024 * 1024);
+    client_config.set_socket_recv_buffer_size(1024 * 1024);
+    client_config.set_ip_tos(0xb8);
+    client_config.set_ttl(16);
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        let mut config = get_test_config();
+        config.set_socket_recv_buffer_size(4 * 1024 * 1024);
+        config.set_ttl(32);
+        config.set_ipv6_only(false);
+        config
+    });
+}
+
+#[test]
+fn client_connects_to_server_with_stricter_amplification_limit() {
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(get_test_config(), || {
+        let mut config = get_test_config();
+        config.set_amplification_factor(2);
+        config
+    });
+}
+
+#[test]
+fn client_connects_to_server_with_stateless_retry() {
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(get_test_config(), || {
+        let mut config = get_test_config();
+        config.enable_stateless_retry(true);
+        config
+    });
+}
+
+#[test]
+fn client_and_server_with_configured_mtu_discovery() {
+    let mut client_config = get_test_config();
+    client_config.set_mtu_probes(vec![1400, 1450], Duration::from_secs(600));
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        let mut config = get_test_config();
+        config.disable_mtu_discovery();
+        config
+    });
+}
+
+#[test]
+fn client_and_server_with_jumbo_udp_payload_size() {
+    let create_config = || {
+        let mut config = get_test_config();
+        config.set_max_udp_payload_size(9000);
+        config
+    };
+
+    let addr = start_server_that_sends_received_data_back(create_config);
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(create_config());
+    assert_eq!(9000, context.receive_buffer_size());
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+}
+
+#[test]
+fn client_with_greased_version_connects_to_server() {
+    let mut client_config = get_test_config();
+    client_config.enable_version_greasing();
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        get_test_config()
+    });
+}
+
+#[test]
+fn client_with_cc_log_writes_log_file() {
+    let log_dir = env::temp_dir().join(format!("picoquic-cc-log-{}", std::process::id()));
+    let mut client_config = get_test_config();
+    client_config.enable_cc_log(&log_dir);
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        get_test_config()
+    });
+
+    assert!(fs::read_dir(&log_dir).expect("log dir exists").count() > 0);
+    let _ = fs::remove_dir_all(&log_dir);
+}
+
+#[test]
+fn client_with_qlog_writes_qlog_trace() {
+    let qlog_dir = env::temp_dir().join(format!("picoquic-qlog-{}", std::process::id()));
+    let mut client_config = get_test_config();
+    client_config.enable_qlog(&qlog_dir);
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        get_test_config()
+    });
+
+    let traces = fs::read_dir(&qlog_dir)
+        .expect("qlog dir exists")
+        .map(|e| e.expect("reads dir entry").path())
+        .collect::<Vec<_>>();
+    assert_eq!(1, traces.len());
+    assert!(traces[0].to_string_lossy().ends_with(".client.qlog"));
+
+    let trace = fs::read_to_string(&traces[0]).expect("reads qlog trace");
+    assert!(trace.starts_with("{\"qlog_version\":\"0.3\""));
+    assert!(trace.contains("\"transport:packet_sent\""));
+    assert!(trace.contains("\"recovery:metrics_updated\""));
+    assert!(trace.contains("\"transport:stream_data_moved\""));
+    let _ = fs::remove_dir_all(&qlog_dir);
+}
+
+#[test]
+fn client_with_key_log_file_writes_tls_secrets() {
+    let key_log_file = env::temp_dir().join(format!("picoquic-keylog-{}", std::process::id()));
+    let mut client_config = get_test_config();
+    client_config.set_key_log_file(&key_log_file);
+
+    client_connects_creates_bidirectional_stream_and_sends_data_impl(client_config, || {
+        get_test_config()
+    });
+
+    let key_log = fs::read_to_string(&key_log_file).expect("reads key log file");
+    assert!(key_log.contains("CLIENT_HANDSHAKE_TRAFFIC_SECRET "));
+    assert!(key_log.contains("CLIENT_TRAFFIC_SECRET_0 "));
+    let _ = fs::remove_file(&key_log_file);
+}
+
+#[test]
+fn client_connects_with_connection_config() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut config = ConnectionConfig::new();
+    config.enable_keep_alive(Duration::from_secs(1));
+    config.set_idle_timeout(Duration::from_secs(30));
+
+    let mut con = evt_loop
+        .block_on(context.new_connection_with_config(
+            ([127, 0, 0, 1], addr.port()).into(),
+            TEST_SERVER_NAME,
+            config,
+        ))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+}
+
+#[test]
+fn client_only_context_connects_and_does_not_accept_connections() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+
+    // The client does not need a certificate.
+    let mut config = Config::new();
+    config.set_root_certificate_filename(format!("{}ca.crt", get_test_certs_path()));
+    let mut client = Context::new_client(evt_loop.executor(), config).expect("creates context");
+    assert_ne!(0, client.local_addr().port());
+
+    let mut con = evt_loop
+        .block_on(client.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+
+    let (mut other, mut other_evt_loop) = create_context_and_evt_loop_with_default_config();
+    let probe = other.new_connection(
+        ([127, 0, 0, 1], client.local_addr().port()).into(),
+        TEST_SERVER_NAME,
+    );
+
+    assert!(other_evt_loop
+        .block_on(Timeout::new(probe, Duration::from_secs(2)))
+        .is_err());
+}
+
+#[test]
+fn client_with_ipv6_flow_label_and_traffic_class_connects() {
+    let (send, recv) = channel();
+
+    thread::spawn(move || {
+        let evt_loop = Runtime::new().expect("creates event loop");
+        let context = ContextBuilder::new(get_test_config())
+            .listen_address("[::1]:0".parse().unwrap())
+            .executor(evt_loop.executor())
+            .build()
+            .expect("creates quic context");
+        send.send(context.local_addr()).unwrap();
+
+        evt_loop
+            .block_on_all(context.for_each(|c| {
+                tokio::spawn(
+                    c.for_each(|s| {
+                        let (send, recv) = s.split();
+                        tokio::spawn(
+                            send.send_all(recv.map(BytesMut::freeze))
+                                .map(|_| ())
+                                .map_err(|_| ()),
+                        );
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+                );
+                Ok(())
+            }))
+            .expect("event loop spins on server context");
+    });
+
+    let server_addr = recv.recv().expect("receives server socket addr");
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+
+    let mut config = get_test_config();
+    // DSCP AF11
+    config.set_ipv6_traffic_class(0x28);
+    let mut context = ContextBuilder::new(config)
+        .listen_address("[::1]:0".parse().unwrap())
+        .executor(evt_loop.executor())
+        .build()
+        .expect("creates quic context");
+
+    let mut config = ConnectionConfig::new();
+    config.set_flow_label(0x1_2345);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection_with_config(server_addr, TEST_SERVER_NAME, config))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+}
+
+#[test]
+#[should_panic(expected = "invalid flow label")]
+fn flow_label_bigger_than_20_bits_panics() {
+    ConnectionConfig::new().set_flow_label(0x10_0000);
+}
+
+#[test]
+fn admission_handler_is_called_for_incoming_connection() {
+    let (send, recv) = channel();
+
+    let addr = start_server_that_sends_received_data_back(move || {
+        let mut config = get_test_config();
+        config.set_admission_handler(move |info: &IncomingConnectionInfo| {
+            let _ = send.send(info.clone());
+            let mut config = ConnectionConfig::new();
+            config.set_max_incoming_streams(1);
+            config
+        });
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+
+    let info = recv.recv().expect("admission handler is called");
+    assert_eq!(Some(TEST_SERVER_NAME.to_string()), info.server_name);
+    assert_eq!(context.local_addr().port(), info.peer_addr.port());
+
+    let params = con.peer_transport_parameters().expect("handshake is finished");
+    assert_eq!(1, params.initial_max_streams_bidi);
+    assert_eq!(1, params.initial_max_streams_uni);
+}
+
+/// Sends "hello server" on a new `Stream` of the given `Connection` and waits for the echo.
+fn send_and_recv_echo(con: &mut Connection, evt_loop: &mut Runtime) -> Result<BytesMut, Error> {
+    let stream = evt_loop.block_on(con.new_bidirectional_stream())?;
+    let stream = evt_loop.block_on(stream.send(Bytes::from("hello server")))?;
+
+    match evt_loop.block_on(stream.into_future().map_err(|(e, _)| e))?.0 {
+        Some(data) => Ok(data),
+        None => Err(ErrorKind::Disconnected.into()),
+    }
+}
+
+fn start_server_with_accept_filter(decision: AcceptDecision) -> (SocketAddr, Receiver<String>) {
+    let (send, recv) = channel();
+
+    let addr = start_server_that_sends_received_data_back(move || {
+        let mut config = get_test_config();
+        config.set_accept_filter(move |info: &IncomingConnectionInfo| {
+            let _ = send.send(info.server_name.clone().unwrap_or_default());
+            decision
+        });
+        config
+    });
+
+    (addr, recv)
+}
+
+#[test]
+fn accept_filter_accepts_connection() {
+    let (addr, recv) = start_server_with_accept_filter(AcceptDecision::Accept);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(
+        &b"hello server"[..],
+        &send_and_recv_echo(&mut con, &mut evt_loop).unwrap()[..]
+    );
+    assert_eq!(TEST_SERVER_NAME, recv.recv().expect("accept filter is called"));
+}
+
+#[test]
+fn accept_filter_rejects_connection_with_error_code() {
+    timebomb::timeout_ms(accept_filter_rejects_connection_with_error_code_inner, 10000);
+}
+
+fn accept_filter_rejects_connection_with_error_code_inner() {
+    let (addr, recv) = start_server_with_accept_filter(AcceptDecision::RejectWithCode(42));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let result = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .and_then(|mut con| send_and_recv_echo(&mut con, &mut evt_loop));
+
+    assert!(result.is_err());
+    assert_eq!(TEST_SERVER_NAME, recv.recv().expect("accept filter is called"));
+}
+
+#[test]
+fn accept_filter_drops_connection() {
+    timebomb::timeout_ms(accept_filter_drops_connection_inner, 10000);
+}
+
+fn accept_filter_drops_connection_inner() {
+    let (addr, _recv) = start_server_with_accept_filter(AcceptDecision::Drop);
+
+    let mut config = get_test_config();
+    config.set_idle_timeout(Duration::from_secs(1));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let result = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .and_then(|mut con| send_and_recv_echo(&mut con, &mut evt_loop));
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn connections_above_max_concurrent_connections_are_rejected() {
+    timebomb::timeout_ms(connections_above_max_concurrent_connections_are_rejected_inner, 10000);
+}
+
+fn connections_above_max_concurrent_connections_are_rejected_inner() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.set_max_concurrent_connections(1);
+        config
+    });
+    let addr: SocketAddr = ([127, 0, 0, 1], addr.port()).into();
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut first = evt_loop
+        .block_on(context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(send_and_recv_echo(&mut first, &mut evt_loop).is_ok());
+
+    let second = evt_loop
+        .block_on(context.new_connection(addr, TEST_SERVER_NAME))
+        .and_then(|mut con| send_and_recv_echo(&mut con, &mut evt_loop));
+    assert!(second.is_err());
+
+    // The first `Connection` is still served.
+    assert!(send_and_recv_echo(&mut first, &mut evt_loop).is_ok());
+}
+
+#[test]
+fn context_looks_up_connections_by_id() {
+    timebomb::timeout_ms(context_looks_up_connections_by_id_inner, 10000);
+}
+
+fn context_looks_up_connections_by_id_inner() {
+    let (server, mut server_evt_loop) = create_context_and_evt_loop_with_default_config();
+    let addr: SocketAddr = ([127, 0, 0, 1], server.local_addr().port()).into();
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+    let con = evt_loop
+        .block_on(context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let (server_con, server) = server_evt_loop
+        .block_on(server.into_future().map_err(|(e, _)| e))
+        .expect("accepts connection");
+    let server_con = server_con.expect("incoming connection");
+
+    let info = server
+        .get_connection(server_con.id())
+        .expect("connection is registered");
+    assert_eq!(ConnectionType::Incoming, info.connection_type);
+    assert_eq!(con.local_addr().port(), info.peer_addr.port());
+
+    let ids = con.connection_ids();
+    assert!(!ids.initial.is_empty());
+    assert_eq!(ids.initial, info.connection_ids.initial);
+
+    assert!(server.get_connection(server_con.id().wrapping_add(1)).is_none());
+}
+
+#[test]
+fn paused_context_refuses_new_connections() {
+    timebomb::timeout_ms(paused_context_refuses_new_connections_inner, 20000);
+}
+
+fn paused_context_refuses_new_connections_inner() {
+    let (mut server, mut server_evt_loop) = create_context_and_evt_loop_with_default_config();
+    let addr: SocketAddr = ([127, 0, 0, 1], server.local_addr().port()).into();
+    server.pause_listening(PauseMode::Busy).expect("pauses listening");
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    // The refused connection ends, before it has any stream.
+    let refused = evt_loop.block_on(
+        context
+            .new_connection(addr, TEST_SERVER_NAME)
+            .and_then(|con| con.into_future().map_err(|(e, _)| e)),
+    );
+    match refused {
+        Err(_) | Ok((None, _)) => {}
+        Ok((Some(_), _)) => panic!("paused context accepted a stream"),
+    }
+
+    server.listener_control().resume().expect("resumes listening");
+
+    let _con = evt_loop
+        .block_on(context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    let (accepted, _server) = server_evt_loop
+        .block_on(server.into_future().map_err(|(e, _)| e))
+        .expect("accepts connection");
+    assert!(accepted.is_some());
+}
+
+fn start_server_with_handshake_rate_limit(action: RateLimitAction) -> SocketAddr {
+    let addr = start_server_that_sends_received_data_back(move || {
+        let mut config = get_test_config();
+        config.set_handshake_rate_limit(HandshakeRateLimit {
+            per_second: 0.001,
+            burst: 1,
+            action,
+        });
+        config
+    });
+
+    ([127, 0, 0, 1], addr.port()).into()
+}
+
+#[test]
+fn handshakes_above_rate_limit_are_answered_with_retry() {
+    timebomb::timeout_ms(handshakes_above_rate_limit_are_answered_with_retry_inner, 10000);
+}
+
+fn handshakes_above_rate_limit_are_answered_with_retry_inner() {
+    let addr = start_server_with_handshake_rate_limit(RateLimitAction::Retry);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    // The client validates its address with the retry token, so all connections succeed.
+    for _ in 0..2 {
+        let mut con = evt_loop
+            .block_on(context.new_connection(addr, TEST_SERVER_NAME))
+            .expect("creates connection");
+        assert!(send_and_recv_echo(&mut con, &mut evt_loop).is_ok());
+    }
+}
+
+#[test]
+fn handshakes_above_rate_limit_are_dropped() {
+    timebomb::timeout_ms(handshakes_above_rate_limit_are_dropped_inner, 10000);
+}
+
+fn handshakes_above_rate_limit_are_dropped_inner() {
+    let addr = start_server_with_handshake_rate_limit(RateLimitAction::Drop);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut first = evt_loop
+        .block_on(context.new_connection(addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(send_and_recv_echo(&mut first, &mut evt_loop).is_ok());
+
+    // The server never answers the second handshake.
+    let second = context.new_connection(addr, TEST_SERVER_NAME);
+    assert!(evt_loop
+        .block_on(Timeout::new(second, Duration::from_secs(2)))
+        .is_err());
+}
+
+#[test]
+fn connections_with_different_congestion_algorithms_send_and_recv_data() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.set_congestion_algorithm(CongestionAlgorithm::Bbr);
+        config
+    });
+
+    let mut config = get_test_config();
+    config.set_congestion_algorithm(CongestionAlgorithm::Cubic);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con_config = ConnectionConfig::new();
+    con_config.set_congestion_algorithm(CongestionAlgorithm::NewReno);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection_with_config(
+            ([127, 0, 0, 1], addr.port()).into(),
+            TEST_SERVER_NAME,
+            con_config,
+        ))
+        .expect("creates connection");
+
+    for algorithm in &[CongestionAlgorithm::Bbr, CongestionAlgorithm::Cubic] {
+        con.set_congestion_algorithm(*algorithm).expect("sets congestion algorithm");
+
+        let stream = evt_loop
+            .block_on(con.new_bidirectional_stream())
+            .expect("creates stream");
+        let stream = evt_loop
+            .block_on(stream.send(Bytes::from("hello server")))
+            .expect("sends data");
+
+        assert_eq!(
+            &b"hello server"[..],
+            &evt_loop
+                .block_on(stream.into_future().map_err(|(e, _)| e))
+                .unwrap()
+                .0
+                .unwrap()[..]
+        );
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn ecn_codepoints_of_received_packets_are_counted() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.enable_ecn(true);
+        config
+    });
+
+    let mut config = get_test_config();
+    config.enable_ecn(true);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .expect("sends data");
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+
+    // The server marks all its packets as ECT(0), the loopback interface keeps the marks.
+    let ecn = con.stats().ecn_received;
+    assert!(ecn.ect0 > 0);
+    assert_eq!(0, ecn.ce);
+}
+
+/// A `CongestionController` with a fixed window, that counts the events.
+struct FixedWindow {
+    sent: Arc<AtomicUsize>,
+    acked: Arc<AtomicUsize>,
+}
+
+impl CongestionController for FixedWindow {
+    fn congestion_window(&self) -> u64 {
+        64 * 1024
+    }
+
+    fn on_packet_sent(&mut self, _: usize, _: &PathInfo) {
+        self.sent.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_ack(&mut self, _: u64, _: &PathInfo) {
+        self.acked.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_loss(&mut self, _: u64, _: bool, _: &PathInfo) {}
+}
+
+#[test]
+fn custom_congestion_controller_is_notified() {
+    let addr = start_server_that_sends_received_data_back(get_test_config);
+
+    let sent = Arc::new(AtomicUsize::new(0));
+    let acked = Arc::new(AtomicUsize::new(0));
+
+    let mut config = get_test_config();
+    let (sent_c, acked_c) = (sent.clone(), acked.clone());
+    config.set_congestion_controller(move || {
+        Box::new(FixedWindow {
+            sent: sent_c.clone(),
+            acked: acked_c.clone(),
+        }) as Box<dyn CongestionController>
+    });
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .expect("sends data");
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+    assert!(sent.load(Ordering::SeqCst) > 0);
+    assert!(acked.load(Ordering::SeqCst) > 0);
+}
+
+#[test]
+fn peer_transport_parameters_are_configured_and_limit_streams() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.set_initial_max_data(1_000_000);
+        config.set_initial_max_stream_data_bidi_remote(100_000);
+        config.set_initial_max_streams_bidi(1);
+        config.set_initial_max_streams_uni(0);
+        config.set_ack_delay_exponent(5);
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let params = con.peer_transport_parameters().expect("handshake is finished");
+    assert_eq!(1_000_000, params.initial_max_data);
+    assert_eq!(100_000, params.initial_max_stream_data_bidi_remote);
+    assert_eq!(1, params.initial_max_streams_bidi);
+    assert_eq!(0, params.initial_max_streams_uni);
+    assert_eq!(5, params.ack_delay_exponent);
+
+    let _stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    // The server only allows one bidirectional `Stream`, so the second one waits for credit.
+    let res = evt_loop.block_on(Timeout::new(
+        con.new_bidirectional_stream(),
+        Duration::from_millis(500),
+    ));
+    assert!(res.map(|_| ()).unwrap_err().is_elapsed());
+}
+
+#[test]
+fn client_transport_parameters_are_advertised_to_server() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let _ = send.send(c.peer_transport_parameters());
+            tokio::spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+            Ok(())
+        })
+    });
+
+    let mut config = get_test_config();
+    config.set_initial_max_data(500_000);
+    config.set_initial_max_stream_data_bidi_local(50_000);
+    config.set_initial_max_streams_bidi(3);
+    config.set_initial_max_streams_uni(2);
+    config.set_max_udp_payload_size(1400);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let _con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let params = recv
+        .recv_timeout(Duration::from_secs(5))
+        .expect("server accepts connection")
+        .expect("handshake is finished");
+    assert_eq!(500_000, params.initial_max_data);
+    assert_eq!(50_000, params.initial_max_stream_data_bidi_local);
+    assert_eq!(3, params.initial_max_streams_bidi);
+    assert_eq!(2, params.initial_max_streams_uni);
+    assert_eq!(1400, params.max_udp_payload_size);
+}
+
+#[test]
+fn handshake_audit_records_accepted_handshake() {
+    let (send, recv) = channel();
+
+    let addr = start_server_that_sends_received_data_back(move || {
+        let mut config = get_test_config();
+        config.set_handshake_audit(move |record: HandshakeRecord| {
+            let _ = send.send(record);
+        });
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let record = recv
+        .recv_timeout(Duration::from_secs(10))
+        .expect("handshake is recorded");
+    assert_eq!(HandshakeOutcome::Accepted, record.outcome);
+    assert_eq!(ConnectionType::Incoming, record.connection_type);
+    assert_eq!(Some(TEST_SERVER_NAME.to_string()), record.server_name);
+    assert_eq!(context.local_addr().port(), record.peer_addr.port());
+}
+
+#[test]
+fn metrics_sink_receives_metrics_of_context() {
+    let (send, recv) = channel();
+
+    let addr = start_server_that_sends_received_data_back(move || {
+        let mut config = get_test_config();
+        config.set_metrics_sink(move |metrics: &Metrics| {
+            let _ = send.send(metrics.clone());
+        });
+        config.set_metrics_interval(Duration::from_millis(100));
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    send_and_recv_echo(&mut con, &mut evt_loop).expect("echoes data");
+
+    let mut handshakes = 0;
+    let metrics = loop {
+        let metrics = recv
+            .recv_timeout(Duration::from_secs(10))
+            .expect("metrics are reported");
+        handshakes += metrics.handshakes;
+
+        if handshakes > 0 {
+            break metrics;
+        }
+    };
+
+    assert_eq!(1, handshakes);
+    assert_eq!(1, metrics.connections_open);
+    assert_eq!(1, metrics.rtt.count());
+    assert!(metrics.bytes_sent > 0);
+    assert!(metrics.bytes_received > 0);
+    assert!(metrics.packets_sent > 0);
+    assert!(metrics.packets_received > 0);
+}
+
+#[test]
+fn packet_observer_sees_sent_and_received_packets() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let packets = Arc::new(Mutex::new(Vec::new()));
+    let mut config = get_test_config();
+    {
+        let packets = packets.clone();
+        config.set_packet_observer(
+            move |direction: PacketDirection,
+                  remote_addr: SocketAddr,
+                  data: &[u8],
+                  metadata: &PacketMetadata| {
+                assert!(!data.is_empty());
+                packets
+                    .lock()
+                    .unwrap()
+                    .push((direction, remote_addr, metadata.clone()));
+            },
+        );
+    }
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+    let server_addr: SocketAddr = ([127, 0, 0, 1], addr.port()).into();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    send_and_recv_echo(&mut con, &mut evt_loop).expect("echoes data");
+
+    let packets = packets.lock().unwrap();
+    assert!(packets.iter().all(|p| p.1 == server_addr));
+
+    let (direction, _, ref metadata) = packets[0];
+    assert_eq!(PacketDirection::Sent, direction);
+    assert_eq!(Some(con.id()), metadata.connection);
+    match metadata.header {
+        Some(PacketHeader::Long { packet_type, .. }) => {
+            assert_eq!(LongPacketType::Initial, packet_type)
+        }
+        ref header => panic!("unexpected header: {:?}", header),
+    }
+
+    assert!(packets.iter().any(|p| p.0 == PacketDirection::Received));
+    assert!(packets.iter().any(|p| match p.2.header {
+        Some(PacketHeader::Short { .. }) => true,
+        _ => false,
+    }));
+}
+
+struct RejectCertificate;
+
+impl VerifyCertificate for RejectCertificate {
+    fn verify(
+        &mut self,
+        _: &VerifyContext,
+        _: &X509Ref,
+        _: &StackRef<X509>,
+    ) -> Result<bool, ErrorStack> {
+        Ok(false)
+    }
+}
+
+#[test]
+fn handshake_audit_records_failed_handshake() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (send, recv) = channel();
+    let mut config = get_test_config();
+    config.set_verify_certificate_handler(RejectCertificate);
+    config.set_handshake_audit(move |record: HandshakeRecord| {
+        let _ = send.send(record);
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    assert!(evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .is_err());
+
+    let record = recv
+        .recv_timeout(Duration::from_secs(10))
+        .expect("handshake is recorded");
+    assert_eq!(ConnectionType::Outgoing, record.connection_type);
+    assert_eq!(addr.port(), record.peer_addr.port());
+    assert!(!record.peer_certificate_fingerprints.is_empty());
+    assert!(record
+        .peer_certificate_fingerprints
+        .iter()
+        .all(|fingerprint| fingerprint.len() == 32));
+
+    match record.outcome {
+        HandshakeOutcome::Failed { .. } => {}
+        outcome => panic!("handshake is not failed: {:?}", outcome),
+    }
+}
+
+#[test]
+fn updated_config_is_used_for_new_connections() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |mut c| {
+        let mut config = get_test_config();
+        config.set_admission_handler(move |info: &IncomingConnectionInfo| {
+            let _ = send.send(info.clone());
+            ConnectionConfig::new()
+        });
+        c.update_config(config).expect("updates config");
+
+        c.for_each(|c| {
+            tokio::spawn(c.for_each(|s| s.for_each(|_| Ok(()))).map_err(|_| ()));
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let _stream = evt_loop
+        .block_on(
+            con.new_bidirectional_stream()
+                .and_then(|s| s.send(Bytes::from("hello server"))),
+        )
+        .expect("creates stream");
+
+    let info = recv.recv().expect("admission handler of the updated config is called");
+    assert_eq!(Some(TEST_SERVER_NAME.to_string()), info.server_name);
+}
+
+#[test]
+fn reloaded_certificates_are_used_for_new_connections() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_certificate_chain_filename(format!("{}ca.crt", get_test_certs_path()));
+            config.set_private_key_filename(format!("{}ca.key", get_test_certs_path()));
+            config
+        },
+        |mut c| {
+            c.reload_certificates(
+                vec![include_bytes!("certs/device.test.crt").to_vec()],
+                include_bytes!("certs/device.key").to_vec(),
+                FileFormat::PEM,
+            )
+            .expect("reloads certificates");
+
+            c.for_each(|_| Ok(()))
+        },
+    );
+
+    let pin = spki_pin(include_bytes!("certs/device.test.crt"));
+    let mut config = Config::new();
+    config.set_verify_certificate_handler(PinnedVerifier::new(vec![pin]));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    assert!(evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .is_ok());
+}
+
+#[test]
+fn reload_certificates_with_other_key_fails() {
+    let (mut context, _evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    assert!(context
+        .reload_certificates(
+            vec![include_bytes!("certs/device.test.crt").to_vec()],
+            include_bytes!("certs/ca.key").to_vec(),
+            FileFormat::PEM,
+        )
+        .is_err());
+}
+
+#[test]
+fn update_config_with_missing_certificate_fails() {
+    let (mut context, _evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut config = get_test_config();
+    config.set_certificate_chain_filename(format!("{}missing.crt", get_test_certs_path()));
+
+    assert!(context.update_config(config).is_err());
+}
+
+#[test]
+fn connection_reports_negotiated_tls_info() {
+    let addr = start_server_thread_with_default_config(|c| c.for_each(|_| Ok(())));
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let info = con.tls_info().expect("handshake is finished");
+    assert_eq!(0x0304, info.version);
+    assert!(info.cipher_suite_name().is_some());
+    assert!(!info.session_resumed);
+}
+
+#[test]
+fn client_and_server_negotiate_alpn() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_alpn_protocols(vec![b"h3".to_vec(), b"echo".to_vec()]);
+            config
+        },
+        |c| {
+            c.for_each(|c| {
+                assert_eq!(Some(b"echo".to_vec()), c.negotiated_alpn());
+                Ok(())
+            })
+        },
+    );
+
+    let mut config = get_test_config();
+    config.set_alpn_protocols(vec![b"other".to_vec(), b"echo".to_vec()]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(Some(b"echo".to_vec()), con.negotiated_alpn());
+}
+
+#[test]
+fn connection_without_common_alpn_fails() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_alpn_protocols(vec![b"h3".to_vec()]);
+            config
+        },
+        |c| c.for_each(|_| Ok(())),
+    );
+
+    let mut config = get_test_config();
+    config.set_alpn_protocols(vec![b"other".to_vec()]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    assert!(evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .is_err());
+}
+
+#[test]
+fn connection_reports_stats_per_path_and_packet_number_space() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stats = con.stats();
+    assert!(stats.initial.packets_sent > 0);
+    assert!(stats.initial.largest_received.is_some());
+    assert!(stats.handshake.packets_sent > 0);
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+    evt_loop
+        .block_on(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    let stats = con.stats();
+    assert!(stats.application.packets_sent > 0);
+    assert!(stats.application.largest_received.is_some());
+
+    assert!(stats.packets_sent >= stats.application.packets_sent);
+    assert!(stats.packets_received > 0);
+    assert!(stats.packets_lost <= stats.retransmissions);
+
+    let path = stats.paths.first().expect("connection has a path");
+    assert_eq!(addr.port(), path.peer_addr.port());
+    assert!(path.congestion_window > 0);
+    assert!(path.mtu >= 1200);
+    assert!(path.pacing_rate > 0);
+    // The time stamp extension is not enabled.
+    assert_eq!(None, path.one_way_delay);
+    assert_eq!(Some(path), stats.primary_path());
+}
+
+#[test]
+fn time_stamps_measure_one_way_delay() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.enable_time_stamps();
+        config
+    });
+
+    let mut config = get_test_config();
+    config.enable_time_stamps();
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(con.peer_transport_parameters().expect("handshake is finished").time_stamps);
+
+    let mut stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    for _ in 0..5 {
+        stream = evt_loop
+            .block_on(stream.send(Bytes::from("hello server")))
+            .unwrap();
+        let (_, next) = evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        stream = next;
+    }
+
+    let stats = con.stats();
+    let path = stats.primary_path().expect("connection has a path");
+    assert!(path.one_way_delay.is_some());
+}
+
+#[test]
+fn path_parameters_resume_congestion_window() {
+    timebomb::timeout_ms(path_parameters_resume_congestion_window_inner, 10000);
+}
+
+fn path_parameters_resume_congestion_window_inner() {
+    const TOTAL: usize = 500_000;
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let mut stream = evt_loop
+        .block_on(stream.send(Bytes::from(vec![0x42; TOTAL])))
+        .unwrap();
+
+    let mut received = 0;
+    while received < TOTAL {
+        let (data, next) = evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        received += data.expect("receives echo").len();
+        stream = next;
+    }
+
+    let params = con.export_path_parameters().expect("exports path parameters");
+    assert_eq!(addr.port(), params.peer_addr.port());
+
+    let mut config = ConnectionConfig::new();
+    config.set_path_parameters(params);
+    let mut con = evt_loop
+        .block_on(context.new_connection_with_config(
+            ([127, 0, 0, 1], addr.port()).into(),
+            TEST_SERVER_NAME,
+            config,
+        ))
+        .expect("creates connection");
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+    evt_loop
+        .block_on(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    let stats = con.stats();
+    let path = stats.primary_path().expect("connection has a path");
+    assert!(path.congestion_window >= params.congestion_window / 2);
+}
+
+#[test]
+fn connection_reports_crypto_throughput() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from(vec![1; 64 * 1024])))
+        .unwrap();
+    evt_loop
+        .block_on(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    let crypto = con.stats().crypto;
+    assert!(crypto.protected_bytes >= 64 * 1024);
+    assert!(crypto.unprotected_bytes > 0);
+    assert!(crypto.protect_throughput().is_some());
+    assert!(crypto.unprotect_throughput().is_some());
+}
+
+#[test]
+fn context_with_unknown_crypto_engine_fails() {
+    let mut config = get_test_config();
+    config.set_crypto_backend(CryptoBackend::Engine("picoquic-does-not-exist".into()));
+
+    let evt_loop = Runtime::new().expect("creates event loop");
+    let err = Context::new(&([0, 0, 0, 0], 0).into(), evt_loop.executor(), config)
+        .err()
+        .expect("context creation fails");
+
+    match err.kind() {
+        ErrorKind::CryptoBackend(id) => assert_eq!("picoquic-does-not-exist", id),
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+fn datagram_config() -> Config {
+    let mut config = get_test_config();
+    config.enable_datagrams(1200);
+    config
+}
+
+#[test]
+fn datagrams_are_sent_and_received() {
+    timebomb::timeout_ms(datagrams_are_sent_and_received_inner, 10000);
+}
+
+fn datagrams_are_sent_and_received_inner() {
+    let addr = start_server_thread(datagram_config, |c| {
+        c.for_each(|mut c| {
+            let (send, recv) = c.incoming_datagrams().expect("takes datagrams").split();
+            tokio::spawn(
+                send.send_all(recv.map(BytesMut::freeze))
+                    .map(|_| ())
+                    .map_err(|_| ()),
+            );
+            tokio::spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(datagram_config());
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let max = con.max_datagram_size();
+    assert!(max > 0 && max < 1200);
+
+    match con.send_datagram(BytesMut::from(vec![0; max + 1])) {
+        Err(ref e) => match e.kind() {
+            ErrorKind::DatagramTooLarge(size, m) => assert_eq!((max + 1, max), (*size, *m)),
+            kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(_) => panic!("oversized datagram is sent"),
+    }
+
+    let datagrams = con.incoming_datagrams().expect("takes datagrams");
+    assert!(con.incoming_datagrams().is_none());
+
+    let (send, recv) = channel();
+    evt_loop.spawn(
+        datagrams
+            .for_each(move |d| {
+                let _ = send.send(d);
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    // Datagrams are unreliable, so resend until the echo arrives.
+    let echo = loop {
+        con.send_datagram(BytesMut::from(&b"hello datagram"[..])).expect("sends datagram");
+
+        if let Ok(echo) = recv.recv_timeout(Duration::from_millis(500)) {
+            break echo;
+        }
+    };
+
+    assert_eq!(&b"hello datagram"[..], &echo[..]);
+}
+
+#[test]
+fn client_advertises_datagram_support_to_server() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(datagram_config, move |c| {
+        c.for_each(move |c| {
+            let _ = send.send(c.max_datagram_size());
+            tokio::spawn(c.for_each(|_| Ok(())).map_err(|_| ()));
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(datagram_config());
+
+    let _con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let max = recv.recv_timeout(Duration::from_secs(5)).expect("server accepts connection");
+    assert!(max > 0 && max < 1200);
+}
+
+#[test]
+fn datagrams_are_unsupported_without_negotiation() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(datagram_config());
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(0, con.max_datagram_size());
+    match con.send_datagram(BytesMut::from(&b"hello"[..])) {
+        Err(ref e) => match e.kind() {
+            ErrorKind::DatagramsUnsupported => {}
+            kind => panic!("unexpected error: {}", kind),
+        },
+        Ok(_) => panic!("datagram is sent without negotiation"),
+    }
+}
+
+#[test]
+fn connection_reports_blocked_streams_and_session_ticket() {
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.set_initial_max_streams_bidi(1);
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let events = con.events().expect("takes events");
+
+    let _stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    // The second `Stream` waits for the stream credit of the server.
+    let _blocked = con.new_bidirectional_stream();
+
+    let events = evt_loop
+        .block_on(Timeout::new(
+            events
+                .filter(|e| {
+                    matches!(
+                        e,
+                        ConnectionEvent::StreamsBlocked { .. }
+                            | ConnectionEvent::SessionTicketReceived
+                    )
+                })
+                .take(2)
+                .collect(),
+            Duration::from_secs(10),
+        ))
+        .expect("reports events");
+    assert!(events.contains(&ConnectionEvent::StreamsBlocked {
+        stype: SType::Bidirectional
+    }));
+    assert!(events.contains(&ConnectionEvent::SessionTicketReceived));
+}
+
+#[test]
+fn resumed_connection_sends_early_data() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+    let server_addr: SocketAddr = ([127, 0, 0, 1], addr.port()).into();
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert!(con.early_data_stream().is_none());
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+    evt_loop
+        .block_on(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+
+    // The server sends the session ticket after the handshake.
+    let ticket = (0..50)
+        .filter_map(|_| {
+            thread::sleep(Duration::from_millis(100));
+            con.export_session_ticket()
+        })
+        .next()
+        .expect("server sends session ticket");
+
+    let mut con = evt_loop
+        .block_on(context.new_connection_with_early_data(
+            server_addr,
+            TEST_SERVER_NAME,
+            Bytes::from("hello early"),
+        ))
+        .expect("creates connection");
+    assert!(con.tls_info().expect("handshake is finished").session_resumed);
+    assert!(con.is_early_data_accepted());
+
+    let stream = con.early_data_stream().expect("early data stream");
+    assert_eq!(
+        &b"hello early"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+
+    // A new `Context` resumes the session with the exported ticket.
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+    let mut config = ConnectionConfig::new();
+    config.set_session_ticket(ticket);
+
+    let con = evt_loop
+        .block_on(context.new_connection_with_config(server_addr, TEST_SERVER_NAME, config))
+        .expect("creates connection");
+    assert!(con.tls_info().expect("handshake is finished").session_resumed);
+}
+
+#[test]
+fn graceful_close_sends_connection_close() {
+    timebomb::timeout_ms(graceful_close_sends_connection_close_inner, 10000);
+}
+
+fn graceful_close_sends_connection_close_inner() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(c.for_each(|_| Ok(())).then(move |_| {
+                let _ = send.send(());
+                Ok(())
+            }));
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    evt_loop
+        .block_on(con.close(42, "shutting down"))
+        .expect("closes connection");
+
+    recv.recv_timeout(Duration::from_secs(5)).expect("server connection is closed");
+}
+
+#[test]
+fn context_close_closes_connections_and_releases_socket() {
+    timebomb::timeout_ms(context_close_closes_connections_and_releases_socket_inner, 20000);
+}
+
+fn context_close_closes_connections_and_releases_socket_inner() {
+    let (server, mut server_evt_loop) = create_context_and_evt_loop_with_default_config();
+    let port = server.local_addr().port();
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], port).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let (server_con, server) = server_evt_loop
+        .block_on(server.into_future().map_err(|(e, _)| e))
+        .expect("accepts connection");
+    assert!(server_con.is_some());
+
+    server_evt_loop
+        .block_on(server.close(Duration::from_secs(5)))
+        .expect("closes context");
+
+    // The client connection ends, after it received the `CONNECTION_CLOSE`.
+    let _ = evt_loop.block_on(con.into_future().map_err(|(e, _)| e));
+
+    std::net::UdpSocket::bind(("0.0.0.0", port)).expect("socket is released");
+}
+
+#[test]
+fn ping_resolves_with_round_trip_time() {
+    timebomb::timeout_ms(ping_resolves_with_round_trip_time_inner, 10000);
+}
+
+fn ping_resolves_with_round_trip_time_inner() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let rtt = evt_loop.block_on(con.ping()).expect("ping is acknowledged");
+    assert!(rtt < Duration::from_secs(5));
+    evt_loop.block_on(con.ping()).expect("second ping is acknowledged");
+}
+
+#[test]
+fn idle_connection_fails_with_idle_timeout() {
+    timebomb::timeout_ms(idle_connection_fails_with_idle_timeout_inner, 20000);
+}
+
+fn idle_connection_fails_with_idle_timeout_inner() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut config = get_test_config();
+    config.set_idle_timeout(Duration::from_secs(1));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let err = evt_loop
+        .block_on(con.into_future().map_err(|(e, _)| e))
+        .err()
+        .expect("connection fails");
+
+    match err.kind() {
+        ErrorKind::IdleTimeout => {}
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn keep_alive_prevents_idle_timeout() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut config = get_test_config();
+    config.set_idle_timeout(Duration::from_secs(1));
+    config.enable_keep_alive(Duration::from_millis(200));
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    thread::sleep(Duration::from_secs(3));
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    assert_eq!(
+        &b"hello server"[..],
+        &evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()[..]
+    );
+}
+
+#[test]
+fn close_when_idle_closes_connection_after_streams_finished() {
+    timebomb::timeout_ms(close_when_idle_closes_connection_after_streams_finished_inner, 10000);
+}
+
+fn close_when_idle_closes_connection_after_streams_finished_inner() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    con.close_when_idle();
+
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello server")))
+        .unwrap();
+
+    // The open `Stream` keeps the `Connection` alive.
+    let (data, stream) = evt_loop
+        .block_on(stream.into_future().map_err(|(e, _)| e))
+        .unwrap();
+    assert_eq!(&b"hello server"[..], &data.unwrap()[..]);
+
+    drop(stream);
+
+    assert!(evt_loop
+        .block_on(con.into_future().map_err(|(e, _)| e))
+        .expect("connection closes")
+        .0
+        .is_none());
+}
+
+#[test]
+fn send_file_range_and_recv() {
+    let path = env::temp_dir().join("picoquic_send_file_range_and_recv");
+    let content = (0..10_000u32).map(|i| (i % 256) as u8).collect::<Vec<_>>();
+    fs::write(&path, &content).expect("writes test file");
+    let range = 100..9_000;
+    let expected = &content[range.start as usize..range.end as usize];
+
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    stream
+        .send_file(File::open(&path).expect("opens test file"), range.clone())
+        .unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < expected.len() {
+        let (data, next) = evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        received.extend_from_slice(&data.expect("receives data"));
+        stream = next;
+    }
+
+    assert_eq!(expected, &received[..]);
+}
+
+#[test]
+fn send_file_range_behind_file_end_drains_backlog() {
+    let path = env::temp_dir().join("picoquic_send_file_range_behind_file_end_drains_backlog");
+    let content = vec![7u8; 1_000];
+    fs::write(&path, &content).expect("writes test file");
+
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    stream
+        .send_file(File::open(&path).expect("opens test file"), 500..5_000)
+        .unwrap();
+    assert_eq!(500, stream.send_backlog());
+
+    evt_loop
+        .block_on(futures::future::poll_fn(|| stream.poll_send_backlog(0)))
+        .expect("sends the file");
+    assert_eq!(0, stream.send_backlog());
+}
+
+#[test]
+fn transfer_sends_all_chunks() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    s.fold(0, |len, data| Ok::<_, Error>(len + data.len()))
+                        .map(move |len| {
+                            let _ = send.send(len);
+                        })
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let chunks = (0..64).map(|_| Bytes::from(vec![1u8; 16 * 1024]));
+    let progress = Arc::new(Mutex::new(None));
+    let progress_clone = progress.clone();
+
+    let (stream, len) = evt_loop
+        .block_on(
+            transfer(stream, futures::stream::iter_ok(chunks))
+                .max_backlog(64 * 1024)
+                .on_progress(move |p| *progress_clone.lock().unwrap() = Some(p)),
+        )
+        .expect("transfers all chunks");
+    assert_eq!(64 * 16 * 1024, len);
+    assert_eq!(
+        Some(TransferProgress {
+            queued: len,
+            sent: len
+        }),
+        *progress.lock().unwrap()
+    );
+
+    drop(stream);
+    assert_eq!(len as usize, recv.recv().expect("receives length"));
+}
+
+#[test]
+fn coalesced_small_writes_arrive_in_order() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    s.concat2().map(move |data| {
+                        let _ = send.send(data);
+                    })
+                })
+                .map_err(|_| ()),
+            );
+
+            Ok(())
+        })
+    });
+
+    let mut config = get_test_config();
+    config.enable_write_coalescing(1024);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let writes = (0..500).map(|i| Bytes::from(format!("{};", i))).collect::<Vec<_>>();
+    let expected = writes.concat();
+
+    let (mut stream, _) = evt_loop
+        .block_on(stream.send_all(futures::stream::iter_ok(writes[..250].to_vec())))
+        .expect("sends first half");
+    stream.set_write_coalescing(None).expect("disables coalescing");
+    let (stream, _) = evt_loop
+        .block_on(stream.send_all(futures::stream::iter_ok(writes[250..].to_vec())))
+        .expect("sends second half");
+    drop(stream);
+
+    let received = recv
+        .recv_timeout(Duration::from_secs(10))
+        .expect("receives data");
+    assert_eq!(&expected[..], &received[..]);
+}
+
+#[test]
+fn context_with_custom_io_sends_and_recvs_data() {
+    let send_data = "hello server";
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+    let socket = UdpSocket::bind(&([0, 0, 0, 0], 0).into()).expect("binds socket");
+    let timer = Delay::new(Instant::now());
+
+    let (mut context, driver) =
+        Context::with_io(socket, timer, get_test_config()).expect("creates quic context");
+    evt_loop.spawn(driver);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from(send_data)))
+        .unwrap();
+
+    assert_eq!(
+        send_data,
+        evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()
+    );
+}
+
+#[test]
+fn contexts_communicate_over_lossy_in_memory_transport() {
+    let send_data = "hello server";
+    let server_addr: SocketAddr = ([10, 0, 0, 2], 4433).into();
+    let conditions = LinkConditions {
+        latency: Duration::from_millis(20),
+        loss: 0.1,
+        seed: 42,
+    };
+    let (client_socket, server_socket) =
+        InMemoryTransport::pair(([10, 0, 0, 1], 5000).into(), server_addr, conditions);
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+
+    let (server, driver) =
+        Context::with_io(server_socket, Delay::new(Instant::now()), get_test_config())
+            .expect("creates server context");
+    evt_loop.spawn(driver);
+    evt_loop.spawn(
+        server
+            .for_each(|c| {
+                tokio::spawn(
+                    c.for_each(|s| {
+                        let (send, recv) = s.split();
+                        tokio::spawn(
+                            send.send_all(recv.map(BytesMut::freeze))
+                                .map(|_| ())
+                                .map_err(|_| ()),
+                        );
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+                );
+                Ok(())
+            })
+            .map_err(|_| ()),
+    );
+
+    let (mut context, driver) =
+        Context::with_io(client_socket, Delay::new(Instant::now()), get_test_config())
+            .expect("creates client context");
+    evt_loop.spawn(driver);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(server_addr, TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(server_addr, con.peer_addr());
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from(send_data)))
+        .unwrap();
+
+    assert_eq!(
+        send_data,
+        evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()
+    );
+}
+
+#[cfg(feature = "std-future")]
+#[test]
+fn context_on_tokio1_sends_and_recvs_data() {
+    let send_data = "hello server";
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let runtime = tokio1::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("creates tokio runtime");
+    let _guard = runtime.enter();
+
+    let mut context = Context::new_tokio1(&([0, 0, 0, 0], 0).into(), get_test_config())
+        .expect("creates quic context");
+
+    let mut con = runtime
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let mut stream = runtime
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    runtime
+        .block_on(futures03::SinkExt::send(&mut stream, Bytes::from(send_data)))
+        .expect("sends data");
+
+    let data = runtime
+        .block_on(futures03::StreamExt::next(&mut stream))
+        .expect("stream is open")
+        .expect("receives data");
+    assert_eq!(send_data, data);
+}
+
+/// Runs each `ContextDriver` on its own thread, without any tokio runtime.
+struct ThreadSpawner;
+
+impl Spawn for ThreadSpawner {
+    fn spawn(&self, driver: ContextDriver) -> Result<(), Error> {
+        thread::spawn(move || driver.wait());
+        Ok(())
+    }
+}
+
+#[test]
+fn context_with_custom_spawner_sends_and_recvs_data() {
+    timebomb::timeout_ms(context_with_custom_spawner_sends_and_recvs_data_inner, 10000);
+}
+
+fn context_with_custom_spawner_sends_and_recvs_data_inner() {
+    let send_data = "hello server";
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut context =
+        Context::new_with_spawner(&([0, 0, 0, 0], 0).into(), ThreadSpawner, get_test_config())
+            .expect("creates quic context");
+
+    let mut con = context
+        .new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME)
+        .wait()
+        .expect("creates connection");
+    let stream = con
+        .new_bidirectional_stream()
+        .wait()
+        .expect("creates stream");
+
+    let stream = stream.send(Bytes::from(send_data)).wait().expect("sends data");
+    let data = stream
+        .into_future()
+        .map(|(m, _)| m)
+        .map_err(|(e, _)| e)
+        .wait()
+        .expect("receives data");
+
+    assert_eq!(send_data, data.expect("stream is open"));
+}
+
+#[test]
+fn send_on_driver_thread_keeps_order() {
+    timebomb::timeout_ms(send_on_driver_thread_keeps_order_inner, 10000);
+}
+
+fn send_on_driver_thread_keeps_order_inner() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    // The driver runs on the same thread as the `Stream`, so the data is handed directly to
+    // picoquic.
+    let mut evt_loop = current_thread::Runtime::new().expect("creates event loop");
+    let socket = UdpSocket::bind(&([0, 0, 0, 0], 0).into()).expect("binds socket");
+    let timer = Delay::new(Instant::now());
+
+    let (mut context, driver) =
+        Context::with_io(socket, timer, get_test_config()).expect("creates quic context");
+    evt_loop.spawn(driver);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+
+    let expected = (0..100).map(|i| format!("chunk{};", i)).collect::<String>();
+    let chunks = (0..100).map(|i| Bytes::from(format!("chunk{};", i)));
+
+    let (mut stream, _) = evt_loop
+        .block_on(stream.send_all(futures::stream::iter_ok(chunks)))
+        .expect("sends all chunks");
+
+    let mut received = Vec::new();
+    while received.len() < expected.len() {
+        let (data, next) = evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap();
+        received.extend_from_slice(&data.expect("receives data"));
+        stream = next;
+    }
+
+    assert_eq!(expected.as_bytes(), &received[..]);
+}
+
+#[test]
+fn context_builder_with_existing_socket_connects() {
+    let addr = start_server_thread_with_default_config(|c| c.for_each(|_| Ok(())));
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("binds socket");
+    let socket_addr = socket.local_addr().unwrap();
+
+    let mut context = ContextBuilder::new(get_test_config())
+        .socket(socket)
+        .executor(evt_loop.executor())
+        .build()
+        .expect("creates quic context");
+    assert_eq!(socket_addr, context.local_addr());
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(con.peer_addr(), ([127, 0, 0, 1], addr.port()).into());
+}
+
+#[test]
+fn context_from_socket_takes_ownership_of_socket() {
+    let addr = start_server_thread_with_default_config(|c| c.for_each(|_| Ok(())));
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("binds socket");
+    let socket_addr = socket.local_addr().unwrap();
+
+    let mut context = Context::from_socket(socket, evt_loop.executor(), get_test_config())
+        .expect("creates quic context");
+    assert_eq!(socket_addr, context.local_addr());
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    assert_eq!(socket_addr, con.local_addr());
+}
+
+#[test]
+fn client_migrates_connection_to_other_socket() {
+    let send_data = "hello server";
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+    let sockets = (0..2)
+        .map(|_| std::net::UdpSocket::bind("127.0.0.1:0").expect("binds socket"))
+        .collect::<Vec<_>>();
+    let new_local_addr = sockets[1].local_addr().unwrap();
+
+    let mut context = sockets
+        .into_iter()
+        .fold(ContextBuilder::new(get_test_config()), |b, s| b.socket(s))
+        .executor(evt_loop.executor())
+        .build()
+        .expect("creates quic context");
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let events = con.events().expect("takes events");
+
+    assert!(con.migrate(([127, 0, 0, 1], 1).into()).is_err());
+
+    con.migrate(new_local_addr).expect("starts migration");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from(send_data)))
+        .unwrap();
+
+    let migrated = events
+        .filter(|e| matches!(e, ConnectionEvent::PathMigrated { .. }))
+        .into_future()
+        .map_err(|(e, _)| e);
+    let (event, _) = evt_loop
+        .block_on(Timeout::new(migrated, Duration::from_secs(10)))
+        .expect("connection migrates");
+    assert_eq!(
+        Some(ConnectionEvent::PathMigrated {
+            local_addr: new_local_addr,
+            peer_addr: ([127, 0, 0, 1], addr.port()).into(),
+        }),
+        event
+    );
+    assert_eq!(new_local_addr, con.local_addr());
+
+    assert_eq!(
+        send_data,
+        evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()
+    );
+}
+
+#[test]
+fn context_listens_on_ipv4_and_ipv6_addresses() {
+    let (send_addrs, recv_addrs) = channel();
+    let (send_con, recv_con) = channel();
+
+    thread::spawn(move || {
+        let evt_loop = Runtime::new().expect("creates event loop");
+        let listen_addresses = ["127.0.0.1:0".parse().unwrap(), "[::1]:0".parse().unwrap()];
+
+        let context = Context::with_listen_addresses(
+            &listen_addresses,
+            evt_loop.executor(),
+            get_test_config(),
+        )
+        .expect("creates quic context");
+        send_addrs.send(context.local_addrs().to_vec()).unwrap();
+
+        evt_loop
+            .block_on_all(context.for_each(move |c| {
+                let _ = send_con.send(c.local_addr());
+                Ok(())
+            }))
+            .expect("event loop spins on server context");
+    });
+
+    let server_addrs = recv_addrs.recv().expect("receives server addrs");
+    assert_eq!(2, server_addrs.len());
+    assert!(server_addrs[0].is_ipv4());
+    assert!(server_addrs[1].is_ipv6());
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+
+    for (server_addr, listen_address) in server_addrs.iter().zip(&["0.0.0.0:0", "[::1]:0"]) {
+        let mut context = ContextBuilder::new(get_test_config())
+            .listen_address(listen_address.parse().unwrap())
+            .executor(evt_loop.executor())
+            .build()
+            .expect("creates quic context");
+
+        let con = evt_loop
+            .block_on(context.new_connection(*server_addr, TEST_SERVER_NAME))
+            .expect("creates connection");
+        assert_eq!(*server_addr, con.peer_addr());
+
+        let local_addr = recv_con
+            .recv_timeout(Duration::from_secs(10))
+            .expect("server receives connection");
+        assert_eq!(*server_addr, local_addr);
+    }
+}
+
+#[test]
+fn context_with_multiple_sockets_uses_socket_of_connection() {
+    let (send_addrs, recv_addrs) = channel();
+    let (send_con, recv_con) = channel();
+
+    thread::spawn(move || {
+        let evt_loop = Runtime::new().expect("creates event loop");
+        let sockets = (0..2)
+            .map(|_| std::net::UdpSocket::bind("127.0.0.1:0").expect("binds socket"))
+            .collect::<Vec<_>>();
+        let addrs = sockets
+            .iter()
+            .map(|s| s.local_addr().unwrap())
+            .collect::<Vec<_>>();
+
+        let context = sockets
+            .into_iter()
+            .fold(ContextBuilder::new(get_test_config()), |b, s| b.socket(s))
+            .executor(evt_loop.executor())
+            .build()
+            .expect("creates quic context");
+        send_addrs.send(addrs).unwrap();
+
+        evt_loop
+            .block_on_all(context.for_each(move |c| {
+                let _ = send_con.send((c.local_addr(), c.peer_addr()));
+                Ok(())
+            }))
+            .expect("event loop spins on server context");
+    });
+
+    let server_addrs = recv_addrs.recv().expect("receives server socket addrs");
+
+    let mut evt_loop = Runtime::new().expect("creates event loop");
+    let sockets = (0..2)
+        .map(|_| std::net::UdpSocket::bind("127.0.0.1:0").expect("binds socket"))
+        .collect::<Vec<_>>();
+    let client_addr = sockets[1].local_addr().unwrap();
+
+    let mut context = sockets
+        .into_iter()
+        .fold(ContextBuilder::new(get_test_config()), |b, s| b.socket(s))
+        .executor(evt_loop.executor())
+        .build()
+        .expect("creates quic context");
+
+    let mut config = ConnectionConfig::new();
+    config.set_local_addr(client_addr);
+
+    let con = evt_loop
+        .block_on(context.new_connection_with_config(server_addrs[1], TEST_SERVER_NAME, config))
+        .expect("creates connection");
+    assert_eq!(client_addr, con.local_addr());
+
+    let (local_addr, peer_addr) = recv_con
+        .recv_timeout(Duration::from_secs(10))
+        .expect("server receives connection");
+    assert_eq!(server_addrs[1], local_addr);
+    assert_eq!(client_addr, peer_addr);
+
+    let mut config = ConnectionConfig::new();
+    config.set_local_addr(([127, 0, 0, 1], 1).into());
+
+    assert!(evt_loop
+        .block_on(context.new_connection_with_config(server_addrs[0], TEST_SERVER_NAME, config))
+        .is_err());
+}
+
+#[cfg(feature = "bincode-codec")]
+#[test]
+fn typed_stream_sends_and_recvs_messages() {
+    let messages = vec![(1u32, String::from("hello")), (2, String::from("server"))];
+
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream: TypedStream<(u32, String), Bincode> = TypedStream::new(stream);
+
+    let (stream, _) = evt_loop
+        .block_on(stream.send_all(futures::stream::iter_ok::<_, Error>(messages.clone())))
+        .unwrap();
+
+    assert_eq!(
+        messages,
+        evt_loop
+            .block_on(stream.take(messages.len() as u64).collect())
+            .unwrap()
+    );
+}
+
+#[cfg(feature = "bench")]
+#[test]
+fn bench_measures_throughput_handshakes_and_latency() {
+    let mut bench = Bench::loopback(get_test_config(), get_test_config(), TEST_SERVER_NAME)
+        .expect("creates bench");
+
+    let throughput = bench.throughput(1024 * 1024).expect("measures throughput");
+    assert_eq!(1024 * 1024, throughput.bytes);
+    assert!(throughput.wire_bytes > throughput.bytes);
+    assert!(throughput.goodput() > 0.0);
+
+    let handshakes = bench.handshake_rate(4).expect("measures handshake rate");
+    assert_eq!(4, handshakes.connections);
+
+    let latency = bench.request_latency(10, 128).expect("measures latency");
+    assert_eq!(10, latency.samples.len());
+    assert!(latency.min() <= latency.max());
+}
+
+#[test]
+fn connection_negotiates_the_preferred_supported_version() {
+    timebomb::timeout_ms(connection_negotiates_the_preferred_supported_version_inner, 10000);
+}
+
+fn connection_negotiates_the_preferred_supported_version_inner() {
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let implemented = QuicVersion::implemented();
+    let preferred = *implemented.last().expect("picoquic implements a version");
+
+    let mut config = get_test_config();
+    config.set_supported_versions(vec![QuicVersion::Other(0x1a2a_3a4a), preferred]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    assert_eq!(Some(preferred), con.negotiated_version());
+}
+
+#[test]
+fn context_without_implemented_version_fails() {
+    let mut config = get_test_config();
+    config.set_supported_versions(vec![QuicVersion::Other(0x1a2a_3a4a)]);
+
+    let evt_loop = Runtime::new().expect("creates event loop");
+    let err = Context::new(&([0, 0, 0, 0], 0).into(), evt_loop.executor(), config)
+        .err()
+        .expect("context creation fails");
+
+    match err.kind() {
+        ErrorKind::UnsupportedVersions(versions) => {
+            assert_eq!(&[QuicVersion::Other(0x1a2a_3a4a)][..], &versions[..])
+        }
+        kind => panic!("unexpected error: {}", kind),
+    }
+}
+
+#[test]
+fn connection_initiates_key_update() {
+    timebomb::timeout_ms(connection_initiates_key_update_inner, 10000);
+}
+
+fn connection_initiates_key_update_inner() {
+    let send_data = "hello server";
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let events = con.events().expect("takes events");
+
+    con.initiate_key_update().expect("requests key update");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let stream = evt_loop
+        .block_on(stream.send(Bytes::from(send_data)))
+        .unwrap();
+    assert_eq!(
+        send_data,
+        evt_loop
+            .block_on(stream.into_future().map_err(|(e, _)| e))
+            .unwrap()
+            .0
+            .unwrap()
+    );
+
+    let key_update = events
+        .filter(|e| matches!(e, ConnectionEvent::KeyUpdate { .. }))
+        .into_future()
+        .map_err(|(e, _)| e);
+    let (event, _) = evt_loop
+        .block_on(Timeout::new(key_update, Duration::from_secs(10)))
+        .expect("keys are updated");
+    assert_eq!(
+        Some(ConnectionEvent::KeyUpdate {
+            initiated_by_peer: false
+        }),
+        event
+    );
+}
+
+#[test]
+fn peer_receives_application_error_of_close() {
+    timebomb::timeout_ms(peer_receives_application_error_of_close_inner, 10000);
+}
+
+fn peer_receives_application_error_of_close_inner() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(c.for_each(|_| Ok(())).then(move |res| {
+                let code = match res.err().as_ref().map(Error::kind) {
+                    Some(ErrorKind::ApplicationError { code, .. }) => Some(*code),
+                    _ => None,
+                };
+                let _ = send.send(code);
+                Ok(())
+            }));
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    evt_loop
+        .block_on(con.close(42, "shutting down"))
+        .expect("closes connection");
+
+    assert_eq!(
+        Some(42),
+        recv.recv_timeout(Duration::from_secs(5))
+            .expect("server connection is closed")
+    );
+}
+
+#[test]
+fn stream_reports_data_blocked_by_flow_control_of_peer() {
+    timebomb::timeout_ms(stream_reports_data_blocked_by_flow_control_of_peer_inner, 10000);
+}
+
+fn stream_reports_data_blocked_by_flow_control_of_peer_inner() {
+    let send_data = vec![0x42; 10_000];
+    let addr = start_server_that_sends_received_data_back(|| {
+        let mut config = get_test_config();
+        config.set_initial_max_stream_data_bidi_remote(1000);
+        config
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+    let events = con.events().expect("takes events");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let id = stream.id();
+    assert!(stream.send_capacity() <= 1000);
+
+    let _stream = evt_loop
+        .block_on(stream.send(Bytes::from(send_data)))
+        .unwrap();
+
+    let blocked = events
+        .filter(|e| matches!(e, ConnectionEvent::StreamDataBlocked { .. }))
+        .into_future()
+        .map_err(|(e, _)| e);
+    let (event, _) = evt_loop
+        .block_on(Timeout::new(blocked, Duration::from_secs(5)))
+        .expect("stream is blocked");
+    assert_eq!(
+        Some(ConnectionEvent::StreamDataBlocked { id, limit: 1000 }),
+        event
+    );
+}
+
+#[test]
+fn set_max_stream_data_grants_credit_beyond_initial_window() {
+    timebomb::timeout_ms(set_max_stream_data_grants_credit_beyond_initial_window_inner, 10000);
+}
+
+fn set_max_stream_data_grants_credit_beyond_initial_window_inner() {
+    const INITIAL_WINDOW: u64 = 1000;
+    const MAX_STREAM_DATA: u64 = 100_000;
+    // More than `MAX_STREAM_DATA`, picoquic needs to keep granting credit on its own.
+    const TOTAL: usize = 300_000;
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_initial_max_stream_data_bidi_remote(INITIAL_WINDOW);
+            config
+        },
+        move |c| {
+            c.for_each(move |c| {
+                let send = send.clone();
+                tokio::spawn(
+                    c.for_each(move |mut s| {
+                        let send = send.clone();
+                        s.set_max_stream_data(MAX_STREAM_DATA).expect("grants credit");
+                        s.fold(0, |len, data| Ok::<_, Error>(len + data.len()))
+                            .map(move |len| {
+                                let _ = send.send(len);
+                            })
+                    })
+                    .map_err(|_| ()),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let mut stream = evt_loop
+        .block_on(stream.send(Bytes::from(vec![0x42; TOTAL])))
+        .expect("sends data");
+    stream.finish().expect("finishes stream");
+
+    assert_eq!(
+        TOTAL,
+        recv.recv_timeout(Duration::from_secs(8))
+            .expect("server receives all data")
+    );
+}
+
+#[test]
+fn receive_window_auto_tuning_grants_more_than_initial_window() {
+    timebomb::timeout_ms(receive_window_auto_tuning_grants_more_than_initial_window_inner, 10000);
+}
+
+fn receive_window_auto_tuning_grants_more_than_initial_window_inner() {
+    const INITIAL_WINDOW: u64 = 16_000;
+    const TOTAL: usize = 2_000_000;
+    let (send, recv) = channel();
+
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_initial_max_data(INITIAL_WINDOW);
+            config.set_initial_max_stream_data_bidi_remote(INITIAL_WINDOW);
+            config.enable_receive_window_auto_tuning(4 * 1024 * 1024);
+            config
+        },
+        move |c| {
+            c.for_each(move |c| {
+                let send = send.clone();
+                tokio::spawn(
+                    c.for_each(move |s| {
+                        let send = send.clone();
+                        s.fold(0, |len, data| Ok::<_, Error>(len + data.len()))
+                            .map(move |len| {
+                                let _ = send.send(len);
+                            })
+                    })
+                    .map_err(|_| ()),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let mut stream = evt_loop
+        .block_on(stream.send(Bytes::from(vec![0x42; TOTAL])))
+        .expect("sends data");
+    stream.finish().expect("finishes stream");
+
+    assert_eq!(
+        TOTAL,
+        recv.recv_timeout(Duration::from_secs(8))
+            .expect("server receives all data")
+    );
+    // Without the auto tuning, the peer never grants more than the initial window at once.
+    assert!(stream.send_capacity() > INITIAL_WINDOW);
+}
+
+#[test]
+fn stop_sending_fails_sends_of_peer() {
+    timebomb::timeout_ms(stop_sending_fails_sends_of_peer_inner, 10000);
+}
+
+fn stop_sending_fails_sends_of_peer_inner() {
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    let data = Interval::new(Instant::now(), Duration::from_millis(10))
+                        .map_err(|_| Error::from(ErrorKind::Unknown))
+                        .map(|_| Bytes::from("download"));
+                    tokio::spawn(s.send_all(data).then(move |res| {
+                        let code = match res.err().as_ref().map(Error::kind) {
+                            Some(ErrorKind::StopSending(code)) => Some(*code),
+                            _ => None,
+                        };
+                        let _ = send.send(code);
+                        Ok(())
+                    }));
+                    Ok(())
+                })
+                .map_err(|_| ()),
+            );
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(
+            con.new_bidirectional_stream()
+                .and_then(|s| s.send(Bytes::from("hello server"))),
+        )
+        .expect("creates stream");
+    let (_, mut stream) = evt_loop
+        .block_on(stream.into_future())
+        .map_err(|(e, _)| e)
+        .expect("receives data");
+
+    stream.stop_sending(42).expect("stops sending");
+
+    assert_eq!(
+        Some(42),
+        recv.recv_timeout(Duration::from_secs(5))
+            .expect("sending of server fails")
+    );
+}
+
+#[test]
+fn framed_stream_preserves_message_boundaries() {
+    timebomb::timeout_ms(framed_stream_preserves_message_boundaries_inner, 10000);
+}
+
+fn framed_stream_preserves_message_boundaries_inner() {
+    let messages = vec![
+        BytesMut::from(&b"hello"[..]),
+        BytesMut::new(),
+        BytesMut::from(vec![0x42; 100_000]),
+        BytesMut::from(&b"server"[..]),
+    ];
+
+    let addr = start_server_that_sends_received_data_back(|| get_test_config());
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop_with_default_config();
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream")
+        .framed();
+
+    let (stream, _) = evt_loop
+        .block_on(stream.send_all(futures::stream::iter_ok::<_, Error>(messages.clone())))
+        .unwrap();
+
+    assert_eq!(
+        messages,
+        evt_loop
+            .block_on(stream.take(messages.len() as u64).collect())
+            .unwrap()
+    );
+}
+
+#[test]
+fn separate_stream_writes_are_received_as_separate_chunks() {
+    timebomb::timeout_ms(separate_stream_writes_are_received_as_separate_chunks_inner, 10000);
+}
+
+fn separate_stream_writes_are_received_as_separate_chunks_inner() {
+    let writes = vec![
+        Bytes::from("hello"),
+        Bytes::from("quic"),
+        Bytes::from("server"),
+    ];
+    let (send, recv) = channel();
+
+    let addr = start_server_thread_with_default_config(move |c| {
+        c.for_each(move |c| {
+            let send = send.clone();
+            tokio::spawn(
+                c.for_each(move |s| {
+                    let send = send.clone();
+                    tokio::spawn(
+                        s.collect()
+                            .map(move |chunks| {
+                                let _ = send.send(chunks);
+                            })
+                            .map_err(|_| ()),
+                    );
+                    Ok(())
+                })
+                .map_err(|_| ()),
+            );
+            Ok(())
+        })
+    });
+
+    let mut config = get_test_config();
+    config.coalesce_stream_writes(false);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let mut con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let stream = evt_loop
+        .block_on(con.new_bidirectional_stream())
+        .expect("creates stream");
+    let (mut stream, _) = evt_loop
+        .block_on(stream.send_all(futures::stream::iter_ok::<_, Error>(writes.clone())))
+        .unwrap();
+    stream.finish().expect("finishes stream");
+
+    let chunks = recv
+        .recv_timeout(Duration::from_secs(5))
+        .expect("server receives the stream");
+    assert_eq!(
+        writes,
+        chunks.into_iter().map(BytesMut::freeze).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn h3_client_receives_response_of_h3_server() {
+    timebomb::timeout_ms(h3_client_receives_response_of_h3_server_inner, 10000);
+}
+
+fn h3_client_receives_response_of_h3_server_inner() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+            config
+        },
+        |c| {
+            c.for_each(|c| {
+                tokio::spawn(
+                    h3::Server::new(c)
+                        .and_then(|server| {
+                            server.for_each(|(request, responder)| {
+                                assert_eq!("GET", request.method);
+                                assert_eq!("/index.html", request.path);
+
+                                let mut response = h3::Response::new(200, "hello h3");
+                                response
+                                    .headers
+                                    .push(("content-type".into(), "text/plain".into()));
+                                responder.send(response)
+                            })
+                        })
+                        .map_err(|e| panic!("HTTP/3 server failed: {}", e)),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let mut config = get_test_config();
+    config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut client = evt_loop
+        .block_on(h3::Client::new(con))
+        .expect("starts HTTP/3");
+    let response = evt_loop
+        .block_on(client.get(&format!("https://{}/index.html", TEST_SERVER_NAME)))
+        .expect("receives response");
+
+    assert_eq!(200, response.status);
+    assert_eq!(Some("text/plain"), response.header("content-type"));
+    assert_eq!(Bytes::from("hello h3"), response.body);
+}
+
+#[test]
+fn h3_serve_answers_requests_with_service() {
+    timebomb::timeout_ms(h3_serve_answers_requests_with_service_inner, 10000);
+}
+
+fn h3_serve_answers_requests_with_service_inner() {
+    let addr = start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+            config
+        },
+        |c| {
+            c.for_each(|c| {
+                tokio::spawn(
+                    h3::serve(c, |request| {
+                        if request.path == "/fail" {
+                            Err(Error::from(ErrorKind::Unknown))
+                        } else {
+                            Ok(h3::Response::new(200, request.path))
+                        }
+                    })
+                    .map_err(|e| panic!("HTTP/3 service failed: {}", e)),
+                );
+                Ok(())
+            })
+        },
+    );
+
+    let mut config = get_test_config();
+    config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut client = evt_loop
+        .block_on(h3::Client::new(con))
+        .expect("starts HTTP/3");
+
+    let response = evt_loop
+        .block_on(client.get(&format!("https://{}/hello", TEST_SERVER_NAME)))
+        .expect("receives response");
+    assert_eq!(200, response.status);
+    assert_eq!(Bytes::from("/hello"), response.body);
+
+    let response = evt_loop
+        .block_on(client.get(&format!("https://{}/fail", TEST_SERVER_NAME)))
+        .expect("receives response");
+    assert_eq!(500, response.status);
+}
+
+/// Starts a WebTransport server, that echoes the data of the first `Stream` of each `Session`.
+fn start_webtransport_echo_server() -> SocketAddr {
+    start_server_thread(
+        || {
+            let mut config = get_test_config();
+            config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+            config
+        },
+        |c| {
+            c.for_each(|c| {
+                tokio::spawn(
+                    webtransport::Server::new(c)
+                        .and_then(|server| {
+                            server.for_each(|request| {
+                                assert_eq!("/echo", request.request().path);
+
+                                request.accept().and_then(|session| {
+                                    session
+                                        .accept_stream()
+                                        .and_then(|s| s.into_future().map_err(|(e, _)| e))
+                                        .and_then(|(data, s)| {
+                                            s.send(data.expect("receives data").freeze())
+                                        })
+                                        .map(move |mut s| {
+                                            s.finish().expect("finishes stream");
+                                            drop(session);
+                                        })
+                                })
+                            })
+                        })
+                        .map_err(|e| panic!("WebTransport server failed: {}", e)),
+                );
+                Ok(())
+            })
+        },
+    )
+}
+
+#[test]
+fn webtransport_session_echoes_stream_data() {
+    timebomb::timeout_ms(webtransport_session_echoes_stream_data_inner, 10000);
+}
+
+fn webtransport_session_echoes_stream_data_inner() {
+    let addr = start_webtransport_echo_server();
+
+    let mut config = get_test_config();
+    config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let url = format!("https://{}/echo", TEST_SERVER_NAME);
+    let mut session = evt_loop
+        .block_on(webtransport::Session::connect(con, &url))
+        .expect("establishes session");
+
+    let stream = evt_loop
+        .block_on(session.open_stream())
+        .expect("opens stream");
+    let mut stream = evt_loop
+        .block_on(stream.send(Bytes::from("hello")))
+        .expect("sends data");
+    stream.finish().expect("finishes stream");
+
+    let data = evt_loop.block_on(stream.concat2()).expect("receives echo");
+    assert_eq!(&b"hello"[..], &data[..]);
+}
+
+#[test]
+fn webtransport_client_establishes_sessions_on_one_connection() {
+    timebomb::timeout_ms(webtransport_client_establishes_sessions_on_one_connection_inner, 10000);
+}
+
+fn webtransport_client_establishes_sessions_on_one_connection_inner() {
+    let addr = start_webtransport_echo_server();
+
+    let mut config = get_test_config();
+    config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(config);
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let mut client = evt_loop
+        .block_on(webtransport::Client::new(con))
+        .expect("starts WebTransport");
+    let url = format!("https://{}/echo", TEST_SERVER_NAME);
+
+    for msg in &["hello", "world"] {
+        let mut session = evt_loop
+            .block_on(client.connect(&url))
+            .expect("establishes session");
+
+        let stream = evt_loop
+            .block_on(session.open_stream())
+            .expect("opens stream");
+        let mut stream = evt_loop
+            .block_on(stream.send(Bytes::from(*msg)))
+            .expect("sends data");
+        stream.finish().expect("finishes stream");
+
+        let data = evt_loop.block_on(stream.concat2()).expect("receives echo");
+        assert_eq!(msg.as_bytes(), &data[..]);
+    }
+}
+
+fn masque_config() -> Config {
+    let mut config = datagram_config();
+    config.set_alpn_protocols(vec![h3::ALPN.to_vec()]);
+    config
+}
+
+#[test]
+fn masque_tunnel_relays_udp_packets_to_target() {
+    timebomb::timeout_ms(masque_tunnel_relays_udp_packets_to_target_inner, 10000);
+}
+
+fn masque_tunnel_relays_udp_packets_to_target_inner() {
+    let target = std::net::UdpSocket::bind("127.0.0.1:0").expect("binds socket");
+    let target_addr = target.local_addr().expect("has local address");
+    thread::spawn(move || {
+        let mut buf = [0; 1500];
+        let (len, from) = target.recv_from(&mut buf).expect("receives packet");
+        target.send_to(&buf[..len], from).expect("sends packet back");
+    });
+
+    let addr = start_server_thread(masque_config, |c| {
+        c.for_each(|c| {
+            tokio::spawn(
+                masque::Proxy::new(c)
+                    .and_then(|proxy| {
+                        proxy.for_each(|request| {
+                            let target =
+                                format!("{}:{}", request.target_host(), request.target_port())
+                                    .parse()
+                                    .expect("target is an address");
+                            tokio::spawn(
+                                request
+                                    .accept(target)
+                                    .map_err(|e| panic!("relay failed: {}", e)),
+                            );
+                            Ok(())
+                        })
+                    })
+                    .map_err(|e| panic!("MASQUE proxy failed: {}", e)),
+            );
+            Ok(())
+        })
+    });
+
+    let (mut context, mut evt_loop) = create_context_and_evt_loop(masque_config());
+
+    let con = evt_loop
+        .block_on(context.new_connection(([127, 0, 0, 1], addr.port()).into(), TEST_SERVER_NAME))
+        .expect("creates connection");
+
+    let proxy = format!("{}:{}", TEST_SERVER_NAME, addr.port());
+    let mut tunnel = evt_loop
+        .block_on(masque::UdpTunnel::connect(con, &proxy, target_addr))
+        .expect("establishes tunnel");
+
+    evt_loop
+        .block_on(futures::future::poll_fn(|| tunnel.poll_send_to(b"ping", &target_addr)))
+        .expect("sends packet");
+
+    let mut buf = [0; 1500];
+    let (len, from) = evt_loop
+        .block_on(futures::future::poll_fn(|| tunnel.poll_recv_from(&mut buf)))
+        .expect("receives packet");
+    assert_eq!(target_addr, from);
+    assert_eq!(&b"ping"[..], &buf[..len]);
 }